@@ -0,0 +1,83 @@
+use axum::{
+    extract::{Path, State},
+    response::Response,
+    routing::{delete, get},
+};
+use cs25_303_core::database::user::lockout::LoginLockout;
+use tracing::{info, instrument};
+use utoipa::OpenApi;
+
+use crate::{
+    app::{
+        SiteState,
+        authentication::{
+            Authentication,
+            permissions::{ManageUsers, response::MissingPermissionResponse},
+        },
+        error::InternalError,
+    },
+    utils::{ErrorReason, builder::ResponseBuilder},
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(active_lockouts, clear_lockout),
+    components(schemas(LoginLockout))
+)]
+pub struct AdminLoginLockoutAPI;
+
+pub fn admin_login_lockout_routes() -> axum::Router<SiteState> {
+    axum::Router::new()
+        .route("/active", get(active_lockouts))
+        .route("/{lockout_id}", delete(clear_lockout))
+}
+/// Returns the users and IP addresses that can not log in because of too many failed logins
+#[utoipa::path(
+    get,
+    path = "/active",
+    responses(
+        (status = 200, description = "Active Lockouts", body = Vec<LoginLockout>, content_type = "application/json"),
+        MissingPermissionResponse<ManageUsers>
+    ),
+    security(
+        ("session" = ["ManageUsers"]),
+    )
+)]
+#[instrument]
+pub async fn active_lockouts(
+    State(site): State<SiteState>,
+    auth: Authentication<ManageUsers>,
+) -> Result<Response, InternalError> {
+    let lockouts = LoginLockout::get_all_active(&site.database).await?;
+    Ok(ResponseBuilder::ok().json(&lockouts))
+}
+/// Ends a lockout. Failed logins before now are no longer counted
+#[utoipa::path(
+    delete,
+    path = "/{lockout_id}",
+    params(
+        ("lockout_id" = i32, Path, description = "Lockout ID")
+    ),
+    responses(
+        (status = 204, description = "Lockout Cleared"),
+        (status = 404, description = "Lockout not found or already ended"),
+        MissingPermissionResponse<ManageUsers>
+    ),
+    security(
+        ("session" = ["ManageUsers"]),
+    )
+)]
+#[instrument]
+pub async fn clear_lockout(
+    State(site): State<SiteState>,
+    Path(lockout_id): Path<i32>,
+    auth: Authentication<ManageUsers>,
+) -> Result<Response, InternalError> {
+    let Some(lockout) = LoginLockout::clear(lockout_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Lockout not found"))
+            .empty());
+    };
+    info!(?lockout, cleared_by = ?auth.user().map(|user| user.id), "Cleared login lockout");
+    Ok(ResponseBuilder::no_content().empty())
+}
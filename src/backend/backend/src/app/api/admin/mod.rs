@@ -2,14 +2,24 @@ use utoipa::OpenApi;
 
 use crate::app::SiteState;
 
+pub mod login_lockout;
+pub mod red_cap;
 pub mod user;
 #[derive(OpenApi)]
 #[openapi(paths(), components(schemas()),
 nest(
     (path = "/user", api = user::AdminUserAPI, tags=["UserAdmin"]),
+    (path = "/red_cap", api = red_cap::AdminRedCapAPI, tags=["RedCapAdmin"]),
+    (path = "/login_lockouts", api = login_lockout::AdminLoginLockoutAPI, tags=["UserAdmin"]),
 ))]
 pub struct AdminAPI;
 
 pub fn admin_routes() -> axum::Router<SiteState> {
-    axum::Router::new().nest("/user", user::admin_user_routes())
+    axum::Router::new()
+        .nest("/user", user::admin_user_routes())
+        .nest("/red_cap", red_cap::admin_red_cap_routes())
+        .nest(
+            "/login_lockouts",
+            login_lockout::admin_login_lockout_routes(),
+        )
 }
@@ -0,0 +1,398 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::Response,
+    routing::{get, post},
+};
+use cs25_303_core::{
+    database::{
+        CSPageParams, PaginatedResponse,
+        red_cap::{
+            changes::{FieldChange, RecordChanges},
+            participants::Participants,
+            sync::{RedCapSyncState, RedCapSyncStatus},
+            sync_conflicts::{RedCapConflictResolution, RedCapSyncConflict},
+            sync_events::{RedCapSyncDirection, RedCapSyncEvent, RedCapSyncInitiator},
+        },
+    },
+    red_cap::{
+        converter::RedCapConverter,
+        sync::{FailedSyncRecord, PulledRecordChanges, RedCapSyncOptions, RedCapSyncReport},
+        tasks::{
+            RedCapTaskError,
+            push::{
+                ParticipantPushPreview, PushFieldChange, PushRowPreview, preview_participant_push,
+            },
+        },
+    },
+};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::{
+    app::{
+        SiteState,
+        authentication::{
+            Authentication,
+            permissions::{SyncRedCap, response::MissingPermissionResponse},
+        },
+        error::InternalError,
+        red_cap_sync::RedCapSyncServiceStatus,
+    },
+    utils::{ErrorReason, builder::ResponseBuilder, json::JsonBody},
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        sync_status,
+        sync_participants,
+        preview_push,
+        sync_events,
+        participant_sync_events,
+        sync_conflicts,
+        participant_sync_conflicts,
+        resolve_conflict
+    ),
+    components(schemas(
+        RedCapSyncServiceStatus,
+        RedCapSyncOptions,
+        RedCapSyncReport,
+        PulledRecordChanges,
+        RecordChanges,
+        FieldChange,
+        FailedSyncRecord,
+        PaginatedResponse<RedCapSyncStatus>,
+        RedCapSyncStatus,
+        RedCapSyncState,
+        ParticipantPushPreview,
+        PushRowPreview,
+        PushFieldChange,
+        PaginatedResponse<RedCapSyncEvent>,
+        RedCapSyncEvent,
+        RedCapSyncDirection,
+        PaginatedResponse<RedCapSyncConflict>,
+        RedCapSyncConflict,
+        RedCapConflictResolution,
+        ResolveConflictRequest
+    ))
+)]
+pub struct AdminRedCapAPI;
+
+pub fn admin_red_cap_routes() -> axum::Router<SiteState> {
+    axum::Router::new()
+        .route("/sync", get(sync_status))
+        .route("/sync/participants", get(sync_participants))
+        .route("/push/{participant_id}/preview", get(preview_push))
+        .route("/events", get(sync_events))
+        .route(
+            "/events/participant/{participant_id}",
+            get(participant_sync_events),
+        )
+        .route("/conflicts", get(sync_conflicts))
+        .route(
+            "/conflicts/participant/{participant_id}",
+            get(participant_sync_conflicts),
+        )
+        .route("/conflicts/{conflict_id}/resolve", post(resolve_conflict))
+}
+/// Returns the state of the Red Cap sync service and the report of the last sync
+#[utoipa::path(
+    get,
+    path = "/sync",
+    responses(
+        (status = 200, description = "Sync Service Status", body = RedCapSyncServiceStatus, content_type = "application/json"),
+        MissingPermissionResponse<SyncRedCap>
+    ),
+    security(
+        ("session" = ["SyncRedCap"]),
+    )
+)]
+#[instrument]
+pub async fn sync_status(
+    State(site): State<SiteState>,
+    auth: Authentication<SyncRedCap>,
+) -> Result<Response, InternalError> {
+    let status = site.red_cap_sync.status().await;
+    Ok(ResponseBuilder::ok().json(&status))
+}
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct SyncParticipantsQuery {
+    /// Only return participants with this status
+    pub status: Option<RedCapSyncState>,
+}
+/// Returns the sync status of each participant. Most recently updated first
+#[utoipa::path(
+    get,
+    path = "/sync/participants",
+    params(
+        CSPageParams,
+        SyncParticipantsQuery
+    ),
+    responses(
+        (status = 200, description = "Participant Sync Statuses", body = PaginatedResponse<RedCapSyncStatus>, content_type = "application/json"),
+        MissingPermissionResponse<SyncRedCap>
+    ),
+    security(
+        ("session" = ["SyncRedCap"]),
+    )
+)]
+#[instrument]
+pub async fn sync_participants(
+    State(site): State<SiteState>,
+    Query(page): Query<CSPageParams>,
+    Query(query): Query<SyncParticipantsQuery>,
+    auth: Authentication<SyncRedCap>,
+) -> Result<Response, InternalError> {
+    let statuses = RedCapSyncStatus::get_all_paginated(query.status, page, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&statuses))
+}
+/// Shows what pushing the participant would send to Red Cap and how it differs from the values in Red Cap.
+///
+/// Nothing is sent to Red Cap
+#[utoipa::path(
+    get,
+    path = "/push/{participant_id}/preview",
+    params(
+        ("participant_id" = i32, Path, description = "Participant ID"),
+    ),
+    responses(
+        (status = 200, description = "Push Preview", body = ParticipantPushPreview, content_type = "application/json"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<SyncRedCap>
+    ),
+    security(
+        ("session" = ["SyncRedCap"]),
+    )
+)]
+#[instrument]
+pub async fn preview_push(
+    State(site): State<SiteState>,
+    Path(participant_id): Path<i32>,
+    auth: Authentication<SyncRedCap>,
+) -> Result<Response, InternalError> {
+    let mut client = site.red_cap_sync.client().await?;
+    if let Some(user) = auth.user() {
+        client = client.with_initiator(RedCapSyncInitiator::User(user.id));
+    }
+    let mut converter = RedCapConverter::new(site.database.clone())
+        .await
+        .map_err(RedCapTaskError::from)?;
+    match preview_participant_push(participant_id, &site.database, &mut converter, &client).await {
+        Ok(preview) => Ok(ResponseBuilder::ok().json(&preview)),
+        Err(RedCapTaskError::ParticipantNotFound) => Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty()),
+        Err(err) => Err(err.into()),
+    }
+}
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct SyncEventsQuery {
+    /// Only return imports or exports
+    pub direction: Option<RedCapSyncDirection>,
+}
+/// Returns every import into and export from Red Cap. Newest first
+#[utoipa::path(
+    get,
+    path = "/events",
+    params(
+        CSPageParams,
+        SyncEventsQuery
+    ),
+    responses(
+        (status = 200, description = "Red Cap Sync Events", body = PaginatedResponse<RedCapSyncEvent>, content_type = "application/json"),
+        MissingPermissionResponse<SyncRedCap>
+    ),
+    security(
+        ("session" = ["SyncRedCap"]),
+    )
+)]
+#[instrument]
+pub async fn sync_events(
+    State(site): State<SiteState>,
+    Query(page): Query<CSPageParams>,
+    Query(query): Query<SyncEventsQuery>,
+    auth: Authentication<SyncRedCap>,
+) -> Result<Response, InternalError> {
+    let events = RedCapSyncEvent::get_all_paginated(query.direction, page, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&events))
+}
+/// Returns the imports and exports of a participant. Newest first
+#[utoipa::path(
+    get,
+    path = "/events/participant/{participant_id}",
+    params(
+        ("participant_id" = i32, Path, description = "Participant ID"),
+        CSPageParams
+    ),
+    responses(
+        (status = 200, description = "Red Cap Sync Events", body = PaginatedResponse<RedCapSyncEvent>, content_type = "application/json"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<SyncRedCap>
+    ),
+    security(
+        ("session" = ["SyncRedCap"]),
+    )
+)]
+#[instrument]
+pub async fn participant_sync_events(
+    State(site): State<SiteState>,
+    Path(participant_id): Path<i32>,
+    Query(page): Query<CSPageParams>,
+    auth: Authentication<SyncRedCap>,
+) -> Result<Response, InternalError> {
+    if !Participants::does_participant_id_exist(participant_id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    let events =
+        RedCapSyncEvent::get_for_participant_paginated(participant_id, page, &site.database)
+            .await?;
+    Ok(ResponseBuilder::ok().json(&events))
+}
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct SyncConflictsQuery {
+    /// Only return resolved or unresolved conflicts
+    pub resolved: Option<bool>,
+}
+/// Returns the fields changed both locally and in Red Cap. Newest first
+#[utoipa::path(
+    get,
+    path = "/conflicts",
+    params(
+        CSPageParams,
+        SyncConflictsQuery
+    ),
+    responses(
+        (status = 200, description = "Red Cap Sync Conflicts", body = PaginatedResponse<RedCapSyncConflict>, content_type = "application/json"),
+        MissingPermissionResponse<SyncRedCap>
+    ),
+    security(
+        ("session" = ["SyncRedCap"]),
+    )
+)]
+#[instrument]
+pub async fn sync_conflicts(
+    State(site): State<SiteState>,
+    Query(page): Query<CSPageParams>,
+    Query(query): Query<SyncConflictsQuery>,
+    auth: Authentication<SyncRedCap>,
+) -> Result<Response, InternalError> {
+    let conflicts =
+        RedCapSyncConflict::get_all_paginated(None, query.resolved, page, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&conflicts))
+}
+/// Returns the conflicts of a participant. Newest first
+#[utoipa::path(
+    get,
+    path = "/conflicts/participant/{participant_id}",
+    params(
+        ("participant_id" = i32, Path, description = "Participant ID"),
+        CSPageParams,
+        SyncConflictsQuery
+    ),
+    responses(
+        (status = 200, description = "Red Cap Sync Conflicts", body = PaginatedResponse<RedCapSyncConflict>, content_type = "application/json"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<SyncRedCap>
+    ),
+    security(
+        ("session" = ["SyncRedCap"]),
+    )
+)]
+#[instrument]
+pub async fn participant_sync_conflicts(
+    State(site): State<SiteState>,
+    Path(participant_id): Path<i32>,
+    Query(page): Query<CSPageParams>,
+    Query(query): Query<SyncConflictsQuery>,
+    auth: Authentication<SyncRedCap>,
+) -> Result<Response, InternalError> {
+    if !Participants::does_participant_id_exist(participant_id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    let conflicts = RedCapSyncConflict::get_all_paginated(
+        Some(participant_id),
+        query.resolved,
+        page,
+        &site.database,
+    )
+    .await?;
+    Ok(ResponseBuilder::ok().json(&conflicts))
+}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ResolveConflictRequest {
+    pub resolution: RedCapConflictResolution,
+    /// Required if the resolution is [RedCapConflictResolution::Manual]. Ignored otherwise
+    #[serde(default)]
+    pub value: Option<String>,
+}
+/// Resolves a conflict. The resolution is sent to Red Cap on the next sync
+///
+/// Once every conflict of a participant is resolved the participant is synced again
+#[utoipa::path(
+    post,
+    path = "/conflicts/{conflict_id}/resolve",
+    params(
+        ("conflict_id" = i32, Path, description = "Conflict ID"),
+    ),
+    request_body(content = ResolveConflictRequest, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Conflict Resolved", body = RedCapSyncConflict, content_type = "application/json"),
+        (status = 400, description = "Missing manual value or the conflict was already applied"),
+        (status = 404, description = "Conflict Not Found"),
+        MissingPermissionResponse<SyncRedCap>
+    ),
+    security(
+        ("session" = ["SyncRedCap"]),
+    )
+)]
+#[instrument]
+pub async fn resolve_conflict(
+    State(site): State<SiteState>,
+    Path(conflict_id): Path<i32>,
+    auth: Authentication<SyncRedCap>,
+    JsonBody(request): JsonBody<ResolveConflictRequest>,
+) -> Result<Response, InternalError> {
+    let Some(mut conflict) = RedCapSyncConflict::find_by_id(conflict_id, &site.database).await?
+    else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Conflict Not Found"))
+            .empty());
+    };
+    if conflict.applied_at.is_some() {
+        return Ok(ResponseBuilder::bad_request()
+            .extension(ErrorReason::from("Conflict Already Applied"))
+            .empty());
+    }
+    let resolved_value = match request.resolution {
+        RedCapConflictResolution::Local => conflict.local_value.clone(),
+        RedCapConflictResolution::Remote => conflict.remote_value.clone(),
+        RedCapConflictResolution::Manual => {
+            let Some(value) = request.value else {
+                return Ok(ResponseBuilder::bad_request()
+                    .extension(ErrorReason::from("Manual Resolution Requires A Value"))
+                    .empty());
+            };
+            Some(value)
+        }
+    };
+    conflict
+        .resolve(
+            request.resolution,
+            resolved_value,
+            auth.user().map(|user| user.id),
+            &site.database,
+        )
+        .await?;
+    Ok(ResponseBuilder::ok().json(&conflict))
+}
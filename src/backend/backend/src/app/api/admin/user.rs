@@ -8,21 +8,37 @@ use chrono::Local;
 use cs25_303_core::database::{
     CSPageParams, PaginatedResponse,
     prelude::*,
-    user::{User, UserColumn, UserType, does_email_exist, does_username_exist, new::NewUser},
+    user::{
+        User, UserColumn, UserType, does_email_exist, does_username_exist,
+        new::{NewUser, create_or_update_user_password},
+    },
 };
+use cs25_303_core::user::Permissions;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, instrument};
+use tracing::{debug, info, instrument};
 use utoipa::{OpenApi, ToSchema};
 
 use crate::{
-    app::{SiteState, authentication::Authentication, error::InternalError},
-    utils::{ErrorReason, builder::ResponseBuilder, conflict::ConflictResponse},
+    app::{
+        SiteState,
+        authentication::{
+            Authentication,
+            permissions::{ManageUsers, response::MissingPermissionResponse},
+            session::SessionDetails,
+            utils::password::encrypt_password,
+        },
+        error::InternalError,
+    },
+    utils::{
+        ErrorReason, builder::ResponseBuilder, conflict::ConflictResponse,
+        password_policy::PasswordPolicyResponse,
+    },
 };
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(all_users, new_user, update_user),
-    components(schemas(PaginatedResponse<User>, User, NewUser, UpdateUser, ConflictResponse))
+    paths(all_users, new_user, update_user, set_user_password, user_sessions, revoke_user_sessions),
+    components(schemas(PaginatedResponse<User>, User, NewUser, NewUserRequest, UpdateUser, SetUserPassword, ConflictResponse, SessionDetails))
 )]
 pub struct AdminUserAPI;
 
@@ -31,6 +47,11 @@ pub fn admin_user_routes() -> axum::Router<SiteState> {
         .route("/all", get(all_users))
         .route("/new", post(new_user))
         .route("/{user_id}/update", post(update_user))
+        .route("/{user_id}/password", post(set_user_password))
+        .route(
+            "/{user_id}/sessions",
+            get(user_sessions).delete(revoke_user_sessions),
+        )
 }
 /// Returns a list of all users
 #[utoipa::path(
@@ -42,6 +63,7 @@ pub fn admin_user_routes() -> axum::Router<SiteState> {
     responses(
         (status = 200, description = "Participants Found", body = PaginatedResponse<User>, content_type = "application/json"),
         (status = 401, description = "Not Authorized to access all users"),
+        MissingPermissionResponse<ManageUsers>
     ),
     security(
         ("session" = ["ManageUsers"]),
@@ -52,7 +74,7 @@ pub fn admin_user_routes() -> axum::Router<SiteState> {
 pub async fn all_users(
     State(site): State<SiteState>,
     Query(page): Query<CSPageParams>,
-    auth: Authentication,
+    auth: Authentication<ManageUsers>,
 ) -> Result<Response, InternalError> {
     let CSPageParams {
         page_size,
@@ -62,26 +84,57 @@ pub async fn all_users(
 
     Ok(ResponseBuilder::ok().json(&users))
 }
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewUserRequest {
+    #[serde(flatten)]
+    pub user: NewUser,
+    /// The initial password. Must meet the password policy
+    #[serde(default)]
+    pub password: Option<String>,
+    /// The user must change the password after logging in. Defaults to true
+    #[serde(default = "default_require_password_reset")]
+    pub require_password_reset: bool,
+}
+fn default_require_password_reset() -> bool {
+    true
+}
 /// Creates a new user
+///
+/// If a password is given it is checked against the password policy
 #[utoipa::path(
     post,
     path = "/new",
-    request_body(content = NewUser, content_type = "application/json"),
+    request_body(content = NewUserRequest, content_type = "application/json"),
     responses(
         (status = 200, description = "Successfully Created a new user", body = User, content_type = "application/json"),
         (status = 401, description = "Not Authorized to create a new user"),
-        ConflictResponse
+        (status = 403, description = "A password was given but Password Authentication is not enabled"),
+        ConflictResponse,
+        PasswordPolicyResponse,
+        MissingPermissionResponse<ManageUsers>
     ),
     security(
         ("session" = ["ManageUsers"]),
     )
 )]
-#[instrument]
+#[instrument(skip(request))]
 pub async fn new_user(
     State(site): State<SiteState>,
-    auth: Authentication,
-    Json(new_user): Json<NewUser>,
+    auth: Authentication<ManageUsers>,
+    Json(request): Json<NewUserRequest>,
 ) -> Result<Response, InternalError> {
+    let NewUserRequest {
+        user: new_user,
+        password,
+        require_password_reset,
+    } = request;
+    let password_hash = match password {
+        Some(password) => match hash_password_with_policy(&site, &password) {
+            Ok(hash) => Some(hash),
+            Err(response) => return Ok(response),
+        },
+        None => None,
+    };
     if new_user.check_if_username_is_in_use(&site.database).await? {
         debug!(?new_user.username, "Username already in use");
         return Ok(ConflictResponse::from("username").into_response());
@@ -92,9 +145,202 @@ pub async fn new_user(
         return Ok(ConflictResponse::from("email").into_response());
     }
 
-    let user = new_user.insert_return_user(&site.database).await?;
+    let mut transaction = site.database.begin().await?;
+    let user = new_user.insert_return_user(&mut *transaction).await?;
+    if let Some(password_hash) = password_hash {
+        create_or_update_user_password(
+            user.id,
+            &password_hash,
+            require_password_reset,
+            &mut *transaction,
+        )
+        .await?;
+    }
+    transaction.commit().await?;
     Ok(ResponseBuilder::ok().json(&user))
 }
+/// Checks the password against the password policy and hashes it
+///
+/// Returns the response to send if the password can not be used
+fn hash_password_with_policy(site: &SiteState, password: &str) -> Result<String, Response> {
+    let Some(password_config) = site.authentication.password.as_ref() else {
+        return Err(ResponseBuilder::forbidden()
+            .extension(ErrorReason::from("Password Authentication is not enabled"))
+            .empty());
+    };
+    let violations = password_config.validate(password);
+    if !violations.is_empty() {
+        return Err(PasswordPolicyResponse(violations).into_response());
+    }
+    encrypt_password(password).ok_or_else(|| {
+        ResponseBuilder::internal_server_error()
+            .extension(ErrorReason::from("Failed to hash password"))
+            .empty()
+    })
+}
+/// Returns a forbidden response if the target is an admin and the caller is not
+///
+/// Otherwise a user manager could take over an admin account
+async fn only_admins_can_manage_admins(
+    target: &User,
+    auth: &Authentication<ManageUsers>,
+    site: &SiteState,
+) -> Result<Option<Response>, InternalError> {
+    if !target
+        .has_permission(Permissions::Admin, &site.database)
+        .await?
+    {
+        return Ok(None);
+    }
+    let caller_is_admin = match auth.user() {
+        Some(user) => {
+            user.has_permission(Permissions::Admin, &site.database)
+                .await?
+        }
+        None => false,
+    };
+    if caller_is_admin {
+        return Ok(None);
+    }
+    Ok(Some(
+        ResponseBuilder::forbidden()
+            .extension(ErrorReason::from("Only admins can manage an admin"))
+            .empty(),
+    ))
+}
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetUserPassword {
+    /// Must meet the password policy
+    pub password: String,
+    /// The user must change the password after logging in. Defaults to true
+    #[serde(default = "default_require_password_reset")]
+    pub require_password_reset: bool,
+}
+/// Sets the password of a user. Every session of the user is logged out
+///
+/// Used to give a user a temporary password that must be changed after logging in.
+/// Only admins can set the password of another admin
+#[utoipa::path(
+    post,
+    path = "/{id}/password",
+    request_body(content = SetUserPassword, content_type = "application/json"),
+    params(
+        ("id" = i32, Path, description = "User ID")
+    ),
+    responses(
+        (status = 204, description = "Password Set"),
+        (status = 401, description = "Not Authorized to update user"),
+        (status = 403, description = "Password Authentication is not enabled or the user is an admin and the caller is not"),
+        (status = 404, description = "User not found"),
+        PasswordPolicyResponse,
+        MissingPermissionResponse<ManageUsers>
+    ),
+    security(
+        ("session" = ["ManageUsers"]),
+    )
+)]
+#[instrument(skip(request))]
+pub async fn set_user_password(
+    State(site): State<SiteState>,
+    Path(user_id): Path<i32>,
+    auth: Authentication<ManageUsers>,
+    Json(request): Json<SetUserPassword>,
+) -> Result<Response, InternalError> {
+    let Some(target) = User::get_by_id(user_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("User not found"))
+            .empty());
+    };
+    if let Some(response) = only_admins_can_manage_admins(&target, &auth, &site).await? {
+        return Ok(response);
+    }
+    let hash = match hash_password_with_policy(&site, &request.password) {
+        Ok(hash) => hash,
+        Err(response) => return Ok(response),
+    };
+    create_or_update_user_password(
+        user_id,
+        &hash,
+        request.require_password_reset,
+        &site.database,
+    )
+    .await?;
+    site.session.delete_all_for_user(user_id)?;
+    Ok(ResponseBuilder::no_content().empty())
+}
+/// Returns the sessions of a user. Oldest first
+#[utoipa::path(
+    get,
+    path = "/{id}/sessions",
+    params(
+        ("id" = i32, Path, description = "User ID")
+    ),
+    responses(
+        (status = 200, description = "Sessions", body = Vec<SessionDetails>, content_type = "application/json"),
+        (status = 404, description = "User not found"),
+        MissingPermissionResponse<ManageUsers>
+    ),
+    security(
+        ("session" = ["ManageUsers"]),
+    )
+)]
+#[instrument]
+pub async fn user_sessions(
+    State(site): State<SiteState>,
+    Path(user_id): Path<i32>,
+    auth: Authentication<ManageUsers>,
+) -> Result<Response, InternalError> {
+    if User::get_by_id(user_id, &site.database).await?.is_none() {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("User not found"))
+            .empty());
+    }
+    let current_session_key = match &auth {
+        Authentication::UserViaSession { session, .. } => Some(session.session_key.as_str()),
+        _ => None,
+    };
+    let sessions = site.session.get_sessions_for_user(user_id)?;
+    let sessions =
+        SessionDetails::from_sessions(sessions, current_session_key, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&sessions))
+}
+/// Logs out every session of a user
+///
+/// Only admins can log out an admin
+#[utoipa::path(
+    delete,
+    path = "/{id}/sessions",
+    params(
+        ("id" = i32, Path, description = "User ID")
+    ),
+    responses(
+        (status = 204, description = "Sessions Revoked"),
+        (status = 403, description = "The user is an admin and the caller is not"),
+        (status = 404, description = "User not found"),
+        MissingPermissionResponse<ManageUsers>
+    ),
+    security(
+        ("session" = ["ManageUsers"]),
+    )
+)]
+#[instrument]
+pub async fn revoke_user_sessions(
+    State(site): State<SiteState>,
+    Path(user_id): Path<i32>,
+    auth: Authentication<ManageUsers>,
+) -> Result<Response, InternalError> {
+    let Some(target) = User::get_by_id(user_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("User not found"))
+            .empty());
+    };
+    if let Some(response) = only_admins_can_manage_admins(&target, &auth, &site).await? {
+        return Ok(response);
+    }
+    let revoked = site.session.delete_all_for_user(user_id)?;
+    info!(?user_id, ?revoked, "Revoked sessions of user");
+    Ok(ResponseBuilder::no_content().empty())
+}
 #[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
 #[serde(default)]
 pub struct UpdateUser {
@@ -110,8 +356,12 @@ pub struct UpdateUser {
     /// The new last name of the user.
     #[serde(with = "crate::utils::serde_sanitize_string")]
     pub last_name: Option<String>,
+    /// Deactivated users can not log in. Deactivating a user logs out all of their sessions
+    pub active: Option<bool>,
 }
-
+/// Updates a user
+///
+/// Only admins can change the username, email or active state of an admin
 #[utoipa::path(
     post,
     path = "/{id}/update",
@@ -122,8 +372,10 @@ pub struct UpdateUser {
     responses(
         (status = 200, description = "Successfully Created a new user", body = User, content_type = "application/json"),
         (status = 401, description = "Not Authorized to update user"),
+        (status = 403, description = "The user is an admin and the caller is not"),
         (status = 404, description = "User not found"),
-        ConflictResponse
+        ConflictResponse,
+        MissingPermissionResponse<ManageUsers>
     ),
     security(
         ("session" = ["ManageUsers"]),
@@ -133,7 +385,7 @@ pub struct UpdateUser {
 pub async fn update_user(
     State(site): State<SiteState>,
     Path(user_id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ManageUsers>,
     Json(update): Json<UpdateUser>,
 ) -> Result<Response, InternalError> {
     let Some(user_to_update) = User::get_by_id(user_id, &site.database).await? else {
@@ -146,7 +398,14 @@ pub async fn update_user(
         email,
         first_name,
         last_name,
+        active,
     } = update;
+    // An admin's email could be changed to take over the account through OpenID Connect
+    if (username.is_some() || email.is_some() || active.is_some())
+        && let Some(response) = only_admins_can_manage_admins(&user_to_update, &auth, &site).await?
+    {
+        return Ok(response);
+    }
 
     let mut update = UpdateQueryBuilder::new(User::table_name());
     update
@@ -176,8 +435,130 @@ pub async fn update_user(
     if let Some(last_name) = last_name {
         update.set(UserColumn::LastName, last_name.value());
     }
+    if let Some(active) = active {
+        update.set(UserColumn::Active, active.value());
+    }
 
     update.query().execute(&site.database).await?;
+    if active == Some(false) {
+        let revoked = site.session.delete_all_for_user(user_id)?;
+        info!(
+            ?user_id,
+            ?revoked,
+            "Deactivated user. Revoked their sessions"
+        );
+    }
     let user = User::get_by_id(user_id, &site.database).await?;
     Ok(ResponseBuilder::ok().json(&user))
 }
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use sqlx::types::Uuid;
+
+    use super::*;
+    use crate::utils::testing::{get_testing_site, new_testing_user, no_db_connection};
+    #[tokio::test]
+    #[ignore]
+    async fn deactivating_a_user_logs_out_their_sessions() -> anyhow::Result<()> {
+        let Some(site) = get_testing_site().await else {
+            no_db_connection();
+            return Ok(());
+        };
+        let admin = new_testing_user(Some("Admin"), &site.database).await?;
+        let admin_session = site
+            .session
+            .create_session_default_lifespan(admin.id, Uuid::new_v4())?;
+        let user = new_testing_user(Some("Clinician"), &site.database).await?;
+        site.session
+            .create_session_default_lifespan(user.id, Uuid::new_v4())?;
+        site.session
+            .create_session_default_lifespan(user.id, Uuid::new_v4())?;
+
+        let auth = Authentication::UserViaSession {
+            user: admin.clone(),
+            session: admin_session.clone(),
+        };
+        let update = UpdateUser {
+            active: Some(false),
+            ..Default::default()
+        };
+        let response = update_user(State(site.clone()), Path(user.id), auth, Json(update)).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(site.session.get_sessions_for_user(user.id)?.is_empty());
+        assert_eq!(
+            site.session.get_sessions_for_user(admin.id)?,
+            vec![admin_session]
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn user_managers_can_not_manage_admins() -> anyhow::Result<()> {
+        let Some(site) = get_testing_site().await else {
+            no_db_connection();
+            return Ok(());
+        };
+        let admin = new_testing_user(Some("Admin"), &site.database).await?;
+        site.session
+            .create_session_default_lifespan(admin.id, Uuid::new_v4())?;
+        let manager = new_testing_user(None, &site.database).await?;
+        sqlx::query("INSERT INTO user_permissions(user_id, permission) VALUES ($1, $2)")
+            .bind(manager.id)
+            .bind(Permissions::ManageUsers)
+            .execute(&site.database)
+            .await?;
+        let manager_session = site
+            .session
+            .create_session_default_lifespan(manager.id, Uuid::new_v4())?;
+        let auth = Authentication::UserViaSession {
+            user: manager.clone(),
+            session: manager_session,
+        };
+
+        let email = UpdateUser {
+            email: Some(format!("taken_over_{}@example.com", admin.id)),
+            ..Default::default()
+        };
+        let response = update_user(
+            State(site.clone()),
+            Path(admin.id),
+            auth.clone(),
+            Json(email),
+        )
+        .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let deactivate = UpdateUser {
+            active: Some(false),
+            ..Default::default()
+        };
+        let response = update_user(
+            State(site.clone()),
+            Path(admin.id),
+            auth.clone(),
+            Json(deactivate),
+        )
+        .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response =
+            revoke_user_sessions(State(site.clone()), Path(admin.id), auth.clone()).await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(site.session.get_sessions_for_user(admin.id)?.len(), 1);
+
+        let unchanged = User::get_by_id(admin.id, &site.database)
+            .await?
+            .expect("Admin should exist");
+        assert_eq!(unchanged, admin);
+
+        // Names of admins can still be changed
+        let name = UpdateUser {
+            first_name: Some("Renamed".to_owned()),
+            ..Default::default()
+        };
+        let response = update_user(State(site.clone()), Path(admin.id), auth, Json(name)).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        Ok(())
+    }
+}
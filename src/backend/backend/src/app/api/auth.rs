@@ -1,21 +1,36 @@
 use axum::{
-    extract::State,
+    extract::{Query, State},
     response::{IntoResponse, Response},
 };
 use axum_extra::{
     TypedHeader,
-    extract::cookie::{Cookie, Expiration},
+    extract::{
+        CookieJar,
+        cookie::{Cookie, Expiration},
+    },
     headers::UserAgent,
 };
-use cs25_303_core::database::user::login::AdditionalFootprint;
-use http::header::SET_COOKIE;
-use tracing::{debug, instrument};
+use cs25_303_core::{
+    database::user::{
+        User,
+        auth::oidc::find_or_provision_oidc_user,
+        login::{AdditionalFootprint, add_login_attempt},
+    },
+    user::auth::oidc::{OidcClient, OidcError},
+};
+use http::header::{LOCATION, SET_COOKIE};
+use serde::Deserialize;
+use tracing::{debug, instrument, warn};
 use utoipa::{OpenApi, ToSchema};
 
 use crate::{
     app::{
         SiteState,
-        authentication::{Authentication, MeWithSession, utils::verify_login},
+        authentication::{
+            AllowPasswordReset, Authentication, MeWithSession,
+            session::Session,
+            utils::{VerifiedLogin, verify_login},
+        },
         error::InternalError,
     },
     utils::{
@@ -26,13 +41,18 @@ use crate::{
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(login, logout),
+    paths(login, login_oidc, login_oidc_callback, logout),
     components(schemas(LoginPasswordBody, MeWithSession))
 )]
 pub struct AuthApi;
 pub fn auth_routes() -> axum::Router<SiteState> {
     axum::Router::new()
         .route("/login/password", axum::routing::post(login))
+        .route("/login/oidc", axum::routing::get(login_oidc))
+        .route(
+            "/login/oidc/callback",
+            axum::routing::get(login_oidc_callback),
+        )
         .route("/logout", axum::routing::get(logout))
 }
 
@@ -47,6 +67,8 @@ pub struct LoginPasswordBody {
     pub password: String,
 }
 /// Attempts a user login with a password.
+///
+/// If `password_reset_required` is true the session can only be used to change the password at `/api/user/password` and to log out.
 #[utoipa::path(
     post,
     path = "/login/password",
@@ -54,8 +76,9 @@ pub struct LoginPasswordBody {
     responses(
         (status = 200, description = "Login successful", body = MeWithSession, content_type = "application/json"),
         (status = 400, description = "Bad Request. Note: This request requires a User-Agent Header"),
-        (status = 401, description = "Unauthorized"),
+        (status = 401, description = "Unauthorized. Also returned while the user is locked"),
         (status = 403, description = "Password Authentication is not enabled"),
+        (status = 429, description = "Too many failed logins from the IP address. The `Retry-After` header is the seconds until the lock ends"),
     ),
     summary = "Attempt User login with a password",
     security(
@@ -88,11 +111,16 @@ pub async fn login(
         user_agent: user_agent.to_string(),
         request_id: request_id.to_string(),
     };
-    let (user, login_id) = match verify_login(
+    let VerifiedLogin {
+        user,
+        login_id,
+        password_reset_required,
+    } = match verify_login(
         email_or_username,
         password,
         ip_addr.to_string(),
         Some(additional_footprint),
+        &site.authentication.login_throttle,
         &site.database,
     )
     .await
@@ -103,6 +131,22 @@ pub async fn login(
         }
     };
 
+    let (session, cookie) = create_session_cookie(&site, &user, login_id)?;
+    let user_with_session = MeWithSession {
+        session,
+        user,
+        password_reset_required,
+    };
+    return Ok(ResponseBuilder::ok()
+        .header(SET_COOKIE, cookie)
+        .json(&user_with_session));
+}
+/// Creates a session for the login. Returns the session and the `Set-Cookie` value
+fn create_session_cookie(
+    site: &SiteState,
+    user: &User,
+    login_id: sqlx::types::Uuid,
+) -> Result<(Session, String), InternalError> {
     let duration = chrono::Duration::days(1);
 
     let session = site.session.create_session(user.id, login_id, duration)?;
@@ -111,10 +155,247 @@ pub async fn login(
         .path("/")
         .expires(Expiration::Session)
         .build();
-    let user_with_session = MeWithSession::from((session.clone(), user));
-    return Ok(ResponseBuilder::ok()
-        .header(SET_COOKIE, cookie.encoded().to_string())
-        .json(&user_with_session));
+    Ok((session, cookie.encoded().to_string()))
+}
+fn oidc_not_enabled() -> Response {
+    ResponseBuilder::forbidden()
+        .extension(ErrorReason::from(
+            "OpenID Connect Authentication is not enabled",
+        ))
+        .json(&APIErrorResponse::<(), ()> {
+            message: "OpenID Connect Authentication is not enabled".into(),
+            details: None,
+            error: None,
+        })
+}
+/// Holds the state of an OpenID Connect login. The callback is only accepted from the browser that started the login
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+/// The cookie is only sent to the callback
+const OIDC_STATE_COOKIE_PATH: &str = "/api/auth/login/oidc";
+/// `Set-Cookie` for the state cookie. The state is alphanumeric so it does not need encoding
+///
+/// Lax so the cookie is sent when the provider redirects back
+fn oidc_state_cookie(state: &str, max_age: u64) -> String {
+    format!(
+        "{OIDC_STATE_COOKIE}={state}; Path={OIDC_STATE_COOKIE_PATH}; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax"
+    )
+}
+/// Starts a login with the OpenID Connect provider.
+///
+/// Redirects to the provider's login page. The provider sends the user back to `/login/oidc/callback`
+#[utoipa::path(
+    get,
+    path = "/login/oidc",
+    responses(
+        (status = 303, description = "Redirect to the OpenID Connect provider"),
+        (status = 403, description = "OpenID Connect Authentication is not enabled"),
+        (status = 429, description = "Too many logins are in progress"),
+    ),
+    summary = "Start a single sign-on login",
+    security(
+        (),
+    )
+)]
+#[instrument]
+pub async fn login_oidc(State(site): State<SiteState>) -> Result<Response, InternalError> {
+    let Some(oidc) = site.oidc.as_ref() else {
+        return Ok(oidc_not_enabled());
+    };
+    let login = match oidc.begin_login().await {
+        Ok(ok) => ok,
+        Err(OidcError::TooManyPendingLogins) => {
+            return Ok(ResponseBuilder::too_many_requests()
+                .extension(ErrorReason::from(
+                    "Too many OpenID Connect logins in progress",
+                ))
+                .empty());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    Ok(ResponseBuilder::see_other()
+        .header(
+            SET_COOKIE,
+            oidc_state_cookie(&login.state, oidc.config().login_timeout),
+        )
+        .header(LOCATION, login.url.to_string())
+        .empty())
+}
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub state: String,
+    #[serde(default)]
+    pub code: Option<String>,
+    /// Set by the provider if the login failed
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_description: Option<String>,
+}
+/// Where the OpenID Connect provider sends the user back to.
+///
+/// The `state` must match the state cookie set by `/login/oidc`.
+///
+/// If the login succeeds a session cookie is set and the user is redirected to `post_login_redirect`
+#[utoipa::path(
+    get,
+    path = "/login/oidc/callback",
+    params(
+        ("state" = String, Query, description = "The state of the login"),
+        ("code" = Option<String>, Query, description = "The authorization code"),
+    ),
+    responses(
+        (status = 303, description = "Login successful. Redirect to the frontend"),
+        (status = 400, description = "Bad Request. Note: This request requires a User-Agent Header"),
+        (status = 401, description = "The login failed, was started by another browser or no user matches the provider's account"),
+        (status = 403, description = "OpenID Connect Authentication is not enabled"),
+    ),
+    summary = "Finish a single sign-on login",
+    security(
+        (),
+    )
+)]
+#[instrument(skip(cookies, query))]
+pub async fn login_oidc_callback(
+    State(site): State<SiteState>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    request_id: RequestId,
+    ConnectionIpAddr(ip_addr): ConnectionIpAddr,
+    cookies: CookieJar,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Result<Response, InternalError> {
+    let Some(oidc) = site.oidc.as_ref() else {
+        return Ok(oidc_not_enabled());
+    };
+    let ip_address = ip_addr.to_string();
+    let additional_footprint = AdditionalFootprint {
+        user_agent: user_agent.to_string(),
+        request_id: request_id.to_string(),
+    };
+    let response = oidc_callback_inner(
+        &site,
+        oidc,
+        ip_address,
+        additional_footprint,
+        cookies,
+        query,
+    )
+    .await?;
+    // The state can only be used once. So the cookie is removed whether the login succeeded or not
+    let (mut parts, body) = response.into_parts();
+    if let Ok(clear) = oidc_state_cookie("", 0).try_into() {
+        parts.headers.append(SET_COOKIE, clear);
+    }
+    Ok(Response::from_parts(parts, body))
+}
+async fn oidc_callback_inner(
+    site: &SiteState,
+    oidc: &OidcClient,
+    ip_address: String,
+    additional_footprint: AdditionalFootprint,
+    cookies: CookieJar,
+    query: OidcCallbackQuery,
+) -> Result<Response, InternalError> {
+    let state_matches = cookies
+        .get(OIDC_STATE_COOKIE)
+        .is_some_and(|cookie| !cookie.value().is_empty() && cookie.value() == query.state);
+    if !state_matches {
+        warn!("OpenID Connect callback was not started by this browser");
+        add_login_attempt(
+            None,
+            &ip_address,
+            false,
+            Some(additional_footprint),
+            &site.database,
+        )
+        .await?;
+        return Ok(oidc_login_failed(
+            "The login was not started by this browser",
+        ));
+    }
+    let claims = match query.code {
+        Some(code) => oidc.finish_login(&query.state, &code).await,
+        None => {
+            warn!(?query.error, ?query.error_description, "OpenID provider returned an error");
+            add_login_attempt(
+                None,
+                &ip_address,
+                false,
+                Some(additional_footprint),
+                &site.database,
+            )
+            .await?;
+            return Ok(oidc_login_failed(
+                query
+                    .error_description
+                    .or(query.error)
+                    .unwrap_or_else(|| "No code was provided".to_owned()),
+            ));
+        }
+    };
+    let claims = match claims {
+        Ok(ok) => ok,
+        Err(err) => {
+            warn!(?err, "OpenID Connect login failed");
+            add_login_attempt(
+                None,
+                &ip_address,
+                false,
+                Some(additional_footprint),
+                &site.database,
+            )
+            .await?;
+            return Ok(oidc_login_failed(err.to_string()));
+        }
+    };
+    let Some(user) = find_or_provision_oidc_user(&claims, oidc.config(), &site.database).await?
+    else {
+        debug!(?claims.sub, "No user matches the OpenID Connect account");
+        add_login_attempt(
+            None,
+            &ip_address,
+            false,
+            Some(additional_footprint),
+            &site.database,
+        )
+        .await?;
+        return Ok(oidc_login_failed(
+            "No user matches the OpenID Connect account",
+        ));
+    };
+    if !user.active {
+        debug!(?user.id, "User is deactivated");
+        add_login_attempt(
+            Some(user.id),
+            &ip_address,
+            false,
+            Some(additional_footprint),
+            &site.database,
+        )
+        .await?;
+        return Ok(oidc_login_failed("User is deactivated"));
+    }
+    let login_id = add_login_attempt(
+        Some(user.id),
+        &ip_address,
+        true,
+        Some(additional_footprint),
+        &site.database,
+    )
+    .await?;
+    let (_, cookie) = create_session_cookie(site, &user, login_id)?;
+    Ok(ResponseBuilder::see_other()
+        .header(SET_COOKIE, cookie)
+        .header(LOCATION, oidc.config().post_login_redirect.as_str())
+        .empty())
+}
+fn oidc_login_failed(reason: impl Into<String>) -> Response {
+    ResponseBuilder::unauthorized()
+        .extension(ErrorReason::from(reason.into()))
+        .json(&APIErrorResponse::<(), ()> {
+            message: "Single sign-on login failed".into(),
+            details: None,
+            error: None,
+        })
 }
 #[utoipa::path(
     get,
@@ -131,7 +412,7 @@ pub async fn login(
 #[instrument]
 pub async fn logout(
     State(site): State<SiteState>,
-    auth: Authentication,
+    AllowPasswordReset(auth): AllowPasswordReset,
 ) -> Result<Response, InternalError> {
     match auth {
         Authentication::UserViaSession { user: _, session } => {
@@ -14,7 +14,14 @@ use tracing::instrument;
 use utoipa::OpenApi;
 
 use crate::{
-    app::{SiteState, authentication::Authentication, error::InternalError},
+    app::{
+        SiteState,
+        authentication::{
+            Authentication,
+            permissions::{ReadParticipants, response::MissingPermissionResponse},
+        },
+        error::InternalError,
+    },
     utils::{ResponseBuilder, json::JsonBody},
 };
 
@@ -48,17 +55,18 @@ pub fn debug_reports() -> axum::Router<SiteState> {
     ),
     request_body(content = ParticipantsWithNoGoals, content_type = "application/json"),
     responses(
-        (status = 200, description = "Participants Found", body = PaginatedResponse<DebugParticipantSummary>)
+        (status = 200, description = "Participants Found", body = PaginatedResponse<DebugParticipantSummary>),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
 pub async fn no_goals(
     State(site): State<SiteState>,
     Query(page): Query<CSPageParams>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
     JsonBody(query): JsonBody<ParticipantsWithNoGoals>,
 ) -> Result<Response, InternalError> {
     let participants = query.execute(page, &site.database).await?;
@@ -74,17 +82,18 @@ pub async fn no_goals(
     ),
     request_body(content = ParticipantsWithNoMedications, content_type = "application/json"),
     responses(
-        (status = 200, description = "Participants Found", body = PaginatedResponse<DebugParticipantSummary>)
+        (status = 200, description = "Participants Found", body = PaginatedResponse<DebugParticipantSummary>),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
 pub async fn no_medications(
     State(site): State<SiteState>,
     Query(page): Query<CSPageParams>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
     JsonBody(query): JsonBody<ParticipantsWithNoMedications>,
 ) -> Result<Response, InternalError> {
     let participants = query.execute(page, &site.database).await?;
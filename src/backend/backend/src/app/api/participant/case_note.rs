@@ -1,32 +1,83 @@
 use axum::{
     extract::{Path, State},
-    response::Response,
-    routing::get,
+    response::{IntoResponse, Response},
+    routing::{get, post},
 };
-use cs25_303_core::database::red_cap::{
-    case_notes::{CaseNoteType, queries::CaseNoteListItem},
-    participants::Participants,
+use cs25_303_core::database::{
+    DBError,
+    red_cap::{
+        case_notes::{
+            CaseNote, CaseNoteHealthMeasures, CaseNoteType,
+            new::{CaseNoteBaseValues, NewBloodPressure, NewCaseNote, NewCaseNoteHealthMeasures},
+            queries::{
+                CaseNoteAnswerDetails, CaseNoteCategoryAnswers, CaseNoteDetails, CaseNoteListItem,
+            },
+            questions::{
+                NewCaseNoteQuestionAnswer, ResolvedCaseNoteAnswer, check_answer_requirements,
+                delete_question_answer,
+            },
+        },
+        participants::Participants,
+    },
 };
-use tracing::instrument;
-use utoipa::OpenApi;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use tracing::{debug, instrument};
+use utoipa::{OpenApi, ToSchema};
 
+use super::participant_updates_disabled;
 use crate::utils::response::ResponseBuilder;
 use crate::{
-    app::{SiteState, authentication::Authentication, error::InternalError},
-    utils::ErrorReason,
+    app::{
+        SiteState,
+        authentication::{
+            Authentication,
+            permissions::{
+                ReadParticipants, UpdateParticipants, response::MissingPermissionResponse,
+            },
+        },
+        error::InternalError,
+    },
+    utils::{
+        ErrorReason, api_error_response::APIErrorResponse, conflict::ConflictResponse,
+        json::JsonBody,
+    },
 };
 #[derive(OpenApi)]
 #[openapi(
-    paths(get_all_case_notes_for_participant),
-    components(schemas(CaseNoteListItem))
+    paths(
+        get_all_case_notes_for_participant,
+        get_case_note,
+        new_case_note,
+        update_case_note,
+        complete_case_note
+    ),
+    components(schemas(
+        CaseNoteListItem,
+        CaseNote,
+        CaseNoteDetails,
+        CaseNoteCategoryAnswers,
+        CaseNoteAnswerDetails,
+        CaseNoteRequest,
+        CaseNoteBaseValues,
+        NewCaseNoteHealthMeasures,
+        NewBloodPressure,
+        NewCaseNoteQuestionAnswer,
+        CaseNoteSaved
+    ))
 )]
 pub struct CaseNoteAPI;
 
 pub fn case_note_routes() -> axum::Router<SiteState> {
-    axum::Router::new().route(
-        "/{participant_id}/list/all",
-        get(get_all_case_notes_for_participant),
-    )
+    axum::Router::new()
+        .route("/{id}", get(get_case_note))
+        .route(
+            "/{participant_id}/list/all",
+            get(get_all_case_notes_for_participant),
+        )
+        .route("/{participant_id}/new", post(new_case_note))
+        .route("/{case_note_id}/update", post(update_case_note))
+        .route("/{case_note_id}/complete", post(complete_case_note))
 }
 /// Returns a list of all case notes for a participant
 #[utoipa::path(
@@ -38,16 +89,17 @@ pub fn case_note_routes() -> axum::Router<SiteState> {
     responses(
         (status = 200, description = "Participants Found", body = Vec<CaseNoteListItem>, content_type = "application/json"),
         (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
 pub async fn get_all_case_notes_for_participant(
     State(site): State<SiteState>,
     Path(id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let case_notes = CaseNoteListItem::get_all_by_participant_id(id, &site.database).await?;
     // If the participant does not exist, return a 404
@@ -59,3 +111,389 @@ pub async fn get_all_case_notes_for_participant(
     }
     Ok(ResponseBuilder::ok().json(&case_notes))
 }
+/// Returns a case note with its health measures and question answers grouped by category
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    params(
+        ("id" = i32, Path, description = "Case Note ID")
+    ),
+    responses(
+        (status = 200, description = "Case Note Found", body = CaseNoteDetails, content_type = "application/json"),
+        (status = 404, description = "Case Note Not Found"),
+        MissingPermissionResponse<ReadParticipants>
+    ),
+    security(
+        ("session" = ["ReadParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn get_case_note(
+    State(site): State<SiteState>,
+    Path(id): Path<i32>,
+    auth: Authentication<ReadParticipants>,
+) -> Result<Response, InternalError> {
+    let Some(case_note) = CaseNoteDetails::find_by_id(id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Case Note Not Found"))
+            .empty());
+    };
+    Ok(ResponseBuilder::ok().json(&case_note))
+}
+/// The body used to create or update a case note
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CaseNoteRequest {
+    #[serde(flatten)]
+    pub case_note: CaseNoteBaseValues,
+    /// If set the health measures will be created or replaced
+    #[serde(default)]
+    pub health_measures: Option<NewCaseNoteHealthMeasures>,
+    /// If set all blood pressure readings are replaced. Requires health measures to exist
+    #[serde(default)]
+    pub blood_pressure: Option<Vec<NewBloodPressure>>,
+    /// Answers to case note questions. Only the questions included are changed
+    #[serde(default)]
+    pub question_answers: Vec<NewCaseNoteQuestionAnswer>,
+    /// Ids of questions whose answers are removed.
+    ///
+    /// Answers to questions that are no longer active must be removed
+    #[serde(default)]
+    pub removed_answers: Vec<i32>,
+}
+/// Returned after a case note is created or updated
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CaseNoteSaved {
+    pub case_note: CaseNote,
+    /// String ids of required questions whose requirements are met but are not answered
+    ///
+    /// The case note can not be completed until these are answered
+    pub missing_required: Vec<String>,
+    /// String ids of questions whose requirements script is broken.
+    ///
+    /// They are treated as inactive until the script is fixed
+    pub broken_requirements: Vec<String>,
+}
+/// Question answers that are valid and allowed by the question requirements
+struct CheckedAnswers {
+    answers: Vec<ResolvedCaseNoteAnswer>,
+    missing_required: Vec<String>,
+    broken_requirements: Vec<String>,
+}
+fn invalid_answer_response<D: Serialize>(message: &'static str, details: D) -> Response {
+    let body: APIErrorResponse<D, ()> = APIErrorResponse {
+        message: message.into(),
+        details: Some(details),
+        error: None,
+    };
+    ResponseBuilder::bad_request()
+        .extension(ErrorReason::from(message))
+        .json(&body)
+}
+impl CaseNoteRequest {
+    /// Resolves the question answers and checks them against the question requirements
+    ///
+    /// Invalid answers and answers to questions whose requirements are not met are returned as a bad request response.
+    /// Including existing answers that are not removed
+    async fn check_answers(
+        &mut self,
+        case_note: Option<i32>,
+        site: &SiteState,
+    ) -> Result<Result<CheckedAnswers, Response>, InternalError> {
+        let mut answers = Vec::with_capacity(self.question_answers.len());
+        for answer in std::mem::take(&mut self.question_answers) {
+            debug!(?answer, "Resolving question answer");
+            match answer.resolve(&site.database).await {
+                Ok(answer) => answers.push(answer),
+                Err(DBError::Questions(err)) => {
+                    return Ok(Err(invalid_answer_response(
+                        "Invalid Question Answer",
+                        err.to_string(),
+                    )));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let check = check_answer_requirements(
+            &site.requirements,
+            case_note,
+            &answers,
+            &self.removed_answers,
+            &site.database,
+        )
+        .await?;
+        if !check.inactive_answered.is_empty() {
+            debug!(?check.inactive_answered, "Answers to inactive questions");
+            return Ok(Err(invalid_answer_response(
+                "Questions Not Active",
+                check.inactive_answered,
+            )));
+        }
+        Ok(Ok(CheckedAnswers {
+            answers,
+            missing_required: check.missing_required,
+            broken_requirements: check.broken_requirements,
+        }))
+    }
+    /// Blood pressure readings require health measures. Either in the request or already on the case note
+    async fn check_blood_pressure(
+        &self,
+        case_note: Option<i32>,
+        site: &SiteState,
+    ) -> Result<Option<Response>, InternalError> {
+        let has_readings = self
+            .blood_pressure
+            .as_ref()
+            .is_some_and(|readings| !readings.is_empty());
+        if !has_readings || self.health_measures.is_some() {
+            return Ok(None);
+        }
+        if let Some(case_note) = case_note
+            && CaseNoteHealthMeasures::find_by_case_note_id(case_note, &site.database)
+                .await?
+                .is_some()
+        {
+            return Ok(None);
+        }
+        Ok(Some(
+            ResponseBuilder::bad_request()
+                .extension(ErrorReason::from(
+                    "Blood pressure readings require health measures",
+                ))
+                .empty(),
+        ))
+    }
+    /// Writes the health measures, blood pressure readings and question answers to the case note
+    ///
+    /// The request must already be checked. Blood pressure readings are only replaced if they were sent
+    async fn write_parts(
+        self,
+        case_note: i32,
+        answers: Vec<ResolvedCaseNoteAnswer>,
+        database: &mut PgConnection,
+    ) -> Result<(), InternalError> {
+        let Self {
+            health_measures,
+            blood_pressure,
+            removed_answers,
+            ..
+        } = self;
+        let measures = match health_measures {
+            Some(measures) => Some(
+                measures
+                    .insert_or_update_return_measure(case_note, &mut *database)
+                    .await?,
+            ),
+            None if blood_pressure.is_some() => {
+                CaseNoteHealthMeasures::find_by_case_note_id(case_note, &mut *database).await?
+            }
+            None => None,
+        };
+        if let (Some(measures), Some(blood_pressure)) = (measures, blood_pressure) {
+            measures.set_bp(blood_pressure, &mut *database).await?;
+        }
+        for question_id in removed_answers {
+            delete_question_answer(question_id, case_note, &mut *database).await?;
+        }
+        for answer in answers {
+            answer.set_for_case_note(case_note, &mut *database).await?;
+        }
+        Ok(())
+    }
+}
+/// Creates a new case note for a participant
+#[utoipa::path(
+    post,
+    path = "/{participant_id}/new",
+    params(
+        ("participant_id" = i32, Path, description = "Participant ID")
+    ),
+    request_body(content = CaseNoteRequest, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Case Note Created", body = CaseNoteSaved, content_type = "application/json"),
+        (status = 400, description = "Invalid question answer, answer to an inactive question or blood pressure reading"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn new_case_note(
+    State(site): State<SiteState>,
+    Path(participant_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(mut request): JsonBody<CaseNoteRequest>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    if !Participants::does_participant_id_exist(participant_id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    let CheckedAnswers {
+        answers,
+        missing_required,
+        broken_requirements,
+    } = match request.check_answers(None, &site).await? {
+        Ok(checked) => checked,
+        Err(response) => return Ok(response),
+    };
+    if let Some(response) = request.check_blood_pressure(None, &site).await? {
+        return Ok(response);
+    }
+    let mut transaction = site.database.begin().await?;
+    let new_case_note: NewCaseNote = request.case_note.clone().into();
+    let case_note = new_case_note
+        .insert_return_case_note(participant_id, &mut *transaction)
+        .await?;
+    request
+        .write_parts(case_note.id, answers, &mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(ResponseBuilder::ok().json(&CaseNoteSaved {
+        case_note,
+        missing_required,
+        broken_requirements,
+    }))
+}
+/// Updates a case note.
+///
+/// Completed case notes can not be updated
+#[utoipa::path(
+    post,
+    path = "/{case_note_id}/update",
+    params(
+        ("case_note_id" = i32, Path, description = "Case Note ID")
+    ),
+    request_body(content = CaseNoteRequest, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Case Note Updated", body = CaseNoteSaved, content_type = "application/json"),
+        (status = 400, description = "Invalid question answer, answer to an inactive question or blood pressure reading"),
+        (status = 404, description = "Case Note Not Found"),
+        ConflictResponse,
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn update_case_note(
+    State(site): State<SiteState>,
+    Path(case_note_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(mut request): JsonBody<CaseNoteRequest>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    let Some(case_note) = CaseNote::find_by_id(case_note_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Case Note Not Found"))
+            .empty());
+    };
+    if case_note.completed {
+        debug!(?case_note, "Case note is already completed");
+        return Ok(ConflictResponse::from("completed").into_response());
+    }
+    let CheckedAnswers {
+        answers,
+        missing_required,
+        broken_requirements,
+    } = match request.check_answers(Some(case_note.id), &site).await? {
+        Ok(checked) => checked,
+        Err(response) => return Ok(response),
+    };
+    if let Some(response) = request
+        .check_blood_pressure(Some(case_note.id), &site)
+        .await?
+    {
+        return Ok(response);
+    }
+    let mut transaction = site.database.begin().await?;
+    request
+        .case_note
+        .clone()
+        .update(case_note.id, &mut *transaction)
+        .await?;
+    request
+        .write_parts(case_note.id, answers, &mut transaction)
+        .await?;
+    transaction.commit().await?;
+    let Some(case_note) = CaseNote::find_by_id(case_note_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Case Note Not Found"))
+            .empty());
+    };
+    Ok(ResponseBuilder::ok().json(&CaseNoteSaved {
+        case_note,
+        missing_required,
+        broken_requirements,
+    }))
+}
+/// Marks a case note as completed. After this the case note can no longer be updated
+///
+/// All required questions whose requirements are met must be answered.
+/// Questions whose requirements are not met must not be answered
+#[utoipa::path(
+    post,
+    path = "/{case_note_id}/complete",
+    params(
+        ("case_note_id" = i32, Path, description = "Case Note ID")
+    ),
+    responses(
+        (status = 200, description = "Case Note Completed", body = CaseNote, content_type = "application/json"),
+        (status = 400, description = "Required questions are not answered or inactive questions are answered"),
+        (status = 404, description = "Case Note Not Found"),
+        ConflictResponse,
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn complete_case_note(
+    State(site): State<SiteState>,
+    Path(case_note_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    let Some(mut case_note) = CaseNote::find_by_id(case_note_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Case Note Not Found"))
+            .empty());
+    };
+    if case_note.completed {
+        return Ok(ConflictResponse::from("completed").into_response());
+    }
+    let check = check_answer_requirements(
+        &site.requirements,
+        Some(case_note.id),
+        &[],
+        &[],
+        &site.database,
+    )
+    .await?;
+    if !check.inactive_answered.is_empty() {
+        debug!(?check.inactive_answered, "Case note has answers to inactive questions");
+        return Ok(invalid_answer_response(
+            "Questions Not Active",
+            check.inactive_answered,
+        ));
+    }
+    if !check.missing_required.is_empty() {
+        debug!(?check.missing_required, "Case note is missing required answers");
+        return Ok(invalid_answer_response(
+            "Missing Required Answers",
+            check.missing_required,
+        ));
+    }
+    case_note.mark_completed(&site.database).await?;
+    Ok(ResponseBuilder::ok().json(&case_note))
+}
@@ -1,25 +1,54 @@
 use axum::{
     extract::{Path, State},
     response::Response,
-    routing::get,
+    routing::{get, post},
 };
 use cs25_303_core::database::red_cap::participants::{
     Participants,
-    goals::{ParticipantGoals, ParticipantGoalsSteps},
+    goals::{
+        NewParticipantGoal, NewParticipantGoalsSteps, ParticipantGoals, ParticipantGoalsSteps,
+    },
 };
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
-use utoipa::OpenApi;
+use utoipa::{OpenApi, ToSchema};
 
+use super::participant_updates_disabled;
 use crate::utils::response::ResponseBuilder;
 use crate::{
-    app::{SiteState, authentication::Authentication, error::InternalError},
-    utils::ErrorReason,
+    app::{
+        SiteState,
+        authentication::{
+            Authentication,
+            permissions::{
+                ReadParticipants, UpdateParticipants, response::MissingPermissionResponse,
+            },
+        },
+        error::InternalError,
+    },
+    utils::{ErrorReason, json::JsonBody},
 };
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(get_participants_goals, get_steps_for_goal, get_steps_without_goal),
-    components(schemas(ParticipantGoals))
+    paths(
+        get_participants_goals,
+        get_steps_for_goal,
+        get_steps_without_goal,
+        new_goal,
+        mark_goal_inactive,
+        new_step,
+        set_step_action_step,
+        set_step_goal
+    ),
+    components(schemas(
+        ParticipantGoals,
+        ParticipantGoalsSteps,
+        NewParticipantGoal,
+        NewParticipantGoalsSteps,
+        SetActionStep,
+        SetStepGoal
+    ))
 )]
 pub struct ParticipantGoalsAPI;
 
@@ -31,6 +60,11 @@ pub fn participant_goals() -> axum::Router<SiteState> {
             "/{participant_id}/steps/without_goal",
             get(get_steps_without_goal),
         )
+        .route("/{participant_id}/new", post(new_goal))
+        .route("/{goal_id}/inactive", post(mark_goal_inactive))
+        .route("/{participant_id}/steps/new", post(new_step))
+        .route("/steps/{step_id}/action_step", post(set_step_action_step))
+        .route("/steps/{step_id}/goal", post(set_step_goal))
 }
 /// Returns all goals for a participant
 #[utoipa::path(
@@ -41,10 +75,11 @@ pub fn participant_goals() -> axum::Router<SiteState> {
     ),
     responses(
         (status = 200, description = "goals for participant", body = Vec<ParticipantGoals>, content_type = "application/json"),
-        (status = 404, description = "Participant Not Found")
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
 
     )
 )]
@@ -52,7 +87,7 @@ pub fn participant_goals() -> axum::Router<SiteState> {
 pub async fn get_participants_goals(
     State(site): State<SiteState>,
     Path(id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let goals = ParticipantGoals::get_all_participant_goals(id, &site.database).await?;
 
@@ -74,17 +109,18 @@ pub async fn get_participants_goals(
     ),
     responses(
         (status = 200, description = "Steps for Goal", body = Vec<ParticipantGoalsSteps>, content_type = "application/json"),
-        (status = 404, description = "Goal Not Found")
+        (status = 404, description = "Goal Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
 pub async fn get_steps_for_goal(
     State(site): State<SiteState>,
     Path(id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let goals = ParticipantGoalsSteps::get_all_steps_for_goal(id, &site.database).await?;
 
@@ -107,17 +143,18 @@ pub async fn get_steps_for_goal(
     ),
     responses(
         (status = 200, description = "Steps without goal", body = Vec<ParticipantGoalsSteps>, content_type = "application/json"),
-        (status = 404, description = "Participant Not Found")
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
 pub async fn get_steps_without_goal(
     State(site): State<SiteState>,
     Path(id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let goals =
         ParticipantGoalsSteps::get_goaless_steps_for_participant(id, &site.database).await?;
@@ -130,3 +167,214 @@ pub async fn get_steps_without_goal(
 
     Ok(ResponseBuilder::ok().json(&goals))
 }
+/// Returns a bad request response if the goal does not exist or belongs to a different participant
+async fn goal_not_for_participant(
+    goal_id: i32,
+    participant_id: i32,
+    site: &SiteState,
+) -> Result<Option<Response>, InternalError> {
+    match ParticipantGoals::get_goal_by_id(goal_id, &site.database).await? {
+        Some(goal) if goal.participant_id == participant_id => Ok(None),
+        _ => Ok(Some(
+            ResponseBuilder::bad_request()
+                .extension(ErrorReason::from("Goal Not Found For Participant"))
+                .empty(),
+        )),
+    }
+}
+/// Creates a new goal for a participant
+#[utoipa::path(
+    post,
+    path = "/{participant_id}/new",
+    params(
+        ("participant_id" = i32, Path,  description = "Participant ID"),
+    ),
+    request_body(content = NewParticipantGoal, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Goal Created", body = ParticipantGoals, content_type = "application/json"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn new_goal(
+    State(site): State<SiteState>,
+    Path(participant_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(goal): JsonBody<NewParticipantGoal>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    if !Participants::does_participant_id_exist(participant_id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    let goal = ParticipantGoals::add_goal(participant_id, goal, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&goal))
+}
+/// Marks a goal as no longer active
+#[utoipa::path(
+    post,
+    path = "/{goal_id}/inactive",
+    params(
+        ("goal_id" = i32, Path,  description = "Goal ID"),
+    ),
+    responses(
+        (status = 200, description = "Goal Updated", body = ParticipantGoals, content_type = "application/json"),
+        (status = 404, description = "Goal Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn mark_goal_inactive(
+    State(site): State<SiteState>,
+    Path(goal_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    let Some(mut goal) = ParticipantGoals::get_goal_by_id(goal_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Goal Not Found"))
+            .empty());
+    };
+    goal.set_active(false, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&goal))
+}
+/// Adds a step for a participant
+///
+/// The step can be attached to one of the participant's goals with `goal_id`
+#[utoipa::path(
+    post,
+    path = "/{participant_id}/steps/new",
+    params(
+        ("participant_id" = i32, Path,  description = "Participant ID"),
+    ),
+    request_body(content = NewParticipantGoalsSteps, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Step Created", body = ParticipantGoalsSteps, content_type = "application/json"),
+        (status = 400, description = "Goal Not Found For Participant"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn new_step(
+    State(site): State<SiteState>,
+    Path(participant_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(step): JsonBody<NewParticipantGoalsSteps>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    if !Participants::does_participant_id_exist(participant_id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    if let Some(goal_id) = step.goal_id
+        && let Some(response) = goal_not_for_participant(goal_id, participant_id, &site).await?
+    {
+        return Ok(response);
+    }
+    let step = ParticipantGoalsSteps::add_step(participant_id, step, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&step))
+}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SetActionStep {
+    pub action_step: bool,
+}
+/// Sets if the step is an action step
+#[utoipa::path(
+    post,
+    path = "/steps/{step_id}/action_step",
+    params(
+        ("step_id" = i32, Path,  description = "Step ID"),
+    ),
+    request_body(content = SetActionStep, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Step Updated", body = ParticipantGoalsSteps, content_type = "application/json"),
+        (status = 404, description = "Step Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn set_step_action_step(
+    State(site): State<SiteState>,
+    Path(step_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(SetActionStep { action_step }): JsonBody<SetActionStep>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    let Some(mut step) = ParticipantGoalsSteps::find_by_id(step_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Step Not Found"))
+            .empty());
+    };
+    step.set_action_step(action_step, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&step))
+}
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SetStepGoal {
+    /// Must belong to the same participant as the step
+    pub goal_id: i32,
+}
+/// Moves a step to a goal.
+///
+/// Used to attach the steps returned by `/{participant_id}/steps/without_goal`
+#[utoipa::path(
+    post,
+    path = "/steps/{step_id}/goal",
+    params(
+        ("step_id" = i32, Path,  description = "Step ID"),
+    ),
+    request_body(content = SetStepGoal, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Step Updated", body = ParticipantGoalsSteps, content_type = "application/json"),
+        (status = 400, description = "Goal Not Found For Participant"),
+        (status = 404, description = "Step Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn set_step_goal(
+    State(site): State<SiteState>,
+    Path(step_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(SetStepGoal { goal_id }): JsonBody<SetStepGoal>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    let Some(mut step) = ParticipantGoalsSteps::find_by_id(step_id, &site.database).await? else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Step Not Found"))
+            .empty());
+    };
+    if let Some(response) = goal_not_for_participant(goal_id, step.participant_id, &site).await? {
+        return Ok(response);
+    }
+    step.set_goal(goal_id, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&step))
+}
@@ -1,31 +1,48 @@
 use axum::{
     extract::{Path, Query, State},
     response::Response,
-    routing::get,
+    routing::{get, post},
 };
+use chrono::NaiveDate;
 use cs25_303_core::database::{
     CSPageParams, PaginatedResponse,
-    red_cap::participants::{ParticipantMedications, Participants},
+    red_cap::participants::{
+        NewMedication, ParticipantMedications, Participants, UpdateMedication,
+    },
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
-use utoipa::{IntoParams, OpenApi};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
+use super::participant_updates_disabled;
 use crate::utils::response::ResponseBuilder;
 use crate::{
-    app::{SiteState, authentication::Authentication, error::InternalError},
-    utils::ErrorReason,
+    app::{
+        SiteState,
+        authentication::{
+            Authentication,
+            permissions::{
+                ReadParticipants, UpdateParticipants, response::MissingPermissionResponse,
+            },
+        },
+        error::InternalError,
+    },
+    utils::{ErrorReason, json::JsonBody},
 };
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(search_medications),
-    components(schemas(ParticipantMedications,PaginatedResponse<ParticipantMedications>))
+    paths(search_medications, add_medication, update_medication, discontinue_medication),
+    components(schemas(ParticipantMedications,PaginatedResponse<ParticipantMedications>, NewMedication, UpdateMedication, DiscontinueMedication))
 )]
 pub struct ParticipantMedicationsAPI;
 
 pub fn participant_medications() -> axum::Router<SiteState> {
-    axum::Router::new().route("/{participant_id}/search", get(search_medications))
+    axum::Router::new()
+        .route("/{participant_id}/search", get(search_medications))
+        .route("/{participant_id}/new", post(add_medication))
+        .route("/{medication_id}/update", post(update_medication))
+        .route("/{medication_id}/discontinue", post(discontinue_medication))
 }
 #[derive(Debug, Clone, Default, Deserialize, IntoParams)]
 #[serde(default)]
@@ -49,17 +66,18 @@ pub struct MedicationSearch {
     ),
     responses(
         (status = 200, description = "Medications for participant", body = PaginatedResponse<ParticipantMedications>, content_type = "application/json"),
-        (status = 404, description = "Participant Not Found")
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
 pub async fn search_medications(
     State(site): State<SiteState>,
     Path(participant_id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
     Query(params): Query<CSPageParams>,
     Query(MedicationSearch { name }): Query<MedicationSearch>,
 ) -> Result<Response, InternalError> {
@@ -80,3 +98,126 @@ pub async fn search_medications(
     }
     Ok(ResponseBuilder::ok().json(&medications))
 }
+/// Adds a medication to a participant
+///
+/// The medication is given the next Red Cap index. Any `red_cap_index` in the body is ignored
+#[utoipa::path(
+    post,
+    path = "/{participant_id}/new",
+    params(
+        ("participant_id" = i32, Path,  description = "Participant ID"),
+    ),
+    request_body(content = NewMedication, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Medication Added", body = ParticipantMedications, content_type = "application/json"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn add_medication(
+    State(site): State<SiteState>,
+    Path(participant_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(medication): JsonBody<NewMedication>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    if !Participants::does_participant_id_exist(participant_id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    let medication =
+        ParticipantMedications::add_medication(participant_id, medication, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&medication))
+}
+/// Updates a medication. Fields that are not set are not changed
+#[utoipa::path(
+    post,
+    path = "/{medication_id}/update",
+    params(
+        ("medication_id" = i32, Path,  description = "Medication ID"),
+    ),
+    request_body(content = UpdateMedication, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Medication Updated", body = ParticipantMedications, content_type = "application/json"),
+        (status = 404, description = "Medication Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn update_medication(
+    State(site): State<SiteState>,
+    Path(medication_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(update): JsonBody<UpdateMedication>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    let Some(medication) =
+        ParticipantMedications::find_by_id(medication_id, &site.database).await?
+    else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Medication Not Found"))
+            .empty());
+    };
+    update.update(&medication, &site.database).await?;
+    let medication = ParticipantMedications::find_by_id(medication_id, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&medication))
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct DiscontinueMedication {
+    /// The date the medication was discontinued
+    ///
+    /// Defaults to the current date
+    pub date_discontinued: Option<NaiveDate>,
+}
+/// Marks a medication as discontinued
+#[utoipa::path(
+    post,
+    path = "/{medication_id}/discontinue",
+    params(
+        ("medication_id" = i32, Path,  description = "Medication ID"),
+    ),
+    request_body(content = DiscontinueMedication, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Medication Discontinued", body = ParticipantMedications, content_type = "application/json"),
+        (status = 404, description = "Medication Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn discontinue_medication(
+    State(site): State<SiteState>,
+    Path(medication_id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(DiscontinueMedication { date_discontinued }): JsonBody<DiscontinueMedication>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    let Some(mut medication) =
+        ParticipantMedications::find_by_id(medication_id, &site.database).await?
+    else {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Medication Not Found"))
+            .empty());
+    };
+    medication
+        .discontinue(date_discontinued, &site.database)
+        .await?;
+    Ok(ResponseBuilder::ok().json(&medication))
+}
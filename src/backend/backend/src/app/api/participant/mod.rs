@@ -1,5 +1,12 @@
 use crate::utils::ErrorReason;
-use crate::{app::authentication::Authentication, utils::json::JsonBody};
+use crate::utils::feature_disabled::FeatureDisabledResponse;
+use crate::{
+    app::authentication::{
+        Authentication,
+        permissions::{ReadParticipants, UpdateParticipants, response::MissingPermissionResponse},
+    },
+    utils::json::JsonBody,
+};
 pub mod case_note;
 pub mod goals;
 pub mod medications;
@@ -7,19 +14,24 @@ pub mod stats;
 use crate::utils::response::ResponseBuilder;
 use axum::{
     extract::{Path, Query, State},
-    response::Response,
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
 use cs25_303_core::database::red_cap::participants::ParticipantDemograhicsResponse;
 use cs25_303_core::database::red_cap::participants::health_overview::HealthOverviewResult;
 use cs25_303_core::database::{
     CSPageParams, PaginatedResponse,
-    red_cap::participants::{
-        ParticipantLookup, ParticipantLookupQuery, ParticipantType, Participants,
+    red_cap::{
+        Locations,
+        participants::{
+            NewDemographics, NewHealthOverview, NewParticipant, ParticipantLookup,
+            ParticipantLookupQuery, ParticipantType, Participants, UpdateDemographics,
+            UpdateHealthOverview, UpdateParticipant,
+        },
     },
 };
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
 use utoipa::{OpenApi, ToSchema};
 
@@ -27,8 +39,9 @@ use crate::app::{SiteState, error::InternalError};
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(look_up_participant, get_participants,get_health_overview, get_demographics),
-    components(schemas(CSPageParams, ParticipantLookup, ParticipantLookupQuery, PaginatedResponse<ParticipantLookup>, Participants, HealthOverviewResult, ParticipantDemograhicsResponse, ParticipantPartNotFound)),
+    paths(look_up_participant, get_participants,get_health_overview, get_demographics, new_participant, update_participant, update_demographics, update_health_overview),
+    components(schemas(CSPageParams, ParticipantLookup, ParticipantLookupQuery, PaginatedResponse<ParticipantLookup>, Participants, HealthOverviewResult, ParticipantDemograhicsResponse, ParticipantPartNotFound,
+        NewParticipantRequest, NewParticipant, NewDemographics, NewHealthOverview, UpdateParticipant, UpdateDemographics, UpdateHealthOverview)),
     nest(
         (path = "/case_notes", api = case_note::CaseNoteAPI, tags=["Participant Case Notes"]),
         (path = "/stats", api = stats::ParticipantStatAPI, tags=["Participant Statistics"]),
@@ -50,6 +63,10 @@ pub fn participant_routes() -> axum::Router<SiteState> {
         .route("/get/{id}", get(get_participants))
         .route("/get/{id}/health_overview", get(get_health_overview))
         .route("/get/{id}/demographics", get(get_demographics))
+        .route("/new", post(new_participant))
+        .route("/update/{id}", post(update_participant))
+        .route("/update/{id}/demographics", post(update_demographics))
+        .route("/update/{id}/health_overview", post(update_health_overview))
         .nest("/case_notes", case_note::case_note_routes())
         .nest("/stats", stats::stat_routes())
         .nest("/goals", goals::participant_goals())
@@ -64,17 +81,18 @@ pub fn participant_routes() -> axum::Router<SiteState> {
     ),
     request_body(content = ParticipantLookupQuery, content_type = "application/json"),
     responses(
-        (status = 200, description = "Participants Found", body = PaginatedResponse<ParticipantLookup>, content_type = "application/json")
+        (status = 200, description = "Participants Found", body = PaginatedResponse<ParticipantLookup>, content_type = "application/json"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
 pub async fn look_up_participant(
     State(site): State<SiteState>,
     Query(page): Query<CSPageParams>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
     JsonBody(participant): JsonBody<ParticipantLookupQuery>,
 ) -> Result<Response, InternalError> {
     let participants = participant.find(page, &site.database).await?;
@@ -89,10 +107,11 @@ pub async fn look_up_participant(
     ),
     responses(
         (status = 200, description = "Participants Found", body = Participants, content_type = "application/json"),
-        (status = 404, description = "Participant Not Found")
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
 
     )
 )]
@@ -100,7 +119,7 @@ pub async fn look_up_participant(
 pub async fn get_participants(
     State(site): State<SiteState>,
     Path(id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let participant = Participants::find_by_id(id, &site.database).await?;
 
@@ -126,10 +145,11 @@ pub struct ParticipantPartNotFound {
     ),
     responses(
         (status = 200, description = "Participants Found", body = HealthOverviewResult, content_type = "application/json"),
-        (status = 404, description = "Participant  Health Overview Not Found", body = ParticipantPartNotFound, content_type = "application/json")
+        (status = 404, description = "Participant  Health Overview Not Found", body = ParticipantPartNotFound, content_type = "application/json"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
 
     )
 )]
@@ -137,7 +157,7 @@ pub struct ParticipantPartNotFound {
 pub async fn get_health_overview(
     State(site): State<SiteState>,
     Path(id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let health_overview = HealthOverviewResult::find_by_participant_id(id, &site.database).await?;
 
@@ -166,10 +186,11 @@ pub async fn get_health_overview(
     ),
     responses(
         (status = 200, description = "Participants Found", body = ParticipantDemograhicsResponse, content_type = "application/json"),
-        (status = 404, description = "Participant Demographics Not Found", body = ParticipantPartNotFound, content_type = "application/json")
+        (status = 404, description = "Participant Demographics Not Found", body = ParticipantPartNotFound, content_type = "application/json"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
 
     )
 )]
@@ -177,7 +198,7 @@ pub async fn get_health_overview(
 pub async fn get_demographics(
     State(site): State<SiteState>,
     Path(id): Path<i32>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let demographics =
         ParticipantDemograhicsResponse::find_by_participant_id(id, &site.database).await?;
@@ -198,3 +219,214 @@ pub async fn get_demographics(
         }
     }
 }
+/// The body used to enroll a new participant
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewParticipantRequest {
+    /// `red_cap_id` and `last_synced_with_redcap` are ignored. They are set by the Red Cap sync
+    #[serde(flatten)]
+    pub participant: NewParticipant,
+    #[serde(default)]
+    pub demographics: Option<NewDemographics>,
+    #[serde(default)]
+    pub health_overview: Option<NewHealthOverview>,
+}
+/// Returns a bad request response if the location does not exist
+async fn invalid_location(
+    location: Option<i32>,
+    site: &SiteState,
+) -> Result<Option<Response>, InternalError> {
+    let Some(location) = location else {
+        return Ok(None);
+    };
+    if Locations::find_by_id(location, &site.database)
+        .await?
+        .is_some()
+    {
+        return Ok(None);
+    }
+    Ok(Some(
+        ResponseBuilder::bad_request()
+            .extension(ErrorReason::from("Location Not Found"))
+            .empty(),
+    ))
+}
+/// Enrolls a new participant
+///
+/// The participant will be pushed to Red Cap on the next sync
+#[utoipa::path(
+    post,
+    path = "/new",
+    request_body(content = NewParticipantRequest, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Participant Created", body = Participants, content_type = "application/json"),
+        (status = 400, description = "Location Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn new_participant(
+    State(site): State<SiteState>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(request): JsonBody<NewParticipantRequest>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    let NewParticipantRequest {
+        mut participant,
+        demographics,
+        health_overview,
+    } = request;
+    if let Some(response) = invalid_location(participant.location, &site).await? {
+        return Ok(response);
+    }
+    participant.red_cap_id = None;
+    participant.last_synced_with_redcap = None;
+
+    let mut transaction = site.database.begin().await?;
+    let participant = participant.insert_returning(&mut *transaction).await?;
+    if let Some(demographics) = demographics {
+        demographics
+            .insert(participant.id, &mut *transaction)
+            .await?;
+    }
+    if let Some(health_overview) = health_overview {
+        health_overview
+            .insert(participant.id, &mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(ResponseBuilder::ok().json(&participant))
+}
+/// Updates a participant's base, contact, status and location information
+///
+/// Fields that are not set are not changed. Fields set to null are cleared
+#[utoipa::path(
+    post,
+    path = "/update/{id}",
+    params(
+        ("id", Path,  description = "Participant ID"),
+    ),
+    request_body(content = UpdateParticipant, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Participant Updated", body = Participants, content_type = "application/json"),
+        (status = 400, description = "Location Not Found"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn update_participant(
+    State(site): State<SiteState>,
+    Path(id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(update): JsonBody<UpdateParticipant>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    if !Participants::does_participant_id_exist(id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    if let Some(response) = invalid_location(update.location.flatten(), &site).await? {
+        return Ok(response);
+    }
+    update.update(id, &site.database).await?;
+    let participant = Participants::find_by_id(id, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&participant))
+}
+/// Updates a participant's demographics. Creating them if they do not exist
+///
+/// Fields that are not set are not changed. Fields set to null are cleared
+#[utoipa::path(
+    post,
+    path = "/update/{id}/demographics",
+    params(
+        ("id", Path,  description = "Participant ID"),
+    ),
+    request_body(content = UpdateDemographics, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Demographics Updated", body = ParticipantDemograhicsResponse, content_type = "application/json"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn update_demographics(
+    State(site): State<SiteState>,
+    Path(id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(update): JsonBody<UpdateDemographics>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    if !Participants::does_participant_id_exist(id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    update.update_or_insert(id, &site.database).await?;
+    let demographics =
+        ParticipantDemograhicsResponse::find_by_participant_id(id, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&demographics))
+}
+/// Updates a participant's health overview. Creating it if it does not exist
+///
+/// Fields that are not set are not changed. Fields set to null are cleared
+#[utoipa::path(
+    post,
+    path = "/update/{id}/health_overview",
+    params(
+        ("id", Path,  description = "Participant ID"),
+    ),
+    request_body(content = UpdateHealthOverview, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Health Overview Updated", body = HealthOverviewResult, content_type = "application/json"),
+        (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<UpdateParticipants>
+    ),
+    security(
+        ("session" = ["UpdateParticipants"]),
+    )
+)]
+#[instrument]
+pub async fn update_health_overview(
+    State(site): State<SiteState>,
+    Path(id): Path<i32>,
+    auth: Authentication<UpdateParticipants>,
+    JsonBody(update): JsonBody<UpdateHealthOverview>,
+) -> Result<Response, InternalError> {
+    if let Some(response) = participant_updates_disabled(&site) {
+        return Ok(response);
+    }
+    if !Participants::does_participant_id_exist(id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Participant Not Found"))
+            .empty());
+    }
+    update.update_or_insert(id, &site.database).await?;
+    let health_overview = HealthOverviewResult::find_by_participant_id(id, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&health_overview))
+}
+/// Returns a [FeatureDisabledResponse] if participant data updates are disabled
+///
+/// See [crate::config::EnabledFeatures::update_participant_data]
+pub(crate) fn participant_updates_disabled(site: &SiteState) -> Option<Response> {
+    if site.features.update_participant_data {
+        None
+    } else {
+        Some(FeatureDisabledResponse::from("update_participant_data").into_response())
+    }
+}
@@ -17,7 +17,14 @@ use serde::Deserialize;
 use tracing::instrument;
 use utoipa::{IntoParams, OpenApi};
 
-use crate::app::{SiteState, authentication::Authentication, error::InternalError};
+use crate::app::{
+    SiteState,
+    authentication::{
+        Authentication,
+        permissions::{ReadParticipants, response::MissingPermissionResponse},
+    },
+    error::InternalError,
+};
 
 #[derive(OpenApi)]
 #[openapi(
@@ -58,9 +65,10 @@ pub struct CalculateBMI {
     responses(
         (status = 200, description = "Participant Weight History", body = PaginatedResponse<WeightHistory>, content_type = "application/json"),
         (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
@@ -69,7 +77,7 @@ pub async fn participant_weight_history(
     Path(participant_id): Path<i32>,
     Query(page): Query<CSPageParams>,
     Query(CalculateBMI { calculate_bmi }): Query<CalculateBMI>,
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let weights = WeightHistory::find_all_for_participant(
         participant_id,
@@ -100,9 +108,10 @@ pub async fn participant_weight_history(
     responses(
         (status = 200, description = "Blood Pressure History", body = PaginatedResponse<BloodPressureHistory>, content_type = "application/json"),
         (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
@@ -111,7 +120,7 @@ pub async fn bp_history(
     Path(participant_id): Path<i32>,
     Query(page): Query<CSPageParams>,
 
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let readings =
         BloodPressureHistory::find_all_for_participant(participant_id, page, &site.database)
@@ -137,9 +146,10 @@ pub async fn bp_history(
     responses(
         (status = 200, description = "Blood glucose History", body = PaginatedResponse<BloodGlucoseHistory>, content_type = "application/json"),
         (status = 404, description = "Participant Not Found"),
+        MissingPermissionResponse<ReadParticipants>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ReadParticipants"]),
     )
 )]
 #[instrument]
@@ -148,7 +158,7 @@ pub async fn glucose_history(
     Path(participant_id): Path<i32>,
     Query(page): Query<CSPageParams>,
 
-    auth: Authentication,
+    auth: Authentication<ReadParticipants>,
 ) -> Result<Response, InternalError> {
     let readings =
         BloodGlucoseHistory::find_all_for_participant(participant_id, page, &site.database).await?;
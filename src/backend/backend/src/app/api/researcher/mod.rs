@@ -17,7 +17,14 @@ use tracing::instrument;
 use utoipa::OpenApi;
 
 use crate::{
-    app::{SiteState, authentication::Authentication, error::InternalError},
+    app::{
+        SiteState,
+        authentication::{
+            Authentication,
+            permissions::{ResearchQuery, response::MissingPermissionResponse},
+        },
+        error::InternalError,
+    },
     utils::{builder::ResponseBuilder, json::JsonBody},
 };
 
@@ -56,17 +63,18 @@ pub fn researcher_routes() -> axum::Router<SiteState> {
     ),
     request_body(content = ResearcherQuery, content_type = "application/json"),
     responses(
-        (status = 200, description = "Participants Found", body = PaginatedResponse<ResearcherQueryResult>, content_type = "application/json")
+        (status = 200, description = "Participants Found", body = PaginatedResponse<ResearcherQueryResult>, content_type = "application/json"),
+        MissingPermissionResponse<ResearchQuery>
     ),
     security(
-        ("session" = []),
+        ("session" = ["ResearchQuery"]),
     )
 )]
 #[instrument]
 pub async fn query(
     State(site): State<SiteState>,
     Query(page): Query<CSPageParams>,
-    auth: Authentication,
+    auth: Authentication<ResearchQuery>,
     JsonBody(participant): JsonBody<ResearcherQuery>,
 ) -> Result<Response, InternalError> {
     let participants = participant.query(page.into(), &site.database).await?;
@@ -1,23 +1,73 @@
-use axum::{response::Response, routing::get};
-use cs25_303_core::database::user::User;
-use utoipa::OpenApi;
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+};
+use chrono::Local;
+use cs25_303_core::{
+    database::user::{
+        User, UserType,
+        auth::{
+            UserPasswordAuthentication,
+            token::{NewUserApiToken, UserApiToken},
+        },
+        new::create_or_update_user_password,
+    },
+    user::{Permissions, auth::PasswordViolation},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use tracing::{debug, instrument};
+use utoipa::{OpenApi, ToSchema};
 
 use crate::{
     app::{
         SiteState,
-        authentication::{Authentication, MeWithSession},
+        authentication::{
+            AllowPasswordReset, Authentication, MeWithSession,
+            session::SessionDetails,
+            utils::password::{encrypt_password, verify_password},
+        },
         error::InternalError,
     },
-    utils::ResponseBuilder,
+    utils::{
+        ErrorReason, ResponseBuilder, json::JsonBody, password_policy::PasswordPolicyResponse,
+    },
 };
 
 #[derive(OpenApi)]
-#[openapi(paths(me, session), components(schemas(MeWithSession)))]
+#[openapi(
+    paths(
+        me,
+        session,
+        api_tokens,
+        new_api_token,
+        revoke_api_token,
+        change_password,
+        sessions,
+        revoke_session
+    ),
+    components(schemas(
+        MeWithSession,
+        UserApiToken,
+        NewUserApiToken,
+        NewUserApiTokenResponse,
+        ChangePasswordRequest,
+        PasswordViolation,
+        SessionDetails
+    ))
+)]
 pub struct UserApi;
 pub fn user_api() -> axum::Router<SiteState> {
     axum::Router::new()
         .route("/me", get(me))
         .route("/session", get(session))
+        .route("/tokens", get(api_tokens))
+        .route("/tokens/new", post(new_api_token))
+        .route("/tokens/{token_id}", delete(revoke_api_token))
+        .route("/password", post(change_password))
+        .route("/sessions", get(sessions))
+        .route("/sessions/{session_id}", delete(revoke_session))
 }
 
 #[utoipa::path(
@@ -33,10 +83,10 @@ pub fn user_api() -> axum::Router<SiteState> {
     )
 )]
 async fn me(auth: Authentication) -> Result<Response, InternalError> {
-    let Authentication::UserViaSession { user, .. } = auth else {
-        unimplemented!("This should be a user");
+    let Some(user) = auth.user() else {
+        return Ok(ResponseBuilder::unauthorized().empty());
     };
-    Ok(ResponseBuilder::ok().json(&user))
+    Ok(ResponseBuilder::ok().json(user))
 }
 #[utoipa::path(
     get,
@@ -54,8 +104,349 @@ async fn session(auth: Authentication) -> Result<Response, InternalError> {
     let Authentication::UserViaSession { user, session } = auth else {
         return Ok(ResponseBuilder::bad_request().empty());
     };
-    Ok(ResponseBuilder::ok().json(&MeWithSession {
-        user: user,
-        session: session,
-    }))
+    Ok(ResponseBuilder::ok().json(&MeWithSession::from((session, user))))
+}
+/// Returns the API tokens of the current user. Newest first
+#[utoipa::path(
+    get,
+    path = "/tokens",
+    responses(
+        (status = 200, description = "API Tokens", body = Vec<UserApiToken>, content_type = "application/json"),
+        (status = 401, description = "Unauthorized"),
+    ),
+    security(
+        ("session" = []),
+    )
+)]
+#[instrument]
+async fn api_tokens(
+    State(site): State<SiteState>,
+    auth: Authentication,
+) -> Result<Response, InternalError> {
+    let Some(user) = auth.user() else {
+        return Ok(ResponseBuilder::unauthorized().empty());
+    };
+    let tokens = UserApiToken::get_all_for_user(user.id, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&tokens))
+}
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NewUserApiTokenResponse {
+    /// Pass as `Authorization: Bearer {token}`. It is only returned once
+    pub token: String,
+    pub details: UserApiToken,
+}
+/// Creates an API token for the current user
+///
+/// The scopes must be permissions the user has. Tokens can only be created from a session
+#[utoipa::path(
+    post,
+    path = "/tokens/new",
+    request_body(content = NewUserApiToken, content_type = "application/json"),
+    responses(
+        (status = 200, description = "API Token Created", body = NewUserApiTokenResponse, content_type = "application/json"),
+        (status = 400, description = "Invalid name, scopes or expiry"),
+        (status = 401, description = "Unauthorized"),
+    ),
+    security(
+        ("session" = []),
+    )
+)]
+#[instrument(skip(request))]
+async fn new_api_token(
+    State(site): State<SiteState>,
+    auth: Authentication,
+    JsonBody(request): JsonBody<NewUserApiToken>,
+) -> Result<Response, InternalError> {
+    let Authentication::UserViaSession { user, .. } = auth else {
+        return Ok(ResponseBuilder::forbidden()
+            .extension(ErrorReason::from(
+                "API tokens must be created from a session",
+            ))
+            .empty());
+    };
+    if request.name.trim().is_empty() {
+        return Ok(ResponseBuilder::bad_request()
+            .extension(ErrorReason::from("Name is required"))
+            .empty());
+    }
+    if request.scopes.is_empty() {
+        return Ok(ResponseBuilder::bad_request()
+            .extension(ErrorReason::from("At least one scope is required"))
+            .empty());
+    }
+    if request
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Local::now().fixed_offset())
+    {
+        return Ok(ResponseBuilder::bad_request()
+            .extension(ErrorReason::from("Expiry must be in the future"))
+            .empty());
+    }
+    for scope in &request.scopes {
+        if !user.has_permission(*scope, &site.database).await? {
+            return Ok(ResponseBuilder::bad_request()
+                .extension(ErrorReason::from(format!(
+                    "You do not have the permission {scope}"
+                )))
+                .empty());
+        }
+    }
+    let (details, token) = request.insert(user.id, &site.database).await?;
+    Ok(ResponseBuilder::ok().json(&NewUserApiTokenResponse { token, details }))
+}
+/// Revokes an API token of the current user
+#[utoipa::path(
+    delete,
+    path = "/tokens/{token_id}",
+    params(
+        ("token_id" = i32, Path, description = "API Token ID"),
+    ),
+    responses(
+        (status = 204, description = "API Token Revoked"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "API Token Not Found"),
+    ),
+    security(
+        ("session" = []),
+    )
+)]
+#[instrument]
+async fn revoke_api_token(
+    State(site): State<SiteState>,
+    Path(token_id): Path<i32>,
+    auth: Authentication,
+) -> Result<Response, InternalError> {
+    let Some(user) = auth.user() else {
+        return Ok(ResponseBuilder::unauthorized().empty());
+    };
+    if !UserApiToken::revoke(token_id, user.id, &site.database).await? {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("API Token Not Found"))
+            .empty());
+    }
+    Ok(ResponseBuilder::no_content().empty())
+}
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    /// The password the user logged in with
+    pub current_password: String,
+    /// Must meet the password policy and be different from the current password
+    pub new_password: String,
+}
+/// Changes the password of the current user
+///
+/// Requires the `self:password` permission. Unless the user must change their password after logging in.
+///
+/// Every other session of the user is logged out
+#[utoipa::path(
+    post,
+    path = "/password",
+    request_body(content = ChangePasswordRequest, content_type = "application/json"),
+    responses(
+        (status = 204, description = "Password Changed"),
+        (status = 401, description = "Unauthorized or the current password is wrong"),
+        (status = 403, description = "Password Authentication is not enabled or missing permission"),
+        PasswordPolicyResponse,
+    ),
+    security(
+        ("session" = ["UpdateSelfPassword"]),
+    )
+)]
+#[instrument(skip(request))]
+async fn change_password(
+    State(site): State<SiteState>,
+    AllowPasswordReset(auth): AllowPasswordReset,
+    JsonBody(request): JsonBody<ChangePasswordRequest>,
+) -> Result<Response, InternalError> {
+    let Some(password_config) = site.authentication.password.as_ref() else {
+        return Ok(ResponseBuilder::forbidden()
+            .extension(ErrorReason::from("Password Authentication is not enabled"))
+            .empty());
+    };
+    let Authentication::UserViaSession { user, session } = &auth else {
+        return Ok(ResponseBuilder::forbidden()
+            .extension(ErrorReason::from(
+                "Passwords can only be changed from a session",
+            ))
+            .empty());
+    };
+    let password_auth =
+        UserPasswordAuthentication::find_by_user_id(user.id, &site.database).await?;
+    let requires_reset = password_auth
+        .as_ref()
+        .is_some_and(|password_auth| password_auth.requires_reset);
+    if !requires_reset
+        && let Err(err) = auth
+            .has_permission(&site, Permissions::UpdateSelfPassword)
+            .await
+    {
+        return Ok(err.into_response());
+    }
+    let current_hash = password_auth.and_then(|password_auth| password_auth.password);
+    if let Err(err) = verify_password(&request.current_password, current_hash.as_deref()) {
+        debug!("Current password is wrong");
+        return Ok(err.into_response());
+    }
+    let mut violations = password_config.validate(&request.new_password);
+    if verify_password(&request.new_password, current_hash.as_deref()).is_ok() {
+        violations.push(PasswordViolation::SameAsCurrent);
+    }
+    if !violations.is_empty() {
+        return Ok(PasswordPolicyResponse(violations).into_response());
+    }
+    let Some(hash) = encrypt_password(&request.new_password) else {
+        return Ok(ResponseBuilder::internal_server_error()
+            .extension(ErrorReason::from("Failed to hash password"))
+            .empty());
+    };
+    create_or_update_user_password(user.id, &hash, false, &site.database).await?;
+    let revoked = site
+        .session
+        .delete_all_for_user_except(user.id, &session.session_key)?;
+    debug!(?revoked, "Logged out other sessions after password change");
+    Ok(ResponseBuilder::no_content().empty())
+}
+/// Returns the sessions of the current user. Oldest first
+///
+/// Includes the IP address and User-Agent each session logged in from. Can not be used with an API token
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    responses(
+        (status = 200, description = "Sessions", body = Vec<SessionDetails>, content_type = "application/json"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Called with an API token"),
+    ),
+    security(
+        ("session" = []),
+    )
+)]
+#[instrument]
+async fn sessions(
+    State(site): State<SiteState>,
+    auth: Authentication,
+) -> Result<Response, InternalError> {
+    let Authentication::UserViaSession { user, session } = &auth else {
+        return Ok(ResponseBuilder::forbidden()
+            .extension(ErrorReason::from(
+                "Sessions can only be listed from a session",
+            ))
+            .empty());
+    };
+    let sessions = site.session.get_sessions_for_user(user.id)?;
+    let sessions =
+        SessionDetails::from_sessions(sessions, Some(session.session_key.as_str()), &site.database)
+            .await?;
+    Ok(ResponseBuilder::ok().json(&sessions))
+}
+/// Logs out a session of the current user. Can not be used with an API token
+#[utoipa::path(
+    delete,
+    path = "/sessions/{session_id}",
+    params(
+        ("session_id" = Uuid, Path, description = "The id of the session from `/sessions`"),
+    ),
+    responses(
+        (status = 204, description = "Session Revoked"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Called with an API token"),
+        (status = 404, description = "Session Not Found"),
+    ),
+    security(
+        ("session" = []),
+    )
+)]
+#[instrument]
+async fn revoke_session(
+    State(site): State<SiteState>,
+    Path(session_id): Path<Uuid>,
+    auth: Authentication,
+) -> Result<Response, InternalError> {
+    let Authentication::UserViaSession { user, .. } = &auth else {
+        return Ok(ResponseBuilder::forbidden()
+            .extension(ErrorReason::from(
+                "Sessions can only be revoked from a session",
+            ))
+            .empty());
+    };
+    if site
+        .session
+        .delete_user_session(user.id, session_id)?
+        .is_none()
+    {
+        return Ok(ResponseBuilder::not_found()
+            .extension(ErrorReason::from("Session Not Found"))
+            .empty());
+    }
+    Ok(ResponseBuilder::no_content().empty())
+}
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use super::*;
+    use crate::utils::testing::{
+        get_testing_site, new_testing_user, no_db_connection, testing_api_token,
+    };
+    #[tokio::test]
+    #[ignore]
+    async fn sessions_can_not_be_managed_with_api_tokens() -> anyhow::Result<()> {
+        let Some(site) = get_testing_site().await else {
+            no_db_connection();
+            return Ok(());
+        };
+        let user = new_testing_user(None, &site.database).await?;
+        let session = site
+            .session
+            .create_session_default_lifespan(user.id, Uuid::new_v4())?;
+        let auth = Authentication::ApiToken {
+            user: user.clone(),
+            token: testing_api_token(user.id, vec![Permissions::Admin]),
+        };
+        let response = sessions(State(site.clone()), auth.clone()).await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = revoke_session(State(site.clone()), Path(session.login_id), auth).await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(site.session.get_session(&session.session_key)?.is_some());
+
+        let auth = Authentication::UserViaSession {
+            user,
+            session: session.clone(),
+        };
+        let response = sessions(State(site.clone()), auth.clone()).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = revoke_session(State(site.clone()), Path(session.login_id), auth).await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(site.session.get_session(&session.session_key)?.is_none());
+        Ok(())
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn changing_password_logs_out_other_sessions() -> anyhow::Result<()> {
+        let Some(site) = get_testing_site().await else {
+            no_db_connection();
+            return Ok(());
+        };
+        let user = new_testing_user(Some("Clinician"), &site.database).await?;
+        let hash = encrypt_password("Current-Password-1").expect("Failed to hash password");
+        create_or_update_user_password(user.id, &hash, false, &site.database).await?;
+        let current = site
+            .session
+            .create_session_default_lifespan(user.id, Uuid::new_v4())?;
+        site.session
+            .create_session_default_lifespan(user.id, Uuid::new_v4())?;
+
+        let auth = AllowPasswordReset(Authentication::UserViaSession {
+            user: user.clone(),
+            session: current.clone(),
+        });
+        let request = ChangePasswordRequest {
+            current_password: "Current-Password-1".to_owned(),
+            new_password: "New-Password-2".to_owned(),
+        };
+        let response = change_password(State(site.clone()), auth, JsonBody(request)).await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(site.session.get_sessions_for_user(user.id)?, vec![current]);
+        Ok(())
+    }
 }
@@ -6,7 +6,10 @@
 //!
 //! This will parse the request and pull specific information from the request.
 //!
-//! In another one of my projects it will would parse Basic Auth, API Tokens and Cookies for sessions.
+//! It parses session cookies, the `Session` authorization header and API tokens passed as `Bearer` tokens.
+//!
+//! Bearer tokens that start with [API_TOKEN_PREFIX](cs25_303_core::database::user::auth::token::API_TOKEN_PREFIX) are API tokens. Any other bearer token is treated as a session key.
+//! API tokens are only accepted by routes with a [PermissionCheck].
 //!
 //! Once it is done it puts the results into [AuthenticationRaw] and says go on to the rest of the request.
 //!
@@ -27,11 +30,18 @@ use axum::{
     response::IntoResponse,
 };
 use axum_extra::extract::cookie::Cookie;
-use cs25_303_core::database::{DBError, user::User};
+use chrono::{DateTime, FixedOffset, Local};
+use cs25_303_core::database::{
+    DBError,
+    user::{
+        User, UserType,
+        auth::{UserPasswordAuthentication, token::UserApiToken},
+        lockout::LoginLockout,
+    },
+};
 use cs25_303_core::user::Permissions;
-use derive_more::derive::From;
 use header::AuthorizationHeader;
-use http::request::Parts;
+use http::{header::RETRY_AFTER, request::Parts};
 use permissions::{PermissionCheck, response::MissingPermission};
 use serde::Serialize;
 use session::{Session, SessionManager};
@@ -43,12 +53,23 @@ pub mod api_middleware;
 pub mod header;
 pub mod session;
 /// The user information with the session information
-#[derive(Debug, Serialize, Clone, From, ToSchema)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct MeWithSession {
     /// The session information
     pub session: Session,
     /// Your user information
     pub user: User,
+    /// If true the session can only be used to change the password and log out
+    pub password_reset_required: bool,
+}
+impl From<(Session, User)> for MeWithSession {
+    fn from((session, user): (Session, User)) -> Self {
+        Self {
+            session,
+            user,
+            password_reset_required: false,
+        }
+    }
 }
 
 /// Possible Errors that can occur during authentication
@@ -64,6 +85,21 @@ pub enum AuthenticationError {
     UnauthorizedWithHiddenReason(ErrorReason),
     #[error(transparent)]
     MissingPermission(#[from] MissingPermission),
+    /// The user must change their password before using anything else
+    #[error("You must change your password.")]
+    PasswordResetRequired,
+    /// Too many failed logins. See [LoginLockout]
+    #[error("Too many failed logins. Locked until {locked_until}")]
+    Locked { locked_until: DateTime<FixedOffset> },
+    /// API tokens can only be used on routes that require a permission
+    #[error("API tokens can not be used here.")]
+    ApiTokenNotAllowed,
+}
+/// The details of a [AuthenticationError::Locked] response
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct LoginLockedDetails {
+    /// Logins are rejected until this time
+    pub locked_until: DateTime<FixedOffset>,
 }
 impl AuthenticationError {
     fn unauthorized_response(reason: Option<ErrorReason>) -> axum::response::Response {
@@ -95,6 +131,33 @@ impl IntoResponse for AuthenticationError {
                 AuthenticationError::unauthorized_response(Some(reason))
             }
             AuthenticationError::Unauthorized => AuthenticationError::unauthorized_response(None),
+            AuthenticationError::PasswordResetRequired => ResponseBuilder::forbidden()
+                .extension(ErrorReason::from("Password reset required"))
+                .json(&APIErrorResponse::<(), ()> {
+                    message: "You must change your password".into(),
+                    details: None,
+                    error: None,
+                }),
+            AuthenticationError::ApiTokenNotAllowed => ResponseBuilder::forbidden()
+                .extension(ErrorReason::from("API tokens can not be used here"))
+                .json(&APIErrorResponse::<(), ()> {
+                    message: "API tokens can not be used here. Log in instead".into(),
+                    details: None,
+                    error: None,
+                }),
+            AuthenticationError::Locked { locked_until } => {
+                let retry_after = (locked_until - Local::now().fixed_offset())
+                    .num_seconds()
+                    .max(1);
+                ResponseBuilder::too_many_requests()
+                    .extension(ErrorReason::from("Login locked"))
+                    .header(RETRY_AFTER, retry_after.to_string())
+                    .json(&APIErrorResponse::<LoginLockedDetails, ()> {
+                        message: "Too many failed logins. Try again later".into(),
+                        details: Some(LoginLockedDetails { locked_until }),
+                        error: None,
+                    })
+            }
         }
     }
 }
@@ -102,32 +165,71 @@ impl IntoResponse for AuthenticationError {
 #[derive(Clone, Debug, PartialEq, EnumIs)]
 #[allow(clippy::large_enum_variant)]
 pub enum Authentication<PC: PermissionCheck = ()> {
-    UserViaSession { user: User, session: Session },
+    UserViaSession {
+        user: User,
+        session: Session,
+    },
+    /// The user is using a long lived API token. Permissions are limited to the scopes of the token
+    ApiToken {
+        user: User,
+        token: UserApiToken,
+    },
 
     Phantom(std::marker::PhantomData<PC>),
 }
 impl<PC: PermissionCheck> Authentication<PC> {
+    /// The user that made the request
+    pub fn user(&self) -> Option<&User> {
+        match self {
+            Authentication::UserViaSession { user, .. } => Some(user),
+            Authentication::ApiToken { user, .. } => Some(user),
+            Authentication::Phantom(_) => None,
+        }
+    }
     /// Checks if the user has the required permission
     ///
     /// # Arguments
     /// * `state` - The state of the website used to make additional sql queries if needed
     /// * `scope` - The scope that the user needs to have
     ///
-    /// ## Note
-    /// This function is just a skeleton and will be replaced with a real authentication method but the structure should remain the same
+    /// Permissions are resolved from the user's own permissions and the permissions of their roles.
+    /// [Permissions::Admin] passes every check.
+    ///
+    /// API tokens also need the permission in their scopes
     pub async fn has_permission(
         &self,
-        _state: &SiteState,
-        _scope: Permissions,
+        state: &SiteState,
+        scope: Permissions,
     ) -> Result<(), AuthenticationError> {
-        Ok(())
+        match self {
+            Authentication::UserViaSession { user, .. } => {
+                if user.has_permission(scope, &state.database).await? {
+                    Ok(())
+                } else {
+                    Err(MissingPermission::from(scope).into())
+                }
+            }
+            Authentication::ApiToken { user, token } => {
+                if token.has_scope(scope) && user.has_permission(scope, &state.database).await? {
+                    Ok(())
+                } else {
+                    Err(MissingPermission::from(scope).into())
+                }
+            }
+            Authentication::Phantom(_) => Err(AuthenticationError::Unauthorized),
+        }
     }
     /// Checks if the user has the required permissions
+    ///
+    /// Returns the first permission that is missing
     pub async fn has_many_scopes(
         &self,
-        _state: &SiteState,
-        _scopes: impl Iterator<Item = Permissions>,
+        state: &SiteState,
+        scopes: impl Iterator<Item = Permissions>,
     ) -> Result<(), AuthenticationError> {
+        for scope in scopes {
+            self.has_permission(state, scope).await?;
+        }
         Ok(())
     }
 }
@@ -140,12 +242,43 @@ where
     type Rejection = AuthenticationError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let raw_extension = parts.extensions.get::<AuthenticationRaw>().cloned();
         let state = SiteState::from_ref(state);
+        Authentication::authenticate(parts, &state, false).await
+    }
+}
+/// Same as [Authentication]. But also accepts users that must change their password
+///
+/// Only used by the routes a user can use before changing their password
+#[derive(Clone, Debug, PartialEq)]
+pub struct AllowPasswordReset<PC: PermissionCheck = ()>(pub Authentication<PC>);
+impl<S, PC> FromRequestParts<S> for AllowPasswordReset<PC>
+where
+    SiteState: FromRef<S>,
+    S: Send + Sync,
+    PC: PermissionCheck,
+{
+    type Rejection = AuthenticationError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = SiteState::from_ref(state);
+        Authentication::authenticate(parts, &state, true)
+            .await
+            .map(AllowPasswordReset)
+    }
+}
+impl<PC: PermissionCheck> Authentication<PC> {
+    async fn authenticate(
+        parts: &Parts,
+        state: &SiteState,
+        allow_password_reset: bool,
+    ) -> Result<Self, AuthenticationError> {
+        let raw_extension = parts.extensions.get::<AuthenticationRaw>().cloned();
         match raw_extension {
             Some(AuthenticationRaw::Session(session)) => {
                 let user = session.get_user(&state.database).await?;
                 if let Some(user) = user {
+                    check_active(&user)?;
+                    check_password_reset(&user, allow_password_reset, state).await?;
                     PC::check_permissions(&user, &state.database).await?;
 
                     Ok(Authentication::UserViaSession { user, session })
@@ -154,6 +287,29 @@ where
                     Err(AuthenticationError::Unauthorized)
                 }
             }
+            Some(AuthenticationRaw::ApiToken(token)) => {
+                let Some(mut token) = UserApiToken::find_by_token(&token, &state.database).await?
+                else {
+                    return Err(AuthenticationError::UnauthorizedWithHiddenReason(
+                        ErrorReason::from("API token not found"),
+                    ));
+                };
+                if token.is_expired() {
+                    return Err(AuthenticationError::UnauthorizedWithHiddenReason(
+                        ErrorReason::from("API token expired"),
+                    ));
+                }
+                let Some(user) = User::get_by_id(token.user_id, &state.database).await? else {
+                    error!(?token, "User of API token not found");
+                    return Err(AuthenticationError::Unauthorized);
+                };
+                check_active(&user)?;
+                check_password_reset(&user, allow_password_reset, state).await?;
+                check_token_scopes::<PC>(&token)?;
+                PC::check_permissions(&user, &state.database).await?;
+                token.mark_used(&state.database).await?;
+                Ok(Authentication::ApiToken { user, token })
+            }
             _ => {
                 error!("No Authentication Data Extracted from Request");
                 Err(AuthenticationError::Unauthorized)
@@ -161,10 +317,51 @@ where
         }
     }
 }
+/// Rejects API tokens that are missing a permission of the route.
+///
+/// Routes without a [PermissionCheck] do not accept API tokens. Tokens are limited to the scopes they were given
+fn check_token_scopes<PC: PermissionCheck>(
+    token: &UserApiToken,
+) -> Result<(), AuthenticationError> {
+    let permissions = PC::permissions_required();
+    if permissions.is_empty() {
+        return Err(AuthenticationError::ApiTokenNotAllowed);
+    }
+    for permission in permissions {
+        if !token.has_scope(*permission) {
+            return Err(MissingPermission::from(*permission).into());
+        }
+    }
+    Ok(())
+}
+/// Rejects deactivated users. Their sessions are deleted when they are deactivated but API tokens remain
+fn check_active(user: &User) -> Result<(), AuthenticationError> {
+    if !user.active {
+        return Err(AuthenticationError::UnauthorizedWithHiddenReason(
+            ErrorReason::from("User is deactivated"),
+        ));
+    }
+    Ok(())
+}
+/// Rejects users that must change their password unless `allow_password_reset` is true
+async fn check_password_reset(
+    user: &User,
+    allow_password_reset: bool,
+    state: &SiteState,
+) -> Result<(), AuthenticationError> {
+    if !allow_password_reset
+        && UserPasswordAuthentication::does_user_require_reset(user.id, &state.database).await?
+    {
+        return Err(AuthenticationError::PasswordResetRequired);
+    }
+    Ok(())
+}
 #[derive(Clone, Debug, PartialEq, EnumIs)]
 pub enum AuthenticationRaw {
     /// The user is logged in with a session
     Session(Session),
+    /// An API token. It is checked against the database when [Authentication] is extracted
+    ApiToken(String),
     /// No Authorization Header was passed.API Routes will most likely reject this
     NoIdentification,
 }
@@ -190,6 +387,9 @@ impl AuthenticationRaw {
             AuthorizationHeader::Session { session } => {
                 AuthenticationRaw::session_cookie(&session, site)
             }
+            AuthorizationHeader::Bearer { token } if UserApiToken::is_api_token(&token) => {
+                AuthenticationRaw::ApiToken(token)
+            }
             AuthorizationHeader::Bearer { token } => {
                 AuthenticationRaw::session_cookie(&token, site)
             }
@@ -198,11 +398,15 @@ impl AuthenticationRaw {
 }
 
 pub mod utils {
-    use cs25_303_core::database::user::{
-        User,
-        auth::UserAndPasswordAuth,
-        find_user_by_email_or_username_with_password_auth,
-        login::{AdditionalFootprint, add_login_attempt},
+    use cs25_303_core::{
+        database::user::{
+            User,
+            auth::UserAndPasswordAuth,
+            find_user_by_email_or_username_with_password_auth,
+            lockout::{LockoutTarget, LoginLockout, record_failed_login},
+            login::{AdditionalFootprint, add_login_attempt},
+        },
+        user::auth::LoginThrottleConfig,
     };
     use sqlx::{PgPool, types::Uuid};
     use tracing::{debug, instrument};
@@ -210,10 +414,24 @@ pub mod utils {
     use crate::utils::ErrorReason;
 
     use super::AuthenticationError;
-
+    /// A successful password login
+    #[derive(Debug)]
+    pub struct VerifiedLogin {
+        pub user: User,
+        /// The id of the `user_login_attempts` row
+        pub login_id: Uuid,
+        /// The user must change their password before using anything else
+        pub password_reset_required: bool,
+    }
+    /// Verifies a password login.
+    ///
+    /// Returns [AuthenticationError::Locked] if the IP address is locked.
+    /// Or if this failed login started a lock of the IP address.
+    ///
+    /// A locked user is rejected like a wrong password. Otherwise locks would tell which usernames exist
     #[inline(always)]
     #[instrument(
-        skip(username, password, database),
+        skip(username, password, throttle, database),
         fields(project_module = "Authentication")
     )]
     pub async fn verify_login(
@@ -221,8 +439,18 @@ pub mod utils {
         password: impl AsRef<str>,
         ip_address: String,
         additional_footprint: Option<AdditionalFootprint>,
+        throttle: &LoginThrottleConfig,
         database: &PgPool,
-    ) -> Result<(User, Uuid), AuthenticationError> {
+    ) -> Result<VerifiedLogin, AuthenticationError> {
+        if throttle.enabled
+            && let Some(lock) =
+                LoginLockout::find_active(LockoutTarget::IpAddress(&ip_address), database).await?
+        {
+            debug!(?lock, "IP address is locked");
+            return Err(AuthenticationError::Locked {
+                locked_until: lock.locked_until,
+            });
+        }
         let user_found: Option<UserAndPasswordAuth> =
             find_user_by_email_or_username_with_password_auth(username, database)
                 .await
@@ -230,7 +458,7 @@ pub mod utils {
 
         let Some(user_found) = user_found else {
             debug!("User not found");
-            add_login_attempt(None, &ip_address, false, additional_footprint, database).await?;
+            record_failure(None, &ip_address, additional_footprint, throttle, database).await?;
             return Err(AuthenticationError::UnauthorizedWithHiddenReason(
                 ErrorReason::from("User not found"),
             ));
@@ -241,13 +469,31 @@ pub mod utils {
             password_auth,
         } = user_found;
 
+        if throttle.enabled
+            && let Some(lock) =
+                LoginLockout::find_active(LockoutTarget::User(user.id), database).await?
+        {
+            debug!(?lock, "User is locked");
+            record_failure(
+                Some(user.id),
+                &ip_address,
+                additional_footprint,
+                throttle,
+                database,
+            )
+            .await?;
+            return Err(AuthenticationError::UnauthorizedWithHiddenReason(
+                ErrorReason::from("User is locked"),
+            ));
+        }
+
         let Some(password_auth) = password_auth else {
             debug!(?user, "User has no password auth");
-            add_login_attempt(
+            record_failure(
                 Some(user.id),
                 &ip_address,
-                false,
                 additional_footprint,
+                throttle,
                 database,
             )
             .await?;
@@ -256,23 +502,37 @@ pub mod utils {
             ));
         };
 
+        if !user.active {
+            debug!(?user.id, "User is deactivated");
+            record_failure(
+                Some(user.id),
+                &ip_address,
+                additional_footprint,
+                throttle,
+                database,
+            )
+            .await?;
+            return Err(AuthenticationError::UnauthorizedWithHiddenReason(
+                ErrorReason::from("User is deactivated"),
+            ));
+        }
         if let Err(err) =
             password::verify_password(password.as_ref(), password_auth.password.as_deref())
         {
             debug!("Invalid Password: {}", err);
 
-            add_login_attempt(
+            record_failure(
                 Some(user.id),
                 &ip_address,
-                false,
                 additional_footprint,
+                throttle,
                 database,
             )
             .await?;
             return Err(err);
         }
         debug!("Login successful");
-        let id = add_login_attempt(
+        let login_id = add_login_attempt(
             Some(user.id),
             &ip_address,
             true,
@@ -280,7 +540,38 @@ pub mod utils {
             database,
         )
         .await?;
-        Ok((user, id))
+        LoginLockout::reset_user(user.id, database).await?;
+        Ok(VerifiedLogin {
+            user,
+            login_id,
+            password_reset_required: password_auth.requires_reset,
+        })
+    }
+    /// Adds the failed login attempt. Then locks the IP address and user if they reached their limit.
+    ///
+    /// Returns [AuthenticationError::Locked] if a lock of the IP address started. Locks of users are not returned
+    async fn record_failure(
+        user_id: Option<i32>,
+        ip_address: &str,
+        additional_footprint: Option<AdditionalFootprint>,
+        throttle: &LoginThrottleConfig,
+        database: &PgPool,
+    ) -> Result<(), AuthenticationError> {
+        add_login_attempt(user_id, ip_address, false, additional_footprint, database).await?;
+        if !throttle.enabled {
+            return Ok(());
+        }
+        let ip_lock =
+            record_failed_login(LockoutTarget::IpAddress(ip_address), throttle, database).await?;
+        if let Some(user_id) = user_id {
+            record_failed_login(LockoutTarget::User(user_id), throttle, database).await?;
+        }
+        match ip_lock {
+            Some(lock) => Err(AuthenticationError::Locked {
+                locked_until: lock.locked_until,
+            }),
+            None => Ok(()),
+        }
     }
 
     pub mod password {
@@ -336,3 +627,197 @@ pub mod utils {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use cs25_303_core::{
+        database::user::{
+            auth::token::NewUserApiToken, lockout::LockoutTarget,
+            new::create_or_update_user_password,
+        },
+        user::auth::LoginThrottleConfig,
+    };
+    use http::StatusCode;
+    use sqlx::types::Uuid;
+
+    use super::{
+        utils::{password::encrypt_password, verify_login},
+        *,
+    };
+    use crate::{
+        app::authentication::permissions::{ReadParticipants, UpdateParticipants},
+        utils::testing::{get_testing_site, new_testing_user, no_db_connection, testing_api_token},
+    };
+    /// Request parts as if the middleware found the raw authentication
+    fn parts_with(raw: AuthenticationRaw) -> Parts {
+        let (mut parts, _) = http::Request::new(()).into_parts();
+        parts.extensions.insert(raw);
+        parts
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn deactivated_users_are_rejected() -> anyhow::Result<()> {
+        let Some(site) = get_testing_site().await else {
+            no_db_connection();
+            return Ok(());
+        };
+        let user = new_testing_user(Some("Clinician"), &site.database).await?;
+        let session = site
+            .session
+            .create_session_default_lifespan(user.id, Uuid::new_v4())?;
+        let parts = parts_with(AuthenticationRaw::Session(session));
+        let result = Authentication::<()>::authenticate(&parts, &site, false).await;
+        assert!(result.is_ok(), "{result:?}");
+
+        sqlx::query("UPDATE users SET active = FALSE WHERE id = $1")
+            .bind(user.id)
+            .execute(&site.database)
+            .await?;
+        let result = Authentication::<()>::authenticate(&parts, &site, false).await;
+        assert!(
+            matches!(
+                result,
+                Err(AuthenticationError::UnauthorizedWithHiddenReason(_))
+            ),
+            "{result:?}"
+        );
+        Ok(())
+    }
+    #[test]
+    fn api_tokens_are_limited_to_their_scopes() {
+        let token = testing_api_token(1, vec![Permissions::ReadParticipants]);
+        assert!(check_token_scopes::<ReadParticipants>(&token).is_ok());
+        let result = check_token_scopes::<UpdateParticipants>(&token);
+        assert!(
+            matches!(
+                result,
+                Err(AuthenticationError::MissingPermission(MissingPermission(
+                    Permissions::UpdateParticipants
+                )))
+            ),
+            "{result:?}"
+        );
+        let result = check_token_scopes::<()>(&token);
+        assert!(
+            matches!(result, Err(AuthenticationError::ApiTokenNotAllowed)),
+            "{result:?}"
+        );
+
+        let admin_token = testing_api_token(1, vec![Permissions::Admin]);
+        assert!(check_token_scopes::<UpdateParticipants>(&admin_token).is_ok());
+        assert!(check_token_scopes::<()>(&admin_token).is_err());
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn api_token_missing_scope_is_rejected() -> anyhow::Result<()> {
+        let Some(site) = get_testing_site().await else {
+            no_db_connection();
+            return Ok(());
+        };
+        // The user can update participants. The token can not
+        let user = new_testing_user(Some("Clinician"), &site.database).await?;
+        let (_, token) = NewUserApiToken {
+            name: "Read Only".to_owned(),
+            scopes: vec![Permissions::ReadParticipants],
+            expires_at: None,
+        }
+        .insert(user.id, &site.database)
+        .await?;
+        let parts = parts_with(AuthenticationRaw::ApiToken(token));
+
+        let result = Authentication::<ReadParticipants>::authenticate(&parts, &site, false).await;
+        assert!(result.is_ok(), "{result:?}");
+        let result = Authentication::<UpdateParticipants>::authenticate(&parts, &site, false).await;
+        assert!(
+            matches!(
+                result,
+                Err(AuthenticationError::MissingPermission(MissingPermission(
+                    Permissions::UpdateParticipants
+                )))
+            ),
+            "{result:?}"
+        );
+        let result = Authentication::<()>::authenticate(&parts, &site, false).await;
+        assert!(
+            matches!(result, Err(AuthenticationError::ApiTokenNotAllowed)),
+            "{result:?}"
+        );
+        Ok(())
+    }
+    /// The status and body of the response to an authentication error
+    async fn error_response(err: AuthenticationError) -> anyhow::Result<(StatusCode, Vec<u8>)> {
+        let response = err.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+        Ok((status, body.to_vec()))
+    }
+    /// Locked users must look the same as usernames that do not exist
+    #[tokio::test]
+    #[ignore]
+    async fn user_locks_do_not_reveal_usernames() -> anyhow::Result<()> {
+        let Some(site) = get_testing_site().await else {
+            no_db_connection();
+            return Ok(());
+        };
+        let throttle = LoginThrottleConfig {
+            max_failures_per_user: 2,
+            max_failures_per_ip: 100,
+            ..Default::default()
+        };
+        let user = new_testing_user(None, &site.database).await?;
+        let hash = encrypt_password("correct_password").expect("Password should hash");
+        create_or_update_user_password(user.id, &hash, false, &site.database).await?;
+        let unknown = format!("unknown_{}", rand::random::<u32>());
+        let ip_address = |name: &str| format!("lockout_test_{name}_{}", rand::random::<u32>());
+        let user_ip = ip_address("user");
+        let unknown_ip = ip_address("unknown");
+
+        for _ in 0..4 {
+            let user_err = verify_login(
+                &user.username,
+                "wrong_password",
+                user_ip.clone(),
+                None,
+                &throttle,
+                &site.database,
+            )
+            .await
+            .unwrap_err();
+            let unknown_err = verify_login(
+                &unknown,
+                "wrong_password",
+                unknown_ip.clone(),
+                None,
+                &throttle,
+                &site.database,
+            )
+            .await
+            .unwrap_err();
+            assert!(
+                !matches!(user_err, AuthenticationError::Locked { .. }),
+                "{user_err:?}"
+            );
+            assert_eq!(
+                error_response(user_err).await?,
+                error_response(unknown_err).await?
+            );
+        }
+        let lock = LoginLockout::find_active(LockoutTarget::User(user.id), &site.database).await?;
+        assert!(lock.is_some());
+        // The lock still applies to the correct password
+        let err = verify_login(
+            &user.username,
+            "correct_password",
+            ip_address("correct"),
+            None,
+            &throttle,
+            &site.database,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(err, AuthenticationError::UnauthorizedWithHiddenReason(_)),
+            "{err:?}"
+        );
+        Ok(())
+    }
+}
@@ -47,26 +47,73 @@ impl PermissionCheck for () {
         Ok(())
     }
 }
-#[allow(unused_macros)]
 macro_rules! permission_check {
     (
-        $(#[$docs:meta])*
-        $name:ident => $($perm:expr),+
+        $(
+            $(#[$docs:meta])*
+            $name:ident => $($perm:expr),+
+        );* $(;)?
     ) => {
-        $(#[$docs])*
-        pub struct $name;
-        impl crate::app::authentication::auth_with_perm::PermissionCheck for $name {
-            fn permissions_required() -> &'static [Permissions] {
-                &[$($perm),+]
+        $(
+            $(#[$docs])*
+            #[derive(Clone, Copy, PartialEq, Eq)]
+            pub struct $name;
+            impl crate::app::authentication::permissions::PermissionCheck for $name {
+                fn permissions_required() -> &'static [Permissions] {
+                    &[$($perm),+]
+                }
             }
-        }
-        impl std::fmt::Debug for $name {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                use crate::app::authentication::auth_with_perm::PermissionCheck;
-                f.debug_struct(stringify!($name))
-                .field("permissions", &Self::permissions_required())
-                .finish()
+            impl std::fmt::Debug for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    use crate::app::authentication::permissions::PermissionCheck;
+                    f.debug_struct(stringify!($name))
+                    .field("permissions", &Self::permissions_required())
+                    .finish()
+                }
             }
-        }
+        )*
     };
 }
+permission_check!(
+    /// Requires [Permissions::ManageUsers]
+    ManageUsers => Permissions::ManageUsers;
+    /// Requires [Permissions::SyncRedCap]
+    SyncRedCap => Permissions::SyncRedCap;
+    /// Requires [Permissions::ReadParticipants]
+    ReadParticipants => Permissions::ReadParticipants;
+    /// Requires [Permissions::UpdateParticipants]
+    UpdateParticipants => Permissions::UpdateParticipants;
+    /// Requires [Permissions::ResearchQuery]
+    ResearchQuery => Permissions::ResearchQuery;
+);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::{get_testing_db, new_testing_user, no_db_connection};
+    /// Clinicians can read participants but can not run research queries
+    #[tokio::test]
+    #[ignore]
+    async fn missing_permission_is_rejected() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection();
+            return Ok(());
+        };
+        let clinician = new_testing_user(Some("Clinician"), &database).await?;
+        ReadParticipants::check_permissions(&clinician, &database).await?;
+        let result = ResearchQuery::check_permissions(&clinician, &database).await;
+        assert!(
+            matches!(
+                result,
+                Err(AuthenticationError::MissingPermission(MissingPermission(
+                    Permissions::ResearchQuery
+                )))
+            ),
+            "{result:?}"
+        );
+
+        let admin = new_testing_user(Some("Admin"), &database).await?;
+        ResearchQuery::check_permissions(&admin, &database).await?;
+        ManageUsers::check_permissions(&admin, &database).await?;
+        Ok(())
+    }
+}
@@ -307,8 +307,55 @@ impl SessionManager {
 
         session
     }
-    pub fn delete_all_for_user(&self, user_id: i32) -> Result<(), SessionError> {
-        let to_remove = self.filter_table(true, |session| session.user_id == user_id)?;
+    /// Returns the sessions of the user that have not expired. Oldest first
+    pub fn get_sessions_for_user(&self, user_id: i32) -> Result<Vec<Session>, SessionError> {
+        let mut sessions = self.filter_table(true, |session| {
+            session.user_id == user_id && !session.is_expired()
+        })?;
+        sessions.sort_by_key(|session| session.created);
+        Ok(sessions)
+    }
+    /// Deletes the session of the user with the login id. See [Session::login_id]
+    #[instrument]
+    pub fn delete_user_session(
+        &self,
+        user_id: i32,
+        login_id: Uuid,
+    ) -> Result<Option<Session>, SessionError> {
+        let session = self
+            .filter_table(true, |session| {
+                session.user_id == user_id && session.login_id == login_id
+            })?
+            .pop();
+        match session {
+            Some(session) => self.delete_session(&session.session_key),
+            None => Ok(None),
+        }
+    }
+    /// Deletes every session of the user. Returns the number of sessions removed
+    #[instrument]
+    pub fn delete_all_for_user(&self, user_id: i32) -> Result<u32, SessionError> {
+        self.delete_where(|session| session.user_id == user_id)
+    }
+    /// Deletes every session of the user except the one with the session key.
+    ///
+    /// Returns the number of sessions removed
+    #[instrument(skip(keep_session_key))]
+    pub fn delete_all_for_user_except(
+        &self,
+        user_id: i32,
+        keep_session_key: &str,
+    ) -> Result<u32, SessionError> {
+        self.delete_where(|session| {
+            session.user_id == user_id && session.session_key != keep_session_key
+        })
+    }
+    fn delete_where<F>(&self, filter: F) -> Result<u32, SessionError>
+    where
+        F: Fn(&Session) -> bool,
+    {
+        let mut sessions_removed = 0u32;
+        let to_remove = self.filter_table(true, filter)?;
         let sessions = self.sessions.begin_write()?;
         {
             let mut table = sessions.open_table(TABLE)?;
@@ -320,6 +367,7 @@ impl SessionManager {
                             let ok = ok.map(|x| Session::try_from(x.value()));
                             debug!("Removed session: {:?}", ok);
                         }
+                        sessions_removed += 1;
                     }
                     Err(err) => {
                         error!("Failed to remove session: {:?}", err);
@@ -328,7 +376,7 @@ impl SessionManager {
             }
         }
         sessions.commit()?;
-        Ok(())
+        Ok(sessions_removed)
     }
 }
 
@@ -342,3 +390,57 @@ pub fn create_session_id(exists_call_back: impl Fn(&str) -> bool) -> String {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::testing_session_manager;
+
+    #[test]
+    pub fn sessions_for_user() -> anyhow::Result<()> {
+        let manager = testing_session_manager();
+        let first = manager.create_session(1, Uuid::new_v4(), Duration::hours(1))?;
+        let second = manager.create_session(1, Uuid::new_v4(), Duration::hours(1))?;
+        manager.create_session(1, Uuid::new_v4(), Duration::hours(-1))?;
+        manager.create_session(2, Uuid::new_v4(), Duration::hours(1))?;
+
+        let sessions = manager.get_sessions_for_user(1)?;
+        assert_eq!(sessions, vec![first, second]);
+        Ok(())
+    }
+    #[test]
+    pub fn delete_user_session_only_deletes_own_sessions() -> anyhow::Result<()> {
+        let manager = testing_session_manager();
+        let session = manager.create_session(1, Uuid::new_v4(), Duration::hours(1))?;
+        let other = manager.create_session(2, Uuid::new_v4(), Duration::hours(1))?;
+
+        assert_eq!(manager.delete_user_session(1, other.login_id)?, None);
+        assert!(manager.get_session(&other.session_key)?.is_some());
+
+        assert_eq!(
+            manager.delete_user_session(1, session.login_id)?,
+            Some(session.clone())
+        );
+        assert!(manager.get_session(&session.session_key)?.is_none());
+        assert_eq!(manager.delete_user_session(1, session.login_id)?, None);
+        Ok(())
+    }
+    #[test]
+    pub fn delete_all_for_user_except_keeps_current() -> anyhow::Result<()> {
+        let manager = testing_session_manager();
+        let current = manager.create_session(1, Uuid::new_v4(), Duration::hours(1))?;
+        manager.create_session(1, Uuid::new_v4(), Duration::hours(1))?;
+        manager.create_session(1, Uuid::new_v4(), Duration::hours(1))?;
+        let other = manager.create_session(2, Uuid::new_v4(), Duration::hours(1))?;
+
+        assert_eq!(
+            manager.delete_all_for_user_except(1, &current.session_key)?,
+            2
+        );
+        assert_eq!(manager.get_sessions_for_user(1)?, vec![current]);
+        assert_eq!(manager.get_sessions_for_user(2)?, vec![other]);
+
+        assert_eq!(manager.delete_all_for_user(2)?, 1);
+        assert!(manager.get_sessions_for_user(2)?.is_empty());
+        Ok(())
+    }
+}
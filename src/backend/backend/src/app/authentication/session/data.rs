@@ -1,5 +1,8 @@
 use chrono::{DateTime, Duration, FixedOffset, Local};
-use cs25_303_core::database::{self, DBError, user::User};
+use cs25_303_core::database::{
+    self, DBError,
+    user::{User, login::UserLoginAttempt},
+};
 use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
 use tracing::error;
@@ -13,6 +16,9 @@ pub type SessionTime = DateTime<FixedOffset>;
 pub struct Session {
     pub user_id: i32,
     pub session_key: String,
+    /// The `user_login_attempts` row of the login that created the session.
+    ///
+    /// Identifies the session in the API so the session key is never shown
     pub login_id: Uuid,
     pub expires: DateTime<FixedOffset>,
     pub created: DateTime<FixedOffset>,
@@ -30,6 +36,54 @@ pub struct SessionPublic {
     /// When the session was created
     pub created: DateTime<FixedOffset>,
 }
+/// A session without the session key. With where the login came from
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct SessionDetails {
+    /// The login id of the session. See [Session::login_id]
+    pub id: Uuid,
+    pub user_id: i32,
+    /// The session making the request
+    pub current: bool,
+    /// When the session expires
+    pub expires: DateTime<FixedOffset>,
+    /// When the session was created
+    pub created: DateTime<FixedOffset>,
+    /// The IP address the user logged in from
+    pub ip_address: Option<String>,
+    /// The User-Agent of the login
+    pub user_agent: Option<String>,
+}
+impl SessionDetails {
+    /// Adds the IP address and User-Agent of the logins to the sessions
+    ///
+    /// `current_session_key` is the session key of the session making the request
+    pub async fn from_sessions(
+        sessions: Vec<Session>,
+        current_session_key: Option<&str>,
+        db: &sqlx::PgPool,
+    ) -> Result<Vec<Self>, DBError> {
+        let login_ids: Vec<Uuid> = sessions.iter().map(|session| session.login_id).collect();
+        let logins = UserLoginAttempt::find_by_ids(&login_ids, db).await?;
+        let details = sessions
+            .into_iter()
+            .map(|session| {
+                let login = logins.iter().find(|login| login.id == session.login_id);
+                Self {
+                    id: session.login_id,
+                    user_id: session.user_id,
+                    current: current_session_key == Some(session.session_key.as_str()),
+                    expires: session.expires,
+                    created: session.created,
+                    ip_address: login.map(|login| login.ip_address.clone()),
+                    user_agent: login
+                        .and_then(|login| login.additional_footprint.as_ref())
+                        .map(|footprint| footprint.user_agent.clone()),
+                }
+            })
+            .collect();
+        Ok(details)
+    }
+}
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, ToSchema)]
 pub struct SmallSession {
     pub user_id: i32,
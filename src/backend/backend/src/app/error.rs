@@ -91,7 +91,10 @@ basic_internal_error!(
     serde_json::Error => "JSON",
     http::Error => "HTTP",
     argon2::Error => "Argon2",
-    argon2::password_hash::Error => "Argon2"
+    argon2::password_hash::Error => "Argon2",
+    cs25_303_core::red_cap::api::RedCapAPIError => "Red Cap",
+    cs25_303_core::red_cap::tasks::RedCapTaskError => "Red Cap",
+    cs25_303_core::user::auth::oidc::OidcError => "OpenID Connect"
 );
 #[derive(Debug)]
 pub struct InternalError(pub Box<dyn IntoErrorResponse>);
@@ -19,6 +19,7 @@ pub mod authentication;
 use tracing::info;
 mod api;
 mod open_api;
+pub mod red_cap_sync;
 mod web;
 use crate::{
     config::FullConfig,
@@ -35,6 +36,7 @@ pub(super) async fn start_web_server(config: FullConfig) -> anyhow::Result<()> {
         auth,
         enabled_features,
         robots,
+        red_cap,
     } = config;
     // Start the logger
     crate::logging::init(log)?;
@@ -46,7 +48,7 @@ pub(super) async fn start_web_server(config: FullConfig) -> anyhow::Result<()> {
     info!("Connected to database");
     let session = SessionManager::new(None, mode)?;
     // Create the website state
-    let inner = SiteStateInner::new(auth, session, enabled_features.clone(), robots);
+    let inner = SiteStateInner::new(auth, session, enabled_features.clone(), robots, red_cap)?;
     let website = SiteState {
         inner: Arc::new(inner),
         database,
@@ -86,7 +86,9 @@ impl Modify for SecurityAddon {
                 SecurityScheme::Http(
                     Http::builder()
                         .scheme(HttpAuthScheme::Bearer)
-                        .description(Some("Bearer Token Or session_key"))
+                        .description(Some(
+                            "An API token created with `/api/user/tokens` or a session_key",
+                        ))
                         .build(),
                 ),
             );
@@ -0,0 +1,188 @@
+//! Background service that syncs with Red Cap on an interval.
+//!
+//! Pulling is enabled by [EnabledFeatures::red_cap_read_syncing](crate::config::EnabledFeatures::red_cap_read_syncing)
+//! and pushing by [EnabledFeatures::red_cap_write_syncing](crate::config::EnabledFeatures::red_cap_write_syncing)
+use std::{
+    fmt::Debug,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration as StdDuration,
+};
+
+use chrono::{DateTime, Duration, FixedOffset, Local};
+use cs25_303_core::{
+    database::red_cap::sync_events::RedCapSyncInitiator,
+    red_cap::{
+        api::{RedCapAPIError, RedCapClientConfig, RedcapClient},
+        sync::{RedCapSyncOptions, RedCapSyncReport, sync_with_red_cap},
+        tasks::RedCapTaskError,
+    },
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
+use tuxs_config_types::chrono_types::duration::ConfigDuration;
+use utoipa::ToSchema;
+
+use crate::config::EnabledFeatures;
+
+use super::SiteStateInner;
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RedCapSyncConfig {
+    /// The url, token and timeout of the Red Cap API. The sync will not start without a token
+    #[serde(flatten)]
+    pub client: RedCapClientConfig,
+    /// How long to wait between syncs
+    pub interval: ConfigDuration,
+}
+impl Default for RedCapSyncConfig {
+    fn default() -> Self {
+        Self {
+            client: RedCapClientConfig::default(),
+            interval: Duration::minutes(15).into(),
+        }
+    }
+}
+/// The current state of the sync service
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct RedCapSyncServiceStatus {
+    /// What parts of the sync are enabled
+    pub options: RedCapSyncOptions,
+    /// Rather or not the service has been started
+    pub running: bool,
+    /// Rather or not a sync is currently in progress
+    pub syncing: bool,
+    /// The report of the last sync that was able to run
+    pub last_report: Option<RedCapSyncReport>,
+    /// The error of the last sync if it could not run at all
+    pub last_error: Option<String>,
+    pub next_sync_at: Option<DateTime<FixedOffset>>,
+}
+pub struct RedCapSyncService {
+    config: RedCapSyncConfig,
+    options: RedCapSyncOptions,
+    running: AtomicBool,
+    status: RwLock<RedCapSyncServiceStatus>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+impl Debug for RedCapSyncService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedCapSyncService")
+            .field("options", &self.options)
+            .field("interval", &self.config.interval)
+            .field("running", &self.running.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+impl RedCapSyncService {
+    pub fn new(config: RedCapSyncConfig, features: &EnabledFeatures) -> Self {
+        let options = RedCapSyncOptions {
+            pull: features.red_cap_read_syncing,
+            push: features.red_cap_write_syncing,
+        };
+        Self {
+            config,
+            options,
+            running: AtomicBool::new(false),
+            status: RwLock::new(RedCapSyncServiceStatus {
+                options,
+                ..Default::default()
+            }),
+            task: Mutex::new(None),
+        }
+    }
+    pub async fn status(&self) -> RedCapSyncServiceStatus {
+        let mut status = self.status.read().await.clone();
+        status.running = self.running.load(Ordering::Relaxed);
+        status
+    }
+    /// Starts the sync task.
+    ///
+    /// Does nothing if syncing is disabled or no token is configured
+    pub async fn start(this: Arc<SiteStateInner>, database: PgPool) {
+        let service = &this.red_cap_sync;
+        if !service.options.is_enabled() {
+            debug!("Red Cap syncing is disabled");
+            return;
+        }
+        if service.config.client.token.is_none() {
+            warn!("Red Cap syncing is enabled but no token is configured");
+            return;
+        }
+        let how_often = match service.config.interval.to_std() {
+            Ok(ok) => ok,
+            Err(err) => {
+                error!("Failed to convert sync interval: {:?}", err);
+                return;
+            }
+        };
+        service.running.store(true, Ordering::Relaxed);
+        info!(
+            options = ?service.options,
+            url = ?service.config.client.url,
+            ?how_often,
+            "Starting Red Cap sync"
+        );
+        let task_state = this.clone();
+        let handle = tokio::spawn(async move {
+            RedCapSyncService::sync_task(task_state, database, how_often).await;
+        });
+        *service.task.lock().await = Some(handle);
+    }
+    pub async fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.task.lock().await.take() {
+            handle.abort();
+        }
+    }
+    async fn sync_task(this: Arc<SiteStateInner>, database: PgPool, how_often: StdDuration) {
+        let service = &this.red_cap_sync;
+        // Records that failed before they existed locally. They are retried on the next sync
+        let mut retry_records = Vec::new();
+        while service.running.load(Ordering::Relaxed) {
+            service.status.write().await.syncing = true;
+            let result = service.sync(&retry_records, &database).await;
+            {
+                let mut status = service.status.write().await;
+                status.syncing = false;
+                status.next_sync_at = Duration::from_std(how_often)
+                    .ok()
+                    .map(|interval| Local::now().fixed_offset() + interval);
+                match result {
+                    Ok(report) => {
+                        retry_records = report.failed_new_records();
+                        status.last_report = Some(report);
+                        status.last_error = None;
+                    }
+                    Err(err) => {
+                        error!(?err, "Red Cap sync failed");
+                        status.last_error = Some(err.to_string());
+                    }
+                }
+            }
+            tokio::time::sleep(how_often).await;
+        }
+    }
+    /// Connects to the Red Cap API used by the sync
+    ///
+    /// Requests are logged as made by the sync job. Use [RedcapClient::with_initiator] when a user made the request
+    pub async fn client(&self) -> Result<RedcapClient, RedCapAPIError> {
+        let client = RedcapClient::with_config(&self.config.client).await?;
+        Ok(client.with_initiator(RedCapSyncInitiator::Job("red_cap_sync".to_owned())))
+    }
+    async fn sync(
+        &self,
+        retry_records: &[i32],
+        database: &PgPool,
+    ) -> Result<RedCapSyncReport, RedCapTaskError> {
+        let client = self.client().await?;
+        sync_with_red_cap(self.options, retry_records, database, &client).await
+    }
+}
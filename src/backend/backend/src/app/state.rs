@@ -1,7 +1,10 @@
 use std::{fmt::Debug, ops::Deref, sync::Arc};
 
 use axum::extract::State;
-use cs25_303_core::user::auth::AuthenticationProvidersConfig;
+use cs25_303_core::{
+    database::red_cap::questions::requirements::RequirementsEvaluator,
+    user::auth::{AuthenticationProvidersConfig, oidc::OidcClient},
+};
 use http::HeaderName;
 use opentelemetry::{
     global,
@@ -17,17 +20,25 @@ use crate::{
 };
 pub static X_FORWARDED_FOR_HEADER: HeaderName = HeaderName::from_static("x-forwarded-for");
 
-use super::authentication::session::SessionManager;
+use super::{
+    authentication::session::SessionManager,
+    red_cap_sync::{RedCapSyncConfig, RedCapSyncService},
+};
 /// The Inner State of the Website.
 ///
 /// This part will be wrapped in an Arc to allow for sharing between different parts of the website and threads
 pub struct SiteStateInner {
     pub authentication: AuthenticationProvidersConfig,
+    /// Set if OpenID Connect login is configured
+    pub oidc: Option<OidcClient>,
     pub session: SessionManager,
     session_cleaner: Mutex<Option<JoinHandle<()>>>,
     pub features: EnabledFeatures,
     pub metrics: AppMetrics,
     pub robots: RobotsConfig,
+    /// Evaluates question requirement scripts. Compiled scripts are cached
+    pub requirements: RequirementsEvaluator,
+    pub red_cap_sync: RedCapSyncService,
 }
 impl SiteStateInner {
     async fn set_session_cleaner(&self, handle: JoinHandle<()>) {
@@ -53,15 +64,24 @@ impl SiteStateInner {
         session: SessionManager,
         features: EnabledFeatures,
         robots: RobotsConfig,
-    ) -> Self {
-        Self {
+        red_cap: RedCapSyncConfig,
+    ) -> anyhow::Result<Self> {
+        let oidc = authentication
+            .oidc
+            .clone()
+            .map(OidcClient::new)
+            .transpose()?;
+        Ok(Self {
             authentication,
+            oidc,
             session,
+            red_cap_sync: RedCapSyncService::new(red_cap, &features),
             features,
             session_cleaner: Mutex::new(None),
             metrics: AppMetrics::default(),
             robots,
-        }
+            requirements: RequirementsEvaluator::default(),
+        })
     }
 }
 #[derive(Debug, Clone)]
@@ -165,6 +185,7 @@ impl SiteState {
     ///
     /// ## Current Starts
     /// - Session Cleaner
+    /// - Red Cap Sync
     pub(super) async fn start(&self) {
         let inner_cloned = self.inner.clone();
 
@@ -173,17 +194,20 @@ impl SiteState {
             self.inner.set_session_cleaner(handle).await;
             info!("Session cleaner started");
         }
+        RedCapSyncService::start(self.inner.clone(), self.database.clone()).await;
     }
     /// Closes the website.
     ///
     /// ## Current Closes
     /// - Database Connection
     /// - Session Cleaner Task
+    /// - Red Cap Sync Task
     pub(super) async fn close(self) {
         // Close the website
         let SiteState {
             database, inner, ..
         } = self;
+        inner.red_cap_sync.stop().await;
         database.close().await;
         {
             inner.session.stop_cleaner();
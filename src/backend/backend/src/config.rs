@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use strum::EnumIs;
 use utoipa::ToSchema;
 pub mod robots;
-use crate::logging::config::LoggingConfig;
+use crate::{app::red_cap_sync::RedCapSyncConfig, logging::config::LoggingConfig};
 pub const CONFIG_PREFIX: &str = "CS-25-303";
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, EnumIs)]
 pub enum Mode {
@@ -59,6 +59,7 @@ pub struct ReadConfigType {
     pub tls: Option<TlsConfig>,
     pub auth: Option<AuthenticationProvidersConfig>,
     pub robots: Option<robots::RobotsConfig>,
+    pub red_cap: Option<RedCapSyncConfig>,
 }
 
 #[derive(Debug, Clone, Default, Serialize)]
@@ -72,6 +73,7 @@ pub struct FullConfig {
     pub tls: Option<TlsConfig>,
     pub auth: AuthenticationProvidersConfig,
     pub robots: robots::RobotsConfig,
+    pub red_cap: RedCapSyncConfig,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -123,7 +125,7 @@ pub fn load_config(path: Option<PathBuf>) -> anyhow::Result<FullConfig> {
         };
     // Merge the environment variables with the configuration file. If neither exists the default values are used.
     // Environment variables take precedence.
-    let (web_server, auth, log, database, mode, enabled_features, robots) = env_or_file_or_default!(
+    let (web_server, auth, log, database, mode, enabled_features, robots, red_cap) = env_or_file_or_default!(
         config_from_file,
         environment,
         web_server,
@@ -132,7 +134,8 @@ pub fn load_config(path: Option<PathBuf>) -> anyhow::Result<FullConfig> {
         database,
         mode,
         enabled_features,
-        robots
+        robots,
+        red_cap
     );
 
     let tls = environment.tls.or(config_from_file.tls.take());
@@ -146,5 +149,6 @@ pub fn load_config(path: Option<PathBuf>) -> anyhow::Result<FullConfig> {
         auth,
         enabled_features,
         robots,
+        red_cap,
     })
 }
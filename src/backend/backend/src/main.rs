@@ -107,6 +107,7 @@ fn save_default_config_file(location: PathBuf) -> anyhow::Result<()> {
         auth: Default::default(),
         enabled_features: Default::default(),
         robots: Default::default(),
+        red_cap: Default::default(),
     };
 
     let toml = toml::to_string_pretty(&config)
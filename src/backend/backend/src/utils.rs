@@ -6,6 +6,8 @@ pub mod other;
 pub use header::*;
 pub use requests::*;
 pub mod request_logging;
+#[cfg(test)]
+pub(crate) mod testing;
 
 pub mod base64_utils {
     use base64::{DecodeError, Engine, engine::general_purpose::STANDARD};
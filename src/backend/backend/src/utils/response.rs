@@ -6,6 +6,8 @@ pub use builder::ResponseBuilder;
 use derive_more::From;
 pub mod api_error_response;
 pub mod conflict;
+pub mod password_policy;
+pub mod feature_disabled;
 pub trait IntoErrorResponse: Error + Send + Sync {
     /// Converts the error into a response
     ///
@@ -39,11 +39,13 @@ impl ResponseBuilder {
     new_response_builder!(
         ok => OK,
         no_content => NO_CONTENT,
+        see_other => SEE_OTHER,
         bad_request => BAD_REQUEST,
         not_found => NOT_FOUND,
         conflict => CONFLICT,
         unauthorized => UNAUTHORIZED,
         forbidden => FORBIDDEN,
+        too_many_requests => TOO_MANY_REQUESTS,
         internal_server_error => INTERNAL_SERVER_ERROR,
         unsupported_media_type => UNSUPPORTED_MEDIA_TYPE
     );
@@ -0,0 +1,27 @@
+use axum::response::{IntoResponse, Response};
+
+use super::{ErrorReason, ResponseBuilder, api_error_response::APIErrorResponse};
+
+/// Returned when a request requires a feature that is disabled in [crate::config::EnabledFeatures]
+///
+/// Responds with a 403 and the name of the disabled feature in the details
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureDisabledResponse(pub &'static str);
+
+impl From<&'static str> for FeatureDisabledResponse {
+    fn from(feature: &'static str) -> Self {
+        Self(feature)
+    }
+}
+impl IntoResponse for FeatureDisabledResponse {
+    fn into_response(self) -> Response {
+        let response: APIErrorResponse<&str, ()> = APIErrorResponse {
+            message: "Feature Disabled".into(),
+            details: Some(self.0),
+            error: None,
+        };
+        ResponseBuilder::forbidden()
+            .extension(ErrorReason::from(format!("Feature {} is disabled", self.0)))
+            .json(&response)
+    }
+}
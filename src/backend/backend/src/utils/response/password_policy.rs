@@ -0,0 +1,59 @@
+use axum::response::{IntoResponse, Response};
+use cs25_303_core::user::auth::PasswordViolation;
+use serde_json::Value;
+
+use super::{ErrorReason, ResponseBuilder, api_error_response::APIErrorResponse};
+/// The password does not meet the password policy. Contains every rule it breaks
+#[derive(Debug)]
+pub struct PasswordPolicyResponse(pub Vec<PasswordViolation>);
+
+impl utoipa::IntoResponses for PasswordPolicyResponse {
+    fn responses() -> std::collections::BTreeMap<
+        String,
+        utoipa::openapi::RefOr<utoipa::openapi::response::Response>,
+    > {
+        utoipa::openapi::response::ResponsesBuilder::new()
+            .responses_from_iter([(
+                "400",
+                utoipa::openapi::ResponseBuilder::new()
+                    .description("The password does not meet the password policy")
+                    .content(
+                        "application/json",
+                        utoipa::openapi::content::ContentBuilder::new()
+                            .example(Some(example()))
+                            .into(),
+                    )
+                    .build(),
+            )])
+            .build()
+            .into()
+    }
+}
+
+fn example() -> Value {
+    PasswordPolicyResponse(vec![
+        PasswordViolation::TooShort { min_length: 8 },
+        PasswordViolation::MissingNumber,
+    ])
+    .body()
+}
+impl PasswordPolicyResponse {
+    fn body(&self) -> Value {
+        let response: APIErrorResponse<&[PasswordViolation], ()> = APIErrorResponse {
+            message: "The password does not meet the password policy".into(),
+            details: Some(&self.0),
+            error: None,
+        };
+        serde_json::to_value(response).unwrap_or_default()
+    }
+}
+impl IntoResponse for PasswordPolicyResponse {
+    fn into_response(self) -> Response {
+        ResponseBuilder::bad_request()
+            .extension(ErrorReason::from(format!(
+                "Password policy violations: {:?}",
+                self.0
+            )))
+            .json(&self.body())
+    }
+}
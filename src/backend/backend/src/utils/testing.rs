@@ -0,0 +1,131 @@
+//! Helpers for tests.
+//!
+//! Database tests use the `[database]` section of `cs-25-303-core.testing.toml`. The same file the core tests use
+use std::{
+    env,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use cs25_303_core::{
+    database::user::{
+        User,
+        auth::token::UserApiToken,
+        new::NewUser,
+        roles::{Roles, UserRoles},
+    },
+    user::Permissions,
+    utils::testing::{db::DBTestingConfig, find_file_with_name_check_parents},
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio::sync::OnceCell as AsyncOnceCell;
+
+use crate::{
+    app::{
+        SiteState, SiteStateInner,
+        authentication::session::{SessionManager, SessionManagerConfig},
+    },
+    config::Mode,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+struct BackendTestingConfig {
+    database: Option<DBTestingConfig>,
+}
+static CONFIG_CELL: OnceLock<Option<DBTestingConfig>> = OnceLock::new();
+static DB_NAME: AsyncOnceCell<String> = AsyncOnceCell::const_new();
+
+fn load_config() -> Result<Option<DBTestingConfig>, anyhow::Error> {
+    let file_path = match env::var_os("CS25_CORE_TEST_CONFIG").map(PathBuf::from) {
+        Some(path) => path,
+        None => {
+            let Some(path) = find_file_with_name_check_parents(
+                env::current_dir()?,
+                "cs-25-303-core.testing.toml",
+                3,
+            ) else {
+                return Ok(None);
+            };
+            path
+        }
+    };
+    let content = std::fs::read_to_string(&file_path)?;
+    let config: BackendTestingConfig = toml::from_str(&content)?;
+    Ok(config.database)
+}
+/// Gets the testing database. The database is created once per test run
+pub async fn get_testing_db() -> Option<PgPool> {
+    let config = CONFIG_CELL
+        .get_or_init(|| load_config().expect("Error loading config"))
+        .as_ref()?;
+    let db_name = DB_NAME
+        .get_or_try_init(|| config.create_testing_db())
+        .await
+        .expect("Error getting db");
+    let connection = config
+        .connect_with_name(db_name)
+        .await
+        .expect("Error connecting to db");
+    Some(connection)
+}
+pub fn no_db_connection() {
+    eprintln!("Database not configured in `cs-25-303-core.testing.toml`");
+}
+/// A session manager with a new session database in the temp directory
+pub fn testing_session_manager() -> SessionManager {
+    let config = SessionManagerConfig {
+        database_location: env::temp_dir()
+            .join(format!("cs25_303_sessions_{}.redb", rand::random::<u32>())),
+        ..Default::default()
+    };
+    SessionManager::new(Some(config), Mode::Debug).expect("Error creating session manager")
+}
+/// A [SiteState] with the default config, the testing database and a new session database
+pub async fn get_testing_site() -> Option<SiteState> {
+    let database = get_testing_db().await?;
+    let inner = SiteStateInner::new(
+        Default::default(),
+        testing_session_manager(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    )
+    .expect("Error creating site state");
+    Some(SiteState {
+        inner: Arc::new(inner),
+        database,
+    })
+}
+/// Creates a user with a random username. Added to the role if one is given
+pub async fn new_testing_user(role: Option<&str>, database: &PgPool) -> anyhow::Result<User> {
+    let username = format!("backend_test_{}", rand::random::<u32>());
+    let user = NewUser {
+        email: format!("{username}@example.com"),
+        username,
+        first_name: "Backend".to_owned(),
+        last_name: "Test".to_owned(),
+    }
+    .insert_return_user(database)
+    .await?;
+    if let Some(role) = role {
+        let role = Roles::get_role_by_name(role, database)
+            .await?
+            .expect("Default role should exist");
+        UserRoles::add_user_role(user.id, role.id, database).await?;
+    }
+    Ok(user)
+}
+/// An API token that is not saved in the database
+pub fn testing_api_token(user_id: i32, scopes: Vec<Permissions>) -> UserApiToken {
+    UserApiToken {
+        id: 0,
+        user_id,
+        name: "Testing Token".to_owned(),
+        token_hash: String::new(),
+        scopes,
+        expires_at: None,
+        last_used_at: None,
+        created_at: chrono::Local::now().fixed_offset(),
+    }
+}
@@ -0,0 +1,4 @@
+-- Tracks when a participant was last modified locally.
+-- If updated_at is after last_synced_with_red_cap the participant needs to be pushed to Red Cap
+ALTER TABLE participants
+    ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
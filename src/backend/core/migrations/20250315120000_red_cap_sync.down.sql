@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS red_cap_sync_status;
+ALTER TABLE case_notes DROP COLUMN IF EXISTS updated_at;
@@ -0,0 +1,19 @@
+-- Tracks when a case note was last modified locally.
+-- If updated_at is after last_synced_with_red_cap the case note needs to be pushed to Red Cap
+ALTER TABLE case_notes
+    ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+-- The result of the last Red Cap sync for each participant
+CREATE TABLE IF NOT EXISTS red_cap_sync_status(
+    participant_id integer PRIMARY KEY,
+        CONSTRAINT FK_red_cap_sync_status_participant_id
+            FOREIGN KEY (participant_id)
+            REFERENCES participants(id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE,
+    status VARCHAR(32) NOT NULL,
+    last_pulled_at TIMESTAMP WITH TIME ZONE,
+    last_pushed_at TIMESTAMP WITH TIME ZONE,
+    error TEXT,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS red_cap_sync_events;
@@ -0,0 +1,29 @@
+-- Every import into and export from Red Cap
+CREATE TABLE IF NOT EXISTS red_cap_sync_events(
+    id serial PRIMARY KEY,
+    -- The Red Cap record id. Null if the request was not for a single record
+    record_id integer,
+    participant_id integer,
+        CONSTRAINT FK_red_cap_sync_events_participant_id
+            FOREIGN KEY (participant_id)
+            REFERENCES participants(id)
+            ON UPDATE CASCADE
+            ON DELETE SET NULL,
+    forms VARCHAR(255)[] NOT NULL DEFAULT '{}',
+    -- Pull or Push
+    direction VARCHAR(32) NOT NULL,
+    initiated_by_user integer,
+        CONSTRAINT FK_red_cap_sync_events_initiated_by_user
+            FOREIGN KEY (initiated_by_user)
+            REFERENCES users(id)
+            ON DELETE SET NULL,
+    initiated_by_job VARCHAR(255),
+    -- SHA-256 of the data sent or received
+    payload_hash VARCHAR(64),
+    -- The response of Red Cap to an import
+    response TEXT,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS red_cap_sync_events_participant_id ON red_cap_sync_events(participant_id);
+CREATE INDEX IF NOT EXISTS red_cap_sync_events_record_id ON red_cap_sync_events(record_id);
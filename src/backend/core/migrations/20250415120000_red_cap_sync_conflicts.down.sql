@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS red_cap_sync_conflicts;
+DROP TABLE IF EXISTS red_cap_sync_snapshots;
+DROP TABLE IF EXISTS participant_sync_entities;
@@ -0,0 +1,63 @@
+-- When each part of a participant was last changed locally and last synced with Red Cap.
+-- Case notes track this on the case_notes table.
+CREATE TABLE IF NOT EXISTS participant_sync_entities(
+    participant_id integer NOT NULL,
+        CONSTRAINT FK_participant_sync_entities_participant_id
+            FOREIGN KEY (participant_id)
+            REFERENCES participants(id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE,
+    -- Participant, Medications or Goals
+    entity VARCHAR(32) NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_synced_with_red_cap TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (participant_id, entity)
+);
+-- Existing changes could be in any part of the participant
+INSERT INTO participant_sync_entities (participant_id, entity, updated_at, last_synced_with_red_cap)
+    SELECT participants.id, entities.entity, participants.updated_at, participants.last_synced_with_red_cap
+    FROM participants
+    CROSS JOIN (VALUES ('Participant'), ('Medications'), ('Goals')) AS entities(entity)
+ON CONFLICT DO NOTHING;
+
+-- The rows of each Red Cap record as of the last sync. Used as the base when looking for conflicts
+CREATE TABLE IF NOT EXISTS red_cap_sync_snapshots(
+    participant_id integer PRIMARY KEY,
+        CONSTRAINT FK_red_cap_sync_snapshots_participant_id
+            FOREIGN KEY (participant_id)
+            REFERENCES participants(id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE,
+    rows JSONB NOT NULL,
+    synced_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Fields changed both locally and in Red Cap since the last sync
+CREATE TABLE IF NOT EXISTS red_cap_sync_conflicts(
+    id serial PRIMARY KEY,
+    participant_id integer NOT NULL,
+        CONSTRAINT FK_red_cap_sync_conflicts_participant_id
+            FOREIGN KEY (participant_id)
+            REFERENCES participants(id)
+            ON UPDATE CASCADE
+            ON DELETE CASCADE,
+    repeat_instrument VARCHAR(255),
+    repeat_instance VARCHAR(32),
+    field VARCHAR(255) NOT NULL,
+    base_value TEXT,
+    local_value TEXT,
+    remote_value TEXT,
+    -- Local, Remote or Manual. Null until an admin resolves the conflict
+    resolution VARCHAR(32),
+    resolved_value TEXT,
+    resolved_by integer,
+        CONSTRAINT FK_red_cap_sync_conflicts_resolved_by
+            FOREIGN KEY (resolved_by)
+            REFERENCES users(id)
+            ON DELETE SET NULL,
+    resolved_at TIMESTAMP WITH TIME ZONE,
+    -- When the resolution was sent to Red Cap by the sync
+    applied_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS red_cap_sync_conflicts_participant_id ON red_cap_sync_conflicts(participant_id);
@@ -0,0 +1,18 @@
+-- Long lived tokens for scripts. Only the SHA-256 of the token is stored
+CREATE TABLE IF NOT EXISTS user_api_tokens(
+    id serial PRIMARY KEY,
+    user_id integer NOT NULL,
+        CONSTRAINT FK_user_api_tokens_user_id
+            FOREIGN KEY (user_id)
+            REFERENCES users(id)
+            ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    -- A subset of the permissions of the user
+    scopes VARCHAR(255)[] NOT NULL DEFAULT '{}',
+    -- Null if the token never expires
+    expires_at TIMESTAMP WITH TIME ZONE,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS user_api_tokens_user_id ON user_api_tokens(user_id);
@@ -0,0 +1,18 @@
+-- Links users to their account at the OpenID Connect provider
+CREATE TABLE IF NOT EXISTS user_authentication_oidc(
+    id serial PRIMARY KEY,
+    user_id integer NOT NULL,
+        CONSTRAINT FK_user_authentication_oidc_user_id
+            FOREIGN KEY (user_id)
+            REFERENCES users(id)
+            ON DELETE CASCADE,
+    issuer TEXT NOT NULL,
+    -- The `sub` claim. Unique per issuer
+    subject TEXT NOT NULL,
+        CONSTRAINT unique_oidc_issuer_subject UNIQUE (issuer, subject),
+    -- The email of the last login
+    email VARCHAR(320),
+    last_login_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS user_authentication_oidc_user_id ON user_authentication_oidc(user_id);
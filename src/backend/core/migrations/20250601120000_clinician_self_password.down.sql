@@ -0,0 +1,3 @@
+DELETE FROM role_permissions
+    WHERE role_id = (SELECT id FROM roles WHERE name = 'Clinician')
+    AND permission = 'self:password';
@@ -0,0 +1,5 @@
+-- Clinicians can change their own password
+INSERT INTO role_permissions(role_id, permission)
+    VALUES
+    ((SELECT id FROM roles WHERE name = 'Clinician'), 'self:password')
+    ON CONFLICT ON CONSTRAINT unique_role_id_permission DO NOTHING;
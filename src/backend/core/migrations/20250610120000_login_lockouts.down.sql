@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS user_login_attempts_ip_address_created_at;
+DROP INDEX IF EXISTS user_login_attempts_user_id_created_at;
+DROP TABLE IF EXISTS login_lockouts;
@@ -0,0 +1,21 @@
+-- Temporary locks of users or IP addresses after too many failed logins
+CREATE TABLE IF NOT EXISTS login_lockouts(
+    id serial PRIMARY KEY,
+    user_id integer,
+        CONSTRAINT FK_login_lockouts_user_id
+            FOREIGN KEY (user_id)
+            REFERENCES users(id)
+            ON DELETE CASCADE,
+    ip_address VARCHAR(255),
+    -- How many locks in a row. The length of the lock doubles with each one
+    lock_count integer NOT NULL DEFAULT 1,
+    -- Failed logins before this are not counted again
+    locked_until TIMESTAMP WITH TIME ZONE NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT unique_login_lockouts_user_id UNIQUE (user_id),
+    CONSTRAINT unique_login_lockouts_ip_address UNIQUE (ip_address),
+    CONSTRAINT login_lockouts_one_target CHECK ((user_id IS NULL) <> (ip_address IS NULL))
+);
+CREATE INDEX IF NOT EXISTS user_login_attempts_user_id_created_at ON user_login_attempts(user_id, created_at);
+CREATE INDEX IF NOT EXISTS user_login_attempts_ip_address_created_at ON user_login_attempts(ip_address, created_at);
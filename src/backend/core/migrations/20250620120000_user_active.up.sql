@@ -0,0 +1,2 @@
+-- Deactivated users can not log in
+ALTER TABLE users ADD COLUMN IF NOT EXISTS active BOOLEAN NOT NULL DEFAULT TRUE;
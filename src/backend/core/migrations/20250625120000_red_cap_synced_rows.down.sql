@@ -0,0 +1,3 @@
+ALTER TABLE participant_goal_steps DROP COLUMN IF EXISTS synced_with_red_cap;
+ALTER TABLE participant_goals DROP COLUMN IF EXISTS synced_with_red_cap;
+ALTER TABLE participant_medications DROP COLUMN IF EXISTS synced_with_red_cap;
@@ -0,0 +1,6 @@
+-- Set once a row has been pushed to or pulled from Red Cap.
+-- Only synced rows are hidden when they are missing from Red Cap. Rows that only exist locally are kept
+-- Existing rows are marked by the next pull if they exist in Red Cap
+ALTER TABLE participant_medications ADD COLUMN IF NOT EXISTS synced_with_red_cap BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE participant_goals ADD COLUMN IF NOT EXISTS synced_with_red_cap BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE participant_goal_steps ADD COLUMN IF NOT EXISTS synced_with_red_cap BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,32 @@
+UPDATE questions SET requirements = 'questions.get(''falls_screen'').equals(false)'
+    WHERE string_id = 'falls_status' AND requirements = 'questions.get("falls_screen").equals(false)';
+UPDATE questions SET requirements = 'questions.get(''falls1'').equals(true) || questions.get(''falls2'').equals(true)'
+    WHERE string_id = 'screen_falls1a' AND requirements = 'questions.get("falls1").equals(true) || questions.get("falls2").equals(true)';
+UPDATE questions SET requirements = 'questions.get(''falls1'').equals(true) || questions.get(''falls2'').equals(true)'
+    WHERE string_id = 'injury_fall' AND requirements = 'questions.get("falls1").equals(true) || questions.get("falls2").equals(true)';
+UPDATE questions SET requirements = 'questions.get("med_rec") == true || questions.get("med_review") == true'
+    WHERE string_id = 'opioid1' AND requirements = 'questions.get("med_rec") == true';
+UPDATE questions SET requirements = 'questions.get("opioid") == true || questions.get("med_review") == true'
+    WHERE string_id = 'opioid2' AND requirements = 'questions.get("opiod") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("med_review") == true'
+    WHERE string_id = 'opioid3' AND requirements = 'questions.get("opioid1") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("med_review") == true'
+    WHERE string_id = 'opioid4' AND requirements = 'questions.get("opioid1") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opioid") == true'
+    WHERE string_id = 'opioid5' AND requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opiod") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opioid") == true'
+    WHERE string_id = 'opioid6' AND requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opiod") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opioid") == true'
+    WHERE string_id = 'opioid7' AND requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opiod") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opioid") == true'
+    WHERE string_id = 'opioid8' AND requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opiod") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opioid") == true'
+    WHERE string_id = 'opioid9' AND requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opiod") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opioid") == true'
+    WHERE string_id = 'opioid10' AND requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opiod") == true';
+UPDATE questions SET requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opioid") == true'
+    WHERE string_id = 'opioid_other' AND requirements = 'questions.get("opioid1") == true || questions.get("opioid4") == "yes" || questions.get("opiod") == true';
+UPDATE questions SET requirements = 'questions.get(''transition_hospital'') == true'
+    WHERE string_id = 'hospital_transition' AND requirements = 'questions.get("transition_hospital") == true';
+UPDATE questions SET requirements = 'questions.get(''transition_ed'') == true'
+    WHERE string_id = 'ed_transition' AND requirements = 'questions.get("transition_ed") == true';
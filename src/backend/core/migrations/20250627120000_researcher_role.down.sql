@@ -0,0 +1,5 @@
+DELETE FROM role_permissions
+    WHERE role_id = (SELECT id FROM roles WHERE name = 'Researcher');
+DELETE FROM user_roles
+    WHERE role_id = (SELECT id FROM roles WHERE name = 'Researcher');
+DELETE FROM roles WHERE name = 'Researcher';
@@ -0,0 +1,8 @@
+-- Research queries search across all participants. So they are not part of the Clinician role
+INSERT INTO roles(name, description)
+    VALUES ('Researcher', 'Researcher role')
+    ON CONFLICT (name) DO NOTHING;
+INSERT INTO role_permissions(role_id, permission)
+    VALUES
+    ((SELECT id FROM roles WHERE name = 'Researcher'), 'research:query')
+    ON CONFLICT ON CONSTRAINT unique_role_id_permission DO NOTHING;
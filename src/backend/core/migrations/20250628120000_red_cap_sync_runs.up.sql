@@ -0,0 +1,13 @@
+-- One row per sync run that finished pulling from Red Cap.
+-- The start of the latest run is where the next pull continues from
+CREATE TABLE IF NOT EXISTS red_cap_sync_runs(
+    id serial PRIMARY KEY,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    -- None if every record was pulled
+    pulled_since TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+-- Continue from the latest pull before this table existed
+INSERT INTO red_cap_sync_runs(started_at)
+    SELECT MAX(last_pulled_at) FROM red_cap_sync_status
+    HAVING MAX(last_pulled_at) IS NOT NULL;
@@ -2,13 +2,14 @@ pub mod new;
 pub mod queries;
 use std::fmt::Debug;
 
-use crate::database::{prelude::*, CSPageParams, PaginatedResponse};
+use crate::database::red_cap::changes::{RecordChanges, update_changed_fields};
+use crate::database::{CSPageParams, PaginatedResponse, prelude::*};
 use crate::red_cap::VisitType;
 use crate::red_cap::converter::case_notes::{
-    OtherCaseNoteData, RedCapCaseNoteBase, RedCapHealthMeasures,
+    RedCapBloodPressureReadings, RedCapCaseNoteBase, RedCapHealthMeasures,
 };
 use chrono::{DateTime, FixedOffset, NaiveDate};
-use new::NewBloodPressure;
+use new::{NewBloodPressure, NewCaseNoteHealthMeasures};
 use pg_extended_sqlx_queries::pagination::PaginationSupportingTool;
 use pg_extended_sqlx_queries::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -110,9 +111,65 @@ pub struct CaseNote {
     pub last_synced_with_red_cap: Option<DateTime<FixedOffset>>,
     /// DATABASE ONLY
     pub created_at: DateTime<FixedOffset>,
+    /// When the case note or any of its parts were last changed locally
+    pub updated_at: DateTime<FixedOffset>,
 }
 
 impl CaseNote {
+    /// Rather or not local changes have been made since the last Red Cap sync
+    ///
+    /// Only completed case notes are pushed to Red Cap
+    pub fn has_unsynced_changes(&self) -> bool {
+        if !self.completed {
+            return false;
+        }
+        match self.last_synced_with_red_cap {
+            Some(last_synced) => self.updated_at > last_synced,
+            None => true,
+        }
+    }
+    /// Bumps the `updated_at` timestamp so the Red Cap sync knows the case note has changed
+    #[tracing::instrument(skip(database))]
+    pub async fn mark_updated(
+        case_note_id: i32,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(CaseNoteColumn::UpdatedAt, SqlFunctionBuilder::now())
+            .filter(CaseNoteColumn::Id.equals(case_note_id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        Ok(())
+    }
+    /// Sets `last_synced_with_red_cap` for all the case notes of a participant
+    ///
+    /// Changes made after `synced_at` are pushed on the next sync
+    #[tracing::instrument(skip(database))]
+    pub async fn mark_synced_for_participant(
+        participant_id: i32,
+        synced_at: DateTime<FixedOffset>,
+        database: &sqlx::PgPool,
+    ) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(CaseNoteColumn::LastSyncedWithRedCap, synced_at.value())
+            .filter(CaseNoteColumn::ParticipantId.equals(participant_id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        Ok(())
+    }
+    /// Ids of the participants that have completed case notes with unsynced changes
+    pub async fn participants_with_unsynced_changes(database: &sqlx::PgPool) -> DBResult<Vec<i32>> {
+        let result = sqlx::query_scalar(
+            "SELECT DISTINCT participant_id FROM case_notes
+            WHERE completed = TRUE
+            AND (last_synced_with_red_cap IS NULL OR updated_at > last_synced_with_red_cap)",
+        )
+        .fetch_all(database)
+        .await?;
+        Ok(result)
+    }
     pub async fn find_by_participant_id_and_redcap_instance(
         participant_id: i32,
         redcap_instance: i32,
@@ -174,21 +231,101 @@ impl CaseNote {
         }
         Ok(())
     }
-    #[tracing::instrument()]
+    /// Marks the case note as completed. Completed case notes can no longer be edited
+    pub async fn mark_completed(&mut self, database: &sqlx::PgPool) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(CaseNoteColumn::Completed, true.value())
+            .set(CaseNoteColumn::UpdatedAt, SqlFunctionBuilder::now())
+            .filter(CaseNoteColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.completed = true;
+        Ok(())
+    }
+    /// Updates the case note, health measures and blood pressure readings with the values pulled from Red Cap.
+    ///
+    /// Only fields that are different are written. Returns the fields that changed.
+    ///
+    /// Question answers are updated separately by [crate::red_cap::tasks::import_case_notes]
+    #[tracing::instrument(skip(database))]
     pub async fn update_from_red_cap(
-        &self,
+        &mut self,
         case_note: RedCapCaseNoteBase,
         health_measures: RedCapHealthMeasures,
-        other: OtherCaseNoteData,
+        bp_readings: RedCapBloodPressureReadings,
         database: &sqlx::PgPool,
-    ) -> DBResult<()> {
-        error!("Not Implemented");
-        //TODO: Implement
-        Ok(())
+    ) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let RedCapCaseNoteBase {
+            location,
+            visit_type,
+            age,
+            reason_for_visit,
+            info_provided_by_caregiver,
+            date_of_visit,
+            ..
+        } = case_note;
+        let mut update = UpdateQueryBuilder::new(Self::table_name());
+        update_changed_fields!(changes, update, "case_note", self, {
+            location => CaseNoteColumn::Location,
+            visit_type => CaseNoteColumn::VisitType,
+            age => CaseNoteColumn::Age,
+            reason_for_visit => CaseNoteColumn::ReasonForVisit,
+            info_provided_by_caregiver => CaseNoteColumn::InfoProvidedByCaregiver,
+            date_of_visit => CaseNoteColumn::DateOfVisit,
+        });
+        if !changes.is_empty() {
+            update
+                .filter(CaseNoteColumn::Id.equals(self.id.value()))
+                .query()
+                .execute(database)
+                .await?;
+        }
+
+        let measures = match CaseNoteHealthMeasures::find_by_case_note_id(self.id, database).await?
+        {
+            Some(mut current) => {
+                changes.extend(current.update_changed(health_measures, database).await?);
+                current
+            }
+            None => {
+                changes.push("health_measures", "created", &(), &health_measures);
+                NewCaseNoteHealthMeasures::from(health_measures)
+                    .insert_return_measure(self.id, database)
+                    .await?
+            }
+        };
+
+        let current_readings: Vec<NewBloodPressure> = measures
+            .get_bp_readings(database)
+            .await?
+            .into_iter()
+            .map(NewBloodPressure::from)
+            .collect();
+        let readings_changed = current_readings.len() != bp_readings.readings.len()
+            || bp_readings
+                .readings
+                .iter()
+                .any(|reading| !current_readings.contains(reading));
+        if readings_changed {
+            changes.push(
+                "health_measures",
+                "blood_pressure",
+                &current_readings,
+                &bp_readings.readings,
+            );
+            let mut transaction = database.begin().await?;
+            measures
+                .set_bp(bp_readings.readings, &mut transaction)
+                .await?;
+            transaction.commit().await?;
+        }
+        Ok(changes)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow, TableType)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow, TableType, ToSchema)]
 #[table(name = "case_note_health_measures")]
 pub struct CaseNoteHealthMeasures {
     pub id: i32,
@@ -212,7 +349,11 @@ pub struct CaseNoteHealthMeasures {
     pub other: Option<String>,
 }
 impl CaseNoteHealthMeasures {
-    pub async fn add_bp(&self, bp: NewBloodPressure, db: &PgPool) -> DBResult<()> {
+    pub async fn add_bp(
+        &self,
+        bp: NewBloodPressure,
+        db: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<()> {
         InsertQueryBuilder::new(HealthMeasureBloodPressure::table_name())
             .insert(HealthMeasureBloodPressureColumn::HealthMeasureId, self.id)
             .insert(
@@ -226,7 +367,11 @@ impl CaseNoteHealthMeasures {
             .await?;
         Ok(())
     }
-    pub async fn add_many_bp(&self, bp: Vec<NewBloodPressure>, db: &PgPool) -> DBResult<()> {
+    pub async fn add_many_bp(
+        &self,
+        bp: Vec<NewBloodPressure>,
+        db: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<()> {
         let mut query = InsertManyBuilder::new(
             HealthMeasureBloodPressure::table_name(),
             vec![
@@ -268,7 +413,71 @@ impl CaseNoteHealthMeasures {
 }
 
 impl CaseNoteHealthMeasures {
-    pub async fn find_by_id(id: i32, database: &sqlx::PgPool) -> DBResult<Option<Self>> {
+    /// Updates only the fields that are different
+    pub async fn update_changed(
+        &mut self,
+        health_measures: RedCapHealthMeasures,
+        database: &sqlx::PgPool,
+    ) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let RedCapHealthMeasures {
+            weight,
+            glucose_tested,
+            glucose_result,
+            fasted_atleast_2_hours,
+            other,
+        } = health_measures;
+        let mut update = UpdateQueryBuilder::new(Self::table_name());
+        update_changed_fields!(changes, update, "health_measures", self, {
+            weight => CaseNoteHealthMeasuresColumn::Weight,
+            glucose_tested => CaseNoteHealthMeasuresColumn::GlucoseTested,
+            glucose_result => CaseNoteHealthMeasuresColumn::GlucoseResult,
+            fasted_atleast_2_hours => CaseNoteHealthMeasuresColumn::FastedAtleast2Hours,
+            other => CaseNoteHealthMeasuresColumn::Other,
+        });
+        if !changes.is_empty() {
+            update
+                .filter(CaseNoteHealthMeasuresColumn::Id.equals(self.id.value()))
+                .query()
+                .execute(database)
+                .await?;
+        }
+        Ok(changes)
+    }
+
+    /// Replaces all blood pressure readings with the ones provided
+    ///
+    /// The case note is marked as updated so the change is pushed to Red Cap
+    pub async fn set_bp(
+        &self,
+        bp: Vec<NewBloodPressure>,
+        db: &mut sqlx::PgConnection,
+    ) -> DBResult<()> {
+        CaseNote::mark_updated(self.case_note_id, &mut *db).await?;
+        sqlx::query("DELETE FROM health_measure_blood_pressure WHERE health_measure_id = $1")
+            .bind(self.id as i64)
+            .execute(&mut *db)
+            .await?;
+        if bp.is_empty() {
+            return Ok(());
+        }
+        self.add_many_bp(bp, db).await
+    }
+    pub async fn get_bp_readings(&self, db: &PgPool) -> DBResult<Vec<HealthMeasureBloodPressure>> {
+        let readings = SelectQueryBuilder::with_columns(
+            HealthMeasureBloodPressure::table_name(),
+            HealthMeasureBloodPressureColumn::all(),
+        )
+        .filter(HealthMeasureBloodPressureColumn::HealthMeasureId.equals(self.id.value()))
+        .query_as()
+        .fetch_all(db)
+        .await?;
+        Ok(readings)
+    }
+    pub async fn find_by_id(
+        id: i32,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<Option<Self>> {
         let result = sqlx::query_as(
             "
             SELECT * FROM case_note_health_measures
@@ -282,7 +491,7 @@ impl CaseNoteHealthMeasures {
     }
     pub async fn find_by_case_note_id(
         case_note_id: i32,
-        database: &sqlx::PgPool,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
     ) -> DBResult<Option<Self>> {
         SelectQueryBuilder::with_columns(Self::table_name(), CaseNoteHealthMeasuresColumn::all())
             .filter(CaseNoteHealthMeasuresColumn::CaseNoteId.equals(case_note_id.value()))
@@ -363,12 +572,12 @@ impl BloodPressureType {
     }
 }
 /// Blood Pressure gets its own table because it happens between 0-3 different ways
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow, TableType)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow, TableType, ToSchema)]
 #[table(name = "health_measure_blood_pressure")]
 pub struct HealthMeasureBloodPressure {
-    pub id: i32,
+    pub id: i64,
     /// Each [CaseNote] can have at most 3 blood pressures
-    pub health_measure_id: i32,
+    pub health_measure_id: i64,
     /// The Type of Blood Pressure
     pub blood_pressure_type: BloodPressureType,
     /// Possible Red CAP IDs: bp_sit_syst, bp_stand_syst
@@ -376,3 +585,145 @@ pub struct HealthMeasureBloodPressure {
     /// Possible Red CAP IDs: bp_sit_dia, bp_stand_dia
     pub diastolic: i16,
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::red_cap::participants::NewParticipant,
+        utils::testing::config::testing::{get_testing_db, no_db_connection},
+    };
+    /// Health measure and blood pressure changes made in a transaction that is not committed are not kept
+    #[tokio::test]
+    #[ignore]
+    pub async fn health_measures_roll_back_with_transaction() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let participant = NewParticipant {
+            first_name: "Test".to_string(),
+            last_name: "User".to_string(),
+            other_contact: Some("CS25-303 health_measures_roll_back".to_string()),
+            ..NewParticipant::default()
+        }
+        .insert_returning(&database)
+        .await?;
+        let case_note = new::NewCaseNote::default()
+            .insert_return_case_note(participant.id, &database)
+            .await?;
+        let measures = NewCaseNoteHealthMeasures {
+            weight: Some(180f32),
+            ..Default::default()
+        }
+        .insert_return_measure(case_note.id, &database)
+        .await?;
+        let reading = NewBloodPressure {
+            blood_pressure_type: BloodPressureType::Sit,
+            systolic: 120,
+            diastolic: 80,
+        };
+        measures
+            .add_many_bp(vec![reading.clone()], &database)
+            .await?;
+
+        let mut transaction = database.begin().await?;
+        let updated = NewCaseNoteHealthMeasures {
+            weight: Some(200f32),
+            ..Default::default()
+        }
+        .insert_or_update_return_measure(case_note.id, &mut transaction)
+        .await?;
+        assert_eq!(updated.weight, Some(200f32));
+        updated.set_bp(vec![], &mut transaction).await?;
+        transaction.rollback().await?;
+
+        let measures = CaseNoteHealthMeasures::find_by_case_note_id(case_note.id, &database)
+            .await?
+            .expect("Health measures should still exist");
+        assert_eq!(measures.weight, Some(180f32));
+        let readings: Vec<NewBloodPressure> = measures
+            .get_bp_readings(&database)
+            .await?
+            .into_iter()
+            .map(NewBloodPressure::from)
+            .collect();
+        assert_eq!(readings, vec![reading]);
+        Ok(())
+    }
+    /// A completed case note that has just been synced with Red Cap
+    async fn synced_case_note(name: &str, database: &PgPool) -> anyhow::Result<CaseNote> {
+        let participant = NewParticipant {
+            first_name: "Test".to_string(),
+            last_name: "User".to_string(),
+            other_contact: Some(format!("CS25-303 {name}")),
+            ..NewParticipant::default()
+        }
+        .insert_returning(database)
+        .await?;
+        let mut case_note = new::NewCaseNote::default()
+            .insert_return_case_note(participant.id, database)
+            .await?;
+        case_note.mark_completed(database).await?;
+        let case_note = CaseNote::find_by_id(case_note.id, database)
+            .await?
+            .expect("Case note should exist");
+        CaseNote::mark_synced_for_participant(participant.id, case_note.updated_at, database)
+            .await?;
+        let case_note = CaseNote::find_by_id(case_note.id, database)
+            .await?
+            .expect("Case note should exist");
+        assert!(!case_note.has_unsynced_changes());
+        Ok(case_note)
+    }
+    async fn has_unsynced_changes(case_note: &CaseNote, database: &PgPool) -> anyhow::Result<bool> {
+        let case_note = CaseNote::find_by_id(case_note.id, database)
+            .await?
+            .expect("Case note should exist");
+        Ok(case_note.has_unsynced_changes())
+    }
+    /// Changing the health measures, blood pressure or answers of a case note must push it to Red Cap again
+    #[tokio::test]
+    #[ignore]
+    pub async fn case_note_parts_mark_case_note_updated() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let case_note = synced_case_note("measures_mark_updated", &database).await?;
+        let mut transaction = database.begin().await?;
+        NewCaseNoteHealthMeasures {
+            weight: Some(150f32),
+            ..Default::default()
+        }
+        .insert_or_update_return_measure(case_note.id, &mut transaction)
+        .await?;
+        transaction.commit().await?;
+        assert!(has_unsynced_changes(&case_note, &database).await?);
+
+        let case_note = synced_case_note("bp_mark_updated", &database).await?;
+        let measures = NewCaseNoteHealthMeasures::default()
+            .insert_return_measure(case_note.id, &database)
+            .await?;
+        assert!(!has_unsynced_changes(&case_note, &database).await?);
+        let mut transaction = database.begin().await?;
+        measures
+            .set_bp(
+                vec![NewBloodPressure {
+                    blood_pressure_type: BloodPressureType::Sit,
+                    systolic: 120,
+                    diastolic: 80,
+                }],
+                &mut transaction,
+            )
+            .await?;
+        transaction.commit().await?;
+        assert!(has_unsynced_changes(&case_note, &database).await?);
+
+        let case_note = synced_case_note("answers_mark_updated", &database).await?;
+        let mut transaction = database.begin().await?;
+        questions::delete_question_answer(1, case_note.id, &mut transaction).await?;
+        transaction.commit().await?;
+        assert!(has_unsynced_changes(&case_note, &database).await?);
+        Ok(())
+    }
+}
@@ -65,6 +65,70 @@ impl NewCaseNote {
         Ok(case_note)
     }
 }
+/// The fields of a case note that a clinician can set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CaseNoteBaseValues {
+    pub location: Option<i32>,
+    pub visit_type: Option<VisitType>,
+    pub age: Option<i16>,
+    pub reason_for_visit: Option<String>,
+    pub info_provided_by_caregiver: Option<String>,
+    pub date_of_visit: NaiveDate,
+}
+impl From<CaseNoteBaseValues> for NewCaseNote {
+    fn from(value: CaseNoteBaseValues) -> Self {
+        let CaseNoteBaseValues {
+            location,
+            visit_type,
+            age,
+            reason_for_visit,
+            info_provided_by_caregiver,
+            date_of_visit,
+        } = value;
+        Self {
+            location,
+            visit_type,
+            age,
+            reason_for_visit,
+            info_provided_by_caregiver,
+            date_of_visit,
+            ..Default::default()
+        }
+    }
+}
+impl CaseNoteBaseValues {
+    /// Replaces the base values of the case note
+    pub async fn update(
+        self,
+        case_note: i32,
+        database: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<()> {
+        let Self {
+            location,
+            visit_type,
+            age,
+            reason_for_visit,
+            info_provided_by_caregiver,
+            date_of_visit,
+        } = self;
+        UpdateQueryBuilder::new(CaseNote::table_name())
+            .set(CaseNoteColumn::Location, location.value())
+            .set(CaseNoteColumn::VisitType, visit_type.value())
+            .set(CaseNoteColumn::Age, age.value())
+            .set(CaseNoteColumn::ReasonForVisit, reason_for_visit.value())
+            .set(
+                CaseNoteColumn::InfoProvidedByCaregiver,
+                info_provided_by_caregiver.value(),
+            )
+            .set(CaseNoteColumn::DateOfVisit, date_of_visit.value())
+            .set(CaseNoteColumn::UpdatedAt, SqlFunctionBuilder::now())
+            .filter(CaseNoteColumn::Id.equals(case_note.value()))
+            .query()
+            .execute(database)
+            .await?;
+        Ok(())
+    }
+}
 impl Default for NewCaseNote {
     fn default() -> Self {
         Self {
@@ -113,6 +177,51 @@ pub struct NewCaseNoteHealthMeasures {
 }
 
 impl NewCaseNoteHealthMeasures {
+    /// Updates the health measures of the case note. Creating them if they do not exist
+    ///
+    /// The case note is marked as updated so the change is pushed to Red Cap
+    pub async fn insert_or_update_return_measure(
+        self,
+        case_note: i32,
+        database: &mut sqlx::PgConnection,
+    ) -> DBResult<CaseNoteHealthMeasures> {
+        CaseNote::mark_updated(case_note, &mut *database).await?;
+        let Some(existing) =
+            CaseNoteHealthMeasures::find_by_case_note_id(case_note, &mut *database).await?
+        else {
+            return self.insert_return_measure(case_note, database).await;
+        };
+        let Self {
+            weight,
+            glucose_tested,
+            glucose_result,
+            fasted_atleast_2_hours,
+            other,
+        } = self;
+        UpdateQueryBuilder::new(CaseNoteHealthMeasures::table_name())
+            .set(CaseNoteHealthMeasuresColumn::Weight, weight.value())
+            .set(
+                CaseNoteHealthMeasuresColumn::GlucoseTested,
+                glucose_tested.value(),
+            )
+            .set(
+                CaseNoteHealthMeasuresColumn::GlucoseResult,
+                glucose_result.value(),
+            )
+            .set(
+                CaseNoteHealthMeasuresColumn::FastedAtleast2Hours,
+                fasted_atleast_2_hours.value(),
+            )
+            .set(CaseNoteHealthMeasuresColumn::Other, other.value())
+            .filter(CaseNoteHealthMeasuresColumn::Id.equals(existing.id.value()))
+            .query()
+            .execute(&mut *database)
+            .await?;
+        let measure = CaseNoteHealthMeasures::find_by_id(existing.id, database)
+            .await?
+            .ok_or(DBError::Other("Health measures removed during update"))?;
+        Ok(measure)
+    }
     pub async fn insert_return_measure(
         self,
         case_note: i32,
@@ -1,8 +1,17 @@
-use crate::{database::prelude::*, red_cap::VisitType};
+use crate::{
+    database::{
+        prelude::*,
+        red_cap::questions::{CleanQuestionResponse, DBQuestionResponse},
+    },
+    red_cap::VisitType,
+};
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 use utoipa::ToSchema;
 
-use super::{CaseNote, CaseNoteColumn, CaseNoteType};
+use super::{
+    CaseNote, CaseNoteColumn, CaseNoteHealthMeasures, CaseNoteType, HealthMeasureBloodPressure,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema, FromRow)]
 pub struct CaseNoteIDAndDate {
@@ -59,3 +68,137 @@ impl CaseNoteType for CaseNoteListItem {
         self.id
     }
 }
+/// A case note with everything attached to it.
+///
+/// Used to render a single visit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CaseNoteDetails {
+    pub case_note: CaseNote,
+    pub health_measures: Option<CaseNoteHealthMeasures>,
+    pub blood_pressure: Vec<HealthMeasureBloodPressure>,
+    /// Question answers grouped by their category
+    pub categories: Vec<CaseNoteCategoryAnswers>,
+}
+impl CaseNoteDetails {
+    #[instrument]
+    pub async fn find_by_id(id: i32, database: &sqlx::PgPool) -> DBResult<Option<Self>> {
+        let Some(case_note) = CaseNote::find_by_id(id, database).await? else {
+            return Ok(None);
+        };
+        let health_measures = CaseNoteHealthMeasures::find_by_case_note_id(id, database).await?;
+        let blood_pressure = match &health_measures {
+            Some(measures) => measures.get_bp_readings(database).await?,
+            None => Vec::new(),
+        };
+        let answers = CaseNoteAnswerRow::find_by_case_note_id(id, database).await?;
+        Ok(Some(Self {
+            case_note,
+            health_measures,
+            blood_pressure,
+            categories: CaseNoteCategoryAnswers::group(answers),
+        }))
+    }
+}
+/// All answered questions within a [crate::database::red_cap::questions::QuestionCategory]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CaseNoteCategoryAnswers {
+    pub category_id: i32,
+    pub category_string_id: String,
+    pub category_name: String,
+    pub answers: Vec<CaseNoteAnswerDetails>,
+}
+impl CaseNoteCategoryAnswers {
+    /// Groups the rows by category. Rows must be sorted by category
+    fn group(rows: Vec<CaseNoteAnswerRow>) -> Vec<Self> {
+        let mut categories: Vec<Self> = Vec::new();
+        for row in rows {
+            let CaseNoteAnswerRow {
+                category_id,
+                category_string_id,
+                category_name,
+                question,
+                description,
+                response,
+            } = row;
+            let answer = CaseNoteAnswerDetails {
+                question,
+                description,
+                response: response.into(),
+            };
+            match categories.last_mut() {
+                Some(category) if category.category_id == category_id => {
+                    category.answers.push(answer);
+                }
+                _ => categories.push(Self {
+                    category_id,
+                    category_string_id,
+                    category_name,
+                    answers: vec![answer],
+                }),
+            }
+        }
+        categories
+    }
+}
+/// An answered question with the question text and resolved options
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CaseNoteAnswerDetails {
+    /// The question text
+    pub question: String,
+    pub description: Option<String>,
+    #[serde(flatten)]
+    #[schema(value_type = Object)]
+    pub response: CleanQuestionResponse,
+}
+#[derive(Debug, Clone, PartialEq, FromRow)]
+struct CaseNoteAnswerRow {
+    category_id: i32,
+    category_string_id: String,
+    category_name: String,
+    question: String,
+    description: Option<String>,
+    #[sqlx(flatten)]
+    response: DBQuestionResponse,
+}
+impl CaseNoteAnswerRow {
+    async fn find_by_case_note_id(
+        case_note_id: i32,
+        database: &sqlx::PgPool,
+    ) -> DBResult<Vec<Self>> {
+        let query = r#"
+        SELECT
+            question_categories.id as category_id,
+            question_categories.string_id as category_string_id,
+            question_categories.name as category_name,
+            questions.question as question,
+            questions.description as description,
+            case_note_question_answers.id as answer_id,
+            questions.id as question_id,
+            questions.string_id as question_string_id,
+            questions.string_id_other as question_string_id_other,
+            case_note_question_answers.response_type as response_type,
+            case_note_question_answers.value_text as value_text,
+            case_note_question_answers.value_number as value_number,
+            case_note_question_answers.value_float as value_float,
+            case_note_question_answers.value_boolean as value_boolean,
+            (question_options.id, question_options.name, question_options.string_id) as value_radio,
+            case
+                when questions.question_type = 'MultiCheckBox' then array(
+                    SELECT (mcb.option_id, qo.name, qo.string_id) FROM case_note_question_answer_mcb as mcb
+                                JOIN public.question_options qo on qo.id = mcb.option_id
+                                WHERE question_answers_id = case_note_question_answers.id)
+                end as options
+        FROM case_note_question_answers
+            JOIN questions on case_note_question_answers.question_id = questions.id
+            JOIN question_categories on questions.category_id = question_categories.id
+            LEFT JOIN question_options on case_note_question_answers.value_radio = question_options.id
+        WHERE case_note_question_answers.case_note_id = $1
+        ORDER BY question_categories.id, questions.id
+        "#;
+        let result = sqlx::query_as(query)
+            .bind(case_note_id)
+            .fetch_all(database)
+            .await?;
+        Ok(result)
+    }
+}
@@ -1,12 +1,21 @@
 //! Due to the amount of data that can be put into red cap. Questions that do not need to be answers at all times or have conditional requirements are stored using a question system.
 use crate::database::{
     prelude::*,
-    red_cap::questions::{DBQuestionResponse, QuestionDataValue, QuestionType},
+    red_cap::questions::{
+        CleanQuestionResponse, DBQuestionResponse, Question, QuestionDataValue,
+        QuestionDataValueByIds, QuestionError, QuestionForm, QuestionOptions, QuestionType,
+        requirements::{
+            QuestionScriptData, QuestionsScriptCtx, RequirementsCheck, RequirementsEvaluator,
+        },
+    },
 };
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use tracing::instrument;
 
+use super::CaseNote;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow, TableType)]
 #[table(name = "case_note_question_answers")]
 pub struct CaseNoteQuestionAnswers {
@@ -24,7 +33,7 @@ impl CaseNoteQuestionAnswers {
     pub async fn add_multi_check_box(
         &self,
         option_id: i32,
-        database: &sqlx::PgPool,
+        database: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
     ) -> DBResult<()> {
         InsertQueryBuilder::new(QuestionAnswerMultiCheck::table_name())
             .insert(
@@ -49,6 +58,21 @@ impl CaseNoteQuestionAnswers {
             .fetch_all(database)
             .await
     }
+    /// The options selected in a multi check box answer
+    pub async fn selected_options(
+        &self,
+        database: &sqlx::PgPool,
+    ) -> DBResult<Vec<QuestionOptions>> {
+        let options = sqlx::query_as(
+            "SELECT question_options.* FROM case_note_question_answer_mcb
+            JOIN question_options ON question_options.id = case_note_question_answer_mcb.option_id
+            WHERE case_note_question_answer_mcb.question_answers_id = $1",
+        )
+        .bind(self.id)
+        .fetch_all(database)
+        .await?;
+        Ok(options)
+    }
 }
 
 /// Table Name: question_answer_multi_check_box
@@ -60,14 +84,163 @@ pub struct QuestionAnswerMultiCheck {
     pub option_id: i32,
 }
 
-#[instrument]
+/// An answer to a case note question sent by a client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NewCaseNoteQuestionAnswer {
+    pub question_id: i32,
+    /// The value of the answer. Options are referenced by their id
+    #[schema(value_type = Object)]
+    pub value: QuestionDataValueByIds,
+}
+impl NewCaseNoteQuestionAnswer {
+    /// Loads the question and ensures the value matches it
+    ///
+    /// The question must be a case note question
+    #[instrument]
+    pub async fn resolve(self, database: &sqlx::PgPool) -> DBResult<ResolvedCaseNoteAnswer> {
+        let Self { question_id, value } = self;
+        let question = Question::find_by_id(question_id, database)
+            .await?
+            .ok_or(QuestionError::QuestionNotFound(question_id))?;
+        let form = question.form(database).await?;
+        if form != QuestionForm::CaseNotes {
+            return Err(QuestionError::QuestionNotInForm {
+                question: question_id,
+                form: QuestionForm::CaseNotes,
+            }
+            .into());
+        }
+        let value = value.resolve_options(&question, database).await?;
+        Ok(ResolvedCaseNoteAnswer { question, value })
+    }
+    /// Validates the answer against the question and sets it on the case note
+    ///
+    /// Any previous answer to the question is replaced
+    #[instrument]
+    pub async fn set_for_case_note(self, case_note: i32, database: &sqlx::PgPool) -> DBResult<()> {
+        let answer = self.resolve(database).await?;
+        let mut transaction = database.begin().await?;
+        answer
+            .set_for_case_note(case_note, &mut transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+/// An answer that has been validated against its question
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedCaseNoteAnswer {
+    pub question: Question,
+    pub value: QuestionDataValue,
+}
+impl ResolvedCaseNoteAnswer {
+    /// Sets the answer on the case note. Replacing any previous answer
+    pub async fn set_for_case_note(
+        self,
+        case_note: i32,
+        database: &mut sqlx::PgConnection,
+    ) -> DBResult<()> {
+        set_question_answer(self.question.id, case_note, self.value, database).await
+    }
+}
+/// Checks the answers of a case note against the requirements of the case note questions
+///
+/// The existing answers of the case note are combined with `new_answers`.
+/// Existing answers to the questions in `removed_answers` are left out.
+/// Use `None` for a case note that has not been created yet.
+///
+/// [RequirementsCheck::inactive_answered] contains existing answers as well.
+/// So answers to questions that become inactive must be removed in the same save
+#[instrument(skip(evaluator, new_answers))]
+pub async fn check_answer_requirements(
+    evaluator: &RequirementsEvaluator,
+    case_note: Option<i32>,
+    new_answers: &[ResolvedCaseNoteAnswer],
+    removed_answers: &[i32],
+    database: &sqlx::PgPool,
+) -> DBResult<RequirementsCheck> {
+    let questions = Question::get_all_in_form(QuestionForm::CaseNotes, database).await?;
+    let mut ctx = QuestionsScriptCtx::default();
+    if let Some(case_note) = case_note {
+        for response in DBQuestionResponse::get_for_case_note(case_note, database).await? {
+            if removed_answers.contains(&response.question_id) {
+                continue;
+            }
+            let response = CleanQuestionResponse::from(response);
+            ctx.insert(
+                response.question_string_id.clone(),
+                QuestionScriptData::from(&response),
+            );
+        }
+    }
+    for answer in new_answers {
+        ctx.insert(
+            answer.question.string_id.clone(),
+            QuestionScriptData::from(&answer.value),
+        );
+    }
+    Ok(evaluator.check(&questions, &ctx).await)
+}
+/// Removes the answer to a question on a case note. Multi check box selections are removed by the cascade
+///
+/// The case note is marked as updated so the change is pushed to Red Cap
+#[instrument(skip(database))]
+pub async fn delete_question_answer(
+    question_id: i32,
+    case_note: i32,
+    database: &mut sqlx::PgConnection,
+) -> DBResult<()> {
+    delete_question_answer_from_red_cap(question_id, case_note, &mut *database).await?;
+    CaseNote::mark_updated(case_note, database).await
+}
+/// Removes an answer that was removed in Red Cap. The case note is not marked as updated
+#[instrument(skip(database))]
+pub async fn delete_question_answer_from_red_cap(
+    question_id: i32,
+    case_note: i32,
+    database: &mut sqlx::PgConnection,
+) -> DBResult<()> {
+    sqlx::query(
+        "DELETE FROM case_note_question_answers WHERE case_note_id = $1 AND question_id = $2",
+    )
+    .bind(case_note)
+    .bind(question_id)
+    .execute(database)
+    .await?;
+    Ok(())
+}
+/// Replaces the answer to a question on a case note
+///
+/// The case note is marked as updated so the change is pushed to Red Cap
+#[instrument(skip(database))]
+pub async fn set_question_answer(
+    question_id: i32,
+    case_note: i32,
+    value: QuestionDataValue,
+    database: &mut sqlx::PgConnection,
+) -> DBResult<()> {
+    set_question_answer_from_red_cap(question_id, case_note, value, &mut *database).await?;
+    CaseNote::mark_updated(case_note, database).await
+}
+/// Replaces the answer with the answer pulled from Red Cap. The case note is not marked as updated
+#[instrument(skip(database))]
+pub async fn set_question_answer_from_red_cap(
+    question_id: i32,
+    case_note: i32,
+    value: QuestionDataValue,
+    database: &mut sqlx::PgConnection,
+) -> DBResult<()> {
+    delete_question_answer_from_red_cap(question_id, case_note, &mut *database).await?;
+    add_question(question_id, case_note, value, database).await
+}
+#[instrument(skip(database))]
 pub async fn add_question(
     question_id: i32,
     case_note: i32,
     value: QuestionDataValue,
-    database: &sqlx::PgPool,
+    database: &mut sqlx::PgConnection,
 ) -> DBResult<()> {
-    // TODO: Handle cases where a question is already answered
+    // Use set_question_answer if the question might already be answered
     let mut query = InsertQueryBuilder::new(CaseNoteQuestionAnswers::table_name());
 
     query
@@ -143,11 +316,13 @@ pub async fn add_question(
                 .insert(CaseNoteQuestionAnswersColumn::ValueText, other.value())
                 .return_all()
                 .query_as::<CaseNoteQuestionAnswers>()
-                .fetch_one(database)
+                .fetch_one(&mut *database)
                 .await?;
 
             for option in options {
-                answer.add_multi_check_box(option.id, database).await?;
+                answer
+                    .add_multi_check_box(option.id, &mut *database)
+                    .await?;
             }
         }
     }
@@ -155,6 +330,41 @@ pub async fn add_question(
 }
 
 impl DBQuestionResponse {
+    pub async fn get_for_case_note(
+        case_note: i32,
+        database: &sqlx::PgPool,
+    ) -> sqlx::Result<Vec<Self>> {
+        let query = r#"
+        SELECT
+            case_note_question_answers.id as answer_id,
+            questions.id as question_id,
+            questions.string_id as question_string_id,
+            questions.string_id_other as question_string_id_other,
+            case_note_question_answers.response_type as response_type,
+            case_note_question_answers.value_text as value_text,
+            case_note_question_answers.value_number as value_number,
+            case_note_question_answers.value_float as value_float,
+            case_note_question_answers.value_boolean as value_boolean,
+            (question_options.id, question_options.name, question_options.string_id) as value_radio,
+            case
+                when questions.question_type = 'MultiCheckBox' then array(
+                    SELECT (mcb.option_id, qo.name, qo.string_id) FROM case_note_question_answer_mcb as mcb
+                                JOIN public.question_options qo on qo.id = mcb.option_id
+                                WHERE question_answers_id = case_note_question_answers.id)
+                end as options
+        FROM case_note_question_answers
+            JOIN questions  on case_note_question_answers.question_id = questions.id
+            LEFT JOIN question_options on case_note_question_answers.value_radio = question_options.id
+        WHERE case_note_question_answers.case_note_id = $1
+        "#;
+
+        let result = sqlx::query_as(query)
+            .bind(case_note)
+            .fetch_all(database)
+            .await?;
+
+        Ok(result)
+    }
     pub async fn get_case_note_all(database: &sqlx::PgPool) -> sqlx::Result<Vec<Self>> {
         let query = r#"
         SELECT
@@ -168,7 +378,7 @@ impl DBQuestionResponse {
             case_note_question_answers.value_float as value_float,
 
             case_note_question_answers.value_boolean as value_boolean,
-            (question_options.id, question_options.name) as value_radio,
+            (question_options.id, question_options.name, question_options.string_id) as value_radio,
             case
                 when questions.question_type = 'MultiCheckBox' then array(
                     SELECT (mcb.option_id, qo.name, qo.string_id) FROM case_note_question_answer_mcb as mcb
@@ -190,10 +400,21 @@ impl DBQuestionResponse {
 mod tests {
 
     use crate::{
-        database::red_cap::questions::{CleanQuestionResponse, DBQuestionResponse},
-        utils::testing::config::testing::{get_testing_config, no_testing_config},
+        database::{
+            DBError,
+            red_cap::questions::{
+                CleanQuestionResponse, DBQuestionResponse, QuestionDataValue, QuestionError,
+                QuestionForm, QuestionType,
+                new::{NewQuestion, NewQuestionCategory},
+            },
+        },
+        utils::testing::config::testing::{
+            get_testing_config, get_testing_db, no_db_connection, no_testing_config,
+        },
     };
 
+    use super::NewCaseNoteQuestionAnswer;
+
     #[tokio::test]
 
     pub async fn test() -> anyhow::Result<()> {
@@ -212,4 +433,51 @@ mod tests {
         println!("{}", json);
         Ok(())
     }
+    /// Answers to questions of other forms can not be saved on a case note
+    #[tokio::test]
+    #[ignore]
+    pub async fn answers_to_other_forms_are_rejected() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let suffix = rand::random::<u32>();
+        let category = NewQuestionCategory {
+            string_id: format!("participant_info_test_{suffix}"),
+            name: "Participant Info Test".to_owned(),
+            description: None,
+            form: QuestionForm::ParticipantInfo,
+        }
+        .insert_return_category(&database)
+        .await?;
+        let question = NewQuestion {
+            category_id: None,
+            question_type: QuestionType::Text,
+            required: false,
+            question: "Not a case note question".to_owned(),
+            string_id: format!("participant_info_question_{suffix}"),
+            string_id_other: None,
+            requirements: None,
+            additional_options: None,
+        }
+        .insert_with_category_return_question(category.id, &database)
+        .await?;
+
+        let err = NewCaseNoteQuestionAnswer {
+            question_id: question.id,
+            value: QuestionDataValue::Text("Answer".to_owned()),
+        }
+        .resolve(&database)
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                DBError::Questions(QuestionError::QuestionNotInForm { question: id, .. })
+                    if id == question.id
+            ),
+            "{err:?}"
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,87 @@
+//! Tracks the fields changed when a record is updated from Red Cap
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// A single field that was changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct FieldChange {
+    /// The part of the record the field belongs to. Such as `participant` or `health_measures`
+    pub part: &'static str,
+    pub field: &'static str,
+    pub old: Value,
+    pub new: Value,
+}
+/// The fields changed in a record
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RecordChanges {
+    pub changes: Vec<FieldChange>,
+}
+impl RecordChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+    /// Records a change if the values are different. Returns true if they are different
+    pub fn compare<T>(&mut self, part: &'static str, field: &'static str, old: &T, new: &T) -> bool
+    where
+        T: PartialEq + Serialize,
+    {
+        if old == new {
+            return false;
+        }
+        self.push(part, field, old, new);
+        true
+    }
+    /// Records a change without comparing the values
+    pub fn push<O, N>(&mut self, part: &'static str, field: &'static str, old: &O, new: &N)
+    where
+        O: Serialize + ?Sized,
+        N: Serialize + ?Sized,
+    {
+        self.changes.push(FieldChange {
+            part,
+            field,
+            old: serde_json::to_value(old).unwrap_or_default(),
+            new: serde_json::to_value(new).unwrap_or_default(),
+        });
+    }
+    pub fn extend(&mut self, other: RecordChanges) {
+        self.changes.extend(other.changes);
+    }
+}
+/// Compares each field of `$current` with the variable of the same name.
+///
+/// Changed fields are added to `$changes`, set on the `$update` query and assigned to `$current`
+macro_rules! update_changed_fields {
+    (
+        $changes:ident, $update:ident, $part:literal, $current:expr,
+        {
+            $($field:ident => $column:expr),* $(,)?
+        }
+    ) => {
+        $(
+            if $changes.compare($part, stringify!($field), &$current.$field, &$field) {
+                $update.set($column, $field.clone().value());
+                $current.$field = $field;
+            }
+        )*
+    };
+}
+pub(crate) use update_changed_fields;
+
+#[cfg(test)]
+mod tests {
+    use super::RecordChanges;
+
+    #[test]
+    pub fn only_different_values_are_recorded() {
+        let mut changes = RecordChanges::default();
+        assert!(!changes.compare("participant", "first_name", &"John", &"John"));
+        assert!(changes.compare("participant", "last_name", &Some("Doe"), &None));
+        assert_eq!(changes.changes.len(), 1);
+        let change = &changes.changes[0];
+        assert_eq!(change.field, "last_name");
+        assert_eq!(change.old, serde_json::json!("Doe"));
+        assert_eq!(change.new, serde_json::Value::Null);
+    }
+}
@@ -1,7 +1,11 @@
 pub mod case_notes;
+pub mod changes;
 pub mod locations;
 pub mod participants;
 pub mod questions;
+pub mod sync;
+pub mod sync_conflicts;
+pub mod sync_events;
 
 pub use locations::*;
 pub mod debug_reports;
@@ -1,4 +1,6 @@
+use super::NewDemographics;
 use crate::database::prelude::*;
+use crate::database::red_cap::changes::{RecordChanges, update_changed_fields};
 use crate::red_cap::{EducationLevel, Ethnicity, Gender, HealthInsurance, PreferredLanguage, Race};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
@@ -11,7 +13,10 @@ pub trait ParticipantDemograhicsType:
         ParticipantDemograhicsColumn::all()
     }
 
-    async fn find_by_participant(id: i32, database: &sqlx::PgPool) -> DBResult<Option<Self>> {
+    async fn find_by_participant(
+        id: i32,
+        database: impl Executor<'_, Database = Postgres>,
+    ) -> DBResult<Option<Self>> {
         let result = SelectQueryBuilder::with_columns(Self::table_name(), Self::columns())
             .filter(ParticipantDemograhicsColumn::ParticipantId.equals(id.value()))
             .query_as()
@@ -52,6 +57,84 @@ impl ParticipantDemograhicsType for ParticipantDemograhics {
         self.id
     }
 }
+impl From<ParticipantDemograhics> for NewDemographics {
+    fn from(value: ParticipantDemograhics) -> Self {
+        let ParticipantDemograhics {
+            age,
+            gender,
+            race,
+            race_other,
+            race_multiracial_other,
+            ethnicity,
+            language,
+            is_veteran,
+            health_insurance,
+            highest_education_level,
+            ..
+        } = value;
+        Self {
+            age,
+            gender,
+            race,
+            race_other,
+            race_multiracial_other,
+            ethnicity,
+            language,
+            is_veteran,
+            health_insurance,
+            highest_education_level,
+        }
+    }
+}
+impl ParticipantDemograhics {
+    /// Updates only the fields that are different. Inserts the demographics if they do not exist
+    pub async fn update_changed(
+        participant_id: i32,
+        demographics: NewDemographics,
+        database: &mut sqlx::PgConnection,
+    ) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let Some(mut current) = Self::find_by_participant(participant_id, &mut *database).await?
+        else {
+            changes.push("demographics", "created", &(), &demographics);
+            demographics.insert(participant_id, database).await?;
+            return Ok(changes);
+        };
+        let NewDemographics {
+            age,
+            gender,
+            race,
+            race_other,
+            race_multiracial_other,
+            ethnicity,
+            language,
+            is_veteran,
+            health_insurance,
+            highest_education_level,
+        } = demographics;
+        let mut update = UpdateQueryBuilder::new(Self::table_name());
+        update_changed_fields!(changes, update, "demographics", current, {
+            age => ParticipantDemograhicsColumn::Age,
+            gender => ParticipantDemograhicsColumn::Gender,
+            race => ParticipantDemograhicsColumn::Race,
+            race_other => ParticipantDemograhicsColumn::RaceOther,
+            race_multiracial_other => ParticipantDemograhicsColumn::RaceMultiracialOther,
+            ethnicity => ParticipantDemograhicsColumn::Ethnicity,
+            language => ParticipantDemograhicsColumn::Language,
+            is_veteran => ParticipantDemograhicsColumn::IsVeteran,
+            health_insurance => ParticipantDemograhicsColumn::HealthInsurance,
+            highest_education_level => ParticipantDemograhicsColumn::HighestEducationLevel,
+        });
+        if !changes.is_empty() {
+            update
+                .filter(ParticipantDemograhicsColumn::Id.equals(current.id.value()))
+                .query()
+                .execute(database)
+                .await?;
+        }
+        Ok(changes)
+    }
+}
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema, FromRow)]
 pub struct ParticipantDemograhicsResponse {
     pub participant_id: i32,
@@ -4,15 +4,22 @@
 //! They have a form that is up to 10 goals. Where each one could be null
 //!
 //! So yeah. We are just going to use a 1:many relationship
-use crate::database::prelude::*;
+use crate::database::{
+    prelude::*,
+    red_cap::changes::{RecordChanges, update_changed_fields},
+};
 use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 use sqlx::{Executor, prelude::FromRow};
+use tracing::instrument;
 use utoipa::ToSchema;
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+
+use super::{ParticipantSyncEntity, Participants};
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default, ToSchema)]
 pub struct NewParticipantGoal {
     pub goal: String,
     pub is_active: Option<bool>,
+    /// Ignored when added through [ParticipantGoals::add_goal]
     pub red_cap_index: Option<i32>,
 }
 impl NewParticipantGoal {
@@ -47,9 +54,57 @@ pub struct ParticipantGoals {
     pub is_active: Option<bool>,
     pub red_cap_index: Option<i32>,
     pub hidden_from_red_cap: bool,
+    /// The goal has been pushed to or pulled from Red Cap.
+    ///
+    /// Only synced goals are hidden when they are missing from Red Cap
+    pub synced_with_red_cap: bool,
     pub created_at: DateTime<FixedOffset>,
 }
 impl ParticipantGoals {
+    /// Adds a goal to a participant
+    ///
+    /// The indexes are processed first so the new goal is placed after all existing goals.
+    /// The participant is locked while the index is picked so goals added at the same time get different indexes
+    #[instrument]
+    pub async fn add_goal(
+        participant_id: i32,
+        mut goal: NewParticipantGoal,
+        database: &PgPool,
+    ) -> DBResult<Self> {
+        let mut transaction = database.begin().await?;
+        Participants::lock_for_update(participant_id, &mut transaction).await?;
+        Self::process_red_cap_indexes(participant_id, &mut transaction).await?;
+        let next_index: i32 = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(red_cap_index), 0) + 1 FROM participant_goals WHERE participant_id = $1",
+        )
+        .bind(participant_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        goal.red_cap_index = Some(next_index);
+        let goal = goal
+            .insert_return_goal(participant_id, &mut *transaction)
+            .await?;
+        Participants::mark_updated(
+            participant_id,
+            ParticipantSyncEntity::Goals,
+            &mut *transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(goal)
+    }
+    #[instrument]
+    pub async fn set_active(&mut self, is_active: bool, database: &PgPool) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(ParticipantGoalsColumn::IsActive, is_active.value())
+            .filter(ParticipantGoalsColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.is_active = Some(is_active);
+        Participants::mark_updated(self.participant_id, ParticipantSyncEntity::Goals, database)
+            .await
+    }
     pub async fn get_goal_by_id(
         goal_id: i32,
         database: impl Executor<'_, Database = sqlx::Postgres>,
@@ -64,6 +119,73 @@ impl ParticipantGoals {
         .await
         .map_err(DBError::from)
     }
+    /// Updates the fields that are different with the values pulled from Red Cap.
+    ///
+    /// Hidden goals stay hidden
+    pub async fn update_from_red_cap(
+        &mut self,
+        goal: NewParticipantGoal,
+        database: &PgPool,
+    ) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let NewParticipantGoal {
+            goal, is_active, ..
+        } = goal;
+        let mut update = UpdateQueryBuilder::new(Self::table_name());
+        update_changed_fields!(changes, update, "goal", self, {
+            goal => ParticipantGoalsColumn::Goal,
+            is_active => ParticipantGoalsColumn::IsActive,
+        });
+        if !changes.is_empty() {
+            update
+                .filter(ParticipantGoalsColumn::Id.equals(self.id.value()))
+                .query()
+                .execute(database)
+                .await?;
+        }
+        Ok(changes)
+    }
+    /// Hides the goal from Red Cap. Used when the goal no longer exists in Red Cap
+    pub async fn hide_from_red_cap(&mut self, database: &PgPool) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(ParticipantGoalsColumn::HiddenFromRedCap, true.value())
+            .filter(ParticipantGoalsColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.hidden_from_red_cap = true;
+        Ok(())
+    }
+    /// Marks the goals with the Red Cap indexes as synced with Red Cap. Called after a pull
+    pub async fn mark_synced_with_red_cap(
+        participant_id: i32,
+        red_cap_indexes: &[i32],
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE participant_goals SET synced_with_red_cap = TRUE
+            WHERE participant_id = $1 AND red_cap_index = ANY($2) AND NOT synced_with_red_cap",
+        )
+        .bind(participant_id)
+        .bind(red_cap_indexes)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    /// Marks every goals that is sent to Red Cap as synced with Red Cap. Called after a push
+    pub async fn mark_all_synced_with_red_cap(
+        participant_id: i32,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE participant_goals SET synced_with_red_cap = TRUE
+            WHERE participant_id = $1 AND red_cap_index IS NOT NULL AND NOT hidden_from_red_cap AND NOT synced_with_red_cap",
+        )
+        .bind(participant_id)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
     pub async fn set_red_cap_index(
         &mut self,
         red_cap_index: i32,
@@ -95,8 +217,11 @@ impl ParticipantGoals {
         .map_err(DBError::from)
     }
 
-    pub async fn process_red_cap_indexes(participant_id: i32, database: &PgPool) -> DBResult<()> {
-        let mut goals = Self::get_all_participant_goals(participant_id, database).await?;
+    pub async fn process_red_cap_indexes(
+        participant_id: i32,
+        database: &mut sqlx::PgConnection,
+    ) -> DBResult<()> {
+        let mut goals = Self::get_all_participant_goals(participant_id, &mut *database).await?;
         goals.sort_by(|a, b| {
             a.red_cap_index
                 .unwrap_or_default()
@@ -109,13 +234,14 @@ impl ParticipantGoals {
                 continue;
             }
 
-            goal.set_red_cap_index(red_cap_index, database).await?;
+            goal.set_red_cap_index(red_cap_index, &mut *database)
+                .await?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default, ToSchema)]
 pub struct NewParticipantGoalsSteps {
     pub goal_id: Option<i32>,
     pub step: String,
@@ -127,14 +253,11 @@ pub struct NewParticipantGoalsSteps {
     /// Select No until goal is achieved
     #[serde(default)]
     pub action_step: Option<bool>,
+    /// Ignored when added through [ParticipantGoalsSteps::add_step]
     pub red_cap_index: Option<i32>,
 }
 impl NewParticipantGoalsSteps {
-    pub async fn insert_return_none(
-        self,
-        participant_id: i32,
-        database: impl Executor<'_, Database = sqlx::Postgres>,
-    ) -> DBResult<()> {
+    fn insert_base(self, participant_id: i32) -> InsertQueryBuilder<'static> {
         let Self {
             goal_id,
             step,
@@ -144,7 +267,8 @@ impl NewParticipantGoalsSteps {
             action_step,
             red_cap_index,
         } = self;
-        InsertQueryBuilder::new(ParticipantGoalsSteps::table_name())
+        let mut builder = InsertQueryBuilder::new(ParticipantGoalsSteps::table_name());
+        builder
             .insert(ParticipantGoalsStepsColumn::ParticipantId, participant_id)
             .insert(ParticipantGoalsStepsColumn::GoalId, goal_id)
             .insert(ParticipantGoalsStepsColumn::Step, step)
@@ -158,7 +282,27 @@ impl NewParticipantGoalsSteps {
                 date_to_be_completed,
             )
             .insert(ParticipantGoalsStepsColumn::ActionStep, action_step)
-            .insert(ParticipantGoalsStepsColumn::RedCapIndex, red_cap_index)
+            .insert(ParticipantGoalsStepsColumn::RedCapIndex, red_cap_index);
+        builder
+    }
+    pub async fn insert_returning(
+        self,
+        participant_id: i32,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<ParticipantGoalsSteps> {
+        self.insert_base(participant_id)
+            .return_all()
+            .query_as()
+            .fetch_one(database)
+            .await
+            .map_err(DBError::from)
+    }
+    pub async fn insert_return_none(
+        self,
+        participant_id: i32,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<()> {
+        self.insert_base(participant_id)
             .query()
             .execute(database)
             .await?;
@@ -194,9 +338,161 @@ pub struct ParticipantGoalsSteps {
     pub action_step: Option<bool>,
     pub red_cap_index: Option<i32>,
     pub hidden_from_red_cap: bool,
+    /// The step has been pushed to or pulled from Red Cap.
+    ///
+    /// Only synced steps are hidden when they are missing from Red Cap
+    pub synced_with_red_cap: bool,
     pub created_at: chrono::DateTime<FixedOffset>,
 }
 impl ParticipantGoalsSteps {
+    pub async fn find_by_id(
+        step_id: i32,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<Option<Self>> {
+        SelectQueryBuilder::with_columns(
+            ParticipantGoalsSteps::table_name(),
+            ParticipantGoalsStepsColumn::all(),
+        )
+        .filter(ParticipantGoalsStepsColumn::Id.equals(step_id.value()))
+        .query_as()
+        .fetch_optional(database)
+        .await
+        .map_err(DBError::from)
+    }
+    /// Adds a step to a participant
+    ///
+    /// The indexes are processed first so the new step is placed after all existing steps.
+    /// The participant is locked while the index is picked so steps added at the same time get different indexes
+    #[instrument]
+    pub async fn add_step(
+        participant_id: i32,
+        mut step: NewParticipantGoalsSteps,
+        database: &PgPool,
+    ) -> DBResult<Self> {
+        let mut transaction = database.begin().await?;
+        Participants::lock_for_update(participant_id, &mut transaction).await?;
+        Self::process_red_cap_indexes(participant_id, &mut transaction).await?;
+        let next_index: i32 = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(red_cap_index), 0) + 1 FROM participant_goal_steps WHERE participant_id = $1",
+        )
+        .bind(participant_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        step.red_cap_index = Some(next_index);
+        let step = step
+            .insert_returning(participant_id, &mut *transaction)
+            .await?;
+        Participants::mark_updated(
+            participant_id,
+            ParticipantSyncEntity::Goals,
+            &mut *transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(step)
+    }
+    #[instrument]
+    pub async fn set_action_step(&mut self, action_step: bool, database: &PgPool) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(ParticipantGoalsStepsColumn::ActionStep, action_step.value())
+            .filter(ParticipantGoalsStepsColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.action_step = Some(action_step);
+        Participants::mark_updated(self.participant_id, ParticipantSyncEntity::Goals, database)
+            .await
+    }
+    /// Moves the step to a different goal. Used to attach goal-less steps to a goal
+    #[instrument]
+    pub async fn set_goal(&mut self, goal_id: i32, database: &PgPool) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(ParticipantGoalsStepsColumn::GoalId, goal_id.value())
+            .filter(ParticipantGoalsStepsColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.goal_id = Some(goal_id);
+        Participants::mark_updated(self.participant_id, ParticipantSyncEntity::Goals, database)
+            .await
+    }
+    /// Updates the fields that are different with the values pulled from Red Cap.
+    ///
+    /// Hidden steps stay hidden
+    pub async fn update_from_red_cap(
+        &mut self,
+        step: NewParticipantGoalsSteps,
+        database: &PgPool,
+    ) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let NewParticipantGoalsSteps {
+            goal_id,
+            step,
+            confidence_level,
+            date_set,
+            date_to_be_completed,
+            action_step,
+            ..
+        } = step;
+        let mut update = UpdateQueryBuilder::new(Self::table_name());
+        update_changed_fields!(changes, update, "goal_step", self, {
+            goal_id => ParticipantGoalsStepsColumn::GoalId,
+            step => ParticipantGoalsStepsColumn::Step,
+            confidence_level => ParticipantGoalsStepsColumn::ConfidenceLevel,
+            date_set => ParticipantGoalsStepsColumn::DateSet,
+            date_to_be_completed => ParticipantGoalsStepsColumn::DateToBeCompleted,
+            action_step => ParticipantGoalsStepsColumn::ActionStep,
+        });
+        if !changes.is_empty() {
+            update
+                .filter(ParticipantGoalsStepsColumn::Id.equals(self.id.value()))
+                .query()
+                .execute(database)
+                .await?;
+        }
+        Ok(changes)
+    }
+    /// Hides the step from Red Cap. Used when the step no longer exists in Red Cap
+    pub async fn hide_from_red_cap(&mut self, database: &PgPool) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(ParticipantGoalsStepsColumn::HiddenFromRedCap, true.value())
+            .filter(ParticipantGoalsStepsColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.hidden_from_red_cap = true;
+        Ok(())
+    }
+    /// Marks the steps with the Red Cap indexes as synced with Red Cap. Called after a pull
+    pub async fn mark_synced_with_red_cap(
+        participant_id: i32,
+        red_cap_indexes: &[i32],
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE participant_goal_steps SET synced_with_red_cap = TRUE
+            WHERE participant_id = $1 AND red_cap_index = ANY($2) AND NOT synced_with_red_cap",
+        )
+        .bind(participant_id)
+        .bind(red_cap_indexes)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    /// Marks every steps that is sent to Red Cap as synced with Red Cap. Called after a push
+    pub async fn mark_all_synced_with_red_cap(
+        participant_id: i32,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE participant_goal_steps SET synced_with_red_cap = TRUE
+            WHERE participant_id = $1 AND red_cap_index IS NOT NULL AND NOT hidden_from_red_cap AND NOT synced_with_red_cap",
+        )
+        .bind(participant_id)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
     pub async fn set_red_cap_index(
         &mut self,
         red_cap_index: i32,
@@ -250,7 +546,7 @@ impl ParticipantGoalsSteps {
             ParticipantGoalsSteps::table_name(),
             ParticipantGoalsStepsColumn::all(),
         )
-        .filter(ParticipantGoalsStepsColumn::GoalId.is_not_null())
+        .filter(ParticipantGoalsStepsColumn::GoalId.is_null())
         .filter(ParticipantGoalsStepsColumn::ParticipantId.equals(participant_id.value()))
         .query_as()
         .fetch_all(database)
@@ -258,8 +554,12 @@ impl ParticipantGoalsSteps {
         .map_err(DBError::from)
     }
 
-    pub async fn process_red_cap_indexes(participant_id: i32, database: &PgPool) -> DBResult<()> {
-        let mut goals = Self::get_all_participant_goals_steps(participant_id, database).await?;
+    pub async fn process_red_cap_indexes(
+        participant_id: i32,
+        database: &mut sqlx::PgConnection,
+    ) -> DBResult<()> {
+        let mut goals =
+            Self::get_all_participant_goals_steps(participant_id, &mut *database).await?;
         goals.sort_by(|a, b| {
             a.red_cap_index
                 .unwrap_or_default()
@@ -272,8 +572,117 @@ impl ParticipantGoalsSteps {
                 continue;
             }
 
-            goal.set_red_cap_index(red_cap_index, database).await?;
+            goal.set_red_cap_index(red_cap_index, &mut *database)
+                .await?;
         }
         Ok(())
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::red_cap::participants::NewParticipant,
+        utils::testing::config::testing::{get_testing_db, no_db_connection},
+    };
+    async fn new_participant(name: &str, database: &PgPool) -> anyhow::Result<Participants> {
+        let participant = NewParticipant {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            other_contact: Some(format!("CS25-303 {name}")),
+            ..Default::default()
+        }
+        .insert_returning(database)
+        .await?;
+        Ok(participant)
+    }
+    /// Only steps without a goal are returned
+    #[tokio::test]
+    #[ignore]
+    pub async fn goaless_steps_do_not_include_steps_with_a_goal() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let participant = new_participant("goaless_steps", &database).await?;
+        let goal = ParticipantGoals::add_goal(
+            participant.id,
+            NewParticipantGoal {
+                goal: "Goal".to_string(),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+        ParticipantGoalsSteps::add_step(
+            participant.id,
+            NewParticipantGoalsSteps {
+                goal_id: Some(goal.id),
+                step: "Step With Goal".to_string(),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+        let goaless = ParticipantGoalsSteps::add_step(
+            participant.id,
+            NewParticipantGoalsSteps {
+                step: "Step Without Goal".to_string(),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+
+        let steps =
+            ParticipantGoalsSteps::get_goaless_steps_for_participant(participant.id, &database)
+                .await?;
+        assert_eq!(steps, vec![goaless]);
+        Ok(())
+    }
+    /// Goals and steps added at the same time must not share a Red Cap index
+    #[tokio::test]
+    #[ignore]
+    pub async fn concurrent_adds_get_different_indexes() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let participant = new_participant("concurrent_goal_indexes", &database).await?;
+        let goal = |name: &str| NewParticipantGoal {
+            goal: name.to_string(),
+            ..Default::default()
+        };
+        let step = |name: &str| NewParticipantGoalsSteps {
+            step: name.to_string(),
+            ..Default::default()
+        };
+        tokio::try_join!(
+            ParticipantGoals::add_goal(participant.id, goal("Goal 1"), &database),
+            ParticipantGoals::add_goal(participant.id, goal("Goal 2"), &database),
+            ParticipantGoals::add_goal(participant.id, goal("Goal 3"), &database),
+            ParticipantGoalsSteps::add_step(participant.id, step("Step 1"), &database),
+            ParticipantGoalsSteps::add_step(participant.id, step("Step 2"), &database),
+            ParticipantGoalsSteps::add_step(participant.id, step("Step 3"), &database),
+        )?;
+
+        let mut goal_indexes: Vec<Option<i32>> =
+            ParticipantGoals::get_all_participant_goals(participant.id, &database)
+                .await?
+                .into_iter()
+                .map(|goal| goal.red_cap_index)
+                .collect();
+        goal_indexes.sort_unstable();
+        assert_eq!(goal_indexes, vec![Some(1), Some(2), Some(3)]);
+
+        let mut step_indexes: Vec<Option<i32>> =
+            ParticipantGoalsSteps::get_all_participant_goals_steps(participant.id, &database)
+                .await?
+                .into_iter()
+                .map(|step| step.red_cap_index)
+                .collect();
+        step_indexes.sort_unstable();
+        assert_eq!(step_indexes, vec![Some(1), Some(2), Some(3)]);
+        Ok(())
+    }
+}
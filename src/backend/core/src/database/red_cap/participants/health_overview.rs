@@ -1,4 +1,6 @@
+use super::NewHealthOverview;
 use crate::database::prelude::*;
+use crate::database::red_cap::changes::{RecordChanges, update_changed_fields};
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgRow;
 use utoipa::ToSchema;
@@ -61,6 +63,69 @@ impl HealthOverviewType for HealthOverview {
         self.id
     }
 }
+impl From<HealthOverview> for NewHealthOverview {
+    fn from(value: HealthOverview) -> Self {
+        let HealthOverview {
+            height,
+            reported_health_conditions,
+            allergies,
+            has_blood_pressure_cuff,
+            takes_more_than_5_medications,
+            mobility_devices,
+            ..
+        } = value;
+        Self {
+            height,
+            reported_health_conditions,
+            allergies,
+            has_blood_pressure_cuff,
+            takes_more_than_5_medications,
+            mobility_devices,
+        }
+    }
+}
+impl HealthOverview {
+    /// Updates only the fields that are different. Inserts the health overview if it does not exist
+    pub async fn update_changed(
+        participant_id: i32,
+        overview: NewHealthOverview,
+        database: &mut sqlx::PgConnection,
+    ) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let Some(mut current) =
+            Self::find_by_participant_id(participant_id, &mut *database).await?
+        else {
+            changes.push("health_overview", "created", &(), &overview);
+            overview.insert(participant_id, database).await?;
+            return Ok(changes);
+        };
+        let NewHealthOverview {
+            height,
+            reported_health_conditions,
+            allergies,
+            has_blood_pressure_cuff,
+            takes_more_than_5_medications,
+            mobility_devices,
+        } = overview;
+        let mut update = UpdateQueryBuilder::new(Self::table_name());
+        update_changed_fields!(changes, update, "health_overview", current, {
+            height => HealthOverviewColumn::Height,
+            reported_health_conditions => HealthOverviewColumn::ReportedHealthConditions,
+            allergies => HealthOverviewColumn::Allergies,
+            has_blood_pressure_cuff => HealthOverviewColumn::HasBloodPressureCuff,
+            takes_more_than_5_medications => HealthOverviewColumn::TakesMoreThan5Medications,
+            mobility_devices => HealthOverviewColumn::MobilityDevices,
+        });
+        if !changes.is_empty() {
+            update
+                .filter(HealthOverviewColumn::Id.equals(current.id.value()))
+                .query()
+                .execute(database)
+                .await?;
+        }
+        Ok(changes)
+    }
+}
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema, FromRow)]
 pub struct HealthOverviewResult {
     pub participant_id: i32,
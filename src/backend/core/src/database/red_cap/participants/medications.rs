@@ -1,6 +1,10 @@
 use std::fmt::Debug;
 
-use crate::database::{CSPageParams, PaginatedResponse, prelude::*};
+use crate::database::{
+    CSPageParams, PaginatedResponse,
+    prelude::*,
+    red_cap::changes::{RecordChanges, update_changed_fields},
+};
 use chrono::{Local, NaiveDate};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
@@ -9,7 +13,7 @@ use utoipa::ToSchema;
 
 use crate::red_cap::MedicationFrequency;
 
-use super::TableType;
+use super::{ParticipantSyncEntity, Participants, TableType};
 /// Participant Medications
 ///
 /// Table Name: participant_medications
@@ -48,13 +52,90 @@ pub struct ParticipantMedications {
     ///
     /// This is done when we hit past the 40 medication limit
     pub hidden_from_red_cap: bool,
+    /// The medication has been pushed to or pulled from Red Cap.
+    ///
+    /// Only synced medications are hidden when they are missing from Red Cap
+    pub synced_with_red_cap: bool,
     /// When the medication was inserted into the database
     pub created_at: chrono::DateTime<FixedOffset>,
 }
 impl ParticipantMedications {
-    pub async fn get_all_participant_medications(
+    #[instrument]
+    pub async fn find_by_id(id: i32, database: &PgPool) -> DBResult<Option<Self>> {
+        let result = SelectQueryBuilder::with_columns(
+            ParticipantMedications::table_name(),
+            ParticipantMedicationsColumn::all(),
+        )
+        .filter(ParticipantMedicationsColumn::Id.equals(id.value()))
+        .query_as()
+        .fetch_optional(database)
+        .await?;
+        Ok(result)
+    }
+    /// Adds a medication to a participant.
+    ///
+    /// The indexes are processed first by [Self::process_medications_indexes] so the new medication is placed after all existing medications.
+    /// The participant is locked while the index is picked so medications added at the same time get different indexes
+    #[instrument]
+    pub async fn add_medication(
         participant_id: i32,
+        mut medication: NewMedication,
+        database: &PgPool,
+    ) -> DBResult<Self> {
+        let mut transaction = database.begin().await?;
+        Participants::lock_for_update(participant_id, &mut transaction).await?;
+        Self::process_medications_indexes(participant_id, &mut transaction).await?;
+        let next_index: i32 = sqlx::query_scalar(
+            "SELECT COALESCE(MAX(red_cap_index), 0) + 1 FROM participant_medications WHERE participant_id = $1",
+        )
+        .bind(participant_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        medication.red_cap_index = Some(next_index);
+        let medication = medication
+            .insert_returning(participant_id, &mut *transaction)
+            .await?;
+        Participants::mark_updated(
+            participant_id,
+            ParticipantSyncEntity::Medications,
+            &mut *transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(medication)
+    }
+    /// Marks the medication as no longer current
+    ///
+    /// If no date is provided the current date is used
+    #[instrument]
+    pub async fn discontinue(
+        &mut self,
+        date_discontinued: Option<NaiveDate>,
         database: &PgPool,
+    ) -> DBResult<()> {
+        let date_discontinued = date_discontinued.unwrap_or_else(|| Local::now().date_naive());
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(
+                ParticipantMedicationsColumn::DateDiscontinued,
+                date_discontinued.value(),
+            )
+            .set(ParticipantMedicationsColumn::IsCurrent, false.value())
+            .filter(ParticipantMedicationsColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.date_discontinued = Some(date_discontinued);
+        self.is_current = Some(false);
+        Participants::mark_updated(
+            self.participant_id,
+            ParticipantSyncEntity::Medications,
+            database,
+        )
+        .await
+    }
+    pub async fn get_all_participant_medications(
+        participant_id: i32,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
     ) -> DBResult<Vec<ParticipantMedications>> {
         let result = SelectQueryBuilder::with_columns(
             ParticipantMedications::table_name(),
@@ -160,10 +241,10 @@ impl ParticipantMedications {
     /// This will also make sure no "gaps" exist in the red_cap_index.
     pub async fn process_medications_indexes(
         participant_id: i32,
-        database: &PgPool,
+        database: &mut sqlx::PgConnection,
     ) -> DBResult<()> {
         let mut medications =
-            Self::get_all_participant_medications(participant_id, database).await?;
+            Self::get_all_participant_medications(participant_id, &mut *database).await?;
         medications.sort_by(|a, b| {
             a.red_cap_index
                 .unwrap_or(i32::MAX)
@@ -173,16 +254,99 @@ impl ParticipantMedications {
         for (index, medication) in medications.iter_mut().enumerate() {
             let red_cap_index = index as i32 + 1;
             medication
-                .set_red_cap_index(red_cap_index, database)
+                .set_red_cap_index(red_cap_index, &mut *database)
                 .await?;
         }
         Ok(())
     }
 
+    /// Updates the fields that are different with the values pulled from Red Cap.
+    ///
+    /// Hidden medications stay hidden
+    pub async fn update_from_red_cap(
+        &mut self,
+        medication: NewMedication,
+        database: &PgPool,
+    ) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let NewMedication {
+            name,
+            dosage,
+            frequency,
+            date_prescribed,
+            date_entered_into_system,
+            is_current,
+            date_discontinued,
+            comments,
+            ..
+        } = medication;
+        // Red Cap does not always have the date it was entered
+        let date_entered_into_system = date_entered_into_system.or(self.date_entered_into_system);
+        let mut update = UpdateQueryBuilder::new(Self::table_name());
+        update_changed_fields!(changes, update, "medication", self, {
+            name => ParticipantMedicationsColumn::Name,
+            dosage => ParticipantMedicationsColumn::Dosage,
+            frequency => ParticipantMedicationsColumn::Frequency,
+            date_prescribed => ParticipantMedicationsColumn::DatePrescribed,
+            date_entered_into_system => ParticipantMedicationsColumn::DateEnteredIntoSystem,
+            is_current => ParticipantMedicationsColumn::IsCurrent,
+            date_discontinued => ParticipantMedicationsColumn::DateDiscontinued,
+            comments => ParticipantMedicationsColumn::Comments,
+        });
+        if !changes.is_empty() {
+            update
+                .filter(ParticipantMedicationsColumn::Id.equals(self.id.value()))
+                .query()
+                .execute(database)
+                .await?;
+        }
+        Ok(changes)
+    }
+    /// Hides the medication from Red Cap. Used when the medication no longer exists in Red Cap
+    pub async fn hide_from_red_cap(&mut self, database: &PgPool) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(ParticipantMedicationsColumn::HiddenFromRedCap, true.value())
+            .filter(ParticipantMedicationsColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.hidden_from_red_cap = true;
+        Ok(())
+    }
+    /// Marks the medications with the Red Cap indexes as synced with Red Cap. Called after a pull
+    pub async fn mark_synced_with_red_cap(
+        participant_id: i32,
+        red_cap_indexes: &[i32],
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE participant_medications SET synced_with_red_cap = TRUE
+            WHERE participant_id = $1 AND red_cap_index = ANY($2) AND NOT synced_with_red_cap",
+        )
+        .bind(participant_id)
+        .bind(red_cap_indexes)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    /// Marks every medications that is sent to Red Cap as synced with Red Cap. Called after a push
+    pub async fn mark_all_synced_with_red_cap(
+        participant_id: i32,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE participant_medications SET synced_with_red_cap = TRUE
+            WHERE participant_id = $1 AND red_cap_index IS NOT NULL AND NOT hidden_from_red_cap AND NOT synced_with_red_cap",
+        )
+        .bind(participant_id)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
     pub async fn set_red_cap_index(
         &mut self,
         red_cap_index: i32,
-        database: &PgPool,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
     ) -> DBResult<()> {
         if self.red_cap_index == Some(red_cap_index) {
             return Ok(());
@@ -198,7 +362,7 @@ impl ParticipantMedications {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default, ToSchema)]
 pub struct NewMedication {
     pub name: String,
     pub dosage: Option<String>,
@@ -208,14 +372,11 @@ pub struct NewMedication {
     pub is_current: Option<bool>,
     pub date_discontinued: Option<NaiveDate>,
     pub comments: Option<String>,
+    /// Ignored when added through [ParticipantMedications::add_medication]
     pub red_cap_index: Option<i32>,
 }
 impl NewMedication {
-    pub async fn insert_return_none(
-        self,
-        participant_id: i32,
-        database: impl Executor<'_, Database = sqlx::Postgres>,
-    ) -> DBResult<()> {
+    fn insert_base(self, participant_id: i32) -> InsertQueryBuilder<'static> {
         let Self {
             name,
             dosage,
@@ -230,7 +391,8 @@ impl NewMedication {
 
         let date_entered_into_system =
             date_entered_into_system.unwrap_or_else(|| Local::now().date_naive());
-        InsertQueryBuilder::new(ParticipantMedications::table_name())
+        let mut builder = InsertQueryBuilder::new(ParticipantMedications::table_name());
+        builder
             .insert(
                 ParticipantMedicationsColumn::ParticipantId,
                 participant_id.value(),
@@ -255,7 +417,27 @@ impl NewMedication {
             .insert(
                 ParticipantMedicationsColumn::RedCapIndex,
                 red_cap_index.value(),
-            )
+            );
+        builder
+    }
+    pub async fn insert_returning(
+        self,
+        participant_id: i32,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<ParticipantMedications> {
+        self.insert_base(participant_id)
+            .return_all()
+            .query_as::<ParticipantMedications>()
+            .fetch_one(database)
+            .await
+            .map_err(DBError::from)
+    }
+    pub async fn insert_return_none(
+        self,
+        participant_id: i32,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<()> {
+        self.insert_base(participant_id)
             .query()
             .execute(database)
             .await?;
@@ -319,7 +501,84 @@ impl NewMedication {
         Ok(())
     }
 }
-
+/// Changes to a medication. Fields that are not set are not changed
+#[derive(Debug, Clone, Deserialize, Serialize, Default, ToSchema)]
+#[serde(default)]
+pub struct UpdateMedication {
+    pub name: Option<String>,
+    pub dosage: Option<String>,
+    pub frequency: Option<MedicationFrequency>,
+    pub date_prescribed: Option<NaiveDate>,
+    pub is_current: Option<bool>,
+    pub comments: Option<String>,
+    /// Hides the medication from Red Cap
+    pub hidden_from_red_cap: Option<bool>,
+}
+impl UpdateMedication {
+    #[instrument]
+    pub async fn update(
+        self,
+        medication: &ParticipantMedications,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        let Self {
+            name,
+            dosage,
+            frequency,
+            date_prescribed,
+            is_current,
+            comments,
+            hidden_from_red_cap,
+        } = self;
+        let mut changed = false;
+        let mut update = UpdateQueryBuilder::new(ParticipantMedications::table_name());
+        update.filter(ParticipantMedicationsColumn::Id.equals(medication.id.value()));
+        if let Some(name) = name {
+            update.set(ParticipantMedicationsColumn::Name, name.value());
+            changed = true;
+        }
+        if let Some(dosage) = dosage {
+            update.set(ParticipantMedicationsColumn::Dosage, dosage.value());
+            changed = true;
+        }
+        if let Some(frequency) = frequency {
+            update.set(ParticipantMedicationsColumn::Frequency, frequency.value());
+            changed = true;
+        }
+        if let Some(date_prescribed) = date_prescribed {
+            update.set(
+                ParticipantMedicationsColumn::DatePrescribed,
+                date_prescribed.value(),
+            );
+            changed = true;
+        }
+        if let Some(is_current) = is_current {
+            update.set(ParticipantMedicationsColumn::IsCurrent, is_current.value());
+            changed = true;
+        }
+        if let Some(comments) = comments {
+            update.set(ParticipantMedicationsColumn::Comments, comments.value());
+            changed = true;
+        }
+        if let Some(hidden_from_red_cap) = hidden_from_red_cap {
+            update.set(
+                ParticipantMedicationsColumn::HiddenFromRedCap,
+                hidden_from_red_cap.value(),
+            );
+            changed = true;
+        }
+        if !changed {
+            return Ok(());
+        }
+        update.query().execute(database).await?;
+        Participants::mark_updated(
+            medication.participant_id,
+            ParticipantSyncEntity::Medications,
+            database,
+        )
+        .await
+    }
+}
 #[cfg(test)]
 mod tests {
     use sqlx::PgPool;
@@ -383,4 +642,85 @@ mod tests {
 
         Ok(())
     }
+    #[tokio::test]
+    async fn test_add_medication_assigns_next_index() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let participant_id = new_participant(&database).await?;
+        let existing = vec![
+            NewMedication {
+                name: "Medication 1".to_string(),
+                red_cap_index: Some(1),
+                ..Default::default()
+            },
+            NewMedication {
+                name: "Medication 2".to_string(),
+                red_cap_index: Some(3),
+                ..Default::default()
+            },
+        ];
+        NewMedication::insert_many(existing, participant_id, &database).await?;
+
+        let medication = ParticipantMedications::add_medication(
+            participant_id,
+            NewMedication {
+                name: "Medication 3".to_string(),
+                red_cap_index: Some(1),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+        assert_eq!(medication.red_cap_index, Some(3));
+
+        let mut medications =
+            ParticipantMedications::get_all_participant_medications(participant_id, &database)
+                .await?;
+        medications.sort_by_key(|medication| medication.red_cap_index);
+        let indexes: Vec<_> = medications.iter().map(|m| m.red_cap_index).collect();
+        assert_eq!(indexes, vec![Some(1), Some(2), Some(3)]);
+        Ok(())
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn concurrent_adds_get_different_indexes() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let participant_id = new_participant(&database).await?;
+        let medication = |name: &str| NewMedication {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        tokio::try_join!(
+            ParticipantMedications::add_medication(
+                participant_id,
+                medication("Medication 1"),
+                &database
+            ),
+            ParticipantMedications::add_medication(
+                participant_id,
+                medication("Medication 2"),
+                &database
+            ),
+            ParticipantMedications::add_medication(
+                participant_id,
+                medication("Medication 3"),
+                &database
+            ),
+        )?;
+
+        let mut indexes: Vec<Option<i32>> =
+            ParticipantMedications::get_all_participant_medications(participant_id, &database)
+                .await?
+                .into_iter()
+                .map(|medication| medication.red_cap_index)
+                .collect();
+        indexes.sort_unstable();
+        assert_eq!(indexes, vec![Some(1), Some(2), Some(3)]);
+        Ok(())
+    }
 }
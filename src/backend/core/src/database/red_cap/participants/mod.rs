@@ -1,5 +1,8 @@
 use crate::{
-    database::prelude::*,
+    database::{
+        prelude::*,
+        red_cap::changes::{RecordChanges, update_changed_fields},
+    },
     red_cap::{
         Programs, SeenAtVCUHS, Status,
         converter::participants::{
@@ -14,9 +17,12 @@ use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
 pub mod goals;
 pub mod health_overview;
+use health_overview::HealthOverview;
 mod lookup;
 mod medications;
 mod new;
+mod update;
+pub use update::*;
 mod researcher;
 pub use researcher::*;
 mod summary;
@@ -51,6 +57,35 @@ pub trait ParticipantType: for<'r> FromRow<'r, PgRow> + Unpin + Send + Sync + Ta
         Ok(result)
     }
 }
+/// A part of a participant that is synced with Red Cap on its own.
+///
+/// Table name: `participant_sync_entities` tracks when each part was last changed locally
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema, Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum ParticipantSyncEntity {
+    /// The participant information, demographics and health overview
+    Participant,
+    Medications,
+    /// Goals and goal steps
+    Goals,
+}
+impl ParticipantSyncEntity {
+    /// The parts of the participant changed locally since the last Red Cap sync
+    pub async fn changed_since_sync(
+        participant_id: i32,
+        database: &sqlx::PgPool,
+    ) -> DBResult<Vec<Self>> {
+        let result = sqlx::query_scalar(
+            "SELECT entity FROM participant_sync_entities
+            WHERE participant_id = $1
+            AND (last_synced_with_red_cap IS NULL OR updated_at > last_synced_with_red_cap)",
+        )
+        .bind(participant_id)
+        .fetch_all(database)
+        .await?;
+        Ok(result)
+    }
+}
 /// Database Table: `participants`
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow, TableType, ToSchema)]
 #[table(name = "participants")]
@@ -88,8 +123,85 @@ pub struct Participants {
     pub added_to_db_at: DateTime<FixedOffset>,
     /// For Database Only
     pub last_synced_with_red_cap: Option<DateTime<FixedOffset>>,
+    /// When the participant or any of its parts were last changed locally
+    pub updated_at: DateTime<FixedOffset>,
 }
 impl Participants {
+    /// Rather or not local changes have been made since the last Red Cap sync
+    pub fn has_unsynced_changes(&self) -> bool {
+        match self.last_synced_with_red_cap {
+            Some(last_synced) => self.updated_at > last_synced,
+            None => true,
+        }
+    }
+    /// Locks the participant until the transaction ends. So changes to the same participant run one after another
+    ///
+    /// Returns None if the participant does not exist
+    pub async fn lock_for_update(
+        participant_id: i32,
+        database: &mut sqlx::PgConnection,
+    ) -> DBResult<Option<Self>> {
+        let participant = sqlx::query_as("SELECT * FROM participants WHERE id = $1 FOR UPDATE")
+            .bind(participant_id)
+            .fetch_optional(database)
+            .await?;
+        Ok(participant)
+    }
+    /// Bumps the `updated_at` timestamp of the participant and the changed part so the Red Cap sync knows the participant has changed
+    #[tracing::instrument(skip(database))]
+    pub async fn mark_updated(
+        participant_id: i32,
+        entity: ParticipantSyncEntity,
+        database: impl Executor<'_, Database = sqlx::Postgres>,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "WITH updated AS (
+                UPDATE participants SET updated_at = CURRENT_TIMESTAMP WHERE id = $1
+            )
+            INSERT INTO participant_sync_entities (participant_id, entity)
+            VALUES ($1, $2)
+            ON CONFLICT (participant_id, entity) DO UPDATE SET updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(participant_id)
+        .bind(entity)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    /// Sets `last_synced_with_red_cap`
+    ///
+    /// Changes made after `synced_at` are pushed on the next sync
+    #[tracing::instrument(skip(database))]
+    pub async fn mark_synced(
+        participant_id: i32,
+        synced_at: DateTime<FixedOffset>,
+        database: &sqlx::PgPool,
+    ) -> DBResult<()> {
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(ParticipantsColumn::LastSyncedWithRedCap, synced_at.value())
+            .filter(ParticipantsColumn::Id.equals(participant_id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        sqlx::query(
+            "UPDATE participant_sync_entities SET last_synced_with_red_cap = $2 WHERE participant_id = $1",
+        )
+        .bind(participant_id)
+        .bind(synced_at)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    /// Ids of the participants with local changes that have not been pushed to Red Cap
+    pub async fn ids_with_unsynced_changes(db: &sqlx::PgPool) -> DBResult<Vec<i32>> {
+        let result = sqlx::query_scalar(
+            "SELECT id FROM participants
+            WHERE last_synced_with_red_cap IS NULL OR updated_at > last_synced_with_red_cap",
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(result)
+    }
     pub async fn get_all_ids(db: &sqlx::PgPool) -> DBResult<Vec<i32>> {
         SelectQueryBuilder::with_columns(Self::table_name(), vec![ParticipantsColumn::Id])
             .query_scalar()
@@ -127,17 +239,76 @@ impl Participants {
 
         Ok(())
     }
-    #[tracing::instrument]
+    /// Updates the participant, demographics and health overview with the values pulled from Red Cap.
+    ///
+    /// Only fields that are different are written. Returns the fields that changed.
+    /// This does not change [Participants::updated_at] as the changes came from Red Cap
+    #[tracing::instrument(skip(database))]
     pub async fn update_from_red_cap(
         &mut self,
         red_cap_participant: RedCapParticipant,
         red_cap_demographics: RedCapParticipantDemographics,
         red_cap_health_overview: RedCapHealthOverview,
-        _db: &sqlx::PgPool,
-    ) -> DBResult<()> {
-        //TODO: Implement
-        error!("Not Implemented");
-        Ok(())
+        database: &sqlx::PgPool,
+    ) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let RedCapParticipant {
+            first_name,
+            last_name,
+            phone_number_one,
+            phone_number_two,
+            other_contact,
+            program,
+            vcuhs_patient_status,
+            location,
+            status,
+            behavioral_risks_identified,
+            date_care_coordination_consent_signed,
+            date_home_visit_consent_signed,
+            signed_up_on,
+            ..
+        } = red_cap_participant;
+        let mut transaction = database.begin().await?;
+        let mut update = UpdateQueryBuilder::new(Self::table_name());
+        update_changed_fields!(changes, update, "participant", self, {
+            first_name => ParticipantsColumn::FirstName,
+            last_name => ParticipantsColumn::LastName,
+            phone_number_one => ParticipantsColumn::PhoneNumberOne,
+            phone_number_two => ParticipantsColumn::PhoneNumberTwo,
+            other_contact => ParticipantsColumn::OtherContact,
+            program => ParticipantsColumn::Program,
+            vcuhs_patient_status => ParticipantsColumn::VcuhsPatientStatus,
+            location => ParticipantsColumn::Location,
+            status => ParticipantsColumn::Status,
+            behavioral_risks_identified => ParticipantsColumn::BehavioralRisksIdentified,
+            date_care_coordination_consent_signed => ParticipantsColumn::DateCareCoordinationConsentSigned,
+            date_home_visit_consent_signed => ParticipantsColumn::DateHomeVisitConsentSigned,
+            signed_up_on => ParticipantsColumn::SignedUpOn,
+        });
+        if !changes.is_empty() {
+            update
+                .filter(ParticipantsColumn::Id.equals(self.id.value()))
+                .query()
+                .execute(&mut *transaction)
+                .await?;
+        }
+        // Red Cap returns no demographics if none of the fields are filled out. Existing ones are kept
+        if let Some(demographics) = Option::<NewDemographics>::from(red_cap_demographics) {
+            changes.extend(
+                ParticipantDemograhics::update_changed(self.id, demographics, &mut transaction)
+                    .await?,
+            );
+        }
+        changes.extend(
+            HealthOverview::update_changed(
+                self.id,
+                NewHealthOverview::from(red_cap_health_overview),
+                &mut transaction,
+            )
+            .await?,
+        );
+        transaction.commit().await?;
+        Ok(changes)
     }
 }
 
@@ -1,5 +1,6 @@
 use crate::{database::prelude::*, red_cap::SeenAtVCUHS};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use crate::{
     database::DBResult,
@@ -15,7 +16,7 @@ use super::{
     health_overview::{HealthOverview, HealthOverviewColumn},
 };
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
 pub struct NewParticipant {
     pub red_cap_id: Option<i32>,
     pub first_name: String,
@@ -95,7 +96,7 @@ impl NewParticipant {
         Ok(new_participant)
     }
 }
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema)]
 pub struct NewDemographics {
     /// Redcap: age
     pub age: Option<i16>,
@@ -167,7 +168,7 @@ impl NewDemographics {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema)]
 pub struct NewHealthOverview {
     pub height: Option<i32>,
     /// Red Cap: health_conditions
@@ -0,0 +1,430 @@
+//! Partial updates for a participant and its parts.
+//!
+//! Fields left out are not changed. Nullable fields set to `null` are cleared.
+//! Only fields that are different are written. [Participants::updated_at] is only bumped if something changed
+use crate::{
+    database::{
+        prelude::*,
+        red_cap::changes::{RecordChanges, update_changed_fields},
+    },
+    red_cap::{
+        EducationLevel, Ethnicity, Gender, HealthInsurance, MobilityDevice, PreferredLanguage,
+        Programs, Race, SeenAtVCUHS, Status,
+    },
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use super::{
+    NewDemographics, NewHealthOverview, ParticipantDemograhics, ParticipantDemograhicsType,
+    ParticipantSyncEntity, Participants, ParticipantsColumn,
+    health_overview::{HealthOverview, HealthOverviewType},
+};
+/// Tells a field set to `null` apart from a field that was left out.
+///
+/// Left out is `None`. Set to `null` is `Some(None)`. Use with `#[serde(default)]`
+fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+/// Changes to the base, contact, status and location information of a participant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
+#[serde(default)]
+pub struct UpdateParticipant {
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    #[serde(deserialize_with = "nullable")]
+    pub phone_number_one: Option<Option<String>>,
+    #[serde(deserialize_with = "nullable")]
+    pub phone_number_two: Option<Option<String>>,
+    #[serde(deserialize_with = "nullable")]
+    pub other_contact: Option<Option<String>>,
+    pub program: Option<Programs>,
+    #[serde(deserialize_with = "nullable")]
+    pub vcuhs_patient_status: Option<Option<SeenAtVCUHS>>,
+    /// Relates to [crate::database::red_cap::Locations]
+    #[serde(deserialize_with = "nullable")]
+    pub location: Option<Option<i32>>,
+    #[serde(deserialize_with = "nullable")]
+    pub status: Option<Option<Status>>,
+    #[serde(deserialize_with = "nullable")]
+    pub behavioral_risks_identified: Option<Option<String>>,
+    #[serde(deserialize_with = "nullable")]
+    pub date_care_coordination_consent_signed: Option<Option<NaiveDate>>,
+    #[serde(deserialize_with = "nullable")]
+    pub date_home_visit_consent_signed: Option<Option<NaiveDate>>,
+    pub signed_up_on: Option<NaiveDate>,
+}
+impl UpdateParticipant {
+    /// Returns the fields that changed. Nothing is written if the participant does not exist
+    #[instrument]
+    pub async fn update(self, participant_id: i32, database: &PgPool) -> DBResult<RecordChanges> {
+        let mut changes = RecordChanges::default();
+        let mut transaction = database.begin().await?;
+        let Some(mut current) =
+            Participants::lock_for_update(participant_id, &mut transaction).await?
+        else {
+            return Ok(changes);
+        };
+        let Self {
+            first_name,
+            last_name,
+            phone_number_one,
+            phone_number_two,
+            other_contact,
+            program,
+            vcuhs_patient_status,
+            location,
+            status,
+            behavioral_risks_identified,
+            date_care_coordination_consent_signed,
+            date_home_visit_consent_signed,
+            signed_up_on,
+        } = self;
+        let first_name = first_name.unwrap_or_else(|| current.first_name.clone());
+        let last_name = last_name.unwrap_or_else(|| current.last_name.clone());
+        let phone_number_one = phone_number_one.unwrap_or_else(|| current.phone_number_one.clone());
+        let phone_number_two = phone_number_two.unwrap_or_else(|| current.phone_number_two.clone());
+        let other_contact = other_contact.unwrap_or_else(|| current.other_contact.clone());
+        let program = program.unwrap_or(current.program);
+        let vcuhs_patient_status = vcuhs_patient_status.unwrap_or(current.vcuhs_patient_status);
+        let location = location.unwrap_or(current.location);
+        let status = status.unwrap_or(current.status);
+        let behavioral_risks_identified = behavioral_risks_identified
+            .unwrap_or_else(|| current.behavioral_risks_identified.clone());
+        let date_care_coordination_consent_signed = date_care_coordination_consent_signed
+            .unwrap_or(current.date_care_coordination_consent_signed);
+        let date_home_visit_consent_signed =
+            date_home_visit_consent_signed.unwrap_or(current.date_home_visit_consent_signed);
+        let signed_up_on = signed_up_on.unwrap_or(current.signed_up_on);
+
+        let mut update = UpdateQueryBuilder::new(Participants::table_name());
+        update_changed_fields!(changes, update, "participant", current, {
+            first_name => ParticipantsColumn::FirstName,
+            last_name => ParticipantsColumn::LastName,
+            phone_number_one => ParticipantsColumn::PhoneNumberOne,
+            phone_number_two => ParticipantsColumn::PhoneNumberTwo,
+            other_contact => ParticipantsColumn::OtherContact,
+            program => ParticipantsColumn::Program,
+            vcuhs_patient_status => ParticipantsColumn::VcuhsPatientStatus,
+            location => ParticipantsColumn::Location,
+            status => ParticipantsColumn::Status,
+            behavioral_risks_identified => ParticipantsColumn::BehavioralRisksIdentified,
+            date_care_coordination_consent_signed => ParticipantsColumn::DateCareCoordinationConsentSigned,
+            date_home_visit_consent_signed => ParticipantsColumn::DateHomeVisitConsentSigned,
+            signed_up_on => ParticipantsColumn::SignedUpOn,
+        });
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        update
+            .filter(ParticipantsColumn::Id.equals(participant_id.value()))
+            .query()
+            .execute(&mut *transaction)
+            .await?;
+        Participants::mark_updated(
+            participant_id,
+            ParticipantSyncEntity::Participant,
+            &mut *transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(changes)
+    }
+}
+/// Changes to a participant's demographics.
+///
+/// If the participant does not have demographics yet they will be created
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
+#[serde(default)]
+pub struct UpdateDemographics {
+    #[serde(deserialize_with = "nullable")]
+    pub age: Option<Option<i16>>,
+    #[serde(deserialize_with = "nullable")]
+    pub gender: Option<Option<Gender>>,
+    #[serde(deserialize_with = "nullable")]
+    pub race: Option<Option<Vec<Race>>>,
+    #[serde(deserialize_with = "nullable")]
+    pub race_other: Option<Option<String>>,
+    #[serde(deserialize_with = "nullable")]
+    pub race_multiracial_other: Option<Option<String>>,
+    #[serde(deserialize_with = "nullable")]
+    pub ethnicity: Option<Option<Ethnicity>>,
+    #[serde(deserialize_with = "nullable")]
+    pub language: Option<Option<PreferredLanguage>>,
+    #[serde(deserialize_with = "nullable")]
+    pub is_veteran: Option<Option<bool>>,
+    pub health_insurance: Option<Vec<HealthInsurance>>,
+    #[serde(deserialize_with = "nullable")]
+    pub highest_education_level: Option<Option<EducationLevel>>,
+}
+impl From<UpdateDemographics> for NewDemographics {
+    fn from(value: UpdateDemographics) -> Self {
+        value.apply_to(NewDemographics::default())
+    }
+}
+impl UpdateDemographics {
+    /// Replaces the fields of `demographics` that are set
+    fn apply_to(self, demographics: NewDemographics) -> NewDemographics {
+        let Self {
+            age,
+            gender,
+            race,
+            race_other,
+            race_multiracial_other,
+            ethnicity,
+            language,
+            is_veteran,
+            health_insurance,
+            highest_education_level,
+        } = self;
+        NewDemographics {
+            age: age.unwrap_or(demographics.age),
+            gender: gender.unwrap_or(demographics.gender),
+            race: race.unwrap_or(demographics.race),
+            race_other: race_other.unwrap_or(demographics.race_other),
+            race_multiracial_other: race_multiracial_other
+                .unwrap_or(demographics.race_multiracial_other),
+            ethnicity: ethnicity.unwrap_or(demographics.ethnicity),
+            language: language.unwrap_or(demographics.language),
+            is_veteran: is_veteran.unwrap_or(demographics.is_veteran),
+            health_insurance: health_insurance.unwrap_or(demographics.health_insurance),
+            highest_education_level: highest_education_level
+                .unwrap_or(demographics.highest_education_level),
+        }
+    }
+    /// Returns the fields that changed. Nothing is written if the participant does not exist
+    #[instrument]
+    pub async fn update_or_insert(
+        self,
+        participant_id: i32,
+        database: &PgPool,
+    ) -> DBResult<RecordChanges> {
+        let mut transaction = database.begin().await?;
+        if Participants::lock_for_update(participant_id, &mut transaction)
+            .await?
+            .is_none()
+        {
+            return Ok(RecordChanges::default());
+        }
+        let current =
+            ParticipantDemograhics::find_by_participant(participant_id, &mut *transaction).await?;
+        let demographics = match current {
+            Some(current) => self.apply_to(current.into()),
+            None => self.into(),
+        };
+        let changes =
+            ParticipantDemograhics::update_changed(participant_id, demographics, &mut transaction)
+                .await?;
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        Participants::mark_updated(
+            participant_id,
+            ParticipantSyncEntity::Participant,
+            &mut *transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(changes)
+    }
+}
+/// Changes to a participant's health overview.
+///
+/// If the participant does not have a health overview yet it will be created
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
+#[serde(default)]
+pub struct UpdateHealthOverview {
+    /// Measured in inches
+    #[serde(deserialize_with = "nullable")]
+    pub height: Option<Option<i32>>,
+    #[serde(deserialize_with = "nullable")]
+    pub reported_health_conditions: Option<Option<String>>,
+    #[serde(deserialize_with = "nullable")]
+    pub allergies: Option<Option<String>>,
+    #[serde(deserialize_with = "nullable")]
+    pub has_blood_pressure_cuff: Option<Option<bool>>,
+    #[serde(deserialize_with = "nullable")]
+    pub takes_more_than_5_medications: Option<Option<bool>>,
+    #[serde(deserialize_with = "nullable")]
+    pub mobility_devices: Option<Option<Vec<MobilityDevice>>>,
+}
+impl From<UpdateHealthOverview> for NewHealthOverview {
+    fn from(value: UpdateHealthOverview) -> Self {
+        value.apply_to(NewHealthOverview::default())
+    }
+}
+impl UpdateHealthOverview {
+    /// Replaces the fields of `overview` that are set
+    fn apply_to(self, overview: NewHealthOverview) -> NewHealthOverview {
+        let Self {
+            height,
+            reported_health_conditions,
+            allergies,
+            has_blood_pressure_cuff,
+            takes_more_than_5_medications,
+            mobility_devices,
+        } = self;
+        NewHealthOverview {
+            height: height.unwrap_or(overview.height),
+            reported_health_conditions: reported_health_conditions
+                .unwrap_or(overview.reported_health_conditions),
+            allergies: allergies.unwrap_or(overview.allergies),
+            has_blood_pressure_cuff: has_blood_pressure_cuff
+                .unwrap_or(overview.has_blood_pressure_cuff),
+            takes_more_than_5_medications: takes_more_than_5_medications
+                .unwrap_or(overview.takes_more_than_5_medications),
+            mobility_devices: mobility_devices.unwrap_or(overview.mobility_devices),
+        }
+    }
+    /// Returns the fields that changed. Nothing is written if the participant does not exist
+    #[instrument]
+    pub async fn update_or_insert(
+        self,
+        participant_id: i32,
+        database: &PgPool,
+    ) -> DBResult<RecordChanges> {
+        let mut transaction = database.begin().await?;
+        if Participants::lock_for_update(participant_id, &mut transaction)
+            .await?
+            .is_none()
+        {
+            return Ok(RecordChanges::default());
+        }
+        let current =
+            HealthOverview::find_by_participant_id(participant_id, &mut *transaction).await?;
+        let overview = match current {
+            Some(current) => self.apply_to(current.into()),
+            None => self.into(),
+        };
+        let changes =
+            HealthOverview::update_changed(participant_id, overview, &mut transaction).await?;
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        Participants::mark_updated(
+            participant_id,
+            ParticipantSyncEntity::Participant,
+            &mut *transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(changes)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::red_cap::participants::{NewParticipant, ParticipantType},
+        utils::testing::config::testing::{get_testing_db, no_db_connection},
+    };
+
+    #[test]
+    pub fn null_is_not_the_same_as_left_out() -> anyhow::Result<()> {
+        let update: UpdateParticipant = serde_json::from_str(r#"{"phone_number_one": null}"#)?;
+        assert_eq!(update.phone_number_one, Some(None));
+        assert_eq!(update.phone_number_two, None);
+
+        let update: UpdateParticipant =
+            serde_json::from_str(r#"{"phone_number_one": "555-555-5555"}"#)?;
+        assert_eq!(
+            update.phone_number_one,
+            Some(Some("555-555-5555".to_owned()))
+        );
+        Ok(())
+    }
+    /// Updates that do not change anything must not mark the participant as changed
+    #[tokio::test]
+    #[ignore]
+    pub async fn update_participant_only_writes_changes() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let participant = NewParticipant {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            phone_number_one: Some("555-555-5555".to_owned()),
+            other_contact: Some("Partial Update Test".to_owned()),
+            ..Default::default()
+        }
+        .insert_returning(&database)
+        .await?;
+
+        let changes = UpdateParticipant {
+            first_name: Some("John".to_owned()),
+            phone_number_one: Some(Some("555-555-5555".to_owned())),
+            ..Default::default()
+        }
+        .update(participant.id, &database)
+        .await?;
+        assert!(changes.is_empty(), "{changes:?}");
+        let unchanged = Participants::find_by_id(participant.id, &database)
+            .await?
+            .expect("Participant should exist");
+        assert_eq!(unchanged.updated_at, participant.updated_at);
+
+        let changes = UpdateParticipant {
+            phone_number_one: Some(None),
+            ..Default::default()
+        }
+        .update(participant.id, &database)
+        .await?;
+        assert_eq!(changes.changes.len(), 1);
+        let updated = Participants::find_by_id(participant.id, &database)
+            .await?
+            .expect("Participant should exist");
+        assert_eq!(updated.phone_number_one, None);
+        assert_eq!(updated.first_name, "John");
+        assert!(updated.updated_at > participant.updated_at);
+        Ok(())
+    }
+    #[tokio::test]
+    #[ignore]
+    pub async fn update_demographics_inserts_then_clears() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let participant = NewParticipant {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            other_contact: Some("Partial Demographics Update Test".to_owned()),
+            ..Default::default()
+        }
+        .insert_returning(&database)
+        .await?;
+        let changes = UpdateDemographics {
+            age: Some(Some(70)),
+            is_veteran: Some(Some(true)),
+            ..Default::default()
+        }
+        .update_or_insert(participant.id, &database)
+        .await?;
+        assert!(!changes.is_empty());
+
+        let changes = UpdateDemographics {
+            age: Some(None),
+            ..Default::default()
+        }
+        .update_or_insert(participant.id, &database)
+        .await?;
+        assert_eq!(changes.changes.len(), 1);
+        let demographics = ParticipantDemograhics::find_by_participant(participant.id, &database)
+            .await?
+            .expect("Demographics should exist");
+        assert_eq!(demographics.age, None);
+        assert_eq!(demographics.is_veteran, Some(true));
+
+        let changes = UpdateDemographics::default()
+            .update_or_insert(participant.id, &database)
+            .await?;
+        assert!(changes.is_empty(), "{changes:?}");
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt::Display};
 
+use ahash::{HashSet, HashSetExt};
 use chrono::{DateTime, FixedOffset};
 use rust_embed::Embed;
 use serde::{Deserialize, Serialize};
@@ -7,8 +8,9 @@ use sqlx::{PgPool, prelude::FromRow};
 use tracing::{debug, error, info};
 
 use super::{
-    DBResult,
+    DBResult, QuestionError,
     new::{NewQuestion, NewQuestionCategory, NewQuestionOptions},
+    requirements::{RequirementsEvaluator, referenced_questions},
 };
 /// Table name: _default_questions
 #[derive(Debug, FromRow)]
@@ -120,7 +122,75 @@ pub async fn get_question_files(
     });
     Ok(question_files)
 }
+/// A problem with the requirements script of a default question
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRequirement {
+    pub file: String,
+    pub question: String,
+    pub error: String,
+}
+impl Display for InvalidRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}: {}", self.file, self.question, self.error)
+    }
+}
+/// Checks the requirement scripts of all the question files.
+///
+/// Every script must compile, evaluate to a boolean and only reference string ids
+/// of questions that exist in one of the files
+pub fn validate_question_files(
+    question_files: &[(Cow<'static, str>, DefaultQuestions)],
+) -> Vec<InvalidRequirement> {
+    let mut string_ids = HashSet::new();
+    for (_, file) in question_files {
+        for DefaultQuestionWithOptions { question, .. } in &file.questions {
+            string_ids.insert(question.string_id.as_str());
+            if let Some(other) = &question.string_id_other {
+                string_ids.insert(other.as_str());
+            }
+        }
+    }
+    let evaluator = RequirementsEvaluator::default();
+    let mut invalid = Vec::new();
+    for (file, questions) in question_files {
+        for DefaultQuestionWithOptions { question, .. } in &questions.questions {
+            let Some(script) = question.requirements.as_deref() else {
+                continue;
+            };
+            let mut invalid_requirement = |error: String| {
+                invalid.push(InvalidRequirement {
+                    file: file.to_string(),
+                    question: question.string_id.clone(),
+                    error,
+                })
+            };
+            for reference in referenced_questions(script) {
+                if !string_ids.contains(reference) {
+                    invalid_requirement(format!("Unknown question `{reference}`"));
+                }
+            }
+            for error in evaluator.validate(script) {
+                invalid_requirement(error);
+            }
+        }
+    }
+    invalid
+}
+/// Validates the requirement scripts of every default question file.
+pub async fn validate_default_questions() -> DBResult<Vec<InvalidRequirement>> {
+    let question_files = get_question_files(None).await?;
+    Ok(validate_question_files(&question_files))
+}
 pub async fn add_default_questions(conn: &PgPool) -> DBResult<()> {
+    // All files are validated. Not just the ones being added.
+    // Because scripts can reference questions in files that were added before
+    let invalid = validate_default_questions().await?;
+    if !invalid.is_empty() {
+        for invalid in &invalid {
+            error!(%invalid, "Invalid requirements script");
+        }
+        return Err(QuestionError::InvalidRequirements(invalid).into());
+    }
     let question_files = get_question_files(Some(conn)).await?;
 
     info!(
@@ -197,9 +267,31 @@ mod tests {
     #[tokio::test]
     pub async fn get_question_file_order() -> anyhow::Result<()> {
         let question_files = super::get_question_files(None).await?;
-        for (file, _) in question_files {
-            println!("{}", file);
+        let names: Vec<&str> = question_files
+            .iter()
+            .map(|(file, _)| file.as_ref())
+            .collect();
+        for (position, (file, questions)) in question_files.iter().enumerate() {
+            for after in questions.after.iter().flatten() {
+                let after_position = names.iter().position(|name| name == after);
+                assert!(
+                    after_position.is_some_and(|after_position| after_position < position),
+                    "{file} must be added after {after}. Order: {names:?}"
+                );
+            }
         }
         Ok(())
     }
+
+    #[tokio::test]
+    pub async fn validate_default_question_requirements() -> anyhow::Result<()> {
+        let invalid = super::validate_default_questions().await?;
+        let invalid: Vec<String> = invalid.iter().map(ToString::to_string).collect();
+        assert!(
+            invalid.is_empty(),
+            "Default questions have invalid requirements:\n{}",
+            invalid.join("\n")
+        );
+        Ok(())
+    }
 }
@@ -51,6 +51,21 @@ pub enum QuestionError {
 
     #[error("Option Not Found By String Id: {0}")]
     OptionNotFoundByStringId(String),
+
+    #[error("Question Not Found: {0}")]
+    QuestionNotFound(i32),
+
+    #[error("Option {option} does not belong to question {question}")]
+    OptionNotInQuestion { option: i32, question: i32 },
+
+    #[error("Answer does not match the question type {0}")]
+    AnswerTypeMismatch(QuestionType),
+
+    #[error("Question {question} is not in the {form:?} form")]
+    QuestionNotInForm { question: i32, form: QuestionForm },
+
+    #[error("Invalid requirement scripts: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    InvalidRequirements(Vec<default::InvalidRequirement>),
 }
 
 /// Where does the question belong to
@@ -125,6 +140,14 @@ pub struct Question {
     pub additional_options: Option<Json<AdditionalQuestionSettings>>,
 }
 impl Question {
+    pub async fn find_by_id(id: i32, conn: &PgPool) -> DBResult<Option<Self>> {
+        let question = SelectQueryBuilder::with_columns(Self::table_name(), QuestionColumn::all())
+            .filter(QuestionColumn::Id.equals(id.value()))
+            .query_as::<Self>()
+            .fetch_optional(conn)
+            .await?;
+        Ok(question)
+    }
     pub async fn find_by_string_id(red_cap_id: &str, conn: &PgPool) -> DBResult<Option<Self>> {
         let question = SelectQueryBuilder::with_columns(Self::table_name(), QuestionColumn::all())
             .filter(QuestionColumn::StringId.equals(red_cap_id.value()))
@@ -151,6 +174,35 @@ impl Question {
             .await?;
         Ok(question)
     }
+    /// Returns all questions in categories belonging to the form
+    /// The form of the category the question belongs to
+    pub async fn form(&self, conn: &PgPool) -> DBResult<QuestionForm> {
+        let form = sqlx::query_scalar("SELECT form FROM question_categories WHERE id = $1")
+            .bind(self.category_id)
+            .fetch_one(conn)
+            .await?;
+        Ok(form)
+    }
+    pub async fn get_all_in_form(form: QuestionForm, conn: &PgPool) -> DBResult<Vec<Self>> {
+        let questions = sqlx::query_as(
+            "SELECT questions.* FROM questions
+                JOIN question_categories ON questions.category_id = question_categories.id
+                WHERE question_categories.form = $1",
+        )
+        .bind(form)
+        .fetch_all(conn)
+        .await?;
+        Ok(questions)
+    }
+    /// Returns all questions that have not been removed
+    pub async fn get_all_active(conn: &PgPool) -> DBResult<Vec<Self>> {
+        let questions = SelectQueryBuilder::with_columns(Self::table_name(), QuestionColumn::all())
+            .filter(QuestionColumn::Removed.equals(false.value()))
+            .query_as::<Self>()
+            .fetch_all(conn)
+            .await?;
+        Ok(questions)
+    }
     pub async fn get_all_in_category(category_id: i32, conn: &PgPool) -> DBResult<Vec<Self>> {
         let questions = SelectQueryBuilder::with_columns(Self::table_name(), QuestionColumn::all())
             .filter(QuestionColumn::CategoryId.equals(category_id.value()))
@@ -183,6 +235,40 @@ pub struct QuestionOptions {
     pub additional_options: Option<Json<AdditionalOptionSettings>>,
 }
 impl QuestionOptions {
+    /// Returns all options that have not been removed
+    pub async fn get_all_active(conn: &PgPool) -> DBResult<Vec<Self>> {
+        let options =
+            SelectQueryBuilder::with_columns(Self::table_name(), QuestionOptionsColumn::all())
+                .filter(QuestionOptionsColumn::Removed.equals(false.value()))
+                .query_as::<Self>()
+                .fetch_all(conn)
+                .await?;
+        Ok(options)
+    }
+    /// Returns every option of the question. Including removed options
+    pub async fn get_all_in_question(question_id: i32, conn: &PgPool) -> DBResult<Vec<Self>> {
+        let options =
+            SelectQueryBuilder::with_columns(Self::table_name(), QuestionOptionsColumn::all())
+                .filter(QuestionOptionsColumn::QuestionId.equals(question_id.value()))
+                .query_as::<Self>()
+                .fetch_all(conn)
+                .await?;
+        Ok(options)
+    }
+    pub async fn find_option_with_id_and_in_question(
+        option_id: i32,
+        question_id: i32,
+        conn: &PgPool,
+    ) -> DBResult<Option<Self>> {
+        let option =
+            SelectQueryBuilder::with_columns(Self::table_name(), QuestionOptionsColumn::all())
+                .filter(QuestionOptionsColumn::Id.equals(option_id.value()))
+                .filter(QuestionOptionsColumn::QuestionId.equals(question_id.value()))
+                .query_as::<Self>()
+                .fetch_optional(conn)
+                .await?;
+        Ok(option)
+    }
     pub async fn find_option_with_string_id_and_in_question(
         string_id: &str,
         question_id: i32,
@@ -288,6 +374,76 @@ impl QuestionDataValue<QuestionOptions, QuestionOptions> {
     }
 }
 
+/// A value where the options are referenced by their id.
+///
+/// This is the format used when a value is sent by a client
+pub type QuestionDataValueByIds = QuestionDataValue<i32, i32>;
+
+impl QuestionDataValueByIds {
+    /// Loads the options and ensures the value matches the question type
+    ///
+    /// # Errors
+    /// - [QuestionError::AnswerTypeMismatch] if the value does not match the question type
+    /// - [QuestionError::OptionNotInQuestion] if an option does not belong to the question
+    pub async fn resolve_options(
+        self,
+        question: &Question,
+        database: &PgPool,
+    ) -> DBResult<QuestionDataValue> {
+        let value = match (question.question_type, self) {
+            (QuestionType::Text, QuestionDataValue::Text(value)) => QuestionDataValue::Text(value),
+            (QuestionType::Number, QuestionDataValue::Number(value)) => {
+                QuestionDataValue::Number(value)
+            }
+            (QuestionType::Float, QuestionDataValue::Float(value)) => {
+                QuestionDataValue::Float(value)
+            }
+            (QuestionType::Float, QuestionDataValue::Number(value)) => {
+                QuestionDataValue::Float(value as f32)
+            }
+            (QuestionType::Boolean, QuestionDataValue::Boolean(value)) => {
+                QuestionDataValue::Boolean(value)
+            }
+            (QuestionType::Radio, QuestionDataValue::Radio { option, other }) => {
+                let option = QuestionOptions::find_option_with_id_and_in_question(
+                    option,
+                    question.id,
+                    database,
+                )
+                .await?
+                .ok_or(QuestionError::OptionNotInQuestion {
+                    option,
+                    question: question.id,
+                })?;
+                QuestionDataValue::Radio { option, other }
+            }
+            (QuestionType::MultiCheckBox, QuestionDataValue::MultiCheckBox { options, other }) => {
+                let mut resolved = Vec::with_capacity(options.len());
+                for option in options {
+                    let option = QuestionOptions::find_option_with_id_and_in_question(
+                        option,
+                        question.id,
+                        database,
+                    )
+                    .await?
+                    .ok_or(QuestionError::OptionNotInQuestion {
+                        option,
+                        question: question.id,
+                    })?;
+                    resolved.push(option);
+                }
+                QuestionDataValue::MultiCheckBox {
+                    options: resolved,
+                    other,
+                }
+            }
+            (question_type, _) => {
+                return Err(QuestionError::AnswerTypeMismatch(question_type).into());
+            }
+        };
+        Ok(value)
+    }
+}
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
 #[sqlx(type_name = "RECORD")]
 pub struct QuestionAnswerMCB {
@@ -299,18 +455,21 @@ pub struct QuestionAnswerMCB {
 pub struct QuestionAnswerRadio {
     pub option_id: i32,
     pub option_name: Option<String>,
+    pub option_string_id: Option<String>,
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
 #[sqlx(type_name = "RECORD")]
 pub struct DBQuestionAnswerRadio {
     pub option_id: Option<i32>,
     pub option_name: Option<String>,
+    pub option_string_id: Option<String>,
 }
 impl From<DBQuestionAnswerRadio> for Option<QuestionAnswerRadio> {
     fn from(val: DBQuestionAnswerRadio) -> Self {
         Some(QuestionAnswerRadio {
             option_id: val.option_id?,
             option_name: val.option_name,
+            option_string_id: val.option_string_id,
         })
     }
 }
@@ -1,24 +1,45 @@
-use ahash::HashMap;
-use rhai::{CustomType, TypeBuilder};
+//! Questions can have a `requirements` script that decides if the question should be asked.
+//!
+//! The scripts are [rhai](https://rhai.rs) expressions that have access to the other answers through `questions`
+//!
+//! ```rhai
+//! questions.get("falls1").equals(true) || questions.get("students").contains("bsn")
+//! ```
+use std::{fmt::Debug, sync::Arc};
 
-use super::{QuestionAnswerMCB, QuestionAnswerRadio, QuestionType};
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use rhai::{AST, CustomType, Engine, EvalAltResult, ParseError, Scope, TypeBuilder};
+use tokio::sync::RwLock;
+use tracing::{debug, error, instrument};
 
-#[derive(Debug, Clone, CustomType)]
+use super::{
+    CleanQuestionResponse, Question, QuestionAnswerMCB, QuestionAnswerRadio, QuestionDataValue,
+    QuestionType,
+};
+
+#[derive(Debug, Clone, Default, CustomType)]
 #[rhai_type(extra = Self::build_extra)]
-struct QuestionsScriptCtx {
+pub struct QuestionsScriptCtx {
     questions: HashMap<String, QuestionScriptData>,
 }
 impl QuestionsScriptCtx {
     pub fn get(&mut self, key: &str) -> QuestionScriptData {
         self.questions.get(key).cloned().unwrap_or_default()
     }
+    /// Sets the answer for the question with the string id
+    pub fn insert(&mut self, string_id: impl Into<String>, data: QuestionScriptData) {
+        self.questions.insert(string_id.into(), data);
+    }
+    /// If the question with the string id has an answer
+    pub fn is_answered(&self, string_id: &str) -> bool {
+        self.questions.contains_key(string_id)
+    }
     fn build_extra(builder: &mut TypeBuilder<Self>) {
         builder
             .with_name("QuestionsScriptCtx")
             .with_fn("get", Self::get);
     }
 }
-
 #[derive(Debug, Clone, PartialEq, CustomType)]
 #[rhai_type(extra = Self::build_extra)]
 pub struct QuestionScriptData {
@@ -50,6 +71,10 @@ impl PartialEq<bool> for QuestionScriptData {
 }
 impl QuestionScriptData {
     fn eq_str(&mut self, other: &str) -> bool {
+        if let Some(radio) = &self.value_radio {
+            return radio.option_string_id.as_deref() == Some(other)
+                || radio.option_name.as_deref() == Some(other);
+        }
         self.value_text.as_deref() == Some(other)
     }
     fn eq_bool(&mut self, other: bool) -> bool {
@@ -58,8 +83,8 @@ impl QuestionScriptData {
     fn eq_number(&mut self, other: i64) -> bool {
         self.value_number == Some(other as i32)
     }
-    fn eq_float(&mut self, other: f32) -> bool {
-        self.value_float == Some(other)
+    fn eq_float(&mut self, other: f64) -> bool {
+        self.value_float == Some(other as f32)
     }
     fn contains(&mut self, key: &str) -> bool {
         match self.record_type {
@@ -71,7 +96,8 @@ impl QuestionScriptData {
             }
             QuestionType::Radio => {
                 if let Some(radio) = &self.value_radio {
-                    return radio.option_name.as_deref() == Some(key);
+                    return radio.option_string_id.as_deref() == Some(key)
+                        || radio.option_name.as_deref() == Some(key);
                 }
             }
             _ => {}
@@ -99,16 +125,280 @@ impl QuestionScriptData {
             .with_fn("eq", Self::eq_str)
             .with_fn("eq", Self::eq_bool)
             .with_fn("eq", Self::eq_number)
-            .with_fn("eq", Self::eq_float);
+            .with_fn("eq", Self::eq_float)
+            .with_fn("equals", Self::eq_str)
+            .with_fn("equals", Self::eq_bool)
+            .with_fn("equals", Self::eq_number)
+            .with_fn("equals", Self::eq_float);
+    }
+}
+impl From<&QuestionDataValue> for QuestionScriptData {
+    fn from(value: &QuestionDataValue) -> Self {
+        match value {
+            QuestionDataValue::Text(text) => Self {
+                record_type: QuestionType::Text,
+                value_text: Some(text.clone()),
+                ..Default::default()
+            },
+            QuestionDataValue::Number(number) => Self {
+                record_type: QuestionType::Number,
+                value_number: Some(*number),
+                ..Default::default()
+            },
+            QuestionDataValue::Float(float) => Self {
+                record_type: QuestionType::Float,
+                value_float: Some(*float),
+                ..Default::default()
+            },
+            QuestionDataValue::Boolean(boolean) => Self {
+                record_type: QuestionType::Boolean,
+                value_boolean: Some(*boolean),
+                ..Default::default()
+            },
+            QuestionDataValue::MultiCheckBox { options, other } => Self {
+                record_type: QuestionType::MultiCheckBox,
+                value_text: other.clone(),
+                options: options
+                    .iter()
+                    .map(|option| QuestionAnswerMCB {
+                        option_id: option.id,
+                        option_name: option.name.clone(),
+                        option_string_id: option.string_id.clone(),
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            QuestionDataValue::Radio { option, other } => Self {
+                record_type: QuestionType::Radio,
+                value_text: other.clone(),
+                value_radio: Some(QuestionAnswerRadio {
+                    option_id: option.id,
+                    option_name: Some(option.name.clone()),
+                    option_string_id: option.string_id.clone(),
+                }),
+                ..Default::default()
+            },
+        }
+    }
+}
+impl From<&CleanQuestionResponse> for QuestionScriptData {
+    fn from(response: &CleanQuestionResponse) -> Self {
+        let mut data = Self {
+            record_type: response.response_type,
+            ..Default::default()
+        };
+        match &response.value {
+            Some(QuestionDataValue::Text(text)) => data.value_text = Some(text.clone()),
+            Some(QuestionDataValue::Number(number)) => data.value_number = Some(*number),
+            Some(QuestionDataValue::Float(float)) => data.value_float = Some(*float),
+            Some(QuestionDataValue::Boolean(boolean)) => data.value_boolean = Some(*boolean),
+            Some(QuestionDataValue::MultiCheckBox { options, other }) => {
+                data.options = options.clone();
+                data.value_text = other.clone();
+            }
+            Some(QuestionDataValue::Radio { option, other }) => {
+                data.value_radio = option.clone();
+                data.value_text = other.clone();
+            }
+            None => {}
+        }
+        data
+    }
+}
+/// Returns the string ids passed to `questions.get` in a script
+///
+/// Single quoted ids are included so they can be reported as unknown ids as well
+pub fn referenced_questions(script: &str) -> Vec<&str> {
+    const GET: &str = "questions.get(";
+    let mut references = Vec::new();
+    let mut rest = script;
+    while let Some(index) = rest.find(GET) {
+        rest = rest[index + GET.len()..].trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        let value = &rest[1..];
+        if let Some(end) = value.find(quote) {
+            references.push(&value[..end]);
+            rest = &value[end + 1..];
+        }
+    }
+    references
+}
+/// The result of checking a set of answers against the question requirements
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequirementsCheck {
+    /// Ids of the questions whose requirements are met
+    pub active: HashSet<i32>,
+    /// String ids of questions that were answered while their requirements are not met
+    pub inactive_answered: Vec<String>,
+    /// String ids of required questions whose requirements are met but have no answer
+    pub missing_required: Vec<String>,
+    /// String ids of questions whose requirements script failed to compile or evaluate.
+    ///
+    /// These questions are treated as inactive
+    pub broken_requirements: Vec<String>,
+}
+struct CachedRequirement {
+    script: String,
+    ast: Arc<AST>,
+}
+/// Compiles and evaluates question requirement scripts.
+///
+/// Each question's script is compiled once and the AST is cached by question id.
+/// If the script of a question changes it will be recompiled
+pub struct RequirementsEvaluator {
+    engine: Engine,
+    cache: RwLock<HashMap<i32, CachedRequirement>>,
+}
+impl Debug for RequirementsEvaluator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequirementsEvaluator")
+            .finish_non_exhaustive()
+    }
+}
+impl Default for RequirementsEvaluator {
+    fn default() -> Self {
+        Self {
+            engine: Self::build_engine(),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+}
+impl RequirementsEvaluator {
+    /// Creates an engine with the types and functions available to requirement scripts
+    pub fn build_engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.build_type::<QuestionsScriptCtx>();
+        engine.build_type::<QuestionScriptData>();
+        engine.register_fn("==", |x: &mut QuestionScriptData, y: bool| x.eq_bool(y));
+        engine.register_fn("==", |x: &mut QuestionScriptData, y: &str| x.eq_str(y));
+        engine.register_fn("==", |x: &mut QuestionScriptData, y: i64| x.eq_number(y));
+        engine.register_fn("==", |x: &mut QuestionScriptData, y: f64| x.eq_float(y));
+        engine
+    }
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+    /// Compiles a requirements script without caching it
+    pub fn compile(&self, script: &str) -> Result<AST, ParseError> {
+        self.engine.compile(script)
+    }
+    async fn get_ast(&self, question_id: i32, script: &str) -> Result<Arc<AST>, ParseError> {
+        if let Some(cached) = self
+            .cache
+            .read()
+            .await
+            .get(&question_id)
+            .filter(|cached| cached.script == script)
+        {
+            return Ok(cached.ast.clone());
+        }
+        debug!(?question_id, ?script, "Compiling requirements");
+        let ast = Arc::new(self.compile(script)?);
+        self.cache.write().await.insert(
+            question_id,
+            CachedRequirement {
+                script: script.to_owned(),
+                ast: ast.clone(),
+            },
+        );
+        Ok(ast)
+    }
+    fn evaluate(&self, ast: &AST, ctx: &QuestionsScriptCtx) -> Result<bool, Box<EvalAltResult>> {
+        let mut scope = Scope::new();
+        scope.push_constant("questions", ctx.clone());
+        self.engine.eval_ast_with_scope::<bool>(&mut scope, ast)
+    }
+    /// Compiles the script and runs it against no answers.
+    ///
+    /// Catches syntax errors, calls to functions that are not registered and scripts that do not return a boolean.
+    /// Returns the error messages. Empty if the script is valid
+    pub fn validate(&self, script: &str) -> Vec<String> {
+        let ast = match self.compile(script) {
+            Ok(ast) => ast,
+            Err(err) => return vec![err.to_string()],
+        };
+        match self.evaluate(&ast, &QuestionsScriptCtx::default()) {
+            Ok(_) => Vec::new(),
+            Err(err) => vec![err.to_string()],
+        }
+    }
+    /// Returns the ids of the questions whose requirements are met by the answers
+    /// and the string ids of the questions whose requirements are broken.
+    ///
+    /// Questions without requirements are always active. Removed questions are never active.
+    ///
+    /// Scripts that fail to compile or evaluate are logged and the question is treated as inactive.
+    /// So a broken script can not allow answers it was meant to prevent
+    #[instrument(skip(questions, ctx))]
+    pub async fn active_questions(
+        &self,
+        questions: &[Question],
+        ctx: &QuestionsScriptCtx,
+    ) -> (HashSet<i32>, Vec<String>) {
+        let mut active = HashSet::with_capacity(questions.len());
+        let mut broken = Vec::new();
+        for question in questions.iter().filter(|question| !question.removed) {
+            let Some(script) = question.requirements.as_deref() else {
+                active.insert(question.id);
+                continue;
+            };
+            let ast = match self.get_ast(question.id, script).await {
+                Ok(ast) => ast,
+                Err(err) => {
+                    error!(?question.string_id, ?err, "Failed to compile requirements");
+                    broken.push(question.string_id.clone());
+                    continue;
+                }
+            };
+            match self.evaluate(&ast, ctx) {
+                Ok(true) => {
+                    active.insert(question.id);
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    error!(?question.string_id, ?err, "Failed to evaluate requirements");
+                    broken.push(question.string_id.clone());
+                }
+            }
+        }
+        (active, broken)
+    }
+    /// Checks the answers against the requirements of the questions
+    pub async fn check(
+        &self,
+        questions: &[Question],
+        ctx: &QuestionsScriptCtx,
+    ) -> RequirementsCheck {
+        let (active, broken_requirements) = self.active_questions(questions, ctx).await;
+        let mut check = RequirementsCheck {
+            broken_requirements,
+            ..Default::default()
+        };
+        for question in questions.iter().filter(|question| !question.removed) {
+            let answered = ctx.is_answered(&question.string_id);
+            let is_active = active.contains(&question.id);
+            if answered && !is_active {
+                check.inactive_answered.push(question.string_id.clone());
+            } else if !answered && is_active && question.required {
+                check.missing_required.push(question.string_id.clone());
+            }
+        }
+        check.active = active;
+        check
     }
 }
 #[cfg(test)]
 mod tests {
 
     use ahash::{HashMap, HashMapExt};
-    use rhai::{Engine, Scope};
+    use rhai::Scope;
 
-    use crate::database::red_cap::questions::requirements::QuestionsScriptCtx;
+    use crate::database::red_cap::questions::{
+        Question, QuestionType,
+        requirements::{QuestionsScriptCtx, RequirementsEvaluator},
+    };
 
     use super::QuestionScriptData;
     #[test]
@@ -154,15 +444,7 @@ mod tests {
                 ..Default::default()
             },
         );
-        let mut engine = Engine::new();
-        let _ = engine.register_custom_operator("==", 100);
-        engine.register_fn("==", |x: &mut QuestionScriptData, y: bool| x.eq_bool(y));
-        engine.register_fn("==", |x: &mut QuestionScriptData, y: &str| x.eq_str(y));
-        engine.register_fn("==", |x: &mut QuestionScriptData, y: i64| x.eq_number(y));
-        engine.register_fn("==", |x: &mut QuestionScriptData, y: f32| x.eq_float(y));
-
-        engine.build_type::<QuestionsScriptCtx>();
-        engine.build_type::<QuestionScriptData>();
+        let engine = RequirementsEvaluator::build_engine();
         engine
             .gen_fn_signatures(false)
             .into_iter()
@@ -175,6 +457,17 @@ mod tests {
             let value: bool = engine.eval_ast_with_scope(&mut scope, &ast).unwrap();
             assert!(value, "Boolean should be true");
         }
+        {
+            let ast =
+                engine.compile_with_scope(&scope, r#"questions.get("boolean").equals(true)"#)?;
+            let value: bool = engine.eval_ast_with_scope(&mut scope, &ast).unwrap();
+            assert!(value, "Boolean should equal true");
+        }
+        {
+            let ast = engine.compile_with_scope(&scope, r#"questions.get("float") == 10.0"#)?;
+            let value: bool = engine.eval_ast_with_scope(&mut scope, &ast).unwrap();
+            assert!(value, "Float should be 10.0");
+        }
         {
             let ast = engine.compile_with_scope(&scope, r#"questions.get("text") == "test""#)?;
             let value: bool = engine.eval_ast_with_scope(&mut scope, &ast).unwrap();
@@ -195,4 +488,89 @@ mod tests {
         }
         Ok(())
     }
+    #[tokio::test]
+    pub async fn broken_requirements_are_inactive() {
+        let question = |id: i32, string_id: &str, requirements: Option<&str>| Question {
+            id,
+            category_id: 1,
+            string_id: string_id.to_owned(),
+            string_id_other: None,
+            question_type: QuestionType::Boolean,
+            question: string_id.to_owned(),
+            description: None,
+            required: true,
+            removed: false,
+            requirements: requirements.map(ToOwned::to_owned),
+            additional_options: None,
+        };
+        let questions = vec![
+            question(1, "falls1", None),
+            question(
+                2,
+                "compile_error",
+                Some(r#"questions.get('falls1') == true"#),
+            ),
+            question(
+                3,
+                "eval_error",
+                Some(r#"questions.get("falls1").unknown()"#),
+            ),
+            question(4, "falls2", Some(r#"questions.get("falls1") == true"#)),
+        ];
+        let mut ctx = QuestionsScriptCtx::default();
+        ctx.insert(
+            "falls1",
+            QuestionScriptData {
+                value_boolean: Some(true),
+                ..Default::default()
+            },
+        );
+        ctx.insert(
+            "eval_error",
+            QuestionScriptData {
+                value_boolean: Some(true),
+                ..Default::default()
+            },
+        );
+        let check = RequirementsEvaluator::default()
+            .check(&questions, &ctx)
+            .await;
+        assert_eq!(check.active, [1, 4].into_iter().collect());
+        assert_eq!(
+            check.broken_requirements,
+            vec!["compile_error".to_owned(), "eval_error".to_owned()]
+        );
+        assert_eq!(check.inactive_answered, vec!["eval_error".to_owned()]);
+        assert_eq!(check.missing_required, vec!["falls2".to_owned()]);
+    }
+    #[test]
+    pub fn validate_scripts() {
+        let references = super::referenced_questions(
+            r#"questions.get("falls1") == true || questions.get( 'falls2' ).contains("yes")"#,
+        );
+        assert_eq!(references, vec!["falls1", "falls2"]);
+
+        let evaluator = RequirementsEvaluator::default();
+        assert!(
+            evaluator
+                .validate(r#"questions.get("falls1") == true"#)
+                .is_empty()
+        );
+        assert!(
+            !evaluator
+                .validate(r#"questions.get('falls1') == true"#)
+                .is_empty(),
+            "Single quotes are character literals"
+        );
+        assert!(
+            !evaluator
+                .validate(r#"questions.get("falls1").unknown()"#)
+                .is_empty(),
+            "Unknown functions should be reported"
+        );
+        assert!(
+            !evaluator.validate(r#"questions.get("falls1")"#).is_empty(),
+            "Scripts must return a boolean"
+        );
+    }
 }
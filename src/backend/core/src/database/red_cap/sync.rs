@@ -0,0 +1,234 @@
+//! The result of the last Red Cap sync for each participant. And where the next pull continues from
+use crate::database::{CSPageParams, PaginatedResponse, prelude::*};
+use pg_extended_sqlx_queries::pagination::PaginationSupportingTool;
+use serde::{Deserialize, Serialize};
+use strum::EnumIs;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIs, Serialize, Deserialize, ToSchema, Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum RedCapSyncState {
+    /// The last sync of the participant succeeded
+    Synced,
+    /// The last sync of the participant failed. See [RedCapSyncStatus::error]
+    Failed,
+    /// Fields were changed locally and in Red Cap. The participant is not synced until an admin resolves them.
+    ///
+    /// See [RedCapSyncConflict](super::sync_conflicts::RedCapSyncConflict)
+    Conflict,
+}
+/// Table name: `red_cap_sync_runs`
+///
+/// A sync run that finished pulling from Red Cap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow, TableType, ToSchema)]
+#[table(name = "red_cap_sync_runs")]
+pub struct RedCapSyncRun {
+    pub id: i32,
+    pub started_at: DateTime<FixedOffset>,
+    /// None if every record was pulled
+    pub pulled_since: Option<DateTime<FixedOffset>>,
+    pub created_at: DateTime<FixedOffset>,
+}
+impl RedCapSyncRun {
+    /// Where the next pull continues from. The start of the latest run that finished pulling
+    ///
+    /// Records changed in Red Cap during that run are pulled again on the next run
+    pub async fn last_pulled_at(database: &PgPool) -> DBResult<Option<DateTime<FixedOffset>>> {
+        let result = sqlx::query_scalar("SELECT MAX(started_at) FROM red_cap_sync_runs")
+            .fetch_one(database)
+            .await?;
+        Ok(result)
+    }
+    /// Records that a run finished pulling. Even if no records changed
+    ///
+    /// Records that failed are retried by [RedCapSyncStatus::failed_red_cap_ids]
+    #[instrument(skip(database))]
+    pub async fn record_pull(
+        started_at: DateTime<FixedOffset>,
+        pulled_since: Option<DateTime<FixedOffset>>,
+        database: &PgPool,
+    ) -> DBResult<Self> {
+        let result = sqlx::query_as(
+            "INSERT INTO red_cap_sync_runs (started_at, pulled_since) VALUES ($1, $2) RETURNING *",
+        )
+        .bind(started_at)
+        .bind(pulled_since)
+        .fetch_one(database)
+        .await?;
+        Ok(result)
+    }
+}
+/// Table name: `red_cap_sync_status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow, TableType, ToSchema)]
+#[table(name = "red_cap_sync_status")]
+pub struct RedCapSyncStatus {
+    /// Relates to [crate::database::red_cap::participants::Participants]
+    pub participant_id: i32,
+    pub status: RedCapSyncState,
+    /// When the participant was last pulled from Red Cap
+    pub last_pulled_at: Option<DateTime<FixedOffset>>,
+    /// When the participant was last pushed to Red Cap
+    pub last_pushed_at: Option<DateTime<FixedOffset>>,
+    /// The error of the last failed sync
+    pub error: Option<String>,
+    pub updated_at: DateTime<FixedOffset>,
+}
+impl RedCapSyncStatus {
+    pub async fn find_by_participant(
+        participant_id: i32,
+        database: &PgPool,
+    ) -> DBResult<Option<Self>> {
+        let result = SelectQueryBuilder::with_columns(Self::table_name(), Self::columns())
+            .filter(RedCapSyncStatusColumn::ParticipantId.equals(participant_id.value()))
+            .query_as()
+            .fetch_optional(database)
+            .await?;
+        Ok(result)
+    }
+    /// Red Cap ids of the participants whose last sync failed
+    pub async fn failed_red_cap_ids(database: &PgPool) -> DBResult<Vec<i32>> {
+        let result = sqlx::query_scalar(
+            "SELECT participants.red_cap_id FROM red_cap_sync_status
+            JOIN participants ON participants.id = red_cap_sync_status.participant_id
+            WHERE red_cap_sync_status.status = $1 AND participants.red_cap_id IS NOT NULL",
+        )
+        .bind(RedCapSyncState::Failed)
+        .fetch_all(database)
+        .await?;
+        Ok(result)
+    }
+    #[instrument(skip(database))]
+    pub async fn record_pulled(
+        participant_id: i32,
+        pulled_at: DateTime<FixedOffset>,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO red_cap_sync_status (participant_id, status, last_pulled_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (participant_id) DO UPDATE SET
+                status = EXCLUDED.status,
+                last_pulled_at = EXCLUDED.last_pulled_at,
+                error = NULL,
+                updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(participant_id)
+        .bind(RedCapSyncState::Synced)
+        .bind(pulled_at)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    #[instrument(skip(database))]
+    pub async fn record_pushed(
+        participant_id: i32,
+        pushed_at: DateTime<FixedOffset>,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO red_cap_sync_status (participant_id, status, last_pushed_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (participant_id) DO UPDATE SET
+                status = EXCLUDED.status,
+                last_pushed_at = EXCLUDED.last_pushed_at,
+                error = NULL,
+                updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(participant_id)
+        .bind(RedCapSyncState::Synced)
+        .bind(pushed_at)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    #[instrument(skip(database))]
+    pub async fn record_conflict(participant_id: i32, database: &PgPool) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO red_cap_sync_status (participant_id, status)
+            VALUES ($1, $2)
+            ON CONFLICT (participant_id) DO UPDATE SET
+                status = EXCLUDED.status,
+                error = NULL,
+                updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(participant_id)
+        .bind(RedCapSyncState::Conflict)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    #[instrument(skip(database))]
+    pub async fn record_failed(
+        participant_id: i32,
+        error: &str,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO red_cap_sync_status (participant_id, status, error)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (participant_id) DO UPDATE SET
+                status = EXCLUDED.status,
+                error = EXCLUDED.error,
+                updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(participant_id)
+        .bind(RedCapSyncState::Failed)
+        .bind(error)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    /// Returns the sync status of every participant that has been synced. Most recently updated first
+    ///
+    /// If `status` is set only participants with that status are returned
+    pub async fn get_all_paginated(
+        status: Option<RedCapSyncState>,
+        page: CSPageParams,
+        database: &PgPool,
+    ) -> DBResult<PaginatedResponse<Self>> {
+        let mut query = SelectQueryBuilder::with_columns(Self::table_name(), Self::columns());
+        if let Some(status) = status {
+            query.filter(RedCapSyncStatusColumn::Status.equals(status.value()));
+        }
+        query
+            .select(
+                SqlFunctionBuilder::count_all()
+                    .then(SqlFunctionBuilder::over())
+                    .alias("total_entries"),
+            )
+            .order_by(RedCapSyncStatusColumn::UpdatedAt, SQLOrder::Descending)
+            .page_params(page);
+        let result = query.query().fetch_all(database).await?;
+        let result = PaginatedResponse::from_rows(result, &page, "total_entries")?;
+        Ok(result)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Local, SubsecRound};
+
+    use super::*;
+    use crate::utils::testing::config::testing::{get_testing_db, no_db_connection};
+    /// The watermark moves forward with every run. Even if no participant was pulled
+    #[tokio::test]
+    #[ignore]
+    pub async fn last_pulled_at_advances_without_changes() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        // Postgres stores microseconds
+        let first = Local::now().fixed_offset().trunc_subsecs(6);
+        RedCapSyncRun::record_pull(first, None, &database).await?;
+        let last_pulled_at = RedCapSyncRun::last_pulled_at(&database).await?;
+        assert!(last_pulled_at.is_some_and(|last| last >= first));
+
+        let second = first + Duration::seconds(1);
+        let run = RedCapSyncRun::record_pull(second, Some(first), &database).await?;
+        assert_eq!(run.pulled_since, Some(first));
+        let last_pulled_at = RedCapSyncRun::last_pulled_at(&database).await?;
+        assert!(last_pulled_at.is_some_and(|last| last >= second));
+        Ok(())
+    }
+}
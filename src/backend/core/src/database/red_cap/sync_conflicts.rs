@@ -0,0 +1,249 @@
+//! Fields that were changed both locally and in Red Cap since the last sync
+//!
+//! See [crate::red_cap::conflicts] for how they are found and applied
+use crate::database::{CSPageParams, PaginatedResponse, prelude::*};
+use ahash::HashMap;
+use pg_extended_sqlx_queries::pagination::PaginationSupportingTool;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json;
+use strum::EnumIs;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+/// Table name: `red_cap_sync_snapshots`
+///
+/// The rows of a Red Cap record as of the last sync
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct RedCapSyncSnapshot {
+    pub participant_id: i32,
+    pub rows: Json<Vec<HashMap<String, Value>>>,
+    pub synced_at: DateTime<FixedOffset>,
+}
+impl RedCapSyncSnapshot {
+    pub async fn find_by_participant(
+        participant_id: i32,
+        database: &PgPool,
+    ) -> DBResult<Option<Self>> {
+        let result =
+            sqlx::query_as("SELECT * FROM red_cap_sync_snapshots WHERE participant_id = $1")
+                .bind(participant_id)
+                .fetch_optional(database)
+                .await?;
+        Ok(result)
+    }
+    #[instrument(skip(rows, database))]
+    pub async fn save(
+        participant_id: i32,
+        rows: Vec<HashMap<String, Value>>,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        sqlx::query(
+            "INSERT INTO red_cap_sync_snapshots (participant_id, rows)
+            VALUES ($1, $2)
+            ON CONFLICT (participant_id) DO UPDATE SET
+                rows = EXCLUDED.rows,
+                synced_at = CURRENT_TIMESTAMP",
+        )
+        .bind(participant_id)
+        .bind(Json(rows))
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIs, Serialize, Deserialize, ToSchema, Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum RedCapConflictResolution {
+    /// Keep the local value and push it to Red Cap
+    Local,
+    /// Keep the value in Red Cap and pull it
+    Remote,
+    /// Push [RedCapSyncConflict::resolved_value] to Red Cap
+    Manual,
+}
+/// Table name: `red_cap_sync_conflicts`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow, TableType, ToSchema)]
+#[table(name = "red_cap_sync_conflicts")]
+pub struct RedCapSyncConflict {
+    pub id: i32,
+    /// Relates to [crate::database::red_cap::participants::Participants]
+    pub participant_id: i32,
+    /// None for the non repeating forms
+    pub repeat_instrument: Option<String>,
+    pub repeat_instance: Option<String>,
+    /// The Red Cap field name
+    pub field: String,
+    /// The value at the last sync. None if the field was empty or the record was never synced
+    pub base_value: Option<String>,
+    pub local_value: Option<String>,
+    pub remote_value: Option<String>,
+    /// None until the conflict is resolved
+    pub resolution: Option<RedCapConflictResolution>,
+    /// The value picked by the resolution
+    pub resolved_value: Option<String>,
+    /// Relates to [crate::database::user::User]
+    pub resolved_by: Option<i32>,
+    pub resolved_at: Option<DateTime<FixedOffset>>,
+    /// When the sync sent the resolution to Red Cap
+    pub applied_at: Option<DateTime<FixedOffset>>,
+    pub created_at: DateTime<FixedOffset>,
+}
+impl RedCapSyncConflict {
+    pub async fn find_by_id(id: i32, database: &PgPool) -> DBResult<Option<Self>> {
+        let result = SelectQueryBuilder::with_columns(Self::table_name(), Self::columns())
+            .filter(RedCapSyncConflictColumn::Id.equals(id.value()))
+            .query_as()
+            .fetch_optional(database)
+            .await?;
+        Ok(result)
+    }
+    /// Conflicts that are resolved but have not been applied by the sync yet
+    pub async fn find_resolved_unapplied(
+        participant_id: i32,
+        database: &PgPool,
+    ) -> DBResult<Vec<Self>> {
+        let result = sqlx::query_as(
+            "SELECT * FROM red_cap_sync_conflicts
+            WHERE participant_id = $1 AND resolution IS NOT NULL AND applied_at IS NULL",
+        )
+        .bind(participant_id)
+        .fetch_all(database)
+        .await?;
+        Ok(result)
+    }
+    /// Ids of the participants with conflicts that have not been resolved
+    pub async fn participants_with_open_conflicts(database: &PgPool) -> DBResult<Vec<i32>> {
+        let result = sqlx::query_scalar(
+            "SELECT DISTINCT participant_id FROM red_cap_sync_conflicts WHERE resolution IS NULL",
+        )
+        .fetch_all(database)
+        .await?;
+        Ok(result)
+    }
+    /// Red Cap ids of the participants whose conflicts are all resolved but not applied yet
+    pub async fn red_cap_ids_ready_to_apply(database: &PgPool) -> DBResult<Vec<i32>> {
+        let result = sqlx::query_scalar(
+            "SELECT participants.red_cap_id FROM red_cap_sync_conflicts
+            JOIN participants ON participants.id = red_cap_sync_conflicts.participant_id
+            WHERE red_cap_sync_conflicts.applied_at IS NULL
+                AND participants.red_cap_id IS NOT NULL
+            GROUP BY participants.red_cap_id
+            HAVING BOOL_AND(red_cap_sync_conflicts.resolution IS NOT NULL)",
+        )
+        .fetch_all(database)
+        .await?;
+        Ok(result)
+    }
+    #[instrument(skip(database))]
+    pub async fn resolve(
+        &mut self,
+        resolution: RedCapConflictResolution,
+        resolved_value: Option<String>,
+        resolved_by: Option<i32>,
+        database: &PgPool,
+    ) -> DBResult<()> {
+        let resolved_at = Local::now().fixed_offset();
+        UpdateQueryBuilder::new(Self::table_name())
+            .set(RedCapSyncConflictColumn::Resolution, resolution.value())
+            .set(
+                RedCapSyncConflictColumn::ResolvedValue,
+                resolved_value.clone().value(),
+            )
+            .set(RedCapSyncConflictColumn::ResolvedBy, resolved_by.value())
+            .set(RedCapSyncConflictColumn::ResolvedAt, resolved_at.value())
+            .filter(RedCapSyncConflictColumn::Id.equals(self.id.value()))
+            .query()
+            .execute(database)
+            .await?;
+        self.resolution = Some(resolution);
+        self.resolved_value = resolved_value;
+        self.resolved_by = resolved_by;
+        self.resolved_at = Some(resolved_at);
+        Ok(())
+    }
+    /// Marks the resolved conflicts of the participant as applied
+    #[instrument(skip(database))]
+    pub async fn mark_applied(participant_id: i32, database: &PgPool) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE red_cap_sync_conflicts SET applied_at = CURRENT_TIMESTAMP
+            WHERE participant_id = $1 AND resolution IS NOT NULL AND applied_at IS NULL",
+        )
+        .bind(participant_id)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+    /// Returns the conflicts. Newest first
+    ///
+    /// If `resolved` is set only resolved or unresolved conflicts are returned
+    pub async fn get_all_paginated(
+        participant_id: Option<i32>,
+        resolved: Option<bool>,
+        page: CSPageParams,
+        database: &PgPool,
+    ) -> DBResult<PaginatedResponse<Self>> {
+        let mut query = SelectQueryBuilder::with_columns(Self::table_name(), Self::columns());
+        if let Some(participant_id) = participant_id {
+            query.filter(RedCapSyncConflictColumn::ParticipantId.equals(participant_id.value()));
+        }
+        match resolved {
+            Some(true) => {
+                query.filter(RedCapSyncConflictColumn::Resolution.is_not_null());
+            }
+            Some(false) => {
+                query.filter(RedCapSyncConflictColumn::Resolution.is_null());
+            }
+            None => {}
+        }
+        query
+            .select(
+                SqlFunctionBuilder::count_all()
+                    .then(SqlFunctionBuilder::over())
+                    .alias("total_entries"),
+            )
+            .order_by(RedCapSyncConflictColumn::CreatedAt, SQLOrder::Descending)
+            .page_params(page);
+        let result = query.query().fetch_all(database).await?;
+        let result = PaginatedResponse::from_rows(result, &page, "total_entries")?;
+        Ok(result)
+    }
+}
+/// A conflict found by the sync. See [crate::red_cap::conflicts::plan_merge]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewRedCapSyncConflict {
+    pub repeat_instrument: Option<String>,
+    pub repeat_instance: Option<String>,
+    pub field: String,
+    pub base_value: Option<String>,
+    pub local_value: Option<String>,
+    pub remote_value: Option<String>,
+}
+impl NewRedCapSyncConflict {
+    #[instrument(skip(database))]
+    pub async fn insert(self, participant_id: i32, database: &PgPool) -> DBResult<()> {
+        let Self {
+            repeat_instrument,
+            repeat_instance,
+            field,
+            base_value,
+            local_value,
+            remote_value,
+        } = self;
+        sqlx::query(
+            "INSERT INTO red_cap_sync_conflicts
+                (participant_id, repeat_instrument, repeat_instance, field, base_value, local_value, remote_value)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(participant_id)
+        .bind(repeat_instrument)
+        .bind(repeat_instance)
+        .bind(field)
+        .bind(base_value)
+        .bind(local_value)
+        .bind(remote_value)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! A log of every import into and export from Red Cap
+use crate::database::{CSPageParams, PaginatedResponse, prelude::*};
+use pg_extended_sqlx_queries::pagination::PaginationSupportingTool;
+use serde::{Deserialize, Serialize};
+use strum::EnumIs;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIs, Serialize, Deserialize, ToSchema, Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum RedCapSyncDirection {
+    /// Data was exported from Red Cap
+    Pull,
+    /// Data was imported into Red Cap
+    Push,
+}
+/// Who started a Red Cap import or export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", content = "value")]
+pub enum RedCapSyncInitiator {
+    /// The id of the user
+    User(i32),
+    /// The name of a background job or command
+    Job(String),
+}
+impl Default for RedCapSyncInitiator {
+    fn default() -> Self {
+        Self::Job("unknown".to_owned())
+    }
+}
+/// Table name: `red_cap_sync_events`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow, TableType, ToSchema)]
+#[table(name = "red_cap_sync_events")]
+pub struct RedCapSyncEvent {
+    pub id: i32,
+    /// The Red Cap record id
+    pub record_id: Option<i32>,
+    /// Relates to [crate::database::red_cap::participants::Participants]
+    ///
+    /// None if the participant did not exist locally yet
+    pub participant_id: Option<i32>,
+    pub forms: Vec<String>,
+    pub direction: RedCapSyncDirection,
+    /// Relates to [crate::database::user::User]
+    pub initiated_by_user: Option<i32>,
+    pub initiated_by_job: Option<String>,
+    /// SHA-256 of the data sent or received
+    pub payload_hash: Option<String>,
+    /// The response of Red Cap to an import
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+}
+impl RedCapSyncEvent {
+    /// Returns the events of a participant. Newest first
+    ///
+    /// Events recorded before the participant existed locally are matched by the Red Cap record id
+    pub async fn get_for_participant_paginated(
+        participant_id: i32,
+        page: CSPageParams,
+        database: &PgPool,
+    ) -> DBResult<PaginatedResponse<Self>> {
+        let result = sqlx::query(
+            "SELECT red_cap_sync_events.*, COUNT(*) OVER() AS total_entries
+            FROM red_cap_sync_events
+            WHERE participant_id = $1
+                OR record_id = (SELECT red_cap_id FROM participants WHERE id = $1)
+            ORDER BY created_at DESC, id DESC
+            LIMIT $2 OFFSET $3",
+        )
+        .bind(participant_id)
+        .bind(page.page_size as i64)
+        .bind((page.page_number.max(1) - 1) as i64 * page.page_size as i64)
+        .fetch_all(database)
+        .await?;
+        let result = PaginatedResponse::from_rows(result, &page, "total_entries")?;
+        Ok(result)
+    }
+    /// Returns every event. Newest first
+    pub async fn get_all_paginated(
+        direction: Option<RedCapSyncDirection>,
+        page: CSPageParams,
+        database: &PgPool,
+    ) -> DBResult<PaginatedResponse<Self>> {
+        let mut query = SelectQueryBuilder::with_columns(Self::table_name(), Self::columns());
+        if let Some(direction) = direction {
+            query.filter(RedCapSyncEventColumn::Direction.equals(direction.value()));
+        }
+        query
+            .select(
+                SqlFunctionBuilder::count_all()
+                    .then(SqlFunctionBuilder::over())
+                    .alias("total_entries"),
+            )
+            .order_by(RedCapSyncEventColumn::CreatedAt, SQLOrder::Descending)
+            .page_params(page);
+        let result = query.query().fetch_all(database).await?;
+        let result = PaginatedResponse::from_rows(result, &page, "total_entries")?;
+        Ok(result)
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewRedCapSyncEvent {
+    pub record_id: Option<i32>,
+    pub participant_id: Option<i32>,
+    pub forms: Vec<String>,
+    pub direction: RedCapSyncDirection,
+    pub initiated_by: RedCapSyncInitiator,
+    pub payload_hash: Option<String>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+}
+impl NewRedCapSyncEvent {
+    #[instrument(skip(database))]
+    pub async fn insert(self, database: &PgPool) -> DBResult<()> {
+        let Self {
+            record_id,
+            participant_id,
+            forms,
+            direction,
+            initiated_by,
+            payload_hash,
+            response,
+            error,
+        } = self;
+        let (initiated_by_user, initiated_by_job) = match initiated_by {
+            RedCapSyncInitiator::User(user) => (Some(user), None),
+            RedCapSyncInitiator::Job(job) => (None, Some(job)),
+        };
+        sqlx::query(
+            "INSERT INTO red_cap_sync_events
+                (record_id, participant_id, forms, direction, initiated_by_user, initiated_by_job, payload_hash, response, error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(record_id)
+        .bind(participant_id)
+        .bind(forms)
+        .bind(direction)
+        .bind(initiated_by_user)
+        .bind(initiated_by_job)
+        .bind(payload_hash)
+        .bind(response)
+        .bind(error)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use sqlx::{PgPool, prelude::FromRow};
 
 use crate::database::prelude::*;
+pub mod oidc;
 pub mod token;
 use super::{DBError, User};
 #[derive(Debug)]
@@ -32,6 +33,16 @@ impl UserPasswordAuthentication {
             .await
             .map_err(DBError::from)
     }
+    /// True if the user must change their password before doing anything else
+    pub async fn does_user_require_reset(user_id: i32, db: &PgPool) -> DBResult<bool> {
+        let requires_reset: Option<bool> = sqlx::query_scalar(
+            "SELECT COALESCE(requires_reset, FALSE) FROM user_authentication_password WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await?;
+        Ok(requires_reset.unwrap_or(false))
+    }
 }
 /// Table: user_authentication_saml
 ///
@@ -0,0 +1,186 @@
+//! Links users to their account at the OpenID Connect provider.
+//!
+//! See [crate::user::auth::oidc] for the login flow
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction, prelude::FromRow};
+use tracing::{debug, info, instrument, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    database::{
+        DBResult,
+        user::{
+            User, UserType,
+            auth::UserPasswordAuthentication,
+            does_username_exist,
+            new::NewUser,
+            roles::{Roles, UserRoles},
+        },
+    },
+    user::{
+        Permissions,
+        auth::oidc::{OidcClaims, OidcConfig},
+    },
+};
+/// Table: `user_authentication_oidc`
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UserOidcAuthentication {
+    pub id: i32,
+    pub user_id: i32,
+    pub issuer: String,
+    /// The `sub` claim
+    pub subject: String,
+    pub email: Option<String>,
+    pub last_login_at: Option<DateTime<FixedOffset>>,
+    pub created_at: DateTime<FixedOffset>,
+}
+impl UserOidcAuthentication {
+    pub async fn find_by_subject(
+        issuer: &str,
+        subject: &str,
+        database: &PgPool,
+    ) -> DBResult<Option<Self>> {
+        let result = sqlx::query_as(
+            "SELECT * FROM user_authentication_oidc WHERE issuer = $1 AND subject = $2",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(database)
+        .await?;
+        Ok(result)
+    }
+    /// Links the user to the provider account. Or updates the email and login time if already linked
+    #[instrument(skip(database))]
+    pub async fn link(
+        user_id: i32,
+        claims: &OidcClaims,
+        database: &mut Transaction<'_, Postgres>,
+    ) -> DBResult<Self> {
+        let result = sqlx::query_as(
+            "INSERT INTO user_authentication_oidc (user_id, issuer, subject, email, last_login_at)
+            VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+            ON CONFLICT ON CONSTRAINT unique_oidc_issuer_subject
+            DO UPDATE SET email = EXCLUDED.email, last_login_at = EXCLUDED.last_login_at
+            RETURNING *",
+        )
+        .bind(user_id)
+        .bind(&claims.iss)
+        .bind(&claims.sub)
+        .bind(claims.email.as_deref())
+        .fetch_one(&mut **database)
+        .await?;
+        Ok(result)
+    }
+}
+/// Finds the user for the claims of a validated ID token.
+///
+/// 1. A user already linked to the `iss` and `sub` of the claims.
+/// 2. A user with the same email. The user is linked if they are not an admin and do not have a password.
+/// 3. A new user if [OidcConfig::provision_users] is true.
+///
+/// Emails are only used if they can be trusted. See [OidcClaims::trusted_email]
+///
+/// The roles matched by [OidcConfig::role_rules] are added to the user. Roles are never removed.
+///
+/// Returns None if the claims can not be matched to a user
+#[instrument(skip(config, database))]
+pub async fn find_or_provision_oidc_user(
+    claims: &OidcClaims,
+    config: &OidcConfig,
+    database: &PgPool,
+) -> DBResult<Option<User>> {
+    let mut transaction = database.begin().await?;
+    let user =
+        match UserOidcAuthentication::find_by_subject(&claims.iss, &claims.sub, database).await? {
+            Some(link) => User::get_by_id(link.user_id, database).await?,
+            None => match claims.trusted_email(config.require_verified_email) {
+                Some(email) => match find_user_by_email(email, database).await? {
+                    Some(user) if can_link_by_email(&user, database).await? => {
+                        info!(?user.id, "Linking existing user to OpenID provider");
+                        Some(user)
+                    }
+                    Some(user) => {
+                        warn!(
+                            ?user.id,
+                            "Refusing to link an admin or a user with a password by email"
+                        );
+                        None
+                    }
+                    None if config.provision_users => {
+                        Some(provision_user(email, claims, &mut transaction, database).await?)
+                    }
+                    None => {
+                        debug!("No user with the email and provisioning is disabled");
+                        None
+                    }
+                },
+                None => {
+                    debug!("Claims do not have a trusted email");
+                    None
+                }
+            },
+        };
+    let Some(user) = user else {
+        return Ok(None);
+    };
+    UserOidcAuthentication::link(user.id, claims, &mut transaction).await?;
+    for role in config.roles_for(claims) {
+        let Some(role) = Roles::get_role_by_name(role, database).await? else {
+            warn!(
+                ?role,
+                "OpenID role rule refers to a role that does not exist"
+            );
+            continue;
+        };
+        UserRoles::add_user_role(user.id, role.id, &mut *transaction).await?;
+    }
+    transaction.commit().await?;
+    Ok(Some(user))
+}
+/// Users that can already log in are not linked by email.
+///
+/// Otherwise anyone who can change a user's email could take over the account with their own provider login.
+/// Admins are never linked by email.
+async fn can_link_by_email(user: &User, database: &PgPool) -> DBResult<bool> {
+    if user
+        .does_user_have_any_scope(&[Permissions::Admin], database)
+        .await?
+    {
+        return Ok(false);
+    }
+    let password = UserPasswordAuthentication::find_by_user_id(user.id, database).await?;
+    Ok(password.is_none())
+}
+async fn find_user_by_email(email: &str, database: &PgPool) -> DBResult<Option<User>> {
+    let result = sqlx::query_as("SELECT * FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(database)
+        .await?;
+    Ok(result)
+}
+/// Creates the user. The username is the `preferred_username` claim if it is free. Otherwise the email
+async fn provision_user(
+    email: &str,
+    claims: &OidcClaims,
+    transaction: &mut Transaction<'_, Postgres>,
+    database: &PgPool,
+) -> DBResult<User> {
+    let mut username = email;
+    if let Some(preferred) = claims.preferred_username.as_deref()
+        && !preferred.is_empty()
+        && !does_username_exist(preferred, database).await?
+    {
+        username = preferred;
+    }
+    let user = NewUser {
+        username: username.to_owned(),
+        email: email.to_owned(),
+        first_name: claims.given_name.clone().unwrap_or_default(),
+        last_name: claims.family_name.clone().unwrap_or_default(),
+    }
+    .insert_return_user(&mut **transaction)
+    .await?;
+    info!(?user.id, "Provisioned user from OpenID provider");
+    Ok(user)
+}
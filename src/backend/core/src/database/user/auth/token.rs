@@ -1 +1,147 @@
+//! Long lived API tokens. Used by scripts that can not log in with a session.
+//!
+//! Only the SHA-256 of a token is stored. The token itself is returned once when it is created
+use chrono::{DateTime, FixedOffset, Local};
+use rand::{Rng, SeedableRng, distr::Alphanumeric, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, prelude::FromRow};
+use tracing::instrument;
+use utoipa::ToSchema;
 
+use crate::{database::DBResult, user::Permissions};
+/// Every API token starts with this. So it can be told apart from a session key
+pub const API_TOKEN_PREFIX: &str = "cs25_";
+/// The number of random characters after [API_TOKEN_PREFIX]
+const API_TOKEN_LENGTH: usize = 40;
+/// Table: `user_api_tokens`
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct UserApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    /// The permissions the token can use. The user must still have them
+    pub scopes: Vec<Permissions>,
+    /// None if the token never expires
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    pub last_used_at: Option<DateTime<FixedOffset>>,
+    pub created_at: DateTime<FixedOffset>,
+}
+impl UserApiToken {
+    /// SHA-256 of the token as hex
+    pub fn hash_token(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+    pub fn is_api_token(token: &str) -> bool {
+        token.starts_with(API_TOKEN_PREFIX)
+    }
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Local::now().fixed_offset())
+    }
+    /// The token has [Permissions::Admin] or the permission itself
+    pub fn has_scope(&self, permission: Permissions) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| *scope == Permissions::Admin || *scope == permission)
+    }
+    #[instrument(skip(token, database))]
+    pub async fn find_by_token(token: &str, database: &PgPool) -> DBResult<Option<Self>> {
+        let result = sqlx::query_as("SELECT * FROM user_api_tokens WHERE token_hash = $1")
+            .bind(Self::hash_token(token))
+            .fetch_optional(database)
+            .await?;
+        Ok(result)
+    }
+    /// Returns the tokens of the user. Newest first
+    pub async fn get_all_for_user(user_id: i32, database: &PgPool) -> DBResult<Vec<Self>> {
+        let result = sqlx::query_as(
+            "SELECT * FROM user_api_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(database)
+        .await?;
+        Ok(result)
+    }
+    #[instrument(skip(database))]
+    pub async fn mark_used(&mut self, database: &PgPool) -> DBResult<()> {
+        let used_at = Local::now().fixed_offset();
+        sqlx::query("UPDATE user_api_tokens SET last_used_at = $1 WHERE id = $2")
+            .bind(used_at)
+            .bind(self.id)
+            .execute(database)
+            .await?;
+        self.last_used_at = Some(used_at);
+        Ok(())
+    }
+    /// Deletes the token of the user. Returns false if the user has no token with the id
+    #[instrument(skip(database))]
+    pub async fn revoke(id: i32, user_id: i32, database: &PgPool) -> DBResult<bool> {
+        let result = sqlx::query("DELETE FROM user_api_tokens WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(database)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct NewUserApiToken {
+    pub name: String,
+    pub scopes: Vec<Permissions>,
+    /// None if the token never expires
+    #[serde(default)]
+    pub expires_at: Option<DateTime<FixedOffset>>,
+}
+impl NewUserApiToken {
+    /// Creates the token. Returns the token and the plain text token.
+    ///
+    /// The plain text token can not be recovered later
+    #[instrument(skip(database))]
+    pub async fn insert(self, user_id: i32, database: &PgPool) -> DBResult<(UserApiToken, String)> {
+        let Self {
+            name,
+            scopes,
+            expires_at,
+        } = self;
+        let token = generate_token();
+        let result = sqlx::query_as(
+            "INSERT INTO user_api_tokens (user_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *",
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(UserApiToken::hash_token(&token))
+        .bind(scopes)
+        .bind(expires_at)
+        .fetch_one(database)
+        .await?;
+        Ok((result, token))
+    }
+}
+fn generate_token() -> String {
+    let mut rand = StdRng::from_os_rng();
+    let random: String = (0..API_TOKEN_LENGTH)
+        .map(|_| rand.sample(Alphanumeric) as char)
+        .collect();
+    format!("{API_TOKEN_PREFIX}{random}")
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn generated_tokens_are_api_tokens() {
+        let token = generate_token();
+        assert!(UserApiToken::is_api_token(&token));
+        assert_eq!(token.len(), API_TOKEN_PREFIX.len() + API_TOKEN_LENGTH);
+        assert_ne!(token, generate_token());
+
+        let hash = UserApiToken::hash_token(&token);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, UserApiToken::hash_token(&token));
+    }
+}
@@ -0,0 +1,229 @@
+//! Temporary locks of users and IP addresses after too many failed logins.
+//!
+//! Failed logins are read from `user_login_attempts`. See [LoginThrottleConfig] for the limits
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, prelude::FromRow};
+use tracing::{info, instrument};
+use utoipa::ToSchema;
+
+use crate::{database::DBResult, user::auth::LoginThrottleConfig};
+/// What a lock applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockoutTarget<'a> {
+    User(i32),
+    IpAddress(&'a str),
+}
+impl LockoutTarget<'_> {
+    fn column(&self) -> &'static str {
+        match self {
+            LockoutTarget::User(_) => "user_id",
+            LockoutTarget::IpAddress(_) => "ip_address",
+        }
+    }
+    fn max_failures(&self, config: &LoginThrottleConfig) -> u32 {
+        match self {
+            LockoutTarget::User(_) => config.max_failures_per_user,
+            LockoutTarget::IpAddress(_) => config.max_failures_per_ip,
+        }
+    }
+}
+/// Table: `login_lockouts`
+///
+/// Either `user_id` or `ip_address` is set. Never both
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct LoginLockout {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub ip_address: Option<String>,
+    /// How many locks in a row. Reset by a successful login or an admin clearing the lock
+    pub lock_count: i32,
+    /// The lock is active until this time
+    pub locked_until: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    pub created_at: DateTime<FixedOffset>,
+}
+impl LoginLockout {
+    pub fn is_active(&self) -> bool {
+        self.locked_until > Local::now().fixed_offset()
+    }
+    /// Returns the lock of the target. Even if it has ended
+    pub async fn find(target: LockoutTarget<'_>, database: &PgPool) -> DBResult<Option<Self>> {
+        let query = format!(
+            "SELECT * FROM login_lockouts WHERE {} = $1",
+            target.column()
+        );
+        let query = sqlx::query_as(&query);
+        let result = match target {
+            LockoutTarget::User(user_id) => query.bind(user_id),
+            LockoutTarget::IpAddress(ip_address) => query.bind(ip_address),
+        }
+        .fetch_optional(database)
+        .await?;
+        Ok(result)
+    }
+    /// Returns the lock of the target if it is active
+    pub async fn find_active(
+        target: LockoutTarget<'_>,
+        database: &PgPool,
+    ) -> DBResult<Option<Self>> {
+        let lock = Self::find(target, database).await?;
+        Ok(lock.filter(LoginLockout::is_active))
+    }
+    /// All active locks. The longest first
+    pub async fn get_all_active(database: &PgPool) -> DBResult<Vec<Self>> {
+        let result = sqlx::query_as(
+            "SELECT * FROM login_lockouts WHERE locked_until > CURRENT_TIMESTAMP ORDER BY locked_until DESC",
+        )
+        .fetch_all(database)
+        .await?;
+        Ok(result)
+    }
+    /// Ends the lock and resets the lock count.
+    ///
+    /// Failed logins before now are no longer counted. Returns None if the lock does not exist
+    #[instrument(skip(database))]
+    pub async fn clear(id: i32, database: &PgPool) -> DBResult<Option<Self>> {
+        let result = sqlx::query_as(
+            "UPDATE login_lockouts SET lock_count = 0, locked_until = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND locked_until > CURRENT_TIMESTAMP RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(database)
+        .await?;
+        Ok(result)
+    }
+    /// Called after a successful login. The next lock of the user starts at the base length again
+    pub async fn reset_user(user_id: i32, database: &PgPool) -> DBResult<()> {
+        sqlx::query(
+            "UPDATE login_lockouts SET lock_count = 0, updated_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND lock_count > 0",
+        )
+        .bind(user_id)
+        .execute(database)
+        .await?;
+        Ok(())
+    }
+}
+/// Counts the failed logins of the target since `since`.
+///
+/// Failed logins before the end of the last lock are not counted.
+/// For users failed logins before their last successful login are not counted either.
+pub async fn count_failed_logins(
+    target: LockoutTarget<'_>,
+    since: DateTime<FixedOffset>,
+    database: &PgPool,
+) -> DBResult<i64> {
+    let column = target.column();
+    let last_success = match target {
+        LockoutTarget::User(_) => {
+            ", (SELECT MAX(created_at) FROM user_login_attempts WHERE user_id = $1 AND success = TRUE)"
+        }
+        // Any user could log in successfully from the IP address
+        LockoutTarget::IpAddress(_) => "",
+    };
+    let query = format!(
+        "SELECT COUNT(*) FROM user_login_attempts
+        WHERE {column} = $1 AND success = FALSE AND created_at > GREATEST(
+            $2,
+            (SELECT locked_until FROM login_lockouts WHERE {column} = $1){last_success}
+        )"
+    );
+    let query = sqlx::query_scalar(&query);
+    let count = match target {
+        LockoutTarget::User(user_id) => query.bind(user_id),
+        LockoutTarget::IpAddress(ip_address) => query.bind(ip_address),
+    }
+    .bind(since)
+    .fetch_one(database)
+    .await?;
+    Ok(count)
+}
+/// Call after a failed login has been added to `user_login_attempts`.
+///
+/// Locks the target if it has reached its limit of failed logins within the window.
+/// The lock is twice as long as the previous one unless the previous one ended more than [LoginThrottleConfig::reset_after] ago.
+///
+/// Returns the new lock
+#[instrument(skip(config, database))]
+pub async fn record_failed_login(
+    target: LockoutTarget<'_>,
+    config: &LoginThrottleConfig,
+    database: &PgPool,
+) -> DBResult<Option<LoginLockout>> {
+    let now = Local::now().fixed_offset();
+    let failures = count_failed_logins(target, now - config.window(), database).await?;
+    if failures < target.max_failures(config) as i64 {
+        return Ok(None);
+    }
+    let lock_count = match LoginLockout::find(target, database).await? {
+        Some(previous) if previous.locked_until + config.reset_after() > now => {
+            previous.lock_count + 1
+        }
+        _ => 1,
+    };
+    let locked_until = now + config.lockout_duration(lock_count);
+    let column = target.column();
+    let query = format!(
+        "INSERT INTO login_lockouts ({column}, lock_count, locked_until) VALUES ($1, $2, $3)
+        ON CONFLICT ({column}) DO UPDATE SET
+            lock_count = EXCLUDED.lock_count,
+            locked_until = EXCLUDED.locked_until,
+            updated_at = CURRENT_TIMESTAMP
+        RETURNING *"
+    );
+    let query = sqlx::query_as(&query);
+    let lock: LoginLockout = match target {
+        LockoutTarget::User(user_id) => query.bind(user_id),
+        LockoutTarget::IpAddress(ip_address) => query.bind(ip_address),
+    }
+    .bind(lock_count)
+    .bind(locked_until)
+    .fetch_one(database)
+    .await?;
+    info!(?failures, ?lock.lock_count, ?lock.locked_until, "Locked logins after too many failures");
+    Ok(Some(lock))
+}
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::user::login::add_login_attempt,
+        utils::testing::config::testing::{get_testing_config, no_testing_config},
+    };
+
+    use super::*;
+    /// Fails enough logins from an IP address to lock it. Then clears the lock
+    #[tokio::test]
+    #[ignore]
+    async fn test_ip_lockout() -> anyhow::Result<()> {
+        let Some(testing_config) = get_testing_config() else {
+            no_testing_config()?;
+            return Ok(());
+        };
+        let database = testing_config.connect_to_db().await?;
+        let config = LoginThrottleConfig {
+            max_failures_per_ip: 3,
+            ..Default::default()
+        };
+        let target = LockoutTarget::IpAddress("192.0.2.1");
+        let mut lock = None;
+        for _ in 0..config.max_failures_per_ip {
+            add_login_attempt(None, "192.0.2.1", false, None, &database).await?;
+            lock = record_failed_login(target, &config, &database).await?;
+        }
+        let lock = lock.expect("IP address should be locked");
+        assert!(lock.is_active());
+        assert!(
+            LoginLockout::find_active(target, &database)
+                .await?
+                .is_some()
+        );
+
+        LoginLockout::clear(lock.id, &database).await?;
+        assert!(
+            LoginLockout::find_active(target, &database)
+                .await?
+                .is_none()
+        );
+        Ok(())
+    }
+}
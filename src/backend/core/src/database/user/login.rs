@@ -17,6 +17,16 @@ pub struct UserLoginAttempt {
     pub created_at: DateTime<FixedOffset>,
 }
 
+impl UserLoginAttempt {
+    /// Returns the login attempts with the ids. Missing ids are skipped
+    pub async fn find_by_ids(ids: &[Uuid], database: &sqlx::PgPool) -> DBResult<Vec<Self>> {
+        let result = sqlx::query_as("SELECT * FROM user_login_attempts WHERE id = ANY($1)")
+            .bind(ids)
+            .fetch_all(database)
+            .await?;
+        Ok(result)
+    }
+}
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct AdditionalFootprint {
     #[serde(default)]
@@ -13,6 +13,7 @@ pub mod new;
 pub mod roles;
 mod tools;
 pub use tools::*;
+pub mod lockout;
 pub mod login;
 pub trait UserType: for<'r> FromRow<'r, PgRow> + Unpin + Send + Sync + Debug + TableQuery {
     fn get_id(&self) -> i32;
@@ -41,28 +42,47 @@ pub trait UserType: for<'r> FromRow<'r, PgRow> + Unpin + Send + Sync + Debug + T
         scope: &[Permissions],
         database: &sqlx::PgPool,
     ) -> Result<bool, sqlx::Error> {
-        let result: i64 = sqlx::query_scalar("
-            SELECT count(1) from users
-                LEFT JOIN user_roles ON user_roles.user_id = users.id
-                LEFT JOIN role_permissions ON role_permissions.role_id = user_roles.role_id AND
-                        (role_permissions.permission = ANY($1))
-                LEFT JOIN user_permissions ON users.id = user_permissions.user_id AND
-                        (user_permissions.permission = ANY($1))
-                WHERE users.id = $2 AND ((user_permissions.permission = ANY($1)) OR (role_permissions.permission = ANY($1)))
-        ")
-                    .bind(scope)
-                    .bind(self.get_id()).fetch_one(database).await?;
+        let result: i64 = sqlx::query_scalar(HAS_ANY_PERMISSION_QUERY)
+            .bind(scope)
+            .bind(self.get_id())
+            .fetch_one(database)
+            .await?;
         Ok(result > 0)
     }
+    /// Checks if the user has the permission either directly or through one of their roles.
+    ///
+    /// [Permissions::Admin] is treated as a superset of all permissions
     #[instrument]
     fn has_permission(
         &self,
         permission: Permissions,
         database: &PgPool,
     ) -> impl Future<Output = Result<bool, DBError>> + Send {
-        async { Ok(true) }
+        let user_id = self.get_id();
+        async move {
+            let scopes = [Permissions::Admin, permission];
+            let result: i64 = sqlx::query_scalar(HAS_ANY_PERMISSION_QUERY)
+                .bind(&scopes[..])
+                .bind(user_id)
+                .fetch_one(database)
+                .await?;
+            Ok(result > 0)
+        }
     }
 }
+/// Counts the matching permissions a user has through `user_permissions` or `role_permissions`
+///
+/// - $1 The permissions to look for
+/// - $2 The user id
+const HAS_ANY_PERMISSION_QUERY: &str = "
+    SELECT count(1) from users
+        LEFT JOIN user_roles ON user_roles.user_id = users.id
+        LEFT JOIN role_permissions ON role_permissions.role_id = user_roles.role_id AND
+                (role_permissions.permission = ANY($1))
+        LEFT JOIN user_permissions ON users.id = user_permissions.user_id AND
+                (user_permissions.permission = ANY($1))
+        WHERE users.id = $2 AND ((user_permissions.permission = ANY($1)) OR (role_permissions.permission = ANY($1)))
+";
 #[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize, ToSchema, TableType)]
 #[table(name = "users")]
 pub struct User {
@@ -76,6 +96,8 @@ pub struct User {
     pub first_name: String,
     /// The last name of the user.
     pub last_name: String,
+    /// Deactivated users can not log in.
+    pub active: bool,
     pub updated_at: DateTime<FixedOffset>,
     pub created_at: DateTime<FixedOffset>,
 }
@@ -153,3 +175,133 @@ pub async fn find_user_by_id(id: i32, db: &sqlx::PgPool) -> DBResult<Option<User
 
     Ok(user)
 }
+#[cfg(test)]
+mod tests {
+    use super::{
+        new::NewUser,
+        roles::{Roles, UserRoles},
+        *,
+    };
+    use crate::utils::testing::config::testing::{get_testing_db, no_db_connection};
+    /// Creates a user with a random username. Added to the role if one is given
+    async fn new_user_with_role(role: Option<&str>, database: &PgPool) -> anyhow::Result<User> {
+        let username = format!("permission_test_{}", rand::random::<u32>());
+        let user = NewUser {
+            email: format!("{username}@example.com"),
+            username,
+            first_name: "Permission".to_owned(),
+            last_name: "Test".to_owned(),
+        }
+        .insert_return_user(database)
+        .await?;
+        if let Some(role) = role {
+            let role = Roles::get_role_by_name(role, database)
+                .await?
+                .expect("Default role should exist");
+            UserRoles::add_user_role(user.id, role.id, database).await?;
+        }
+        Ok(user)
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn permissions_are_inherited_from_roles() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let clinician = new_user_with_role(Some("Clinician"), &database).await?;
+        assert!(
+            clinician
+                .has_permission(Permissions::ReadParticipants, &database)
+                .await?
+        );
+        assert!(
+            clinician
+                .has_permission(Permissions::UpdateParticipants, &database)
+                .await?
+        );
+        // Research queries are not part of the Clinician role
+        assert!(
+            !clinician
+                .has_permission(Permissions::ResearchQuery, &database)
+                .await?
+        );
+        assert!(
+            !clinician
+                .has_permission(Permissions::ManageUsers, &database)
+                .await?
+        );
+
+        let researcher = new_user_with_role(Some("Researcher"), &database).await?;
+        assert!(
+            researcher
+                .has_permission(Permissions::ResearchQuery, &database)
+                .await?
+        );
+        assert!(
+            !researcher
+                .has_permission(Permissions::ReadParticipants, &database)
+                .await?
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn admin_has_every_permission() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let admin = new_user_with_role(Some("Admin"), &database).await?;
+        for permission in [
+            Permissions::ManageUsers,
+            Permissions::SyncRedCap,
+            Permissions::ReadParticipants,
+            Permissions::UpdateParticipants,
+            Permissions::ResearchQuery,
+        ] {
+            assert!(
+                admin.has_permission(permission, &database).await?,
+                "Admin should have {permission:?}"
+            );
+        }
+        Ok(())
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn user_permissions_without_a_role() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let user = new_user_with_role(None, &database).await?;
+        assert!(
+            !user
+                .has_permission(Permissions::ResearchQuery, &database)
+                .await?
+        );
+
+        sqlx::query("INSERT INTO user_permissions(user_id, permission) VALUES ($1, $2)")
+            .bind(user.id)
+            .bind(Permissions::ResearchQuery)
+            .execute(&database)
+            .await?;
+        assert!(
+            user.has_permission(Permissions::ResearchQuery, &database)
+                .await?
+        );
+        assert!(
+            !user
+                .has_permission(Permissions::ReadParticipants, &database)
+                .await?
+        );
+        assert!(
+            user.does_user_have_any_scope(
+                &[Permissions::ReadParticipants, Permissions::ResearchQuery],
+                &database
+            )
+            .await?
+        );
+        Ok(())
+    }
+}
@@ -46,15 +46,23 @@ impl NewUser {
         Ok(user)
     }
 }
-#[instrument]
+/// Sets the password hash of the user.
+///
+/// If `requires_reset` is true the user must change the password after their next login
+#[instrument(skip(password, database))]
 pub async fn create_or_update_user_password(
     user_id: i32,
     password: &str,
+    requires_reset: bool,
     database: impl Executor<'_, Database = sqlx::Postgres>,
 ) -> DBResult<()> {
     InsertQueryBuilder::new(UserPasswordAuthentication::table_name())
         .insert(UserPasswordAuthenticationColumn::UserId, user_id)
         .insert(UserPasswordAuthenticationColumn::Password, password)
+        .insert(
+            UserPasswordAuthenticationColumn::RequiresReset,
+            requires_reset,
+        )
         .on_conflict(
             ConflictTarget::Constraint("unique_user_id_password"),
             ConflictActionBuilder::do_update()
@@ -63,10 +71,7 @@ pub async fn create_or_update_user_password(
                     UserPasswordAuthenticationColumn::UpdatedAt.dyn_column(),
                     DynExpr::new(SqlFunctionBuilder::now()),
                 )
-                .set_column(
-                    UserPasswordAuthenticationColumn::RequiresReset.dyn_column(),
-                    DynExpr::new(false),
-                ),
+                .set_column_to_excluded(UserPasswordAuthenticationColumn::RequiresReset),
         )
         .query()
         .execute(database)
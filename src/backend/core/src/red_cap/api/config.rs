@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// The Red Cap API used by VCU
+pub const DEFAULT_RED_CAP_URL: &str = "https://redcap.vcu.edu/api/";
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedCapClientConfig {
+    /// The url of the Red Cap API
+    ///
+    /// Defaults to [DEFAULT_RED_CAP_URL]
+    pub url: String,
+    /// The Red Cap API token
+    pub token: Option<String>,
+    /// How many seconds to wait for Red Cap to respond
+    pub timeout: u64,
+    /// How many seconds to wait for a connection to Red Cap
+    pub connect_timeout: u64,
+    /// How many times a request is retried after a server error or a network error
+    pub max_retries: u32,
+    /// Milliseconds to wait before the first retry. Doubled after every retry
+    pub retry_delay: u64,
+}
+impl Default for RedCapClientConfig {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_RED_CAP_URL.to_owned(),
+            token: None,
+            timeout: 60,
+            connect_timeout: 10,
+            max_retries: 3,
+            retry_delay: 500,
+        }
+    }
+}
+impl RedCapClientConfig {
+    pub fn with_token(token: impl Into<String>) -> Self {
+        Self {
+            token: Some(token.into()),
+            ..Default::default()
+        }
+    }
+    /// Uses the token of the old top level `red_cap_token` key. `token` is kept if both are set
+    pub fn apply_deprecated_token(&mut self, red_cap_token: Option<String>) {
+        let Some(red_cap_token) = red_cap_token else {
+            return;
+        };
+        warn!("`red_cap_token` is deprecated. Move it to `token` under `[red_cap]`");
+        if self.token.is_none() {
+            self.token = Some(red_cap_token);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::RedCapClientConfig;
+
+    #[test]
+    pub fn deprecated_token_is_used_if_token_is_not_set() {
+        let mut config = RedCapClientConfig::default();
+        config.apply_deprecated_token(Some("OLD".to_owned()));
+        assert_eq!(config.token.as_deref(), Some("OLD"));
+
+        let mut config = RedCapClientConfig::with_token("NEW");
+        config.apply_deprecated_token(Some("OLD".to_owned()));
+        assert_eq!(config.token.as_deref(), Some("NEW"));
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+use crate::database::red_cap::questions::QuestionType;
+
+use super::{GenericError, RedCapParseError};
+
+/// The type of a field in the Red Cap data dictionary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum RedCapFieldType {
+    Text,
+    Notes,
+    Dropdown,
+    Radio,
+    Checkbox,
+    #[serde(rename = "yesno")]
+    #[strum(serialize = "yesno")]
+    YesNo,
+    #[serde(rename = "truefalse")]
+    #[strum(serialize = "truefalse")]
+    TrueFalse,
+    Calc,
+    File,
+    Slider,
+    Descriptive,
+    Sql,
+    #[serde(other)]
+    Unknown,
+}
+impl RedCapFieldType {
+    /// Can a question of this type be stored in a field of this type
+    pub fn is_compatible_with(&self, question_type: QuestionType) -> bool {
+        use RedCapFieldType::*;
+        match question_type {
+            QuestionType::MultiCheckBox => matches!(self, Checkbox),
+            QuestionType::Radio => matches!(self, Radio | Dropdown),
+            // Some yes/no questions are radios with a custom true value. See BooleanQuestionSettings
+            QuestionType::Boolean => matches!(self, YesNo | TrueFalse | Radio | Dropdown),
+            QuestionType::Number => matches!(self, Text | Slider | Calc),
+            QuestionType::Float => matches!(self, Text | Calc),
+            QuestionType::Text => matches!(self, Text | Notes),
+        }
+    }
+    /// Does the field hold a value. Descriptive fields are only text shown on the form
+    pub fn has_value(&self) -> bool {
+        !matches!(self, Self::Descriptive)
+    }
+}
+/// A choice of a radio, dropdown or checkbox field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedCapChoice {
+    pub index: i32,
+    pub label: String,
+}
+/// A field in the Red Cap data dictionary. Exported with `content=metadata`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedCapMetadataField {
+    pub field_name: String,
+    pub form_name: String,
+    #[serde(default)]
+    pub section_header: String,
+    pub field_type: RedCapFieldType,
+    #[serde(default)]
+    pub field_label: String,
+    /// The choices for radio, dropdown and checkbox fields. The equation for calc fields
+    #[serde(default)]
+    pub select_choices_or_calculations: String,
+    #[serde(default)]
+    pub field_note: String,
+    /// The validation of text fields. Such as `integer`, `number` or `date_ymd`
+    #[serde(default)]
+    pub text_validation_type_or_show_slider_number: String,
+    #[serde(default)]
+    pub branching_logic: String,
+    /// `y` if the field is required
+    #[serde(default)]
+    pub required_field: String,
+}
+impl RedCapMetadataField {
+    pub fn is_required(&self) -> bool {
+        self.required_field.eq_ignore_ascii_case("y")
+    }
+    /// Parses the choices of radio, dropdown and checkbox fields
+    ///
+    /// Red Cap stores them as `1, Yes | 2, No`
+    pub fn choices(&self) -> Result<Vec<RedCapChoice>, RedCapParseError> {
+        if !matches!(
+            self.field_type,
+            RedCapFieldType::Radio | RedCapFieldType::Dropdown | RedCapFieldType::Checkbox
+        ) {
+            return Ok(Vec::new());
+        }
+        parse_choices(&self.select_choices_or_calculations)
+    }
+    /// The question type best matching this field. None if the field does not hold an answer
+    pub fn suggested_question_type(&self) -> Option<QuestionType> {
+        let question_type = match self.field_type {
+            RedCapFieldType::Checkbox => QuestionType::MultiCheckBox,
+            RedCapFieldType::Radio | RedCapFieldType::Dropdown => QuestionType::Radio,
+            RedCapFieldType::YesNo | RedCapFieldType::TrueFalse => QuestionType::Boolean,
+            RedCapFieldType::Slider => QuestionType::Number,
+            RedCapFieldType::Text => match self.text_validation_type_or_show_slider_number.as_str()
+            {
+                "integer" => QuestionType::Number,
+                validation if validation.starts_with("number") => QuestionType::Float,
+                _ => QuestionType::Text,
+            },
+            RedCapFieldType::Notes => QuestionType::Text,
+            _ => return None,
+        };
+        Some(question_type)
+    }
+}
+/// Parses choices in the format `1, Yes | 2, No`
+pub fn parse_choices(choices: &str) -> Result<Vec<RedCapChoice>, RedCapParseError> {
+    choices
+        .split('|')
+        .map(str::trim)
+        .filter(|choice| !choice.is_empty())
+        .map(|choice| {
+            let Some((index, label)) = choice.split_once(',') else {
+                return Err(RedCapParseError::InvalidChoice {
+                    input: choice.to_owned(),
+                    reason: GenericError::Other("Missing `,` between index and label".to_owned()),
+                });
+            };
+            let index = index
+                .trim()
+                .parse()
+                .map_err(|err| RedCapParseError::InvalidChoice {
+                    input: choice.to_owned(),
+                    reason: GenericError::ParseNumber(err),
+                })?;
+            Ok(RedCapChoice {
+                index,
+                label: label.trim().to_owned(),
+            })
+        })
+        .collect()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parse_field() -> anyhow::Result<()> {
+        let field: RedCapMetadataField = serde_json::from_str(
+            r#"{
+                "field_name": "ercall",
+                "form_name": "case_note",
+                "section_header": "",
+                "field_type": "radio",
+                "field_label": "Was 911 called for immediate assistance?",
+                "select_choices_or_calculations": "0, No- not required | 1, Yes, and taken to hospital in ambulance|2, Yes, but refused ambulance",
+                "field_note": "",
+                "text_validation_type_or_show_slider_number": "",
+                "branching_logic": "",
+                "required_field": "y",
+                "matrix_group_name": ""
+            }"#,
+        )?;
+        assert_eq!(field.field_type, RedCapFieldType::Radio);
+        assert!(field.is_required());
+        assert_eq!(field.suggested_question_type(), Some(QuestionType::Radio));
+        assert_eq!(
+            field.choices()?,
+            vec![
+                RedCapChoice {
+                    index: 0,
+                    label: "No- not required".to_owned()
+                },
+                RedCapChoice {
+                    index: 1,
+                    label: "Yes, and taken to hospital in ambulance".to_owned()
+                },
+                RedCapChoice {
+                    index: 2,
+                    label: "Yes, but refused ambulance".to_owned()
+                },
+            ]
+        );
+        Ok(())
+    }
+    #[test]
+    pub fn unknown_field_type() -> anyhow::Result<()> {
+        let field_type: RedCapFieldType = serde_json::from_str(r#""new_type""#)?;
+        assert_eq!(field_type, RedCapFieldType::Unknown);
+        Ok(())
+    }
+    #[test]
+    pub fn invalid_choice() {
+        assert!(parse_choices("1, Yes | No").is_err());
+        assert!(parse_choices("a, Yes").is_err());
+    }
+}
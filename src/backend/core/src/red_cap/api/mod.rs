@@ -1,13 +1,21 @@
-use ahash::{HashMap, HashMapExt};
+use ahash::{HashMap, HashMapExt, HashSet};
+use chrono::{DateTime, FixedOffset, Local};
 use reqwest::{
     Response, StatusCode, Url,
     header::{CONTENT_TYPE, HeaderValue},
 };
+use serde::Deserialize;
 use serde_json::Value;
-use std::{fmt::Debug, num::ParseIntError};
+use std::{fmt::Debug, num::ParseIntError, time::Duration};
 use thiserror::Error;
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
+
+use crate::database::red_cap::sync_events::RedCapSyncInitiator;
+mod config;
+mod metadata;
 mod request;
+pub use config::*;
+pub use metadata::*;
 pub use request::*;
 pub mod responses;
 pub mod utils;
@@ -19,6 +27,8 @@ pub enum RedCapParseError {
     MissingField { field: String },
     #[error("Not a valid checkbox key: {0}")]
     NotAValidCheckBoxKey(String),
+    #[error("Invalid choice: {input:?}, reason: {reason:?}")]
+    InvalidChoice { input: String, reason: GenericError },
 }
 #[derive(Debug, Error)]
 pub enum GenericError {
@@ -39,6 +49,43 @@ pub enum RedCapAPIError {
 
     #[error("{0}")]
     BadStatus(StatusCode),
+    #[error("Invalid Red Cap URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("No Red Cap token was provided")]
+    MissingToken,
+    /// Red Cap responded with an error message
+    #[error("Red Cap returned an error ({status}): {message}")]
+    RedCap { status: StatusCode, message: String },
+    #[error("Red Cap imported {imported} of the {expected} records sent")]
+    IncompleteImport { expected: usize, imported: usize },
+}
+/// The body Red Cap responds with when a request fails
+#[derive(Debug, Deserialize)]
+struct RedCapErrorResponse {
+    error: String,
+}
+impl RedCapErrorResponse {
+    fn parse(body: &str) -> Option<Self> {
+        if !body.trim_start().starts_with('{') {
+            return None;
+        }
+        serde_json::from_str(body).ok()
+    }
+}
+/// What a failed request may be retried on.
+///
+/// Connection errors and server errors are always retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestRetry {
+    /// Sending the request again has the same result. Timeouts are retried
+    Idempotent,
+    /// Red Cap may have handled the request before it timed out. Timeouts are not retried
+    NotIdempotent,
+}
+impl RequestRetry {
+    fn retry_timeouts(self) -> bool {
+        self == Self::Idempotent
+    }
 }
 const CONTENT_TYPE_VALUE: HeaderValue =
     HeaderValue::from_static("application/x-www-form-urlencoded");
@@ -48,44 +95,122 @@ pub struct RedcapClient {
     pub token: String,
     pub client: reqwest::Client,
     api_url: Url,
+    /// Recorded in the sync audit log for every import and export
+    initiator: RedCapSyncInitiator,
+    max_retries: u32,
+    retry_delay: Duration,
 }
 impl RedcapClient {
+    /// Connects to the default Red Cap API with the given token
     pub async fn new(token: impl Into<String>) -> Result<Self, RedCapAPIError> {
+        Self::with_config(&RedCapClientConfig::with_token(token)).await
+    }
+    /// Connects to the Red Cap API described by the config.
+    ///
+    /// The connection is checked by requesting the Red Cap version
+    pub async fn with_config(config: &RedCapClientConfig) -> Result<Self, RedCapAPIError> {
+        let token = config.token.clone().ok_or(RedCapAPIError::MissingToken)?;
         let client = Self {
-            token: token.into(),
-            client: reqwest::Client::default(),
-            api_url: Url::parse("https://redcap.vcu.edu/api/").unwrap(),
+            token,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout))
+                .connect_timeout(Duration::from_secs(config.connect_timeout))
+                .build()?,
+            api_url: Url::parse(&config.url)?,
+            initiator: RedCapSyncInitiator::default(),
+            max_retries: config.max_retries,
+            retry_delay: Duration::from_millis(config.retry_delay),
         };
         client.get_version().await?;
 
         Ok(client)
     }
+    /// Sets who is responsible for the requests made by this client
+    pub fn with_initiator(mut self, initiator: RedCapSyncInitiator) -> Self {
+        self.initiator = initiator;
+        self
+    }
+    pub fn initiator(&self) -> &RedCapSyncInitiator {
+        &self.initiator
+    }
     fn create_request_map(&self) -> HashMap<&str, &str> {
         let mut map = HashMap::new();
         map.insert("token", self.token.as_str());
         map
     }
-    async fn perform_request(&self, map: HashMap<&str, &str>) -> Result<Response, RedCapAPIError> {
-        let request = self
-            .client
-            .post(self.api_url.clone())
-            .header(CONTENT_TYPE, CONTENT_TYPE_VALUE)
-            .form(&map)
-            .build()?;
-        Ok(self.client.execute(request).await?)
+    /// Sends the request and returns the body of the response.
+    ///
+    /// Failed requests are retried with an exponential backoff. See [RequestRetry] for what is retried.
+    /// Error bodies are returned as [RedCapAPIError::RedCap]
+    async fn perform_request(
+        &self,
+        map: HashMap<&str, &str>,
+        retry: RequestRetry,
+    ) -> Result<String, RedCapAPIError> {
+        let mut attempt = 0;
+        loop {
+            let request = self
+                .client
+                .post(self.api_url.clone())
+                .header(CONTENT_TYPE, CONTENT_TYPE_VALUE)
+                .form(&map)
+                .build()?;
+            let result = self.client.execute(request).await;
+            let should_retry = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(err) => err.is_connect() || (err.is_timeout() && retry.retry_timeouts()),
+            };
+            if should_retry && attempt < self.max_retries {
+                let delay = self
+                    .retry_delay
+                    .saturating_mul(2u32.saturating_pow(attempt));
+                let status = result.as_ref().ok().map(Response::status);
+                let err = result.as_ref().err();
+                warn!(
+                    ?status,
+                    ?err,
+                    ?attempt,
+                    ?delay,
+                    "Red Cap request failed. Retrying"
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+            return Self::read_response(result?).await;
+        }
+    }
+    async fn read_response(response: Response) -> Result<String, RedCapAPIError> {
+        let status = response.status();
+        let body = response.text().await?;
+        if let Some(RedCapErrorResponse { error }) = RedCapErrorResponse::parse(&body) {
+            return Err(RedCapAPIError::RedCap {
+                status,
+                message: error,
+            });
+        }
+        if !status.is_success() {
+            return Err(RedCapAPIError::BadStatus(status));
+        }
+        Ok(body)
     }
     #[instrument]
     pub async fn get_version(&self) -> Result<String, RedCapAPIError> {
         let mut map = self.create_request_map();
         map.insert("content", "version");
 
-        let response = self.perform_request(map).await?;
-        if response.status().is_success() {
-            let response = response.text().await?;
-            Ok(response)
-        } else {
-            Err(RedCapAPIError::BadStatus(response.status()))
-        }
+        self.perform_request(map, RequestRetry::Idempotent).await
+    }
+    /// Exports the data dictionary of the project
+    #[instrument]
+    pub async fn get_metadata(&self) -> Result<Vec<RedCapMetadataField>, RedCapAPIError> {
+        let mut map = self.create_request_map();
+        map.insert("content", "metadata");
+        map.insert("format", Format::Json.as_ref());
+
+        let response = self.perform_request(map, RequestRetry::Idempotent).await?;
+        let fields: Vec<RedCapMetadataField> = serde_json::from_str(&response)?;
+        Ok(fields)
     }
     #[instrument]
     pub async fn get_flat_json_forms(
@@ -94,11 +219,14 @@ impl RedcapClient {
             forms,
             records,
             fields,
+            date_range_begin,
         }: ExportOptions,
     ) -> Result<Vec<HashMap<String, Value>>, RedCapAPIError> {
         let forms_as_string = forms.map(|forms| forms.to_string());
         let records_as_string = records.map(|record| record.to_string());
         let fields_as_string = fields.map(|fields| fields.to_string());
+        let date_range_begin_as_string =
+            date_range_begin.map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string());
         let mut map = self.create_request_map();
         map.insert("content", "record");
         map.insert("action", "export");
@@ -113,22 +241,53 @@ impl RedcapClient {
         if let Some(records) = records_as_string.as_deref() {
             map.insert("records", records);
         }
+        if let Some(date_range_begin) = date_range_begin_as_string.as_deref() {
+            map.insert("dateRangeBegin", date_range_begin);
+        }
 
-        let response = self.perform_request(map).await?;
-        let response = response.text().await?;
+        let response = self.perform_request(map, RequestRetry::Idempotent).await?;
 
         // Why? Redcap made everything a string. Except for one field....
         let records: Vec<HashMap<String, Value>> = serde_json::from_str(&response)?;
         Ok(records)
     }
 
+    /// Returns the ids of the records created or modified after `changed_since`. All records if None
+    ///
+    /// `changed_since` is converted to the local time zone. Red Cap expects the time in the server's time zone
+    #[instrument]
+    pub async fn get_record_ids(
+        &self,
+        changed_since: Option<DateTime<FixedOffset>>,
+    ) -> Result<Vec<i32>, RedCapAPIError> {
+        let records = self
+            .get_flat_json_forms(ExportOptions {
+                fields: Some(vec![Fields::RecordID].into()),
+                date_range_begin: changed_since
+                    .map(|changed_since| changed_since.with_timezone(&Local).naive_local()),
+                ..Default::default()
+            })
+            .await?;
+        let mut record_ids: Vec<i32> = records
+            .iter()
+            .filter_map(|record| record.get("record_id"))
+            .filter_map(|record_id| match record_id {
+                Value::String(record_id) => record_id.parse().ok(),
+                Value::Number(record_id) => record_id.as_i64().map(|record_id| record_id as i32),
+                _ => None,
+            })
+            .collect();
+        // Repeating instruments return a row per instance
+        record_ids.sort_unstable();
+        record_ids.dedup();
+        Ok(record_ids)
+    }
     #[instrument]
     pub async fn get_next_record_id(&self) -> Result<i32, RedCapAPIError> {
         let mut map = self.create_request_map();
         map.insert("content", "generateNextRecordName");
 
-        let response = self.perform_request(map).await?;
-        let response = response.text().await?;
+        let response = self.perform_request(map, RequestRetry::Idempotent).await?;
         let next_number: i32 = response.parse()?;
         Ok(next_number)
     }
@@ -146,16 +305,20 @@ impl RedcapClient {
         map.insert("action", "delete");
         map.insert("records", &records);
 
-        let response = self.perform_request(map).await?;
-        let response = response.text().await?;
-        println!("{}", response);
+        let response = self.perform_request(map, RequestRetry::Idempotent).await?;
+        debug!(%response, "Deleted records");
         Ok(())
     }
+    /// Imports the records. Returns the response of Red Cap. The ids of the imported records
+    ///
+    /// Fails with [RedCapAPIError::IncompleteImport] if Red Cap did not import every record that was sent.
+    ///
+    /// Timeouts are not retried if a record has a `"new"` repeat instance
     #[instrument]
     pub async fn import_records(
         &self,
         records: Vec<HashMap<String, String>>,
-    ) -> Result<(), RedCapAPIError> {
+    ) -> Result<String, RedCapAPIError> {
         let records_json = serde_json::to_string(&records)?;
         if tracing::enabled!(tracing::Level::TRACE) {
             debug!("{}", records_json);
@@ -169,21 +332,52 @@ impl RedcapClient {
         map.insert("dataFormat", "YMD");
         map.insert("returnContent", "ids");
 
-        let response = self.perform_request(map).await?;
-        let response = response.text().await?;
-        println!("{}", response);
-        Ok(())
+        // Red Cap gives every "new" instance a new number. Sending them again could duplicate them
+        let retry = if records.iter().any(|record| {
+            record
+                .get("redcap_repeat_instance")
+                .is_some_and(|instance| instance == "new")
+        }) {
+            RequestRetry::NotIdempotent
+        } else {
+            RequestRetry::Idempotent
+        };
+        let response = self.perform_request(map, retry).await?;
+        debug!(%response, "Imported records");
+        let expected = records
+            .iter()
+            .filter_map(|record| record.get("record_id"))
+            .collect::<HashSet<_>>()
+            .len();
+        let imported: Vec<Value> = serde_json::from_str(&response)?;
+        if imported.len() != expected {
+            return Err(RedCapAPIError::IncompleteImport {
+                expected,
+                imported: imported.len(),
+            });
+        }
+        Ok(response)
     }
 }
 #[cfg(test)]
 mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicU32, Ordering},
+        },
+        time::Duration,
+    };
 
-    use anyhow::Context;
+    use ahash::{HashMap, HashMapExt};
+    use reqwest::Url;
+    use tokio::net::TcpListener;
     use tracing::warn;
 
     use crate::{
+        database::red_cap::sync_events::RedCapSyncInitiator,
         red_cap::{
-            api::{ExportOptions, Fields, Forms, RedcapClient},
+            api::{ExportOptions, Fields, Forms, RedCapAPIError, RedcapClient},
             converter::{
                 RedCapConverter,
                 case_notes::{OtherCaseNoteData, RedCapCaseNoteBase, RedCapHealthMeasures},
@@ -197,6 +391,58 @@ mod tests {
         },
         utils::testing::config::testing::{get_testing_config, no_testing_config},
     };
+    /// A server that accepts connections but never answers. Returns the API URL and the number of connections
+    async fn unresponsive_server() -> anyhow::Result<(Url, Arc<AtomicU32>)> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let url = Url::parse(&format!("http://{}/api/", listener.local_addr()?))?;
+        let connections = Arc::new(AtomicU32::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                open.push(stream);
+            }
+        });
+        Ok((url, connections))
+    }
+    #[tokio::test]
+    pub async fn timed_out_imports_of_new_instances_are_not_retried() -> anyhow::Result<()> {
+        let (api_url, connections) = unresponsive_server().await?;
+        let client = RedcapClient {
+            token: "TOKEN".to_owned(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_millis(200))
+                .build()?,
+            api_url,
+            initiator: RedCapSyncInitiator::default(),
+            max_retries: 2,
+            retry_delay: Duration::from_millis(10),
+        };
+        let mut case_note = HashMap::new();
+        case_note.insert("record_id".to_owned(), "1".to_owned());
+        case_note.insert(
+            "redcap_repeat_instrument".to_owned(),
+            "case_note".to_owned(),
+        );
+        case_note.insert("redcap_repeat_instance".to_owned(), "new".to_owned());
+
+        let err = client
+            .import_records(vec![case_note.clone()])
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, RedCapAPIError::Reqwest(err) if err.is_timeout()),
+            "{err:?}"
+        );
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        // Importing the same instance again is safe
+        case_note.insert("redcap_repeat_instance".to_owned(), "1".to_owned());
+        client.import_records(vec![case_note]).await.unwrap_err();
+        assert_eq!(connections.load(Ordering::SeqCst), 4);
+        Ok(())
+    }
 
     #[tokio::test]
     #[ignore]
@@ -206,7 +452,7 @@ mod tests {
             return Ok(());
         };
         config.init_logger();
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         let next_id = client.get_next_record_id().await.unwrap();
 
         println!("Next ID: {}", next_id);
@@ -222,7 +468,7 @@ mod tests {
             return Ok(());
         };
         config.init_logger();
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         let records = client
             .get_flat_json_forms(ExportOptions {
                 fields: Some(vec![Fields::RecordID].into()),
@@ -246,7 +492,7 @@ mod tests {
 
         let database = config.connect_to_db().await?;
         let mut converter = RedCapConverter::new(database).await?;
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
 
         let records = client
             .get_flat_json_forms(ExportOptions {
@@ -283,12 +529,13 @@ mod tests {
         config.init_logger();
         let database = config.connect_to_db().await?;
         let mut converter = RedCapConverter::new(database).await?;
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         let records = client
             .get_flat_json_forms(ExportOptions {
                 forms: Some(vec![Forms::CaseNotes].into()),
                 records: Some(vec![1].into()),
                 fields: Some(vec![Fields::RecordID].into()),
+                ..Default::default()
             })
             .await
             .unwrap();
@@ -318,7 +565,7 @@ mod tests {
             return Ok(());
         };
         config.init_logger();
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         let records = client
             .get_flat_json_forms(ExportOptions {
                 forms: Some(vec![Forms::WellnessGoals].into()),
@@ -345,7 +592,7 @@ mod tests {
             return Ok(());
         };
         config.init_logger();
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         let records = client
             .get_flat_json_forms(ExportOptions {
                 forms: Some(vec![Forms::Medications].into()),
@@ -1,5 +1,6 @@
 use std::fmt::Display;
 
+use chrono::NaiveDateTime;
 use serde::Serialize;
 use strum::{AsRefStr, Display, EnumString};
 
@@ -22,7 +23,7 @@ pub enum Fields {
     RecordID,
     Other(&'static str),
 }
-#[derive(Debug, EnumString, Display, Serialize, AsRefStr)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display, Serialize, AsRefStr)]
 #[strum(serialize_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum Format {
@@ -70,6 +71,10 @@ pub struct ExportOptions {
     pub forms: Option<ConcatVec<Forms>>,
     pub records: Option<ConcatVec<usize>>,
     pub fields: Option<ConcatVec<Fields>>,
+    /// Only export records created or modified after this time.
+    ///
+    /// Red Cap expects the time in the server's time zone
+    pub date_range_begin: Option<NaiveDateTime>,
 }
 
 impl ExportOptions {
@@ -86,6 +91,9 @@ impl ExportOptions {
         self.records.get_or_insert_with(Default::default)
     }
 
+    pub fn with_date_range_begin(&mut self, date_range_begin: NaiveDateTime) {
+        self.date_range_begin = Some(date_range_begin);
+    }
     pub fn add_form(&mut self, form: Forms) -> &mut Self {
         self.get_forms_mut().0.push(form);
         self
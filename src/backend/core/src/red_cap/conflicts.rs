@@ -0,0 +1,334 @@
+//! Finds fields that were changed both locally and in Red Cap since the last sync.
+//!
+//! Each field of the local rows is compared with the same field in Red Cap and in the snapshot taken at the last sync.
+//! - Only changed locally. The local value is pushed
+//! - Only changed in Red Cap. The value is pulled
+//! - Changed on both sides to different values. A [RedCapSyncConflict] is created and nothing is synced until an admin resolves it
+//!
+//! Records last synced before snapshots existed have no base. On their first sync the snapshot is seeded from Red Cap.
+//! So fields that only differ from Red Cap, such as by formatting, are pushed and are not conflicts.
+use ahash::{HashMap, HashMapExt};
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::database::red_cap::{
+    case_notes::CaseNote,
+    participants::{ParticipantSyncEntity, Participants},
+    sync_conflicts::{NewRedCapSyncConflict, RedCapConflictResolution, RedCapSyncConflict},
+};
+
+use super::{
+    converter::RedCapConverter,
+    tasks::{
+        RedCapTaskError,
+        push::{
+            ROW_KEYS, RedCapPayload, case_note_payloads, goals_payload, matching_row,
+            medications_payload, non_empty, participant_payload,
+        },
+    },
+};
+/// The local rows of a participant that changed since the last sync
+#[derive(Debug, Default)]
+pub struct LocalChanges {
+    /// Rows that already exist in Red Cap
+    pub rows: Vec<RedCapPayload>,
+    /// Case notes that have not been pushed yet. They can not conflict
+    pub new_case_notes: Vec<(CaseNote, RedCapPayload)>,
+}
+impl LocalChanges {
+    /// Builds the rows of the parts of the participant changed since the last sync
+    pub async fn for_participant(
+        participant: &Participants,
+        record_id: i32,
+        database: &PgPool,
+        converter: &mut RedCapConverter,
+    ) -> Result<Self, RedCapTaskError> {
+        let mut changes = Self::default();
+        for entity in ParticipantSyncEntity::changed_since_sync(participant.id, database).await? {
+            let row = match entity {
+                ParticipantSyncEntity::Participant => {
+                    participant_payload(participant, record_id, database, converter).await?
+                }
+                ParticipantSyncEntity::Medications => {
+                    medications_payload(participant, record_id, database).await?
+                }
+                ParticipantSyncEntity::Goals => {
+                    goals_payload(participant, record_id, database, converter).await?
+                }
+            };
+            changes.rows.push(row);
+        }
+        for (case_note, payload) in
+            case_note_payloads(participant, record_id, database, converter).await?
+        {
+            if case_note.red_cap_instance.is_none() {
+                changes.new_case_notes.push((case_note, payload));
+                continue;
+            }
+            if case_note.has_unsynced_changes() {
+                changes.rows.push(payload);
+            }
+        }
+        Ok(changes)
+    }
+}
+/// The instance Red Cap gives the next new case note of the exported record.
+///
+/// One more than the highest case note instance. Case notes created directly in Red Cap are counted too
+pub fn next_case_note_instance(remote: &[HashMap<String, Value>]) -> i32 {
+    remote
+        .iter()
+        .filter(|row| non_empty(row, "redcap_repeat_instrument").as_deref() == Some("case_note"))
+        .filter_map(|row| {
+            non_empty(row, "redcap_repeat_instance")?
+                .parse::<i32>()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0)
+        + 1
+}
+/// What to do with the local changes of a record
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MergePlan {
+    /// Rows to import into Red Cap. Each only holds the fields that should be pushed
+    pub push: Vec<RedCapPayload>,
+    pub conflicts: Vec<NewRedCapSyncConflict>,
+}
+type FieldKey<'a> = (Option<&'a str>, Option<&'a str>, &'a str);
+/// Compares the local rows with Red Cap and the snapshot of the last sync.
+///
+/// `resolutions` are resolved conflicts of the record. Their fields are pushed or kept as the resolution says
+pub fn plan_merge(
+    base: &[HashMap<String, Value>],
+    local: &[RedCapPayload],
+    remote: &[HashMap<String, Value>],
+    resolutions: &[RedCapSyncConflict],
+) -> MergePlan {
+    let resolutions: HashMap<FieldKey<'_>, &RedCapSyncConflict> = resolutions
+        .iter()
+        .map(|conflict| {
+            let key = (
+                conflict.repeat_instrument.as_deref(),
+                conflict.repeat_instance.as_deref(),
+                conflict.field.as_str(),
+            );
+            (key, conflict)
+        })
+        .collect();
+    let mut plan = MergePlan::default();
+    for row in local {
+        let repeat_instrument = row
+            .get("redcap_repeat_instrument")
+            .filter(|instrument| !instrument.is_empty())
+            .cloned();
+        let repeat_instance = repeat_instrument
+            .as_ref()
+            .and_then(|_| row.get("redcap_repeat_instance"))
+            .cloned();
+        let base_row = matching_row(base, &repeat_instrument, &repeat_instance);
+        let remote_row = matching_row(remote, &repeat_instrument, &repeat_instance);
+
+        let mut push = RedCapPayload::new();
+        for (field, local_value) in row {
+            if ROW_KEYS.contains(&field.as_str()) {
+                continue;
+            }
+            let local_value = Some(local_value.clone()).filter(|value| !value.is_empty());
+            let base_value = base_row.and_then(|row| non_empty(row, field));
+            let remote_value = remote_row.and_then(|row| non_empty(row, field));
+
+            let key = (
+                repeat_instrument.as_deref(),
+                repeat_instance.as_deref(),
+                field.as_str(),
+            );
+            if let Some(conflict) = resolutions.get(&key) {
+                match conflict.resolution {
+                    Some(RedCapConflictResolution::Local) => {
+                        push.insert(field.clone(), local_value.unwrap_or_default());
+                    }
+                    Some(RedCapConflictResolution::Manual) => {
+                        push.insert(
+                            field.clone(),
+                            conflict.resolved_value.clone().unwrap_or_default(),
+                        );
+                    }
+                    Some(RedCapConflictResolution::Remote) | None => {}
+                }
+                continue;
+            }
+            if local_value == base_value || local_value == remote_value {
+                // Unchanged locally or already the same. The pull brings in any change from Red Cap
+                continue;
+            }
+            if remote_value == base_value {
+                push.insert(field.clone(), local_value.unwrap_or_default());
+                continue;
+            }
+            plan.conflicts.push(NewRedCapSyncConflict {
+                repeat_instrument: repeat_instrument.clone(),
+                repeat_instance: repeat_instance.clone(),
+                field: field.clone(),
+                base_value,
+                local_value,
+                remote_value,
+            });
+        }
+        if !push.is_empty() {
+            for key in ROW_KEYS {
+                if let Some(value) = row.get(key) {
+                    push.insert(key.to_owned(), value.clone());
+                }
+            }
+            plan.push.push(push);
+        }
+    }
+    plan
+}
+#[cfg(test)]
+mod tests {
+    use ahash::{HashMap, HashMapExt};
+    use chrono::Local;
+    use serde_json::Value;
+
+    use super::{next_case_note_instance, plan_merge};
+    use crate::{
+        database::red_cap::sync_conflicts::{RedCapConflictResolution, RedCapSyncConflict},
+        red_cap::tasks::push::RedCapPayload,
+    };
+
+    fn payload(values: &[(&str, &str)]) -> RedCapPayload {
+        let mut row = HashMap::new();
+        for (key, value) in values {
+            row.insert(key.to_string(), value.to_string());
+        }
+        row
+    }
+    fn exported(values: &[(&str, &str)]) -> HashMap<String, Value> {
+        payload(values)
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect()
+    }
+
+    #[test]
+    pub fn changes_on_one_side_are_not_conflicts() {
+        let base = vec![exported(&[
+            ("record_id", "1"),
+            ("first_name", "John"),
+            ("last_name", "Doe"),
+        ])];
+        let remote = vec![exported(&[
+            ("record_id", "1"),
+            ("first_name", "John"),
+            ("last_name", "Smith"),
+        ])];
+        let local = vec![payload(&[
+            ("record_id", "1"),
+            ("first_name", "Johnny"),
+            ("last_name", "Doe"),
+        ])];
+        let plan = plan_merge(&base, &local, &remote, &[]);
+        assert!(plan.conflicts.is_empty());
+        assert_eq!(
+            plan.push,
+            vec![payload(&[("record_id", "1"), ("first_name", "Johnny")])]
+        );
+    }
+    #[test]
+    pub fn changes_on_both_sides_are_conflicts() {
+        let base = vec![exported(&[
+            ("record_id", "1"),
+            ("redcap_repeat_instrument", "case_note"),
+            ("redcap_repeat_instance", "2"),
+            ("reason", "Checkup"),
+        ])];
+        let remote = vec![exported(&[
+            ("record_id", "1"),
+            ("redcap_repeat_instrument", "case_note"),
+            ("redcap_repeat_instance", "2"),
+            ("reason", "Fall"),
+        ])];
+        let local = vec![payload(&[
+            ("record_id", "1"),
+            ("redcap_repeat_instrument", "case_note"),
+            ("redcap_repeat_instance", "2"),
+            ("reason", "Follow up"),
+        ])];
+        let plan = plan_merge(&base, &local, &remote, &[]);
+        assert!(plan.push.is_empty());
+        assert_eq!(plan.conflicts.len(), 1);
+        let conflict = &plan.conflicts[0];
+        assert_eq!(conflict.repeat_instance.as_deref(), Some("2"));
+        assert_eq!(conflict.field, "reason");
+        assert_eq!(conflict.base_value.as_deref(), Some("Checkup"));
+        assert_eq!(conflict.local_value.as_deref(), Some("Follow up"));
+        assert_eq!(conflict.remote_value.as_deref(), Some("Fall"));
+
+        // With an empty base any difference is a conflict
+        let plan = plan_merge(&[], &local, &remote, &[]);
+        assert_eq!(plan.conflicts.len(), 1);
+    }
+    #[test]
+    pub fn next_instance_follows_remote_case_notes() {
+        let mut remote = vec![exported(&[("record_id", "1"), ("first_name", "John")])];
+        assert_eq!(next_case_note_instance(&remote), 1);
+        for instance in ["1", "3"] {
+            remote.push(exported(&[
+                ("record_id", "1"),
+                ("redcap_repeat_instrument", "case_note"),
+                ("redcap_repeat_instance", instance),
+            ]));
+        }
+        remote.push(exported(&[
+            ("record_id", "1"),
+            ("redcap_repeat_instrument", "goals"),
+            ("redcap_repeat_instance", "7"),
+        ]));
+        assert_eq!(next_case_note_instance(&remote), 4);
+    }
+    #[test]
+    pub fn resolutions_are_applied() {
+        let remote = vec![exported(&[
+            ("record_id", "1"),
+            ("first_name", "John"),
+            ("last_name", "Smith"),
+        ])];
+        let local = vec![payload(&[
+            ("record_id", "1"),
+            ("first_name", "Johnny"),
+            ("last_name", "Doe"),
+        ])];
+        let resolved = |field: &str, resolution, resolved_value: Option<&str>| RedCapSyncConflict {
+            id: 1,
+            participant_id: 1,
+            repeat_instrument: None,
+            repeat_instance: None,
+            field: field.to_owned(),
+            base_value: None,
+            local_value: None,
+            remote_value: None,
+            resolution: Some(resolution),
+            resolved_value: resolved_value.map(str::to_owned),
+            resolved_by: None,
+            resolved_at: Some(Local::now().fixed_offset()),
+            applied_at: None,
+            created_at: Local::now().fixed_offset(),
+        };
+        let resolutions = vec![
+            resolved("first_name", RedCapConflictResolution::Remote, None),
+            resolved(
+                "last_name",
+                RedCapConflictResolution::Manual,
+                Some("Doe-Smith"),
+            ),
+        ];
+        let plan = plan_merge(&[], &local, &remote, &resolutions);
+        assert!(plan.conflicts.is_empty());
+        assert_eq!(
+            plan.push,
+            vec![payload(&[("record_id", "1"), ("last_name", "Doe-Smith")])]
+        );
+    }
+}
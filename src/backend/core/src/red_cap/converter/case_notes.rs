@@ -1,19 +1,30 @@
 use ahash::{HashMap, HashMapExt};
 use chrono::{Local, NaiveDate};
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use strum::IntoEnumIterator;
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    database::red_cap::{
-        case_notes::{
-            BloodPressureType, CaseNote, CaseNoteHealthMeasures, HealthMeasureBloodPressure,
-            new::{NewBloodPressure, NewCaseNote, NewCaseNoteHealthMeasures},
+    database::{
+        DBError,
+        red_cap::{
+            case_notes::{
+                BloodPressureType, CaseNote, CaseNoteHealthMeasures, HealthMeasureBloodPressure,
+                new::{NewBloodPressure, NewCaseNote, NewCaseNoteHealthMeasures},
+                questions::CaseNoteQuestionAnswers,
+            },
+            locations::RedCapLocationRules,
+            questions::{
+                AdditionalQuestionSettings, Question, QuestionDataValue, QuestionOptions,
+                QuestionType,
+            },
         },
-        locations::RedCapLocationRules,
-        questions::{Question, QuestionDataValue, QuestionOptions, QuestionType},
     },
-    red_cap::{MultiSelect, RedCapDataSet, RedCapExportDataType, RedCapType, VisitType},
+    red_cap::{
+        MultiSelect, RedCapDataSet, RedCapExportDataType, RedCapType, VisitType,
+        api::utils::CheckboxValue,
+    },
 };
 
 use super::{RedCapConverter, RedCapConverterError};
@@ -362,6 +373,127 @@ impl OtherCaseNoteData {
         }
         Ok(Self { values })
     }
+    /// Loads the question answers of a case note
+    pub async fn find_by_case_note_id(
+        case_note_id: i32,
+        database: &PgPool,
+    ) -> Result<Self, RedCapConverterError> {
+        let answers = CaseNoteQuestionAnswers::find_all_by_case_note_id(case_note_id, database)
+            .await
+            .map_err(DBError::from)?;
+        let mut values = HashMap::with_capacity(answers.len());
+        for answer in answers {
+            let question_id = answer.question_id;
+            let value = match answer.response_type {
+                QuestionType::Text => answer.value_text.map(QuestionDataValue::Text),
+                QuestionType::Number => answer.value_number.map(QuestionDataValue::Number),
+                QuestionType::Float => answer.value_float.map(QuestionDataValue::Float),
+                QuestionType::Boolean => answer.value_boolean.map(QuestionDataValue::Boolean),
+                QuestionType::Radio => {
+                    let option = match answer.value_radio {
+                        Some(option) => {
+                            QuestionOptions::find_option_with_id_and_in_question(
+                                option,
+                                question_id,
+                                database,
+                            )
+                            .await?
+                        }
+                        None => None,
+                    };
+                    option.map(|option| QuestionDataValue::Radio {
+                        option,
+                        other: answer.value_text,
+                    })
+                }
+                QuestionType::MultiCheckBox => {
+                    let options = answer.selected_options(database).await?;
+                    Some(QuestionDataValue::MultiCheckBox {
+                        options,
+                        other: answer.value_text,
+                    })
+                }
+            };
+            if let Some(value) = value {
+                values.insert(question_id, value);
+            }
+        }
+        Ok(Self { values })
+    }
+    /// The reverse of [OtherCaseNoteData::read]
+    ///
+    /// Multi check boxes are written with every option of the question. So unselected options are cleared in Red Cap
+    pub async fn write<D: RedCapDataSet>(
+        &self,
+        data: &mut D,
+        converter: &mut RedCapConverter,
+    ) -> Result<(), RedCapConverterError> {
+        for (question_id, value) in &self.values {
+            let Some(question) = Question::find_by_id(*question_id, &converter.database).await?
+            else {
+                warn!(?question_id, "Question Not Found");
+                continue;
+            };
+            let other = match value {
+                QuestionDataValue::Text(text) => {
+                    data.insert(&question.string_id, text.clone().into());
+                    None
+                }
+                QuestionDataValue::Number(number) => {
+                    data.insert(&question.string_id, (*number).into());
+                    None
+                }
+                QuestionDataValue::Float(float) => {
+                    data.insert(&question.string_id, (*float).into());
+                    None
+                }
+                QuestionDataValue::Boolean(value) => {
+                    let settings = match question.additional_options.as_deref() {
+                        Some(AdditionalQuestionSettings::Boolean(settings)) => settings.clone(),
+                        _ => Default::default(),
+                    };
+                    let value = if *value {
+                        settings.true_value
+                    } else {
+                        settings.false_value
+                    };
+                    data.insert(&question.string_id, value.into());
+                    None
+                }
+                QuestionDataValue::Radio { option, other } => {
+                    let Some(index) = option.red_cap_option_index else {
+                        warn!(?question, ?option, "Option does not have a Red Cap index");
+                        continue;
+                    };
+                    data.insert(&question.string_id, index.into());
+                    other.as_ref()
+                }
+                QuestionDataValue::MultiCheckBox { options, other } => {
+                    let mut multi_select = MultiSelect::new(&question.string_id);
+                    for option in
+                        QuestionOptions::get_all_in_question(question.id, &converter.database)
+                            .await?
+                    {
+                        let Some(index) = option.red_cap_option_index else {
+                            continue;
+                        };
+                        let checked = if options.iter().any(|selected| selected.id == option.id) {
+                            CheckboxValue::Checked
+                        } else {
+                            CheckboxValue::Unchecked
+                        };
+                        multi_select.insert(index, checked);
+                    }
+                    data.insert(&question.string_id, multi_select.into());
+                    other.as_ref()
+                }
+            };
+            if let (Some(other_id), Some(other)) = (&question.string_id_other, other) {
+                data.insert(other_id, other.clone().into());
+            }
+        }
+        Ok(())
+    }
 }
 
 async fn process_multiselect(
@@ -453,3 +585,86 @@ impl RedCapBloodPressureReadings {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use ahash::{HashMap, HashMapExt};
+    use serde_json::Value;
+
+    use super::OtherCaseNoteData;
+    use crate::{
+        database::red_cap::questions::{
+            Question, QuestionDataValue, QuestionForm, QuestionOptions,
+        },
+        red_cap::{
+            converter::RedCapConverter,
+            processing::{flatten_data_to_red_cap_format, process_flat_json},
+        },
+        utils::testing::config::testing::{get_testing_db, no_db_connection},
+    };
+
+    #[tokio::test]
+    pub async fn multi_check_box_answers_round_trip() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let Some(question) = Question::get_all_in_form(QuestionForm::CaseNotes, &database)
+            .await?
+            .into_iter()
+            .find(|question| {
+                question.question_type.is_multi_check_box() && question.string_id_other.is_some()
+            })
+        else {
+            eprintln!("No multi check box question with an other field");
+            return Ok(());
+        };
+        let selected: Vec<QuestionOptions> =
+            QuestionOptions::get_all_in_question(question.id, &database)
+                .await?
+                .into_iter()
+                .filter(|option| option.red_cap_option_index.is_some())
+                .take(2)
+                .collect();
+        if selected.is_empty() {
+            eprintln!("Question {} has no options in Red Cap", question.string_id);
+            return Ok(());
+        }
+        let mut values = HashMap::new();
+        values.insert(
+            question.id,
+            QuestionDataValue::MultiCheckBox {
+                options: selected.clone(),
+                other: Some("Other answer".to_owned()),
+            },
+        );
+        let answers = OtherCaseNoteData { values };
+
+        let mut converter = RedCapConverter::new(database).await?;
+        let mut data = HashMap::new();
+        answers.write(&mut data, &mut converter).await?;
+        let flat = flatten_data_to_red_cap_format(data);
+        assert!(flat.contains_key(&format!(
+            "{}___{}",
+            question.string_id,
+            selected[0].red_cap_option_index.unwrap()
+        )));
+
+        let flat = flat
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect();
+        let read = OtherCaseNoteData::read(&process_flat_json(flat), &mut converter).await?;
+        let Some(QuestionDataValue::MultiCheckBox { options, other }) =
+            read.values.get(&question.id)
+        else {
+            panic!("Expected a multi check box answer. Got {:?}", read.values);
+        };
+        let mut read_ids: Vec<i32> = options.iter().map(|option| option.id).collect();
+        let mut expected_ids: Vec<i32> = selected.iter().map(|option| option.id).collect();
+        read_ids.sort_unstable();
+        expected_ids.sort_unstable();
+        assert_eq!(read_ids, expected_ids);
+        assert_eq!(other.as_deref(), Some("Other answer"));
+        Ok(())
+    }
+}
@@ -0,0 +1,529 @@
+//! A local Red Cap API for testing.
+//!
+//! Implements the parts of the API used by [RedcapClient](super::api::RedcapClient).
+//! - `version`
+//! - `record` export, import and delete
+//! - `generateNextRecordName`
+//!
+//! Records are kept in memory. If [MockRedCapConfig::file] is set they are loaded from it on start and saved after every change.
+//!
+//! ## Differences from Red Cap
+//! - There is no project metadata. So exporting by `forms` only removes repeating instruments that were not requested.
+//! - Values are stored exactly as they are imported. Nothing is validated.
+use std::{
+    cmp::Ordering,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{self, AtomicU32},
+    },
+};
+
+use ahash::{HashMap, HashSet};
+use axum::{
+    Form, Json, Router,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use thiserror::Error;
+use tokio::{net::TcpListener, sync::RwLock, task::JoinHandle};
+use tracing::{debug, error, info};
+
+use super::api::RedCapClientConfig;
+
+/// The version reported by the mock
+pub const MOCK_RED_CAP_VERSION: &str = "14.0.0";
+const RECORD_ID: &str = "record_id";
+const REPEAT_INSTRUMENT: &str = "redcap_repeat_instrument";
+const REPEAT_INSTANCE: &str = "redcap_repeat_instance";
+const DATE_RANGE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+#[derive(Debug, Error)]
+pub enum MockRedCapError {
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockRedCapConfig {
+    /// The token clients must send
+    pub token: String,
+    /// Where to persist the records. Records are only kept in memory if None
+    pub file: Option<PathBuf>,
+}
+impl Default for MockRedCapConfig {
+    fn default() -> Self {
+        Self {
+            token: "MOCK-RED-CAP-TOKEN".to_owned(),
+            file: None,
+        }
+    }
+}
+/// A single row of a flat export.
+///
+/// Repeating instruments have a row per instance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MockRedCapRow {
+    pub modified_at: NaiveDateTime,
+    pub values: BTreeMap<String, String>,
+}
+impl MockRedCapRow {
+    fn value(&self, key: &str) -> &str {
+        self.values.get(key).map(String::as_str).unwrap_or_default()
+    }
+    pub fn record_id(&self) -> Option<i32> {
+        self.value(RECORD_ID).parse().ok()
+    }
+    pub fn repeat_instrument(&self) -> Option<&str> {
+        Some(self.value(REPEAT_INSTRUMENT)).filter(|value| !value.is_empty())
+    }
+    /// Rows are identified by the record id, repeating instrument and instance
+    fn is_same_row(&self, values: &HashMap<String, String>) -> bool {
+        [RECORD_ID, REPEAT_INSTRUMENT, REPEAT_INSTANCE]
+            .iter()
+            .all(|key| self.value(key) == values.get(*key).map(String::as_str).unwrap_or_default())
+    }
+    /// Red Cap lists the record first then its repeating instruments
+    fn export_order(&self, other: &Self) -> Ordering {
+        self.record_id()
+            .cmp(&other.record_id())
+            .then_with(|| self.repeat_instrument().cmp(&other.repeat_instrument()))
+            .then_with(|| {
+                let instance = |row: &Self| row.value(REPEAT_INSTANCE).parse::<i32>().ok();
+                instance(self).cmp(&instance(other))
+            })
+    }
+}
+#[derive(Debug)]
+struct MockRedCapState {
+    config: MockRedCapConfig,
+    rows: RwLock<Vec<MockRedCapRow>>,
+    /// Requests left to answer with a server error
+    failing_requests: AtomicU32,
+}
+impl MockRedCapState {
+    async fn save(&self, rows: &[MockRedCapRow]) -> Result<(), MockRedCapError> {
+        if let Some(file) = &self.config.file {
+            tokio::fs::write(file, serde_json::to_vec_pretty(rows)?).await?;
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Clone)]
+pub struct MockRedCap {
+    state: Arc<MockRedCapState>,
+}
+impl MockRedCap {
+    /// Creates the mock. Loading the records from [MockRedCapConfig::file] if it exists
+    pub async fn new(config: MockRedCapConfig) -> Result<Self, MockRedCapError> {
+        let rows = match &config.file {
+            Some(file) if file.exists() => serde_json::from_slice(&tokio::fs::read(file).await?)?,
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            state: Arc::new(MockRedCapState {
+                config,
+                rows: RwLock::new(rows),
+                failing_requests: AtomicU32::new(0),
+            }),
+        })
+    }
+    /// All rows currently stored
+    pub async fn rows(&self) -> Vec<MockRedCapRow> {
+        self.state.rows.read().await.clone()
+    }
+    /// Answers the next `count` requests with `503 Service Unavailable`. For testing retries
+    pub fn fail_next_requests(&self, count: u32) {
+        self.state
+            .failing_requests
+            .store(count, atomic::Ordering::SeqCst);
+    }
+    /// The API is served at `/api/` like Red Cap
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/api/", post(handle_request))
+            .with_state(self.state.clone())
+    }
+    /// Serves the mock on the address. Use port 0 to pick a free port
+    pub async fn start(self, address: SocketAddr) -> Result<RunningMockRedCap, MockRedCapError> {
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+        let router = self.router();
+        let handle = tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, router).await {
+                error!(?err, "Mock Red Cap stopped");
+            }
+        });
+        info!(?address, "Mock Red Cap started");
+        Ok(RunningMockRedCap {
+            address,
+            mock: self,
+            handle,
+        })
+    }
+}
+/// A mock that is being served. It is stopped when dropped
+#[derive(Debug)]
+pub struct RunningMockRedCap {
+    pub address: SocketAddr,
+    pub mock: MockRedCap,
+    handle: JoinHandle<()>,
+}
+impl RunningMockRedCap {
+    pub fn url(&self) -> String {
+        format!("http://{}/api/", self.address)
+    }
+    /// Config for a [RedcapClient](super::api::RedcapClient) that connects to this mock
+    pub fn client_config(&self) -> RedCapClientConfig {
+        RedCapClientConfig {
+            url: self.url(),
+            token: Some(self.mock.state.config.token.clone()),
+            ..Default::default()
+        }
+    }
+}
+impl Drop for RunningMockRedCap {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+type Params = HashMap<String, String>;
+
+fn error_response(status: StatusCode, error: impl Into<String>) -> Response {
+    (status, Json(json!({ "error": error.into() }))).into_response()
+}
+fn split_list(value: Option<&String>) -> Option<HashSet<&str>> {
+    value.map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect()
+    })
+}
+async fn handle_request(
+    State(state): State<Arc<MockRedCapState>>,
+    Form(params): Form<Params>,
+) -> Response {
+    let failing = state.failing_requests.fetch_update(
+        atomic::Ordering::SeqCst,
+        atomic::Ordering::SeqCst,
+        |count| count.checked_sub(1),
+    );
+    if failing.is_ok() {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "Red Cap is unavailable");
+    }
+    if params.get("token") != Some(&state.config.token) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "You do not have permissions to use the API",
+        );
+    }
+    let content = params
+        .get("content")
+        .map(String::as_str)
+        .unwrap_or_default();
+    let action = params.get("action").map(String::as_str).unwrap_or("export");
+    debug!(?content, ?action, "Mock Red Cap request");
+    let result = match (content, action) {
+        ("version", _) => Ok(MOCK_RED_CAP_VERSION.into_response()),
+        ("generateNextRecordName", _) => Ok(next_record_name(&state).await),
+        ("record", "export") => Ok(export_records(&state, &params).await),
+        ("record", "import") => import_records(&state, &params).await,
+        ("record", "delete") => delete_records(&state, &params).await,
+        _ => Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported request: content={content} action={action}"),
+        )),
+    };
+    result.unwrap_or_else(|err| {
+        error!(?err, "Mock Red Cap failed to save records");
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    })
+}
+async fn next_record_name(state: &MockRedCapState) -> Response {
+    let rows = state.rows.read().await;
+    let next = rows
+        .iter()
+        .filter_map(MockRedCapRow::record_id)
+        .max()
+        .unwrap_or_default()
+        + 1;
+    next.to_string().into_response()
+}
+async fn export_records(state: &MockRedCapState, params: &Params) -> Response {
+    let records = split_list(params.get("records"));
+    let forms = split_list(params.get("forms"));
+    let fields = split_list(params.get("fields"));
+    let date_range_begin = match params.get("dateRangeBegin") {
+        Some(value) => match NaiveDateTime::parse_from_str(value, DATE_RANGE_FORMAT) {
+            Ok(ok) => Some(ok),
+            Err(err) => {
+                return error_response(StatusCode::BAD_REQUEST, format!("dateRangeBegin: {err}"));
+            }
+        },
+        None => None,
+    };
+    let rows = state.rows.read().await;
+    // Red Cap returns every row of a record if any of them changed
+    let changed_records: Option<HashSet<i32>> = date_range_begin.map(|begin| {
+        rows.iter()
+            .filter(|row| row.modified_at >= begin)
+            .filter_map(MockRedCapRow::record_id)
+            .collect()
+    });
+    let mut exported: Vec<&MockRedCapRow> = rows
+        .iter()
+        .filter(|row| match &records {
+            Some(records) => records.contains(row.value(RECORD_ID)),
+            None => true,
+        })
+        .filter(|row| match (&changed_records, row.record_id()) {
+            (Some(changed), Some(record_id)) => changed.contains(&record_id),
+            (Some(_), None) => false,
+            (None, _) => true,
+        })
+        .filter(|row| match (&forms, row.repeat_instrument()) {
+            (Some(forms), Some(instrument)) => forms.contains(instrument),
+            _ => true,
+        })
+        .collect();
+    exported.sort_by(|a, b| a.export_order(b));
+
+    let exported: Vec<BTreeMap<&str, &str>> = exported
+        .into_iter()
+        .map(|row| {
+            let mut values: BTreeMap<&str, &str> = [RECORD_ID, REPEAT_INSTRUMENT, REPEAT_INSTANCE]
+                .into_iter()
+                .map(|key| (key, row.value(key)))
+                .collect();
+            for (key, value) in &row.values {
+                // Without forms there is nothing else to include besides the fields
+                let requested = match (&fields, &forms) {
+                    (Some(fields), None) => fields.contains(key.as_str()),
+                    _ => true,
+                };
+                if requested {
+                    values.insert(key, value);
+                }
+            }
+            values
+        })
+        .collect();
+    Json(exported).into_response()
+}
+async fn import_records(
+    state: &MockRedCapState,
+    params: &Params,
+) -> Result<Response, MockRedCapError> {
+    let Some(data) = params.get("data") else {
+        return Ok(error_response(StatusCode::BAD_REQUEST, "No data provided"));
+    };
+    let imported: Vec<Params> = match serde_json::from_str(data) {
+        Ok(ok) => ok,
+        Err(err) => return Ok(error_response(StatusCode::BAD_REQUEST, err.to_string())),
+    };
+    if let Some(row) = imported.iter().find(|row| {
+        row.get(RECORD_ID)
+            .is_none_or(|id| id.parse::<i32>().is_err())
+    }) {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            format!("Row does not have a valid record_id: {row:?}"),
+        ));
+    }
+    let now = Local::now().naive_local();
+    let mut rows = state.rows.write().await;
+    let mut ids: Vec<String> = Vec::new();
+    for values in imported {
+        let id = values.get(RECORD_ID).cloned().unwrap_or_default();
+        match rows.iter_mut().find(|row| row.is_same_row(&values)) {
+            Some(row) => {
+                // Blank values do not overwrite existing ones. Same as Red Cap's default overwrite behavior
+                for (key, value) in values {
+                    if !value.is_empty() {
+                        row.values.insert(key, value);
+                    }
+                }
+                row.modified_at = now;
+            }
+            None => rows.push(MockRedCapRow {
+                modified_at: now,
+                values: values
+                    .into_iter()
+                    .filter(|(_, value)| !value.is_empty())
+                    .collect(),
+            }),
+        }
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    state.save(&rows).await?;
+    let response = if params.get("returnContent").map(String::as_str) == Some("ids") {
+        Json(json!(ids)).into_response()
+    } else {
+        Json(json!({ "count": ids.len() })).into_response()
+    };
+    Ok(response)
+}
+async fn delete_records(
+    state: &MockRedCapState,
+    params: &Params,
+) -> Result<Response, MockRedCapError> {
+    let Some(records) = split_list(params.get("records")) else {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "No records provided",
+        ));
+    };
+    let mut rows = state.rows.write().await;
+    let deleted: HashSet<i32> = rows
+        .iter()
+        .filter(|row| records.contains(row.value(RECORD_ID)))
+        .filter_map(MockRedCapRow::record_id)
+        .collect();
+    rows.retain(|row| !records.contains(row.value(RECORD_ID)));
+    state.save(&rows).await?;
+    Ok(deleted.len().to_string().into_response())
+}
+#[cfg(test)]
+mod tests {
+    use ahash::{HashMap, HashMapExt};
+    use chrono::{Duration, Local};
+
+    use reqwest::StatusCode;
+
+    use super::{MockRedCap, MockRedCapConfig};
+    use crate::red_cap::api::{ExportOptions, Fields, Forms, RedCapAPIError, RedcapClient};
+
+    fn row(values: &[(&str, &str)]) -> HashMap<String, String> {
+        let mut row = HashMap::new();
+        for (key, value) in values {
+            row.insert(key.to_string(), value.to_string());
+        }
+        row
+    }
+
+    #[tokio::test]
+    pub async fn records_round_trip() -> anyhow::Result<()> {
+        let mock = MockRedCap::new(MockRedCapConfig::default())
+            .await?
+            .start(([127, 0, 0, 1], 0).into())
+            .await?;
+        let client = RedcapClient::with_config(&mock.client_config()).await?;
+
+        assert_eq!(client.get_next_record_id().await?, 1);
+        let before_import = Local::now().fixed_offset() - Duration::seconds(1);
+        client
+            .import_records(vec![
+                row(&[("record_id", "1"), ("first_name", "John")]),
+                row(&[
+                    ("record_id", "1"),
+                    ("redcap_repeat_instrument", "case_note"),
+                    ("redcap_repeat_instance", "1"),
+                    ("visit_date", "2025-01-01"),
+                ]),
+                row(&[("record_id", "2"), ("first_name", "Jane")]),
+            ])
+            .await?;
+        assert_eq!(client.get_next_record_id().await?, 3);
+        assert_eq!(client.get_record_ids(None).await?, vec![1, 2]);
+        assert_eq!(
+            client.get_record_ids(Some(before_import)).await?,
+            vec![1, 2]
+        );
+        let after_import = Local::now().fixed_offset() + Duration::seconds(1);
+        assert!(client.get_record_ids(Some(after_import)).await?.is_empty());
+
+        // Blank values do not overwrite
+        client
+            .import_records(vec![row(&[
+                ("record_id", "1"),
+                ("first_name", ""),
+                ("last_name", "Doe"),
+            ])])
+            .await?;
+        let records = client
+            .get_flat_json_forms(ExportOptions {
+                records: Some(vec![1].into()),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["first_name"], "John");
+        assert_eq!(records[0]["last_name"], "Doe");
+        assert_eq!(records[1]["redcap_repeat_instrument"], "case_note");
+
+        let case_notes = client
+            .get_flat_json_forms(ExportOptions {
+                forms: Some(vec![Forms::Medications].into()),
+                fields: Some(vec![Fields::RecordID].into()),
+                records: Some(vec![1].into()),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(case_notes.len(), 1, "Case notes were not requested");
+
+        client.delete_records([1].into_iter()).await?;
+        assert_eq!(client.get_record_ids(None).await?, vec![2]);
+        Ok(())
+    }
+    #[tokio::test]
+    pub async fn invalid_token_is_rejected() -> anyhow::Result<()> {
+        let mock = MockRedCap::new(MockRedCapConfig::default())
+            .await?
+            .start(([127, 0, 0, 1], 0).into())
+            .await?;
+        let mut config = mock.client_config();
+        config.token = Some("NOT-THE-TOKEN".to_owned());
+        let err = RedcapClient::with_config(&config).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                RedCapAPIError::RedCap {
+                    status: StatusCode::FORBIDDEN,
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    pub async fn server_errors_are_retried() -> anyhow::Result<()> {
+        let mock = MockRedCap::new(MockRedCapConfig::default())
+            .await?
+            .start(([127, 0, 0, 1], 0).into())
+            .await?;
+        let mut config = mock.client_config();
+        config.max_retries = 2;
+        config.retry_delay = 10;
+        let client = RedcapClient::with_config(&config).await?;
+
+        mock.mock.fail_next_requests(2);
+        assert_eq!(client.get_next_record_id().await?, 1);
+
+        mock.mock.fail_next_requests(3);
+        let err = client.get_next_record_id().await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                RedCapAPIError::RedCap {
+                    status: StatusCode::SERVICE_UNAVAILABLE,
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        Ok(())
+    }
+}
@@ -14,6 +14,11 @@ pub use enum_types::*;
 pub mod utils;
 
 pub mod api;
+#[cfg(any(test, feature = "mock-red-cap"))]
+pub mod mock;
+pub mod conflicts;
+pub mod question_diff;
+pub mod sync;
 // TODO: Use a faster hash map. It doesn't have to be DDOS resistant
 pub type RedCapDataMap = HashMap<String, RedCapExportDataType>;
 
@@ -301,7 +306,6 @@ impl RedCapExportDataType {
 
 #[cfg(test)]
 mod tests {
-    use anyhow::Context;
 
     use crate::utils::testing::config::testing::get_testing_config;
 
@@ -314,8 +318,7 @@ mod tests {
         testing_config.init_logger();
 
         let database = testing_config.connect_to_db().await?;
-        let client =
-            RedcapClient::new(testing_config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&testing_config.red_cap).await?;
 
         Ok((client, database))
     }
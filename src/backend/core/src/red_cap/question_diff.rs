@@ -0,0 +1,507 @@
+//! Compares the questions with the Red Cap data dictionary
+//!
+//! The string ids of questions and the red cap indexes of their options must match the fields in Red Cap.
+//! This finds the places where they have drifted apart.
+use std::{collections::BTreeMap, fmt::Display};
+
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::database::{
+    DBResult,
+    red_cap::questions::{
+        Question, QuestionForm, QuestionOptions, QuestionType,
+        default::{DefaultQuestionWithOptions, DefaultQuestions},
+        new::{NewQuestion, NewQuestionCategory, NewQuestionOptions},
+    },
+};
+
+use super::api::{Forms, RedCapChoice, RedCapFieldType, RedCapMetadataField};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuestionWithOptions {
+    pub question: Question,
+    pub options: Vec<QuestionOptions>,
+}
+impl QuestionWithOptions {
+    /// Loads every question and option that has not been removed
+    pub async fn get_all_active(conn: &PgPool) -> DBResult<Vec<Self>> {
+        let questions = Question::get_all_active(conn).await?;
+        let mut options: HashMap<i32, Vec<QuestionOptions>> = HashMap::new();
+        for option in QuestionOptions::get_all_active(conn).await? {
+            options.entry(option.question_id).or_default().push(option);
+        }
+        Ok(questions
+            .into_iter()
+            .map(|question| Self {
+                options: options.remove(&question.id).unwrap_or_default(),
+                question,
+            })
+            .collect())
+    }
+}
+/// A difference between a question and the Red Cap data dictionary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type")]
+pub enum QuestionDifference {
+    /// The question's string id is not a field in Red Cap
+    MissingField { question: String },
+    /// The question's other string id is not a field in Red Cap
+    MissingOtherField { question: String, other: String },
+    /// The field can not hold an answer of the question's type
+    TypeMismatch {
+        question: String,
+        question_type: QuestionType,
+        field_type: RedCapFieldType,
+    },
+    /// The choices of the field could not be parsed
+    InvalidChoices { question: String, error: String },
+    /// The choice at the option's index has a different label
+    RenamedChoice {
+        question: String,
+        index: i32,
+        option: String,
+        red_cap: String,
+    },
+    /// The option's label is in Red Cap under a different index
+    ChangedOptionIndex {
+        question: String,
+        option: String,
+        old: Option<i32>,
+        new: i32,
+    },
+    /// The option is not a choice in Red Cap
+    MissingChoice {
+        question: String,
+        option: String,
+        index: Option<i32>,
+    },
+    /// The Red Cap choice does not have an option
+    UnmappedChoice {
+        question: String,
+        index: i32,
+        label: String,
+    },
+}
+impl Display for QuestionDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuestionDifference::MissingField { question } => {
+                write!(f, "{question}: No field in Red Cap")
+            }
+            QuestionDifference::MissingOtherField { question, other } => {
+                write!(f, "{question}: Other field `{other}` is not in Red Cap")
+            }
+            QuestionDifference::TypeMismatch {
+                question,
+                question_type,
+                field_type,
+            } => write!(
+                f,
+                "{question}: {question_type} question but the Red Cap field is {field_type}"
+            ),
+            QuestionDifference::InvalidChoices { question, error } => {
+                write!(f, "{question}: Invalid choices in Red Cap. {error}")
+            }
+            QuestionDifference::RenamedChoice {
+                question,
+                index,
+                option,
+                red_cap,
+            } => write!(
+                f,
+                "{question}: Choice {index} is `{red_cap}` in Red Cap but `{option}` here"
+            ),
+            QuestionDifference::ChangedOptionIndex {
+                question,
+                option,
+                old,
+                new,
+            } => match old {
+                Some(old) => write!(
+                    f,
+                    "{question}: `{option}` has index {old} but is {new} in Red Cap"
+                ),
+                None => write!(
+                    f,
+                    "{question}: `{option}` has no index but is {new} in Red Cap"
+                ),
+            },
+            QuestionDifference::MissingChoice {
+                question,
+                option,
+                index,
+            } => match index {
+                Some(index) => write!(
+                    f,
+                    "{question}: `{option}` ({index}) is not a choice in Red Cap"
+                ),
+                None => write!(f, "{question}: `{option}` is not a choice in Red Cap"),
+            },
+            QuestionDifference::UnmappedChoice {
+                question,
+                index,
+                label,
+            } => write!(
+                f,
+                "{question}: Red Cap choice {index} `{label}` has no option"
+            ),
+        }
+    }
+}
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuestionDiff {
+    pub differences: Vec<QuestionDifference>,
+    /// Fields in forms containing questions that no question uses
+    pub unmapped_fields: Vec<RedCapMetadataField>,
+}
+impl QuestionDiff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty() && self.unmapped_fields.is_empty()
+    }
+}
+fn same_label(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+/// Compares the questions with the fields of the Red Cap data dictionary
+pub fn diff_questions(
+    fields: &[RedCapMetadataField],
+    questions: &[QuestionWithOptions],
+) -> QuestionDiff {
+    let fields_by_name: HashMap<&str, &RedCapMetadataField> = fields
+        .iter()
+        .map(|field| (field.field_name.as_str(), field))
+        .collect();
+    let mut used_fields = HashSet::new();
+    let mut forms_with_questions = HashSet::new();
+    let mut differences = Vec::new();
+
+    for QuestionWithOptions { question, options } in questions {
+        let Some(field) = fields_by_name.get(question.string_id.as_str()) else {
+            differences.push(QuestionDifference::MissingField {
+                question: question.string_id.clone(),
+            });
+            continue;
+        };
+        used_fields.insert(field.field_name.as_str());
+        forms_with_questions.insert(field.form_name.as_str());
+
+        if let Some(other) = &question.string_id_other {
+            if fields_by_name.contains_key(other.as_str()) {
+                used_fields.insert(other.as_str());
+            } else {
+                differences.push(QuestionDifference::MissingOtherField {
+                    question: question.string_id.clone(),
+                    other: other.clone(),
+                });
+            }
+        }
+        if !field.field_type.is_compatible_with(question.question_type) {
+            differences.push(QuestionDifference::TypeMismatch {
+                question: question.string_id.clone(),
+                question_type: question.question_type,
+                field_type: field.field_type,
+            });
+            continue;
+        }
+        if !matches!(
+            question.question_type,
+            QuestionType::Radio | QuestionType::MultiCheckBox
+        ) {
+            continue;
+        }
+        match field.choices() {
+            Ok(choices) => {
+                diff_options(&question.string_id, options, &choices, &mut differences);
+            }
+            Err(err) => differences.push(QuestionDifference::InvalidChoices {
+                question: question.string_id.clone(),
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    let unmapped_fields = fields
+        .iter()
+        .filter(|field| {
+            field.field_type.has_value()
+                && forms_with_questions.contains(field.form_name.as_str())
+                && !used_fields.contains(field.field_name.as_str())
+        })
+        .cloned()
+        .collect();
+    QuestionDiff {
+        differences,
+        unmapped_fields,
+    }
+}
+fn diff_options(
+    question: &str,
+    options: &[QuestionOptions],
+    choices: &[RedCapChoice],
+    differences: &mut Vec<QuestionDifference>,
+) {
+    let mut matched_choices = HashSet::new();
+    for option in options {
+        let by_index = option
+            .red_cap_option_index
+            .and_then(|index| choices.iter().find(|choice| choice.index == index));
+        if let Some(choice) = by_index
+            && same_label(&choice.label, &option.name)
+        {
+            matched_choices.insert(choice.index);
+            continue;
+        }
+        let by_label = choices
+            .iter()
+            .find(|choice| same_label(&choice.label, &option.name));
+        match (by_index, by_label) {
+            (_, Some(choice)) => {
+                matched_choices.insert(choice.index);
+                differences.push(QuestionDifference::ChangedOptionIndex {
+                    question: question.to_owned(),
+                    option: option.name.clone(),
+                    old: option.red_cap_option_index,
+                    new: choice.index,
+                });
+            }
+            (Some(choice), None) => {
+                matched_choices.insert(choice.index);
+                differences.push(QuestionDifference::RenamedChoice {
+                    question: question.to_owned(),
+                    index: choice.index,
+                    option: option.name.clone(),
+                    red_cap: choice.label.clone(),
+                });
+            }
+            (None, None) => differences.push(QuestionDifference::MissingChoice {
+                question: question.to_owned(),
+                option: option.name.clone(),
+                index: option.red_cap_option_index,
+            }),
+        }
+    }
+    for choice in choices {
+        if !matched_choices.contains(&choice.index) {
+            differences.push(QuestionDifference::UnmappedChoice {
+                question: question.to_owned(),
+                index: choice.index,
+                label: choice.label.clone(),
+            });
+        }
+    }
+}
+/// Creates a question file for each form of the unmapped fields.
+///
+/// Fields that can not hold an answer are skipped. Branching logic is not converted to requirement scripts.
+///
+/// Returns the name of the file and its contents
+pub fn generate_question_files(
+    unmapped_fields: &[RedCapMetadataField],
+) -> Vec<(String, DefaultQuestions)> {
+    let mut files: BTreeMap<&str, DefaultQuestions> = BTreeMap::new();
+    for field in unmapped_fields {
+        let Some(question_type) = field.suggested_question_type() else {
+            continue;
+        };
+        let options = field
+            .choices()
+            .ok()
+            .filter(|choices| !choices.is_empty())
+            .map(|choices| {
+                choices
+                    .into_iter()
+                    .map(|choice| NewQuestionOptions {
+                        question_id: None,
+                        name: choice.label,
+                        string_id: None,
+                        description: None,
+                        red_cap_option_index: Some(choice.index),
+                        additional_options: None,
+                    })
+                    .collect()
+            });
+        let file = files
+            .entry(field.form_name.as_str())
+            .or_insert_with(|| DefaultQuestions {
+                after: None,
+                category: generated_category(&field.form_name),
+                questions: Vec::new(),
+            });
+        file.questions.push(DefaultQuestionWithOptions {
+            question: NewQuestion {
+                category_id: None,
+                question_type,
+                required: field.is_required(),
+                question: field.field_label.trim().to_owned(),
+                string_id: field.field_name.clone(),
+                string_id_other: None,
+                requirements: None,
+                additional_options: None,
+            },
+            options,
+        });
+    }
+    files
+        .into_iter()
+        .map(|(form, questions)| (format!("red_cap_{form}.json"), questions))
+        .collect()
+}
+fn generated_category(form_name: &str) -> NewQuestionCategory {
+    let form = if form_name == Forms::CaseNotes.to_string() {
+        QuestionForm::CaseNotes
+    } else {
+        QuestionForm::ParticipantInfo
+    };
+    let name = form_name
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    NewQuestionCategory {
+        string_id: format!("red_cap_{form_name}"),
+        name,
+        description: Some("Generated from the Red Cap data dictionary".to_owned()),
+        form,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, field_type: RedCapFieldType, choices: &str) -> RedCapMetadataField {
+        RedCapMetadataField {
+            field_name: name.to_owned(),
+            form_name: "case_note".to_owned(),
+            section_header: String::new(),
+            field_type,
+            field_label: format!("{name} label"),
+            select_choices_or_calculations: choices.to_owned(),
+            field_note: String::new(),
+            text_validation_type_or_show_slider_number: String::new(),
+            branching_logic: String::new(),
+            required_field: String::new(),
+        }
+    }
+    fn question(string_id: &str, question_type: QuestionType) -> Question {
+        Question {
+            id: 1,
+            category_id: 1,
+            string_id: string_id.to_owned(),
+            string_id_other: None,
+            question_type,
+            question: string_id.to_owned(),
+            description: None,
+            required: false,
+            removed: false,
+            requirements: None,
+            additional_options: None,
+        }
+    }
+    fn option(name: &str, index: i32) -> QuestionOptions {
+        QuestionOptions {
+            question_id: 1,
+            name: name.to_owned(),
+            red_cap_option_index: Some(index),
+            ..Default::default()
+        }
+    }
+    #[test]
+    pub fn finds_differences() {
+        let fields = vec![
+            field(
+                "ercall",
+                RedCapFieldType::Radio,
+                "0, No | 1, Ambulance | 3, Refused",
+            ),
+            field("falls1", RedCapFieldType::Text, ""),
+            field("new_field", RedCapFieldType::Text, ""),
+            field("info", RedCapFieldType::Descriptive, ""),
+        ];
+        let questions = vec![
+            QuestionWithOptions {
+                question: question("ercall", QuestionType::Radio),
+                options: vec![
+                    option("No", 0),
+                    option("Taken to hospital", 1),
+                    option("Refused", 2),
+                    option("Unknown", 4),
+                ],
+            },
+            QuestionWithOptions {
+                question: question("falls1", QuestionType::Boolean),
+                options: vec![],
+            },
+            QuestionWithOptions {
+                question: question("removed_from_red_cap", QuestionType::Text),
+                options: vec![],
+            },
+        ];
+        let diff = diff_questions(&fields, &questions);
+        assert_eq!(
+            diff.differences,
+            vec![
+                QuestionDifference::RenamedChoice {
+                    question: "ercall".to_owned(),
+                    index: 1,
+                    option: "Taken to hospital".to_owned(),
+                    red_cap: "Ambulance".to_owned(),
+                },
+                QuestionDifference::ChangedOptionIndex {
+                    question: "ercall".to_owned(),
+                    option: "Refused".to_owned(),
+                    old: Some(2),
+                    new: 3,
+                },
+                QuestionDifference::MissingChoice {
+                    question: "ercall".to_owned(),
+                    option: "Unknown".to_owned(),
+                    index: Some(4),
+                },
+                QuestionDifference::TypeMismatch {
+                    question: "falls1".to_owned(),
+                    question_type: QuestionType::Boolean,
+                    field_type: RedCapFieldType::Text,
+                },
+                QuestionDifference::MissingField {
+                    question: "removed_from_red_cap".to_owned(),
+                },
+            ]
+        );
+        let unmapped: Vec<_> = diff
+            .unmapped_fields
+            .iter()
+            .map(|field| field.field_name.as_str())
+            .collect();
+        assert_eq!(unmapped, vec!["new_field"]);
+    }
+    #[test]
+    pub fn generates_question_files() {
+        let fields = vec![
+            field("new_radio", RedCapFieldType::Radio, "1, Yes | 2, No"),
+            field("new_text", RedCapFieldType::Notes, ""),
+            field("info", RedCapFieldType::Descriptive, ""),
+        ];
+        let files = generate_question_files(&fields);
+        assert_eq!(files.len(), 1);
+        let (name, file) = &files[0];
+        assert_eq!(name, "red_cap_case_note.json");
+        assert_eq!(file.category.form, QuestionForm::CaseNotes);
+        assert_eq!(file.category.name, "Case Note");
+        assert_eq!(file.questions.len(), 2);
+        let radio = &file.questions[0];
+        assert_eq!(radio.question.question_type, QuestionType::Radio);
+        let options = radio.options.as_ref().expect("Radio should have options");
+        assert_eq!(options[1].name, "No");
+        assert_eq!(options[1].red_cap_option_index, Some(2));
+        assert!(file.questions[1].options.is_none());
+    }
+}
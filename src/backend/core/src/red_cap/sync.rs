@@ -0,0 +1,462 @@
+//! Incremental two way sync between this system and Red Cap.
+//!
+//! ## Pull
+//! Records created or modified in Red Cap since the last pull are pulled.
+//! Participants that also have local changes are merged. See [conflicts](super::conflicts).
+//! Fields changed on both sides become conflicts and the participant is skipped until an admin resolves them.
+//!
+//! ## Push
+//! Participants changed locally and participants with completed case notes changed locally are pushed.
+use ahash::{HashSet, HashSetExt};
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{debug, error, info, instrument, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    database::red_cap::{
+        case_notes::CaseNote,
+        changes::RecordChanges,
+        participants::{ParticipantType, Participants},
+        sync::{RedCapSyncRun, RedCapSyncStatus},
+        sync_conflicts::{RedCapSyncConflict, RedCapSyncSnapshot},
+    },
+    red_cap::{
+        api::{ExportOptions, Forms, RedcapClient},
+        conflicts::{LocalChanges, next_case_note_instance, plan_merge},
+        converter::RedCapConverter,
+        tasks::{
+            RedCapTaskError,
+            audit::{audited_export, audited_import},
+            pull_case_notes, pull_goals, pull_medications, pull_record_base_types,
+            push::{
+                push_case_notes_to_redcap, push_participant_goals_to_red_cap,
+                push_participant_medications_to_red_cap, push_participant_to_red_cap,
+            },
+        },
+    },
+};
+/// What parts of the sync should run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+pub struct RedCapSyncOptions {
+    /// Pull records changed in Red Cap
+    pub pull: bool,
+    /// Push participants and case notes changed locally
+    pub push: bool,
+}
+impl RedCapSyncOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.pull || self.push
+    }
+}
+/// A record that failed to sync
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct FailedSyncRecord {
+    /// None if the record does not exist locally yet
+    pub participant_id: Option<i32>,
+    /// None if the participant has not been pushed to Red Cap yet
+    pub red_cap_id: Option<i32>,
+    pub error: String,
+}
+/// The fields changed in a participant by pulling it from Red Cap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PulledRecordChanges {
+    pub participant_id: i32,
+    pub changes: RecordChanges,
+}
+/// The result of a single sync run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RedCapSyncReport {
+    pub started_at: DateTime<FixedOffset>,
+    pub finished_at: Option<DateTime<FixedOffset>>,
+    /// Only records changed after this time were pulled. None if every record was pulled
+    pub pulled_since: Option<DateTime<FixedOffset>>,
+    /// Ids of the participants pulled from Red Cap
+    pub pulled: Vec<i32>,
+    /// The fields changed by the pull. Only participants that changed are included
+    pub changes: Vec<PulledRecordChanges>,
+    /// Ids of the participants pushed to Red Cap
+    pub pushed: Vec<i32>,
+    /// Ids of the participants not synced because they have unresolved conflicts
+    pub skipped: Vec<i32>,
+    /// Ids of the participants with new conflicts. See [RedCapSyncConflict]
+    pub conflicts: Vec<i32>,
+    pub failed: Vec<FailedSyncRecord>,
+}
+impl RedCapSyncReport {
+    fn new(started_at: DateTime<FixedOffset>) -> Self {
+        Self {
+            started_at,
+            finished_at: None,
+            pulled_since: None,
+            pulled: Vec::new(),
+            changes: Vec::new(),
+            pushed: Vec::new(),
+            skipped: Vec::new(),
+            conflicts: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+    fn add_changes(&mut self, participant_id: i32, changes: RecordChanges) {
+        if !changes.is_empty() {
+            self.changes.push(PulledRecordChanges {
+                participant_id,
+                changes,
+            });
+        }
+    }
+    /// Red Cap ids of records that failed before they existed locally.
+    ///
+    /// These are not tracked in the database so they need to be passed to the next sync
+    pub fn failed_new_records(&self) -> Vec<i32> {
+        self.failed
+            .iter()
+            .filter(|failed| failed.participant_id.is_none())
+            .filter_map(|failed| failed.red_cap_id)
+            .collect()
+    }
+}
+/// Runs a sync with Red Cap.
+///
+/// `retry_records` are Red Cap ids to pull even if they have not changed. Use [RedCapSyncReport::failed_new_records] from the previous run.
+///
+/// Errors for a single record are added to the report. An error is only returned if the sync could not run at all
+#[instrument(skip(database, client))]
+pub async fn sync_with_red_cap(
+    options: RedCapSyncOptions,
+    retry_records: &[i32],
+    database: &PgPool,
+    client: &RedcapClient,
+) -> Result<RedCapSyncReport, RedCapTaskError> {
+    let mut report = RedCapSyncReport::new(Local::now().fixed_offset());
+    let mut converter = RedCapConverter::new(database.clone()).await?;
+    if options.pull {
+        pull_changed_records(&mut report, retry_records, database, &mut converter, client).await?;
+    }
+    if options.push {
+        push_changed_participants(&mut report, database, &mut converter, client).await?;
+    }
+    report.finished_at = Some(Local::now().fixed_offset());
+    info!(
+        pulled = report.pulled.len(),
+        pushed = report.pushed.len(),
+        skipped = report.skipped.len(),
+        conflicts = report.conflicts.len(),
+        failed = report.failed.len(),
+        "Red Cap sync finished"
+    );
+    Ok(report)
+}
+
+async fn pull_changed_records(
+    report: &mut RedCapSyncReport,
+    retry_records: &[i32],
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<(), RedCapTaskError> {
+    report.pulled_since = RedCapSyncRun::last_pulled_at(database).await?;
+    let mut records = client.get_record_ids(report.pulled_since).await?;
+    records.extend(retry_records);
+    records.extend(RedCapSyncStatus::failed_red_cap_ids(database).await?);
+    records.extend(RedCapSyncConflict::red_cap_ids_ready_to_apply(database).await?);
+    records.sort_unstable();
+    records.dedup();
+    debug!(?records, "Records to pull");
+
+    let mut locally_changed: HashSet<i32> = HashSet::new();
+    locally_changed.extend(Participants::ids_with_unsynced_changes(database).await?);
+    locally_changed.extend(CaseNote::participants_with_unsynced_changes(database).await?);
+    let open_conflicts: HashSet<i32> =
+        RedCapSyncConflict::participants_with_open_conflicts(database)
+            .await?
+            .into_iter()
+            .collect();
+
+    for record_id in records {
+        let existing = Participants::find_by_red_cap_id(record_id, database).await?;
+        let result = match &existing {
+            Some(participant) if open_conflicts.contains(&participant.id) => {
+                warn!(
+                    ?record_id,
+                    participant = participant.id,
+                    "Skipping pull. Participant has unresolved conflicts"
+                );
+                report.skipped.push(participant.id);
+                continue;
+            }
+            Some(participant) if locally_changed.contains(&participant.id) => {
+                merge_record(
+                    participant,
+                    record_id,
+                    report.started_at,
+                    database,
+                    converter,
+                    client,
+                )
+                .await
+            }
+            _ => pull_record(record_id, report.started_at, database, converter, client)
+                .await
+                .map(|(participant_id, changes)| PullOutcome::Pulled(participant_id, changes)),
+        };
+        match result {
+            Ok(PullOutcome::Pulled(participant_id, changes)) => {
+                report.pulled.push(participant_id);
+                report.add_changes(participant_id, changes);
+            }
+            Ok(PullOutcome::Merged(participant_id, changes)) => {
+                report.pulled.push(participant_id);
+                report.pushed.push(participant_id);
+                report.add_changes(participant_id, changes);
+            }
+            Ok(PullOutcome::Conflicts(participant_id)) => report.conflicts.push(participant_id),
+            Err(err) => {
+                error!(?record_id, ?err, "Failed to pull record");
+                let participant_id = match existing {
+                    Some(participant) => Some(participant.id),
+                    None => Participants::find_by_red_cap_id(record_id, database)
+                        .await?
+                        .map(|participant| participant.id),
+                };
+                if let Some(participant_id) = participant_id {
+                    RedCapSyncStatus::record_failed(participant_id, &err.to_string(), database)
+                        .await?;
+                }
+                report.failed.push(FailedSyncRecord {
+                    participant_id,
+                    red_cap_id: Some(record_id),
+                    error: err.to_string(),
+                });
+            }
+        }
+    }
+    // The next pull continues from the start of this one. Even if nothing changed
+    RedCapSyncRun::record_pull(report.started_at, report.pulled_since, database).await?;
+    Ok(())
+}
+/// Pulls a single record. Returns the id of the participant and the fields that changed
+async fn pull_record(
+    record_id: i32,
+    started_at: DateTime<FixedOffset>,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<(i32, RecordChanges), RedCapTaskError> {
+    let mut changes = pull_record_base_types(record_id, database, converter, client).await?;
+    changes.extend(pull_medications(record_id, database, client).await?);
+    changes.extend(pull_goals(record_id, database, client).await?);
+    changes.extend(pull_case_notes(record_id, database, converter, client).await?);
+
+    let Some(participant) = Participants::find_by_red_cap_id(record_id, database).await? else {
+        return Err(RedCapTaskError::ParticipantNotFound);
+    };
+    // Changes made while pulling have an updated_at after started_at. So they are pushed next time.
+    // Updates pulled from Red Cap do not bump updated_at
+    Participants::mark_synced(participant.id, started_at, database).await?;
+    CaseNote::mark_synced_for_participant(participant.id, started_at, database).await?;
+    RedCapSyncStatus::record_pulled(participant.id, started_at, database).await?;
+    save_snapshot(participant.id, record_id, database, client).await?;
+    Ok((participant.id, changes))
+}
+/// What happened to a record changed in Red Cap
+enum PullOutcome {
+    Pulled(i32, RecordChanges),
+    /// The participant also had local changes. They were pushed before the record was pulled
+    Merged(i32, RecordChanges),
+    /// Fields were changed on both sides. Nothing was synced
+    Conflicts(i32),
+}
+/// Syncs a record that was changed both locally and in Red Cap.
+///
+/// The local changes are compared with Red Cap and the snapshot of the last sync.
+/// If nothing conflicts the local changes are pushed and the record is pulled.
+/// Otherwise the conflicts are saved for an admin to resolve and nothing is synced
+async fn merge_record(
+    participant: &Participants,
+    record_id: i32,
+    started_at: DateTime<FixedOffset>,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<PullOutcome, RedCapTaskError> {
+    let remote = audited_export(
+        record_id,
+        ExportOptions {
+            records: Some(vec![record_id as usize].into()),
+            ..Default::default()
+        },
+        database,
+        client,
+    )
+    .await?;
+    let base = match RedCapSyncSnapshot::find_by_participant(participant.id, database).await? {
+        Some(snapshot) => snapshot.rows.0,
+        None => {
+            // Last synced before snapshots existed. Red Cap is taken as the base so only real local changes are pushed
+            info!(
+                ?record_id,
+                participant = participant.id,
+                "Seeding the sync snapshot from Red Cap"
+            );
+            RedCapSyncSnapshot::save(participant.id, remote.clone(), database).await?;
+            remote.clone()
+        }
+    };
+    let local = LocalChanges::for_participant(participant, record_id, database, converter).await?;
+    let resolutions = RedCapSyncConflict::find_resolved_unapplied(participant.id, database).await?;
+    let plan = plan_merge(&base, &local.rows, &remote, &resolutions);
+    if !plan.conflicts.is_empty() {
+        warn!(
+            ?record_id,
+            participant = participant.id,
+            conflicts = plan.conflicts.len(),
+            "Participant was changed locally and in Red Cap"
+        );
+        for conflict in plan.conflicts {
+            conflict.insert(participant.id, database).await?;
+        }
+        RedCapSyncStatus::record_conflict(participant.id, database).await?;
+        return Ok(PullOutcome::Conflicts(participant.id));
+    }
+    if !plan.push.is_empty() {
+        // The rows only hold the changed fields. Which can belong to any form
+        audited_import(record_id, &[], plan.push, database, client).await?;
+    }
+    // Red Cap gives new instances after the highest existing one. Including case notes created in Red Cap
+    let next_instance = next_case_note_instance(&remote);
+    for (offset, (case_note, payload)) in local.new_case_notes.into_iter().enumerate() {
+        audited_import(
+            record_id,
+            &[Forms::CaseNotes],
+            vec![payload],
+            database,
+            client,
+        )
+        .await?;
+        let instance = next_instance + offset as i32;
+        case_note.update_instance_id(instance, database).await?;
+    }
+    let (_, changes) = pull_record(record_id, started_at, database, converter, client).await?;
+    RedCapSyncConflict::mark_applied(participant.id, database).await?;
+    RedCapSyncStatus::record_pushed(participant.id, started_at, database).await?;
+    Ok(PullOutcome::Merged(participant.id, changes))
+}
+/// Saves the record as it is in Red Cap now. It is the base for finding conflicts on the next sync
+async fn save_snapshot(
+    participant_id: i32,
+    record_id: i32,
+    database: &PgPool,
+    client: &RedcapClient,
+) -> Result<(), RedCapTaskError> {
+    let rows = audited_export(
+        record_id,
+        ExportOptions {
+            records: Some(vec![record_id as usize].into()),
+            ..Default::default()
+        },
+        database,
+        client,
+    )
+    .await?;
+    RedCapSyncSnapshot::save(participant_id, rows, database).await?;
+    Ok(())
+}
+async fn push_changed_participants(
+    report: &mut RedCapSyncReport,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<(), RedCapTaskError> {
+    let changed_participants: HashSet<i32> = Participants::ids_with_unsynced_changes(database)
+        .await?
+        .into_iter()
+        .collect();
+    let changed_case_notes: HashSet<i32> = CaseNote::participants_with_unsynced_changes(database)
+        .await?
+        .into_iter()
+        .collect();
+    // Pushing would overwrite the values in Red Cap before the conflicts are resolved
+    let open_conflicts: HashSet<i32> =
+        RedCapSyncConflict::participants_with_open_conflicts(database)
+            .await?
+            .into_iter()
+            .collect();
+    let mut participants: Vec<i32> = changed_participants
+        .union(&changed_case_notes)
+        .filter(|participant_id| !open_conflicts.contains(participant_id))
+        .copied()
+        .collect();
+    participants.sort_unstable();
+    debug!(?participants, "Participants to push");
+    for participant_id in participants {
+        let push_participant = changed_participants.contains(&participant_id);
+        let push_case_notes = changed_case_notes.contains(&participant_id);
+        let result = push_participant_parts(
+            participant_id,
+            push_participant,
+            push_case_notes,
+            report.started_at,
+            database,
+            converter,
+            client,
+        )
+        .await;
+        match result {
+            Ok(()) => report.pushed.push(participant_id),
+            Err(err) => {
+                error!(?participant_id, ?err, "Failed to push participant");
+                RedCapSyncStatus::record_failed(participant_id, &err.to_string(), database).await?;
+                let red_cap_id = Participants::find_by_id(participant_id, database)
+                    .await?
+                    .and_then(|participant| participant.red_cap_id);
+                report.failed.push(FailedSyncRecord {
+                    participant_id: Some(participant_id),
+                    red_cap_id,
+                    error: err.to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+/// Pushes the changed parts of a participant.
+///
+/// Participants that do not exist in Red Cap yet are always pushed first so they get a record id
+async fn push_participant_parts(
+    participant_id: i32,
+    push_participant: bool,
+    push_case_notes: bool,
+    started_at: DateTime<FixedOffset>,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<(), RedCapTaskError> {
+    let Some(participant) = Participants::find_by_id(participant_id, database).await? else {
+        return Err(RedCapTaskError::ParticipantNotFound);
+    };
+    if push_participant || participant.red_cap_id.is_none() {
+        push_participant_to_red_cap(participant_id, database, converter, client).await?;
+        push_participant_medications_to_red_cap(participant_id, database, client).await?;
+        push_participant_goals_to_red_cap(participant_id, database, converter, client).await?;
+    }
+    if push_case_notes {
+        push_case_notes_to_redcap(participant_id, database, converter, client).await?;
+    }
+    // Changes made while pushing have an updated_at after started_at. So they are pushed next time
+    Participants::mark_synced(participant_id, started_at, database).await?;
+    CaseNote::mark_synced_for_participant(participant_id, started_at, database).await?;
+    RedCapSyncStatus::record_pushed(participant_id, started_at, database).await?;
+    let record_id = match participant.red_cap_id {
+        Some(record_id) => Some(record_id),
+        // The participant was given a record id by the push
+        None => Participants::find_by_id(participant_id, database)
+            .await?
+            .and_then(|participant| participant.red_cap_id),
+    };
+    if let Some(record_id) = record_id {
+        save_snapshot(participant_id, record_id, database, client).await?;
+    }
+    Ok(())
+}
@@ -1,4 +1,6 @@
+pub mod audit;
 pub mod from;
+pub mod import;
 pub mod push;
 pub use from::*;
 
@@ -14,6 +16,8 @@ pub enum RedCapTaskError {
     #[error(transparent)]
     RedCapConversionError(#[from] RedCapConverterError),
 
+    #[error("Record {0} was not found in Red Cap")]
+    RecordNotFound(i32),
     #[error("Participant not found")]
     ParticipantNotFound,
 
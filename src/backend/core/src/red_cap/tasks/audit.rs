@@ -0,0 +1,125 @@
+//! Wraps the Red Cap imports and exports made by the tasks so each one is written to the sync audit log.
+//!
+//! Failing to write the log entry is logged and otherwise ignored. It should never stop a sync
+use std::collections::BTreeMap;
+
+use ahash::HashMap;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{instrument, warn};
+
+use crate::{
+    database::red_cap::{
+        participants::{ParticipantType, Participants},
+        sync_events::{NewRedCapSyncEvent, RedCapSyncDirection},
+    },
+    red_cap::api::{ExportOptions, Forms, RedCapAPIError, RedcapClient},
+};
+/// SHA-256 of the rows as JSON. The keys are sorted so the same rows always have the same hash
+pub fn payload_hash<K, V>(rows: &[HashMap<K, V>]) -> Option<String>
+where
+    K: Serialize + Ord,
+    V: Serialize,
+{
+    let sorted: Vec<BTreeMap<&K, &V>> = rows.iter().map(|row| row.iter().collect()).collect();
+    let json = serde_json::to_vec(&sorted).ok()?;
+    Some(format!("{:x}", Sha256::digest(json)))
+}
+/// Exports the rows of a record and records the export
+#[instrument(skip(database, client))]
+pub async fn audited_export(
+    record_id: i32,
+    options: ExportOptions,
+    database: &PgPool,
+    client: &RedcapClient,
+) -> Result<Vec<HashMap<String, Value>>, RedCapAPIError> {
+    let forms = options
+        .forms
+        .as_ref()
+        .map(|forms| forms.0.iter().map(|form| form.to_string()).collect())
+        .unwrap_or_default();
+    let result = client.get_flat_json_forms(options).await;
+    let (payload_hash, error) = match &result {
+        Ok(rows) => (payload_hash(rows), None),
+        Err(err) => (None, Some(err.to_string())),
+    };
+    let event = NewRedCapSyncEvent {
+        record_id: Some(record_id),
+        participant_id: None,
+        forms,
+        direction: RedCapSyncDirection::Pull,
+        initiated_by: client.initiator().clone(),
+        payload_hash,
+        response: None,
+        error,
+    };
+    record_event(event, database).await;
+    result
+}
+/// Imports the rows of a record and records the import along with the response of Red Cap
+#[instrument(skip(records, database, client))]
+pub async fn audited_import(
+    record_id: i32,
+    forms: &[Forms],
+    records: Vec<HashMap<String, String>>,
+    database: &PgPool,
+    client: &RedcapClient,
+) -> Result<String, RedCapAPIError> {
+    let payload_hash = payload_hash(&records);
+    let result = client.import_records(records).await;
+    let (response, error) = match &result {
+        Ok(response) => (Some(response.clone()), None),
+        Err(err) => (None, Some(err.to_string())),
+    };
+    let event = NewRedCapSyncEvent {
+        record_id: Some(record_id),
+        participant_id: None,
+        forms: forms.iter().map(|form| form.to_string()).collect(),
+        direction: RedCapSyncDirection::Push,
+        initiated_by: client.initiator().clone(),
+        payload_hash,
+        response,
+        error,
+    };
+    record_event(event, database).await;
+    result
+}
+/// Fills in the participant of the record and writes the event
+async fn record_event(mut event: NewRedCapSyncEvent, database: &PgPool) {
+    let record_id = event.record_id;
+    if let Some(record_id) = record_id {
+        match Participants::find_by_red_cap_id(record_id, database).await {
+            Ok(participant) => event.participant_id = participant.map(|participant| participant.id),
+            Err(err) => warn!(
+                ?err,
+                ?record_id,
+                "Failed to find the participant for the sync log"
+            ),
+        }
+    }
+    if let Err(err) = event.insert(database).await {
+        warn!(?err, ?record_id, "Failed to write the Red Cap sync log");
+    }
+}
+#[cfg(test)]
+mod tests {
+    use ahash::HashMapExt;
+
+    use super::*;
+
+    #[test]
+    pub fn payload_hash_ignores_key_order() {
+        let mut first = HashMap::new();
+        first.insert("record_id".to_owned(), "1".to_owned());
+        first.insert("first_name".to_owned(), "John".to_owned());
+        let mut second = HashMap::new();
+        second.insert("first_name".to_owned(), "John".to_owned());
+        second.insert("record_id".to_owned(), "1".to_owned());
+
+        let hash = payload_hash(&[first]).unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(Some(hash), payload_hash(&[second]));
+    }
+}
@@ -1,17 +1,29 @@
 use ahash::{HashMap, HashMapExt};
+use serde_json::{Value, json};
 use sqlx::PgPool;
 use tracing::{debug, error, info, instrument, warn};
 
 use crate::{
-    database::red_cap::{
-        case_notes::{
-            CaseNote,
-            new::{NewCaseNote, NewCaseNoteHealthMeasures},
-        },
-        participants::{
-            NewDemographics, NewHealthOverview, NewMedication, NewParticipant, ParticipantType,
-            Participants,
-            goals::{NewParticipantGoal, NewParticipantGoalsSteps},
+    database::{
+        DBError,
+        red_cap::{
+            case_notes::{
+                CaseNote,
+                new::{NewCaseNote, NewCaseNoteHealthMeasures},
+                questions::{
+                    delete_question_answer_from_red_cap, set_question_answer_from_red_cap,
+                },
+            },
+            changes::RecordChanges,
+            participants::{
+                NewDemographics, NewHealthOverview, NewMedication, NewParticipant,
+                ParticipantMedications, ParticipantType, Participants,
+                goals::{
+                    NewParticipantGoal, NewParticipantGoalsSteps, ParticipantGoals,
+                    ParticipantGoalsSteps,
+                },
+            },
+            questions::QuestionDataValue,
         },
     },
     red_cap::{
@@ -33,23 +45,52 @@ use crate::{
     },
 };
 
-use super::RedCapTaskError;
+use super::{RedCapTaskError, audit::audited_export};
 
 pub async fn pull_record_base_types(
     record_id: i32,
     database: &PgPool,
     converter: &mut RedCapConverter,
     client: &RedcapClient,
-) -> Result<(), RedCapTaskError> {
-    let mut records = client
-        .get_flat_json_forms(ExportOptions {
+) -> Result<RecordChanges, RedCapTaskError> {
+    let records = audited_export(
+        record_id,
+        ExportOptions {
             forms: Some(vec![Forms::ParticipantInformation, Forms::HealthOverview].into()),
             records: Some(vec![record_id as usize].into()),
 
             ..Default::default()
+        },
+        database,
+        client,
+    )
+    .await?;
+    import_record_base_types(record_id, records, database, converter).await
+}
+/// Returns the first row that is not an instance of a repeating instrument.
+///
+/// This row holds the fields of all the non repeating forms
+fn base_row(records: Vec<HashMap<String, Value>>) -> Option<HashMap<String, Value>> {
+    records
+        .into_iter()
+        .find(|record| match record.get("redcap_repeat_instrument") {
+            None | Some(Value::Null) => true,
+            Some(Value::String(instrument)) => instrument.is_empty(),
+            Some(_) => false,
         })
-        .await?;
-    let first_record = records.remove(0);
+}
+/// Imports the participant information and health overview from the exported rows of a record.
+///
+/// Returns the fields changed in an existing participant
+pub async fn import_record_base_types(
+    record_id: i32,
+    records: Vec<HashMap<String, Value>>,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+) -> Result<RecordChanges, RedCapTaskError> {
+    let Some(first_record) = base_row(records) else {
+        return Err(RedCapTaskError::RecordNotFound(record_id));
+    };
     let record = process_flat_json(first_record);
 
     let red_cap_participant = RedCapParticipant::read_participant(&record, converter).await?;
@@ -61,11 +102,15 @@ pub async fn pull_record_base_types(
         ?overview,
         "Read red cap data"
     );
+    let mut changes = RecordChanges::default();
     if let Some(mut participant) = Participants::find_by_red_cap_id(record_id, database).await? {
         info!(?participant, "Updating participant from red cap");
-        participant
+        changes = participant
             .update_from_red_cap(red_cap_participant, demographics, overview, database)
             .await?;
+        if !changes.is_empty() {
+            info!(?record_id, ?changes, "Participant changed in red cap");
+        }
     } else {
         let new_participant: NewParticipant = red_cap_participant.into();
         let new_demographics: Option<NewDemographics> = demographics.into();
@@ -79,85 +124,180 @@ pub async fn pull_record_base_types(
 
         new_overview.insert(participant.id, database).await?;
     }
-    Ok(())
+    Ok(changes)
 }
+/// Pulls the medications of a participant.
+///
+/// Medications are matched to existing ones by their red_cap_index. Matches are updated in place.
+/// Medications that were synced before but no longer exist in Red Cap are hidden from Red Cap.
+/// Medications that were never pushed are kept. Pulling again without changes does nothing
 #[instrument]
 pub async fn pull_medications(
     record_id: i32,
     database: &PgPool,
     client: &RedcapClient,
-) -> Result<(), RedCapTaskError> {
+) -> Result<RecordChanges, RedCapTaskError> {
+    let records = audited_export(
+        record_id,
+        ExportOptions {
+            forms: Some(vec![Forms::Medications].into()),
+            records: Some(vec![record_id as usize].into()),
+            ..Default::default()
+        },
+        database,
+        client,
+    )
+    .await?;
+    import_medications(record_id, records, database).await
+}
+/// Imports the medications from the exported rows of a record. See [pull_medications]
+#[instrument(skip(records))]
+pub async fn import_medications(
+    record_id: i32,
+    records: Vec<HashMap<String, Value>>,
+    database: &PgPool,
+) -> Result<RecordChanges, RedCapTaskError> {
     let Some(participant) = Participants::find_by_red_cap_id(record_id, database).await? else {
         error!(?record_id, "Participant must be loaded before medications");
         return Err(RedCapTaskError::ParticipantNotFound);
     };
-    let mut records = client
-        .get_flat_json_forms(ExportOptions {
-            forms: Some(vec![Forms::Medications].into()),
-            records: Some(vec![record_id as usize].into()),
-            ..Default::default()
-        })
-        .await?;
-    if records.is_empty() {
+    let Some(first_record) = base_row(records) else {
         warn!(?record_id, "No medications found for participant");
-        return Ok(());
-    }
-    let first_record = records.remove(0);
+        return Ok(RecordChanges::default());
+    };
     let record = process_flat_json(first_record);
     let medications = RedCapMedication::read(&record);
+
+    let mut changes = RecordChanges::default();
+    let mut existing: HashMap<i32, ParticipantMedications> =
+        ParticipantMedications::get_all_participant_medications(participant.id, database)
+            .await?
+            .into_iter()
+            .filter_map(|medication| Some((medication.red_cap_index?, medication)))
+            .collect();
+    let mut synced_indexes = Vec::with_capacity(medications.len());
     for medication in medications {
         debug!(?medication, ?participant, "Read medication from red cap");
         let new_medication: NewMedication = medication.into();
-
-        new_medication
-            .insert_return_none(participant.id, database)
-            .await?;
+        synced_indexes.extend(new_medication.red_cap_index);
+        let current = new_medication
+            .red_cap_index
+            .and_then(|index| existing.remove(&index));
+        if let Some(mut current) = current {
+            changes.extend(
+                current
+                    .update_from_red_cap(new_medication, database)
+                    .await?,
+            );
+        } else {
+            changes.push("medication", "created", &(), &new_medication);
+            new_medication
+                .insert_return_none(participant.id, database)
+                .await?;
+        }
     }
-    Ok(())
+    ParticipantMedications::mark_synced_with_red_cap(participant.id, &synced_indexes, database)
+        .await?;
+    for mut removed in existing.into_values() {
+        // Medications that were never synced have not been pushed yet
+        if removed.hidden_from_red_cap || !removed.synced_with_red_cap {
+            continue;
+        }
+        changes.push("medication", "hidden_from_red_cap", &false, &true);
+        removed.hide_from_red_cap(database).await?;
+    }
+    Ok(changes)
 }
+/// Pulls the goals and steps of a participant.
+///
+/// Goals and steps are matched to existing ones by their red_cap_index. Matches are updated in place.
+/// Goals and steps that were synced before but no longer exist in Red Cap are hidden from Red Cap.
+/// Goals and steps that were never pushed are kept. Pulling again without changes does nothing
 #[instrument]
 pub async fn pull_goals(
     record_id: i32,
     database: &PgPool,
     client: &RedcapClient,
-) -> Result<(), RedCapTaskError> {
+) -> Result<RecordChanges, RedCapTaskError> {
+    let records = audited_export(
+        record_id,
+        ExportOptions {
+            forms: Some(vec![Forms::WellnessGoals].into()),
+            records: Some(vec![record_id as usize].into()),
+            ..Default::default()
+        },
+        database,
+        client,
+    )
+    .await?;
+    import_goals(record_id, records, database).await
+}
+/// Imports the goals and steps from the exported rows of a record. See [pull_goals]
+#[instrument(skip(records))]
+pub async fn import_goals(
+    record_id: i32,
+    records: Vec<HashMap<String, Value>>,
+    database: &PgPool,
+) -> Result<RecordChanges, RedCapTaskError> {
     let Some(participant) = Participants::find_by_red_cap_id(record_id, database).await? else {
         error!(?record_id, "Participant must be loaded before goals");
         return Err(RedCapTaskError::ParticipantNotFound);
     };
-    let mut records = client
-        .get_flat_json_forms(ExportOptions {
-            forms: Some(vec![Forms::WellnessGoals].into()),
-            records: Some(vec![record_id as usize].into()),
-            ..Default::default()
-        })
-        .await?;
-    if records.is_empty() {
+    let Some(first_record) = base_row(records) else {
         warn!(?record_id, "No goals found for participant");
-        return Ok(());
-    }
-    let first_record = records.remove(0);
+        return Ok(RecordChanges::default());
+    };
     let record = process_flat_json(first_record);
     let RedCapCompleteGoals { goals, steps } = RedCapCompleteGoals::read(&record)?;
-    // TODO: Mark Old Goals as hidden_from_red_cap
-    // TODO: Try to match goals to existing goals based on red_cap_index
 
+    let mut changes = RecordChanges::default();
+    let mut existing_goals: HashMap<i32, ParticipantGoals> =
+        ParticipantGoals::get_all_participant_goals(participant.id, database)
+            .await?
+            .into_iter()
+            .filter_map(|goal| Some((goal.red_cap_index?, goal)))
+            .collect();
+    // Red Cap index to the id of the goal. Steps reference goals by their Red Cap index
     let mut index_to_goal = HashMap::new();
 
     for goal in goals {
-        debug!(?goal, ?participant, "Read medication from red cap");
+        debug!(?goal, ?participant, "Read goal from red cap");
         let index = goal.red_cap_index;
         let new_goal: NewParticipantGoal = goal.into();
 
-        let goal = new_goal
-            .insert_return_goal(participant.id, database)
-            .await?;
+        let goal_id = if let Some(mut current) = existing_goals.remove(&index) {
+            changes.extend(current.update_from_red_cap(new_goal, database).await?);
+            current.id
+        } else {
+            changes.push("goal", "created", &(), &new_goal);
+            new_goal
+                .insert_return_goal(participant.id, database)
+                .await?
+                .id
+        };
 
-        index_to_goal.insert(index, goal);
+        index_to_goal.insert(index, goal_id);
+    }
+    let synced_indexes: Vec<i32> = index_to_goal.keys().copied().collect();
+    ParticipantGoals::mark_synced_with_red_cap(participant.id, &synced_indexes, database).await?;
+    for mut removed in existing_goals.into_values() {
+        // Goals that were never synced have not been pushed yet
+        if removed.hidden_from_red_cap || !removed.synced_with_red_cap {
+            continue;
+        }
+        changes.push("goal", "hidden_from_red_cap", &false, &true);
+        removed.hide_from_red_cap(database).await?;
     }
 
+    let mut existing_steps: HashMap<i32, ParticipantGoalsSteps> =
+        ParticipantGoalsSteps::get_all_participant_goals_steps(participant.id, database)
+            .await?
+            .into_iter()
+            .filter_map(|step| Some((step.red_cap_index?, step)))
+            .collect();
+    let mut synced_indexes = Vec::with_capacity(steps.len());
     for step in steps {
-        debug!(?step, ?participant, "Read medication from red cap");
+        debug!(?step, ?participant, "Read goal step from red cap");
         let RedCapGoalsSteps {
             associated_goal,
             step,
@@ -168,12 +308,13 @@ pub async fn pull_goals(
             red_cap_index,
         } = step;
         let goal_id = if let Some(goal_id) = associated_goal {
-            let goal = index_to_goal.get(&goal_id);
+            let goal = index_to_goal.get(&goal_id).copied();
             debug!(?goal, "Goal found");
-            goal.map(|x| x.id)
+            goal
         } else {
             None
         };
+        synced_indexes.push(red_cap_index);
         let new_step: NewParticipantGoalsSteps = NewParticipantGoalsSteps {
             goal_id,
             step,
@@ -184,40 +325,75 @@ pub async fn pull_goals(
             red_cap_index: Some(red_cap_index),
         };
 
-        new_step
-            .insert_return_none(participant.id, database)
-            .await?;
+        if let Some(mut current) = existing_steps.remove(&red_cap_index) {
+            changes.extend(current.update_from_red_cap(new_step, database).await?);
+        } else {
+            changes.push("goal_step", "created", &(), &new_step);
+            new_step
+                .insert_return_none(participant.id, database)
+                .await?;
+        }
+    }
+    ParticipantGoalsSteps::mark_synced_with_red_cap(participant.id, &synced_indexes, database)
+        .await?;
+    for mut removed in existing_steps.into_values() {
+        // Steps that were never synced have not been pushed yet
+        if removed.hidden_from_red_cap || !removed.synced_with_red_cap {
+            continue;
+        }
+        changes.push("goal_step", "hidden_from_red_cap", &false, &true);
+        removed.hide_from_red_cap(database).await?;
     }
-    Ok(())
+    Ok(changes)
 }
+/// Pulls the case notes of a participant.
+///
+/// Case notes are matched to existing ones by their Red Cap instance. Returns the changed fields and the created case notes
 pub async fn pull_case_notes(
     record_id: i32,
     database: &PgPool,
     converter: &mut RedCapConverter,
     client: &RedcapClient,
-) -> Result<(), RedCapTaskError> {
+) -> Result<RecordChanges, RedCapTaskError> {
+    let records = audited_export(
+        record_id,
+        ExportOptions {
+            forms: Some(vec![Forms::CaseNotes].into()),
+            records: Some(vec![record_id as usize].into()),
+            fields: Some(vec![Fields::RecordID].into()),
+            ..Default::default()
+        },
+        database,
+        client,
+    )
+    .await?;
+    import_case_notes(record_id, records, database, converter).await
+}
+/// Imports the case notes from the exported rows of a record.
+///
+/// Rows that are not a case note instance are skipped
+pub async fn import_case_notes(
+    record_id: i32,
+    records: Vec<HashMap<String, Value>>,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+) -> Result<RecordChanges, RedCapTaskError> {
     let Some(participant) = Participants::find_by_red_cap_id(record_id, database).await? else {
         error!(?record_id, "Participant must be loaded before goals");
         return Err(RedCapTaskError::ParticipantNotFound);
     };
-    let records = client
-        .get_flat_json_forms(ExportOptions {
-            forms: Some(vec![Forms::CaseNotes].into()),
-            records: Some(vec![record_id as usize].into()),
-            fields: Some(vec![Fields::RecordID].into()),
-        })
-        .await?;
+    let mut changes = RecordChanges::default();
     if records.is_empty() {
         warn!(?record_id, "No case notes found for participant");
-        return Ok(());
+        return Ok(changes);
     }
     for case_note in records {
         let record = process_flat_json(case_note);
 
-        add_case_note(&participant, record, database, converter).await?;
+        changes.extend(add_case_note(&participant, record, database, converter).await?);
     }
 
-    Ok(())
+    Ok(changes)
 }
 
 async fn add_case_note(
@@ -225,10 +401,11 @@ async fn add_case_note(
     record: HashMap<String, RedCapExportDataType>,
     database: &PgPool,
     converter: &mut RedCapConverter,
-) -> Result<(), RedCapTaskError> {
+) -> Result<RecordChanges, RedCapTaskError> {
+    let mut changes = RecordChanges::default();
     let Some(case_note) = RedCapCaseNoteBase::read_case_note_base(&record, converter).await? else {
         // Case Note does not have an instance number. Meaning it isn't complete?
-        return Ok(());
+        return Ok(changes);
     };
 
     let health_measures = RedCapHealthMeasures::read_health_measures(&record).await?;
@@ -236,7 +413,7 @@ async fn add_case_note(
     let bp_readings = RedCapBloodPressureReadings::read(&record);
 
     let other = OtherCaseNoteData::read(&record, converter).await?;
-    if let Some(existing_case_note) = CaseNote::find_by_participant_id_and_redcap_instance(
+    if let Some(mut existing_case_note) = CaseNote::find_by_participant_id_and_redcap_instance(
         participants.id,
         case_note.red_cap_instance.unwrap(),
         database,
@@ -244,40 +421,353 @@ async fn add_case_note(
     .await?
     {
         // Update the case note
-        existing_case_note
-            .update_from_red_cap(case_note, health_measures, other, database)
+        changes = existing_case_note
+            .update_from_red_cap(case_note, health_measures, bp_readings, database)
             .await?;
+        changes.extend(update_case_note_answers(existing_case_note.id, other, database).await?);
+        if !changes.is_empty() {
+            info!(
+                case_note = existing_case_note.id,
+                ?changes,
+                "Case note changed in red cap"
+            );
+        }
     } else {
         // Insert the case note
         let new_case_note: NewCaseNote = case_note.into();
         let new_health_measures: NewCaseNoteHealthMeasures = health_measures.into();
 
+        let mut transaction = database.begin().await.map_err(DBError::from)?;
         let case_note = new_case_note
-            .insert_return_case_note(participants.id, database)
+            .insert_return_case_note(participants.id, &mut *transaction)
             .await?;
 
         let measures = new_health_measures
-            .insert_return_measure(case_note.id, database)
+            .insert_return_measure(case_note.id, &mut *transaction)
+            .await?;
+        measures
+            .add_many_bp(bp_readings.readings, &mut *transaction)
             .await?;
-        measures.add_many_bp(bp_readings.readings, database).await?;
         for (question_id, value) in other.values {
             debug!(?question_id, ?value, "Adding question");
             crate::database::red_cap::case_notes::questions::add_question(
                 question_id,
                 case_note.id,
                 value,
-                database,
+                &mut transaction,
             )
             .await?;
         }
+        transaction.commit().await.map_err(DBError::from)?;
+        changes.push("case_note", "created", &(), &case_note.red_cap_instance);
+    }
+    Ok(changes)
+}
+/// Replaces the answers of an existing case note with the answers pulled from Red Cap.
+///
+/// Only answers that are different are written. Answers that are no longer in Red Cap are removed
+#[instrument(skip(database))]
+async fn update_case_note_answers(
+    case_note_id: i32,
+    pulled: OtherCaseNoteData,
+    database: &PgPool,
+) -> Result<RecordChanges, RedCapTaskError> {
+    let mut current = OtherCaseNoteData::find_by_case_note_id(case_note_id, database)
+        .await?
+        .values;
+    let mut changes = RecordChanges::default();
+    let mut transaction = database.begin().await.map_err(DBError::from)?;
+    for (question_id, value) in pulled.values {
+        let old = current.remove(&question_id);
+        if old.as_ref().is_some_and(|old| is_same_answer(old, &value)) {
+            continue;
+        }
+        changes.push(
+            "question_answer",
+            "value",
+            &json!({ "question_id": question_id, "value": old }),
+            &json!({ "question_id": question_id, "value": value }),
+        );
+        set_question_answer_from_red_cap(question_id, case_note_id, value, &mut transaction)
+            .await?;
+    }
+    for (question_id, old) in current {
+        changes.push(
+            "question_answer",
+            "value",
+            &json!({ "question_id": question_id, "value": old }),
+            &json!({ "question_id": question_id, "value": null }),
+        );
+        delete_question_answer_from_red_cap(question_id, case_note_id, &mut transaction).await?;
+    }
+    transaction.commit().await.map_err(DBError::from)?;
+    Ok(changes)
+}
+/// Options are compared by id. The order of selected options does not matter
+fn is_same_answer(old: &QuestionDataValue, new: &QuestionDataValue) -> bool {
+    match (old, new) {
+        (
+            QuestionDataValue::Radio { option, other },
+            QuestionDataValue::Radio {
+                option: new_option,
+                other: new_other,
+            },
+        ) => option.id == new_option.id && other == new_other,
+        (
+            QuestionDataValue::MultiCheckBox { options, other },
+            QuestionDataValue::MultiCheckBox {
+                options: new_options,
+                other: new_other,
+            },
+        ) => {
+            let mut options: Vec<i32> = options.iter().map(|option| option.id).collect();
+            let mut new_options: Vec<i32> = new_options.iter().map(|option| option.id).collect();
+            options.sort_unstable();
+            new_options.sort_unstable();
+            options == new_options && other == new_other
+        }
+        (old, new) => old == new,
     }
-    Ok(())
 }
 #[cfg(test)]
 mod tests {
-    use tracing::info;
+    use ahash::{HashMap, HashMapExt};
+    use anyhow::Context;
+    use tracing::{info, warn};
+
+    use crate::{
+        database::red_cap::{
+            case_notes::{CaseNote, new::NewCaseNote, questions::add_question},
+            participants::{
+                NewMedication, NewParticipant, ParticipantMedications, ParticipantType,
+                Participants,
+                goals::{
+                    NewParticipantGoal, NewParticipantGoalsSteps, ParticipantGoals,
+                    ParticipantGoalsSteps,
+                },
+            },
+            questions::{Question, QuestionDataValue},
+        },
+        red_cap::{
+            api::RedcapClient,
+            converter::{RedCapConverter, case_notes::OtherCaseNoteData},
+            mock::{MockRedCap, MockRedCapConfig},
+            tasks::push::{
+                push_participant_goals_to_red_cap, push_participant_medications_to_red_cap,
+                push_participant_to_red_cap,
+            },
+            tests::load_red_cap_api_and_db,
+        },
+        utils::testing::config::testing::{get_testing_db, no_db_connection},
+    };
+    /// Pulling medications and goals that were just pushed should not change anything
+    #[tokio::test]
+    pub async fn repeated_pulls_do_not_duplicate() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let mock = MockRedCap::new(MockRedCapConfig::default())
+            .await?
+            .start(([127, 0, 0, 1], 0).into())
+            .await?;
+        let client = RedcapClient::with_config(&mock.client_config()).await?;
+        let mut converter: RedCapConverter = RedCapConverter::new(database.clone()).await?;
+        let participant = NewParticipant {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            other_contact: Some("Repeated Pull Test".to_owned()),
+            ..Default::default()
+        }
+        .insert_returning(&database)
+        .await?;
+        ParticipantMedications::add_medication(
+            participant.id,
+            NewMedication {
+                name: "Medication 1".to_string(),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+        ParticipantGoals::add_goal(
+            participant.id,
+            NewParticipantGoal {
+                goal: "Goal 1".to_string(),
+                is_active: Some(true),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+        push_participant_to_red_cap(participant.id, &database, &mut converter, &client).await?;
+        push_participant_medications_to_red_cap(participant.id, &database, &client).await?;
+        push_participant_goals_to_red_cap(participant.id, &database, &mut converter, &client)
+            .await?;
+        let record_id = Participants::find_by_id(participant.id, &database)
+            .await?
+            .and_then(|participant| participant.red_cap_id)
+            .context("Participant was not given a red cap id")?;
+
+        // Only exists locally. It was never pushed so it must not be hidden
+        ParticipantMedications::add_medication(
+            participant.id,
+            NewMedication {
+                name: "Medication 2".to_string(),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
 
-    use crate::red_cap::{converter::RedCapConverter, tests::load_red_cap_api_and_db};
+        let changes = super::pull_medications(record_id, &database, &client).await?;
+        assert!(changes.is_empty(), "{changes:?}");
+        let changes = super::pull_goals(record_id, &database, &client).await?;
+        assert!(changes.is_empty(), "{changes:?}");
+
+        for _ in 0..2 {
+            assert!(
+                super::pull_medications(record_id, &database, &client)
+                    .await?
+                    .is_empty()
+            );
+            assert!(
+                super::pull_goals(record_id, &database, &client)
+                    .await?
+                    .is_empty()
+            );
+        }
+        let medications =
+            ParticipantMedications::get_all_participant_medications(participant.id, &database)
+                .await?;
+        assert_eq!(medications.len(), 2);
+        assert!(
+            medications
+                .iter()
+                .all(|medication| !medication.hidden_from_red_cap)
+        );
+        assert_eq!(
+            medications
+                .iter()
+                .filter(|medication| medication.synced_with_red_cap)
+                .count(),
+            1
+        );
+        let goals = ParticipantGoals::get_all_participant_goals(participant.id, &database).await?;
+        assert_eq!(goals.len(), 1);
+        assert!(goals[0].synced_with_red_cap);
+
+        // The next push sends the local medication to Red Cap
+        push_participant_medications_to_red_cap(participant.id, &database, &client).await?;
+        let medications =
+            ParticipantMedications::get_all_participant_medications(participant.id, &database)
+                .await?;
+        assert!(
+            medications
+                .iter()
+                .all(|medication| medication.synced_with_red_cap)
+        );
+        Ok(())
+    }
+
+    /// Rows hidden locally are still in Red Cap. Pulling them must not show them again
+    #[tokio::test]
+    #[ignore]
+    pub async fn pulls_keep_hidden_rows_hidden() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let mock = MockRedCap::new(MockRedCapConfig::default())
+            .await?
+            .start(([127, 0, 0, 1], 0).into())
+            .await?;
+        let client = RedcapClient::with_config(&mock.client_config()).await?;
+        let mut converter: RedCapConverter = RedCapConverter::new(database.clone()).await?;
+        let participant = NewParticipant {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            other_contact: Some("Hidden Pull Test".to_owned()),
+            ..Default::default()
+        }
+        .insert_returning(&database)
+        .await?;
+        ParticipantMedications::add_medication(
+            participant.id,
+            NewMedication {
+                name: "Medication 1".to_string(),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+        let goal = ParticipantGoals::add_goal(
+            participant.id,
+            NewParticipantGoal {
+                goal: "Goal 1".to_string(),
+                is_active: Some(true),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+        ParticipantGoalsSteps::add_step(
+            participant.id,
+            NewParticipantGoalsSteps {
+                goal_id: Some(goal.id),
+                step: "Step 1".to_string(),
+                ..Default::default()
+            },
+            &database,
+        )
+        .await?;
+        push_participant_to_red_cap(participant.id, &database, &mut converter, &client).await?;
+        push_participant_medications_to_red_cap(participant.id, &database, &client).await?;
+        push_participant_goals_to_red_cap(participant.id, &database, &mut converter, &client)
+            .await?;
+        let record_id = Participants::find_by_id(participant.id, &database)
+            .await?
+            .and_then(|participant| participant.red_cap_id)
+            .context("Participant was not given a red cap id")?;
+
+        for mut medication in
+            ParticipantMedications::get_all_participant_medications(participant.id, &database)
+                .await?
+        {
+            medication.hide_from_red_cap(&database).await?;
+        }
+        for mut goal in
+            ParticipantGoals::get_all_participant_goals(participant.id, &database).await?
+        {
+            goal.hide_from_red_cap(&database).await?;
+        }
+        for mut step in
+            ParticipantGoalsSteps::get_all_participant_goals_steps(participant.id, &database)
+                .await?
+        {
+            step.hide_from_red_cap(&database).await?;
+        }
+
+        let changes = super::pull_medications(record_id, &database, &client).await?;
+        assert!(changes.is_empty(), "{changes:?}");
+        let changes = super::pull_goals(record_id, &database, &client).await?;
+        assert!(changes.is_empty(), "{changes:?}");
+
+        let medications =
+            ParticipantMedications::get_all_participant_medications(participant.id, &database)
+                .await?;
+        assert_eq!(medications.len(), 1);
+        assert!(medications[0].hidden_from_red_cap);
+        let goals = ParticipantGoals::get_all_participant_goals(participant.id, &database).await?;
+        assert_eq!(goals.len(), 1);
+        assert!(goals[0].hidden_from_red_cap);
+        let steps =
+            ParticipantGoalsSteps::get_all_participant_goals_steps(participant.id, &database)
+                .await?;
+        assert_eq!(steps.len(), 1);
+        assert!(steps[0].hidden_from_red_cap);
+        Ok(())
+    }
 
     #[tokio::test]
     #[ignore]
@@ -295,4 +785,82 @@ mod tests {
         super::pull_case_notes(1, &database, &mut converter, &client).await?;
         Ok(())
     }
+    /// Answers pulled for an existing case note replace the local answers and are returned as changes
+    #[tokio::test]
+    #[ignore]
+    pub async fn pulled_answers_update_existing_case_note() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let Some(question): Option<Question> =
+            sqlx::query_as("SELECT * FROM questions WHERE question_type = 'Text' LIMIT 1")
+                .fetch_optional(&database)
+                .await?
+        else {
+            warn!("No text questions in the database. Skipping");
+            return Ok(());
+        };
+        let participant = NewParticipant {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            other_contact: Some("Pulled Answers Test".to_owned()),
+            ..Default::default()
+        }
+        .insert_returning(&database)
+        .await?;
+        let case_note = NewCaseNote::default()
+            .insert_return_case_note(participant.id, &database)
+            .await?;
+        let mut transaction = database.begin().await?;
+        add_question(
+            question.id,
+            case_note.id,
+            QuestionDataValue::Text("Local".to_owned()),
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+
+        let pulled = |value: &str| OtherCaseNoteData {
+            values: HashMap::from_iter([(question.id, QuestionDataValue::Text(value.to_owned()))]),
+        };
+        let changes =
+            super::update_case_note_answers(case_note.id, pulled("Local"), &database).await?;
+        assert!(changes.is_empty(), "{changes:?}");
+
+        let changes =
+            super::update_case_note_answers(case_note.id, pulled("Red Cap"), &database).await?;
+        assert_eq!(changes.changes.len(), 1);
+        // Pulled answers are not local changes
+        let updated_at = CaseNote::find_by_id(case_note.id, &database)
+            .await?
+            .context("Case note should exist")?
+            .updated_at;
+        assert_eq!(updated_at, case_note.updated_at);
+        let answers = OtherCaseNoteData::find_by_case_note_id(case_note.id, &database).await?;
+        assert_eq!(
+            answers.values.get(&question.id),
+            Some(&QuestionDataValue::Text("Red Cap".to_owned()))
+        );
+
+        // Cleared in Red Cap
+        let changes = super::update_case_note_answers(
+            case_note.id,
+            OtherCaseNoteData {
+                values: HashMap::new(),
+            },
+            &database,
+        )
+        .await?;
+        assert_eq!(changes.changes.len(), 1);
+        let answers = OtherCaseNoteData::find_by_case_note_id(case_note.id, &database).await?;
+        assert!(answers.values.is_empty());
+        let updated_at = CaseNote::find_by_id(case_note.id, &database)
+            .await?
+            .context("Case note should exist")?
+            .updated_at;
+        assert_eq!(updated_at, case_note.updated_at);
+        Ok(())
+    }
 }
@@ -0,0 +1,168 @@
+//! Imports Red Cap export files. So a database can be seeded without access to the API
+//!
+//! The file must be a flat export of the raw values (Not the labels) as CSV or JSON.
+//! The rows are passed through the same import logic used when pulling from the API.
+use std::{collections::BTreeMap, io::Read, path::Path, str::FromStr};
+
+use ahash::HashMap;
+use serde_json::Value;
+use sqlx::PgPool;
+use thiserror::Error;
+use tracing::{debug, info};
+
+use crate::{
+    database::red_cap::changes::RecordChanges,
+    red_cap::{api::Format, converter::RedCapConverter},
+};
+
+use super::{
+    RedCapTaskError, import_case_notes, import_goals, import_medications, import_record_base_types,
+};
+/// A row of a flat Red Cap export
+pub type ExportRow = HashMap<String, Value>;
+
+#[derive(Debug, Error)]
+pub enum ExportFileError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("Unsupported export format `{0}`. Only flat CSV and JSON exports are supported")]
+    UnsupportedFormat(String),
+    #[error("Row {0} does not have a valid record_id")]
+    MissingRecordId(usize),
+}
+/// The rows of a Red Cap export grouped by record id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExportFile {
+    pub records: BTreeMap<i32, Vec<ExportRow>>,
+}
+impl ExportFile {
+    /// Reads an export file. If format is None it is picked from the file extension
+    pub fn open(path: impl AsRef<Path>, format: Option<Format>) -> Result<Self, ExportFileError> {
+        let path = path.as_ref();
+        let format = match format {
+            Some(format) => format,
+            None => {
+                let extension = path
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .unwrap_or_default()
+                    .to_lowercase();
+                Format::from_str(&extension)
+                    .map_err(|_| ExportFileError::UnsupportedFormat(extension))?
+            }
+        };
+        let file = std::fs::File::open(path)?;
+        match format {
+            Format::Json => Self::read_json(file),
+            Format::Csv => Self::read_csv(file),
+            other => Err(ExportFileError::UnsupportedFormat(other.to_string())),
+        }
+    }
+    pub fn read_json(reader: impl Read) -> Result<Self, ExportFileError> {
+        let rows: Vec<ExportRow> = serde_json::from_reader(reader)?;
+        Self::from_rows(rows)
+    }
+    /// Reads a CSV export. The header row must contain the raw variable names
+    pub fn read_csv(reader: impl Read) -> Result<Self, ExportFileError> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let headers: Vec<String> = reader
+            .headers()?
+            .iter()
+            // Excel likes to add a byte order mark
+            .map(|header| header.trim_start_matches('\u{feff}').to_owned())
+            .collect();
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            let row: ExportRow = headers
+                .iter()
+                .cloned()
+                .zip(record.iter().map(|value| Value::String(value.to_owned())))
+                .collect();
+            rows.push(row);
+        }
+        Self::from_rows(rows)
+    }
+    pub fn from_rows(rows: Vec<ExportRow>) -> Result<Self, ExportFileError> {
+        let mut records: BTreeMap<i32, Vec<ExportRow>> = BTreeMap::new();
+        for (index, row) in rows.into_iter().enumerate() {
+            let record_id = match row.get("record_id") {
+                Some(Value::String(record_id)) => record_id.trim().parse().ok(),
+                Some(Value::Number(record_id)) => {
+                    record_id.as_i64().map(|record_id| record_id as i32)
+                }
+                _ => None,
+            };
+            let Some(record_id) = record_id else {
+                return Err(ExportFileError::MissingRecordId(index));
+            };
+            records.entry(record_id).or_default().push(row);
+        }
+        Ok(Self { records })
+    }
+}
+/// Imports every form of a record from its exported rows.
+///
+/// Does the same as pulling the record from the API
+pub async fn import_record(
+    record_id: i32,
+    rows: Vec<ExportRow>,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+) -> Result<RecordChanges, RedCapTaskError> {
+    debug!(?record_id, rows = rows.len(), "Importing record");
+    let mut changes =
+        import_record_base_types(record_id, rows.clone(), database, converter).await?;
+    changes.extend(import_medications(record_id, rows.clone(), database).await?);
+    changes.extend(import_goals(record_id, rows.clone(), database).await?);
+    changes.extend(import_case_notes(record_id, rows, database, converter).await?);
+    if !changes.is_empty() {
+        info!(?record_id, ?changes, "Record changed in the export");
+    }
+    Ok(changes)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn read_csv() -> anyhow::Result<()> {
+        let csv = "\u{feff}record_id,redcap_repeat_instrument,redcap_repeat_instance,first_name,health_ed___1
+1,,,John,0
+1,case_note,1,,1
+2,,,\"Doe, Jane\",0
+";
+        let file = ExportFile::read_csv(csv.as_bytes())?;
+        assert_eq!(file.records.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+        let record_one = &file.records[&1];
+        assert_eq!(record_one.len(), 2);
+        assert_eq!(record_one[0]["first_name"], "John");
+        assert_eq!(record_one[1]["redcap_repeat_instrument"], "case_note");
+        assert_eq!(record_one[1]["health_ed___1"], "1");
+        assert_eq!(file.records[&2][0]["first_name"], "Doe, Jane");
+        Ok(())
+    }
+    #[test]
+    pub fn read_json() -> anyhow::Result<()> {
+        let json = r#"[
+            {"record_id": "1", "redcap_repeat_instrument": "", "first_name": "John"},
+            {"record_id": 2, "redcap_repeat_instrument": "", "first_name": "Jane"}
+        ]"#;
+        let file = ExportFile::read_json(json.as_bytes())?;
+        assert_eq!(file.records.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(file.records[&2][0]["first_name"], "Jane");
+        Ok(())
+    }
+    #[test]
+    pub fn missing_record_id() {
+        let json = r#"[{"record_id": "1"}, {"first_name": "John"}]"#;
+        assert!(matches!(
+            ExportFile::read_json(json.as_bytes()),
+            Err(ExportFileError::MissingRecordId(1))
+        ));
+    }
+}
@@ -1,7 +1,12 @@
+use std::collections::BTreeMap;
+
 use ahash::{HashMap, HashMapExt};
 
+use serde::Serialize;
+use serde_json::Value;
 use sqlx::PgPool;
 use tracing::{debug, error, info};
+use utoipa::ToSchema;
 
 use crate::{
     database::red_cap::{
@@ -14,10 +19,10 @@ use crate::{
         },
     },
     red_cap::{
-        api::RedcapClient,
+        api::{ExportOptions, Forms, RedcapClient},
         converter::{
             RedCapConverter,
-            case_notes::{RedCapCaseNoteBase, RedCapHealthMeasures},
+            case_notes::{OtherCaseNoteData, RedCapCaseNoteBase, RedCapHealthMeasures},
             goals::{RedCapGoals, RedCapGoalsSteps},
             medications::RedCapMedication,
             participants::{
@@ -28,17 +33,20 @@ use crate::{
     },
 };
 
-use super::RedCapTaskError;
+use super::{
+    RedCapTaskError,
+    audit::{audited_export, audited_import},
+};
+/// A flattened row that is sent to Red Cap
+pub type RedCapPayload = HashMap<String, String>;
 
-pub async fn push_participant_to_red_cap(
-    participant: i32,
+/// Builds the participant information, demographics and health overview row
+pub(crate) async fn participant_payload(
+    participant: &Participants,
+    record_id: i32,
     database: &PgPool,
     converter: &mut RedCapConverter,
-    client: &RedcapClient,
-) -> Result<(), RedCapTaskError> {
-    let Some(mut participant) = Participants::find_by_id(participant, database).await? else {
-        return Err(RedCapTaskError::ParticipantNotFound);
-    };
+) -> Result<RedCapPayload, RedCapTaskError> {
     let demographics = ParticipantDemograhics::find_by_participant(participant.id, database)
         .await?
         .map(RedCapParticipantDemographics::from);
@@ -46,14 +54,7 @@ pub async fn push_participant_to_red_cap(
         .await?
         .map(RedCapHealthOverview::from);
     let mut red_cap_participant = RedCapParticipant::from(participant.clone());
-    let new_participant_id = if red_cap_participant.red_cap_id.is_none() {
-        let next_id = client.get_next_record_id().await?;
-        red_cap_participant.red_cap_id = Some(next_id);
-        info!("Setting new red cap id to {}", next_id);
-        Some(next_id)
-    } else {
-        None
-    };
+    red_cap_participant.red_cap_id = Some(record_id);
     let mut data = HashMap::new();
     red_cap_participant
         .write_to_data_set(&mut data, converter)
@@ -64,33 +65,19 @@ pub async fn push_participant_to_red_cap(
     if let Some(health_overview) = health_overview {
         health_overview.write(&mut data);
     }
-    let flattened_data = flatten_data_to_red_cap_format(data);
-    let records = vec![flattened_data];
-    client.import_records(records).await?;
-
-    if let Some(new_participant_id) = new_participant_id {
-        participant
-            .set_red_cap_id(Some(new_participant_id), database)
-            .await?;
-    }
-    Ok(())
+    Ok(flatten_data_to_red_cap_format(data))
 }
-pub async fn push_participant_medications_to_red_cap(
-    participant: i32,
+/// Builds the medications row. Medications hidden from Red Cap are skipped
+pub(crate) async fn medications_payload(
+    participant: &Participants,
+    record_id: i32,
     database: &PgPool,
-    client: &RedcapClient,
-) -> Result<(), RedCapTaskError> {
-    let Some(participant) = Participants::find_by_id(participant, database).await? else {
-        return Err(RedCapTaskError::ParticipantNotFound);
-    };
-    let Some(record_id) = participant.red_cap_id else {
-        error!(?participant, "Participant does not have a red cap id");
-        return Err(RedCapTaskError::ParticipantBaseNotPushed);
-    };
+) -> Result<RedCapPayload, RedCapTaskError> {
     let medications =
         ParticipantMedications::get_all_participant_medications(participant.id, database).await?;
     let medications: Vec<RedCapMedication> = medications
         .into_iter()
+        .filter(|x| !x.hidden_from_red_cap)
         .filter_map(|x| {
             if x.red_cap_index.is_none() {
                 error!("Medication does not have a red cap index");
@@ -107,28 +94,19 @@ pub async fn push_participant_medications_to_red_cap(
     }
     data.insert("record_id".into(), record_id.into());
 
-    let flattened_data = flatten_data_to_red_cap_format(data);
-    let records = vec![flattened_data];
-
-    client.import_records(records).await?;
-    Ok(())
+    Ok(flatten_data_to_red_cap_format(data))
 }
-pub async fn push_participant_goals_to_red_cap(
-    participant: i32,
+/// Builds the goals and steps row. Goals and steps hidden from Red Cap are skipped
+pub(crate) async fn goals_payload(
+    participant: &Participants,
+    record_id: i32,
     database: &PgPool,
     converter: &mut RedCapConverter,
-    client: &RedcapClient,
-) -> Result<(), RedCapTaskError> {
-    let Some(participant) = Participants::find_by_id(participant, database).await? else {
-        return Err(RedCapTaskError::ParticipantNotFound);
-    };
-    let Some(record_id) = participant.red_cap_id else {
-        error!(?participant, "Participant does not have a red cap id");
-        return Err(RedCapTaskError::ParticipantBaseNotPushed);
-    };
+) -> Result<RedCapPayload, RedCapTaskError> {
     let goals = ParticipantGoals::get_all_participant_goals(participant.id, database).await?;
     let goals: Vec<RedCapGoals> = goals
         .into_iter()
+        .filter(|x| !x.hidden_from_red_cap)
         .filter_map(|x| {
             if x.red_cap_index.is_none() {
                 error!("Medication does not have a red cap index");
@@ -141,7 +119,7 @@ pub async fn push_participant_goals_to_red_cap(
     let goal_steps =
         ParticipantGoalsSteps::get_all_participant_goals_steps(participant.id, database).await?;
     let mut red_cap_goal_steps = Vec::with_capacity(goal_steps.capacity());
-    for goal_step in goal_steps {
+    for goal_step in goal_steps.into_iter().filter(|x| !x.hidden_from_red_cap) {
         let red_cap_goal_step = RedCapGoalsSteps::from_db(goal_step, converter).await?;
         red_cap_goal_steps.push(red_cap_goal_step);
     }
@@ -156,26 +134,17 @@ pub async fn push_participant_goals_to_red_cap(
     }
     data.insert("record_id".into(), record_id.into());
 
-    let flattened_data = flatten_data_to_red_cap_format(data);
-    let records = vec![flattened_data];
-
-    client.import_records(records).await?;
-    Ok(())
+    Ok(flatten_data_to_red_cap_format(data))
 }
-pub async fn push_case_notes_to_redcap(
-    participant: i32,
+/// Builds a row for each case note. Ordered by their Red Cap instance. Case notes without an instance are last
+///
+/// Includes the answers to the case note questions
+pub(crate) async fn case_note_payloads(
+    participant: &Participants,
+    record_id: i32,
     database: &PgPool,
     converter: &mut RedCapConverter,
-    client: &RedcapClient,
-) -> Result<(), RedCapTaskError> {
-    let Some(participant) = Participants::find_by_id(participant, database).await? else {
-        error!("Participant not found");
-        return Err(RedCapTaskError::ParticipantNotFound);
-    };
-    let Some(record_id) = participant.red_cap_id else {
-        error!("Participant does not have a red cap id");
-        return Err(RedCapTaskError::ParticipantBaseNotPushed);
-    };
+) -> Result<Vec<(CaseNote, RedCapPayload)>, RedCapTaskError> {
     let mut case_notes = CaseNote::find_by_participant_id(participant.id, database)
         .await
         .unwrap_or_default();
@@ -184,10 +153,8 @@ pub async fn push_case_notes_to_redcap(
             .unwrap_or(i32::MAX)
             .cmp(&b.red_cap_instance.unwrap_or(i32::MAX))
     });
-
-    for (index, case_note) in case_notes.into_iter().enumerate() {
-        let instance_id = case_note.red_cap_instance.as_ref();
-        debug!(?instance_id, ?index, "Processing case note");
+    let mut payloads = Vec::with_capacity(case_notes.len());
+    for case_note in case_notes {
         let mut data = HashMap::new();
         let red_cap_case_note: RedCapCaseNoteBase = case_note.clone().into();
 
@@ -202,11 +169,121 @@ pub async fn push_case_notes_to_redcap(
         if let Some(health_measures) = health_measures {
             health_measures.write_health_measures(&mut data);
         }
+        OtherCaseNoteData::find_by_case_note_id(case_note.id, database)
+            .await?
+            .write(&mut data, converter)
+            .await?;
         data.insert("record_id".into(), record_id.into());
-        println!("{:?}", data);
-        let flattened_data = flatten_data_to_red_cap_format(data);
-        let records = vec![flattened_data];
-        client.import_records(records).await?;
+        debug!(?data, "Case note payload");
+        payloads.push((case_note, flatten_data_to_red_cap_format(data)));
+    }
+    Ok(payloads)
+}
+async fn find_participant_with_record_id(
+    participant: i32,
+    database: &PgPool,
+) -> Result<(Participants, i32), RedCapTaskError> {
+    let Some(participant) = Participants::find_by_id(participant, database).await? else {
+        error!("Participant not found");
+        return Err(RedCapTaskError::ParticipantNotFound);
+    };
+    let Some(record_id) = participant.red_cap_id else {
+        error!(?participant, "Participant does not have a red cap id");
+        return Err(RedCapTaskError::ParticipantBaseNotPushed);
+    };
+    Ok((participant, record_id))
+}
+pub async fn push_participant_to_red_cap(
+    participant: i32,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<(), RedCapTaskError> {
+    let Some(mut participant) = Participants::find_by_id(participant, database).await? else {
+        return Err(RedCapTaskError::ParticipantNotFound);
+    };
+    let (record_id, new_record) = match participant.red_cap_id {
+        Some(record_id) => (record_id, false),
+        None => {
+            let next_id = client.get_next_record_id().await?;
+            info!("Setting new red cap id to {}", next_id);
+            (next_id, true)
+        }
+    };
+    let payload = participant_payload(&participant, record_id, database, converter).await?;
+    audited_import(
+        record_id,
+        &[Forms::ParticipantInformation, Forms::HealthOverview],
+        vec![payload],
+        database,
+        client,
+    )
+    .await?;
+
+    if new_record {
+        participant
+            .set_red_cap_id(Some(record_id), database)
+            .await?;
+    }
+    Ok(())
+}
+pub async fn push_participant_medications_to_red_cap(
+    participant: i32,
+    database: &PgPool,
+    client: &RedcapClient,
+) -> Result<(), RedCapTaskError> {
+    let (participant, record_id) = find_participant_with_record_id(participant, database).await?;
+    let payload = medications_payload(&participant, record_id, database).await?;
+    audited_import(
+        record_id,
+        &[Forms::Medications],
+        vec![payload],
+        database,
+        client,
+    )
+    .await?;
+    ParticipantMedications::mark_all_synced_with_red_cap(participant.id, database).await?;
+    Ok(())
+}
+pub async fn push_participant_goals_to_red_cap(
+    participant: i32,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<(), RedCapTaskError> {
+    let (participant, record_id) = find_participant_with_record_id(participant, database).await?;
+    let payload = goals_payload(&participant, record_id, database, converter).await?;
+    audited_import(
+        record_id,
+        &[Forms::WellnessGoals],
+        vec![payload],
+        database,
+        client,
+    )
+    .await?;
+    ParticipantGoals::mark_all_synced_with_red_cap(participant.id, database).await?;
+    ParticipantGoalsSteps::mark_all_synced_with_red_cap(participant.id, database).await?;
+    Ok(())
+}
+pub async fn push_case_notes_to_redcap(
+    participant: i32,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<(), RedCapTaskError> {
+    let (participant, record_id) = find_participant_with_record_id(participant, database).await?;
+    let payloads = case_note_payloads(&participant, record_id, database, converter).await?;
+    for (index, (case_note, payload)) in payloads.into_iter().enumerate() {
+        let instance_id = case_note.red_cap_instance.as_ref();
+        debug!(?instance_id, ?index, "Pushing case note");
+        audited_import(
+            record_id,
+            &[Forms::CaseNotes],
+            vec![payload],
+            database,
+            client,
+        )
+        .await?;
 
         if case_note.red_cap_instance.is_none() {
             case_note
@@ -216,16 +293,279 @@ pub async fn push_case_notes_to_redcap(
     }
     Ok(())
 }
+/// A field that would be changed by a push
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PushFieldChange {
+    pub field: String,
+    /// The value currently in Red Cap. None if the field is empty
+    pub old: Option<String>,
+    pub new: String,
+}
+/// A row that would be sent to Red Cap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PushRowPreview {
+    /// The repeating instrument of the row. None for the non repeating forms
+    pub repeat_instrument: Option<String>,
+    /// The instance of the repeating instrument. `new` if it will be created
+    pub repeat_instance: Option<String>,
+    /// The exact data that would be imported
+    pub payload: BTreeMap<String, String>,
+    /// The fields whose value in Red Cap differs from the payload
+    pub changes: Vec<PushFieldChange>,
+}
+/// What pushing a participant would send to Red Cap. See [preview_participant_push]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ParticipantPushPreview {
+    pub participant_id: i32,
+    /// The record id in Red Cap. For a new record this is the next available id
+    pub record_id: i32,
+    /// The record does not exist in Red Cap yet
+    pub new_record: bool,
+    pub rows: Vec<PushRowPreview>,
+}
+impl ParticipantPushPreview {
+    pub fn has_changes(&self) -> bool {
+        self.rows.iter().any(|row| !row.changes.is_empty())
+    }
+}
+/// Fields that identify the row instead of holding data
+pub(crate) const ROW_KEYS: [&str; 3] = [
+    "record_id",
+    "redcap_repeat_instrument",
+    "redcap_repeat_instance",
+];
+fn red_cap_value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Null => String::new(),
+        Value::Bool(value) => (*value as u8).to_string(),
+        other => other.to_string(),
+    }
+}
+pub(crate) fn non_empty(row: &HashMap<String, Value>, key: &str) -> Option<String> {
+    row.get(key)
+        .map(red_cap_value_to_string)
+        .filter(|value| !value.is_empty())
+}
+/// Finds the row of the same repeating instrument and instance. The non repeating row if the instrument is None
+pub(crate) fn matching_row<'a>(
+    rows: &'a [HashMap<String, Value>],
+    repeat_instrument: &Option<String>,
+    repeat_instance: &Option<String>,
+) -> Option<&'a HashMap<String, Value>> {
+    rows.iter().find(|row| {
+        non_empty(row, "redcap_repeat_instrument") == *repeat_instrument
+            && (repeat_instrument.is_none()
+                || non_empty(row, "redcap_repeat_instance") == *repeat_instance)
+    })
+}
+/// Compares a payload with the row of the same instance currently in Red Cap
+fn preview_row(payload: RedCapPayload, existing: &[HashMap<String, Value>]) -> PushRowPreview {
+    let repeat_instrument = payload
+        .get("redcap_repeat_instrument")
+        .filter(|instrument| !instrument.is_empty())
+        .cloned();
+    let repeat_instance = payload.get("redcap_repeat_instance").cloned();
+    let current = matching_row(existing, &repeat_instrument, &repeat_instance);
+    let payload: BTreeMap<String, String> = payload.into_iter().collect();
+    let changes = payload
+        .iter()
+        .filter(|(field, _)| !ROW_KEYS.contains(&field.as_str()))
+        .filter_map(|(field, new)| {
+            let old = current.and_then(|row| non_empty(row, field));
+            if old.as_deref().unwrap_or_default() == new {
+                return None;
+            }
+            Some(PushFieldChange {
+                field: field.clone(),
+                old,
+                new: new.clone(),
+            })
+        })
+        .collect();
+    PushRowPreview {
+        repeat_instrument,
+        repeat_instance,
+        payload,
+        changes,
+    }
+}
+/// Builds everything a push of the participant would send and compares it with the values in Red Cap.
+///
+/// Nothing is imported into Red Cap and nothing is changed locally
+pub async fn preview_participant_push(
+    participant: i32,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Result<ParticipantPushPreview, RedCapTaskError> {
+    let Some(participant) = Participants::find_by_id(participant, database).await? else {
+        return Err(RedCapTaskError::ParticipantNotFound);
+    };
+    let (record_id, new_record) = match participant.red_cap_id {
+        Some(record_id) => (record_id, false),
+        None => (client.get_next_record_id().await?, true),
+    };
+    let mut payloads = vec![
+        participant_payload(&participant, record_id, database, converter).await?,
+        medications_payload(&participant, record_id, database).await?,
+        goals_payload(&participant, record_id, database, converter).await?,
+    ];
+    payloads.extend(
+        case_note_payloads(&participant, record_id, database, converter)
+            .await?
+            .into_iter()
+            .map(|(_, payload)| payload),
+    );
+    let existing = if new_record {
+        Vec::new()
+    } else {
+        audited_export(
+            record_id,
+            ExportOptions {
+                records: Some(vec![record_id as usize].into()),
+                ..Default::default()
+            },
+            database,
+            client,
+        )
+        .await?
+    };
+    let rows = payloads
+        .into_iter()
+        .map(|payload| preview_row(payload, &existing))
+        .collect();
+    Ok(ParticipantPushPreview {
+        participant_id: participant.id,
+        record_id,
+        new_record,
+        rows,
+    })
+}
 #[cfg(test)]
 mod tests {
 
+    use ahash::{HashMap, HashMapExt};
     use anyhow::Context;
 
     use crate::{
-        red_cap::{api::RedcapClient, converter::RedCapConverter},
-        utils::testing::config::testing::{get_testing_config, no_testing_config},
+        database::red_cap::participants::{NewParticipant, ParticipantType, Participants},
+        red_cap::{
+            api::RedcapClient,
+            converter::RedCapConverter,
+            mock::{MockRedCap, MockRedCapConfig},
+            tasks::pull_record_base_types,
+        },
+        utils::testing::config::testing::{
+            get_testing_config, get_testing_db, no_db_connection, no_testing_config,
+        },
     };
+    /// Pushes a participant to a mock Red Cap then pulls a change made in Red Cap
+    #[tokio::test]
+    pub async fn push_and_pull_with_mock_red_cap() -> anyhow::Result<()> {
+        let Some(database) = get_testing_db().await else {
+            no_db_connection()?;
+            return Ok(());
+        };
+        let mock = MockRedCap::new(MockRedCapConfig::default())
+            .await?
+            .start(([127, 0, 0, 1], 0).into())
+            .await?;
+        let client = RedcapClient::with_config(&mock.client_config()).await?;
+        let mut converter: RedCapConverter = RedCapConverter::new(database.clone()).await?;
+        let participant = NewParticipant {
+            first_name: "John".to_string(),
+            last_name: "Doe".to_string(),
+            other_contact: Some("Mock Red Cap Test".to_owned()),
+            ..Default::default()
+        }
+        .insert_returning(&database)
+        .await?;
 
+        super::push_participant_to_red_cap(participant.id, &database, &mut converter, &client)
+            .await?;
+        let record_id = Participants::find_by_id(participant.id, &database)
+            .await?
+            .and_then(|participant| participant.red_cap_id)
+            .context("Participant was not given a red cap id")?;
+        assert_eq!(client.get_record_ids(None).await?, vec![record_id]);
+
+        let mut changed_in_red_cap = HashMap::new();
+        changed_in_red_cap.insert("record_id".to_owned(), record_id.to_string());
+        changed_in_red_cap.insert("first_name".to_owned(), "Jonathan".to_owned());
+        client.import_records(vec![changed_in_red_cap]).await?;
+
+        pull_record_base_types(record_id, &database, &mut converter, &client).await?;
+        let pulled = Participants::find_by_id(participant.id, &database)
+            .await?
+            .context("Participant was deleted")?;
+        assert_eq!(pulled.first_name, "Jonathan");
+        assert_eq!(pulled.last_name, "Doe");
+        Ok(())
+    }
+
+    #[test]
+    pub fn preview_row_compares_the_same_instance() {
+        let row = |values: &[(&str, &str)]| -> HashMap<String, serde_json::Value> {
+            values
+                .iter()
+                .map(|(key, value)| (key.to_string(), (*value).into()))
+                .collect()
+        };
+        let existing = vec![
+            row(&[
+                ("record_id", "1"),
+                ("redcap_repeat_instrument", ""),
+                ("first_name", "John"),
+            ]),
+            row(&[
+                ("record_id", "1"),
+                ("redcap_repeat_instrument", "case_note"),
+                ("redcap_repeat_instance", "1"),
+                ("reason", "Checkup"),
+            ]),
+            row(&[
+                ("record_id", "1"),
+                ("redcap_repeat_instrument", "case_note"),
+                ("redcap_repeat_instance", "2"),
+                ("reason", "Fall"),
+            ]),
+        ];
+        let mut payload = HashMap::new();
+        payload.insert("record_id".to_owned(), "1".to_owned());
+        payload.insert(
+            "redcap_repeat_instrument".to_owned(),
+            "case_note".to_owned(),
+        );
+        payload.insert("redcap_repeat_instance".to_owned(), "2".to_owned());
+        payload.insert("reason".to_owned(), "Fall".to_owned());
+        payload.insert("subjective_info".to_owned(), "Tripped".to_owned());
+
+        let preview = super::preview_row(payload, &existing);
+        assert_eq!(preview.repeat_instrument.as_deref(), Some("case_note"));
+        assert_eq!(
+            preview.changes,
+            vec![super::PushFieldChange {
+                field: "subjective_info".to_owned(),
+                old: None,
+                new: "Tripped".to_owned(),
+            }]
+        );
+
+        let mut payload = HashMap::new();
+        payload.insert("record_id".to_owned(), "1".to_owned());
+        payload.insert("first_name".to_owned(), "Jonathan".to_owned());
+        let preview = super::preview_row(payload, &existing);
+        assert_eq!(preview.repeat_instrument, None);
+        assert_eq!(
+            preview.changes,
+            vec![super::PushFieldChange {
+                field: "first_name".to_owned(),
+                old: Some("John".to_owned()),
+                new: "Jonathan".to_owned(),
+            }]
+        );
+    }
     #[tokio::test]
     #[ignore]
     pub async fn import_record_to_red_cap() -> anyhow::Result<()> {
@@ -235,7 +575,7 @@ mod tests {
         };
         config.init_logger();
         let database = config.connect_to_db().await?;
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         let mut converter: RedCapConverter = RedCapConverter::new(database.clone()).await?;
 
         super::push_participant_to_red_cap(1, &database, &mut converter, &client).await?;
@@ -251,7 +591,7 @@ mod tests {
         };
         config.init_logger();
         let database = config.connect_to_db().await?;
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         //let mut converter = RedCapConverter::new(database.clone()).await?;
 
         super::push_participant_medications_to_red_cap(1, &database, &client).await?;
@@ -267,7 +607,7 @@ mod tests {
         };
         config.init_logger();
         let database = config.connect_to_db().await?;
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         let mut converter: RedCapConverter = RedCapConverter::new(database.clone()).await?;
         super::push_participant_goals_to_red_cap(1, &database, &mut converter, &client).await?;
         Ok(())
@@ -282,7 +622,7 @@ mod tests {
         };
         config.init_logger();
         let database = config.connect_to_db().await?;
-        let client = RedcapClient::new(config.red_cap_token.context("No RED_CAP_TOKEN")?).await?;
+        let client = RedcapClient::with_config(&config.red_cap).await?;
         let mut converter: RedCapConverter = RedCapConverter::new(database.clone()).await?;
 
         super::push_case_notes_to_redcap(1, &database, &mut converter, &client).await?;
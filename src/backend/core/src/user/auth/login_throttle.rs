@@ -0,0 +1,68 @@
+//! Throttling of failed logins. The locks are stored in [crate::database::user::lockout]
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+/// Failed logins are counted per user and per IP address within a sliding window.
+///
+/// Once a limit is reached the user or IP address is locked. Each lock in a row is twice as long as the last.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoginThrottleConfig {
+    pub enabled: bool,
+    /// Seconds that failed logins are counted for
+    pub window: u64,
+    /// Failed logins of a user within the window before the user is locked
+    pub max_failures_per_user: u32,
+    /// Failed logins from an IP address within the window before the IP address is locked
+    pub max_failures_per_ip: u32,
+    /// Seconds of the first lock
+    pub base_lockout: u64,
+    /// The longest a lock can be in seconds
+    pub max_lockout: u64,
+    /// Seconds after a lock ends before the next lock starts at `base_lockout` again
+    pub reset_after: u64,
+}
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window: 15 * 60,
+            max_failures_per_user: 5,
+            max_failures_per_ip: 20,
+            base_lockout: 60,
+            max_lockout: 60 * 60,
+            reset_after: 24 * 60 * 60,
+        }
+    }
+}
+impl LoginThrottleConfig {
+    pub fn window(&self) -> Duration {
+        Duration::seconds(self.window as i64)
+    }
+    pub fn reset_after(&self) -> Duration {
+        Duration::seconds(self.reset_after as i64)
+    }
+    /// The length of a lock. `lock_count` is how many locks in a row including this one
+    pub fn lockout_duration(&self, lock_count: i32) -> Duration {
+        let doublings = lock_count.saturating_sub(1).clamp(0, 32) as u32;
+        let seconds = self
+            .base_lockout
+            .saturating_mul(2u64.saturating_pow(doublings))
+            .min(self.max_lockout);
+        Duration::seconds(seconds as i64)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockout_doubles_until_max() {
+        let config = LoginThrottleConfig::default();
+        assert_eq!(config.lockout_duration(1), Duration::seconds(60));
+        assert_eq!(config.lockout_duration(2), Duration::seconds(120));
+        assert_eq!(config.lockout_duration(3), Duration::seconds(240));
+        assert_eq!(config.lockout_duration(7), Duration::seconds(60 * 60));
+        assert_eq!(config.lockout_duration(1000), Duration::seconds(60 * 60));
+    }
+}
@@ -1,10 +1,18 @@
 use serde::{Deserialize, Serialize};
+mod login_throttle;
+pub mod oidc;
+mod password;
+pub use login_throttle::LoginThrottleConfig;
+use oidc::OidcConfig;
+pub use password::PasswordViolation;
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AuthenticationProviders {
     /// Will use the the `user_authentication_password` table to authenticate users.
     Password,
     /// Will use the systems configured SAML provider to authenticate users.
     SAML,
+    /// Will use the configured OpenID Connect provider to authenticate users.
+    OIDC,
 }
 /// What Authentications Providers are enabled.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,12 +21,19 @@ pub struct AuthenticationProvidersConfig {
     pub password: Option<PasswordConfig>,
     /// SAML based authentication.
     pub saml: Option<SAMLProvider>,
+    /// OpenID Connect based single sign-on.
+    pub oidc: Option<OidcConfig>,
+    /// Throttling of failed password logins.
+    #[serde(default)]
+    pub login_throttle: LoginThrottleConfig,
 }
 impl Default for AuthenticationProvidersConfig {
     fn default() -> Self {
         Self {
             password: Some(Default::default()),
             saml: None,
+            oidc: None,
+            login_throttle: Default::default(),
         }
     }
 }
@@ -26,6 +41,7 @@ impl Default for AuthenticationProvidersConfig {
 pub struct SAMLProvider {
     // TODO: Configure SAML provider
 }
+/// The password policy. See [PasswordConfig::validate]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct PasswordConfig {
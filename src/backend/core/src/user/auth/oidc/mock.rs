@@ -0,0 +1,528 @@
+//! A local OpenID Connect provider for testing.
+//!
+//! Implements the parts used by [OidcClient](super::OidcClient).
+//! - `/.well-known/openid-configuration`
+//! - `/jwks`
+//! - `/authorize` Does not show a login page. The user is logged in as [MockOidc::user] and sent back right away
+//! - `/token` Checks the PKCE verifier and returns an ID token signed with RS256
+//!
+//! The signing key is a fixed test key. It must never be trusted outside of testing.
+use std::{net::SocketAddr, sync::Arc};
+
+use ahash::{HashMap, HashMapExt};
+use axum::{
+    Form, Json, Router,
+    extract::{Query, State},
+    http::{StatusCode, header::LOCATION},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::{Duration, Local};
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use rand::{Rng, SeedableRng, distr::Alphanumeric, rngs::StdRng};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use thiserror::Error;
+use tokio::{net::TcpListener, sync::RwLock, task::JoinHandle};
+use tracing::{debug, error, info};
+use url::Url;
+
+use super::{OidcConfig, code_challenge};
+/// PKCS#1 RSA key used to sign ID tokens
+const MOCK_SIGNING_KEY: &str = include_str!("mock_key.pem");
+/// The base64url encoded modulus of [MOCK_SIGNING_KEY]
+const MOCK_SIGNING_KEY_MODULUS: &str = "ztVmOnLT33paEusl9zOGzT02_vwkN9tvFErmuGFapjv0qHn62D0lu57X1pahF_rtQ7ZNfb0KQ_BpbOeLN7sMLm6stkND3BDTroBMA6Kmd8i5KOKRXEDU1ymr-fSW_b4K54itAn2UoPVWGf2KhKTAis9IHX_e1dFdZHpRQVyiHXcgBDeHcL3EJmPhAhD5lVl8ZONE_oEWQ4FkPmIwpW002ncbgpvLGaGBRq_a1i0ekUIEwaK9GVb3wef61V0gI1XM1ZfZD-TJ22Jwb6kosdY4eTl4eXmccrybWs9CFhCwFGKNhWkouPPidgyQxiXkwAzoF16lrq6M4p5ItfiosNcoDQ";
+const MOCK_SIGNING_KEY_EXPONENT: &str = "AQAB";
+const MOCK_KEY_ID: &str = "mock-oidc";
+#[derive(Debug, Error)]
+pub enum MockOidcError {
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+}
+#[derive(Debug, Clone)]
+pub struct MockOidcConfig {
+    pub client_id: String,
+    /// If set the token endpoint requires it
+    pub client_secret: Option<String>,
+    /// The claims of the user that logs in. `iss`, `aud`, `exp`, `iat` and `nonce` are added
+    pub user: Map<String, Value>,
+}
+impl Default for MockOidcConfig {
+    fn default() -> Self {
+        Self {
+            client_id: "mock-client".to_owned(),
+            client_secret: None,
+            user: mock_user("mock-user", "mock.user@vcu.edu", "Mock", "User"),
+        }
+    }
+}
+/// Claims for a user with a verified email
+pub fn mock_user(
+    sub: &str,
+    email: &str,
+    given_name: &str,
+    family_name: &str,
+) -> Map<String, Value> {
+    let Value::Object(claims) = json!({
+        "sub": sub,
+        "email": email,
+        "email_verified": true,
+        "given_name": given_name,
+        "family_name": family_name,
+        "preferred_username": email.split('@').next().unwrap_or(email),
+    }) else {
+        unreachable!("json! of an object is an object");
+    };
+    claims
+}
+/// An authorization code that has not been exchanged yet
+#[derive(Debug)]
+struct IssuedCode {
+    code_challenge: String,
+    redirect_uri: String,
+    nonce: Option<String>,
+    claims: Map<String, Value>,
+}
+#[derive(Debug)]
+struct MockOidcState {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    user: RwLock<Map<String, Value>>,
+    codes: RwLock<HashMap<String, IssuedCode>>,
+}
+#[derive(Debug, Clone)]
+pub struct MockOidc {
+    state: Arc<MockOidcState>,
+}
+impl MockOidc {
+    /// The claims of the user that will log in next
+    pub async fn user(&self) -> Map<String, Value> {
+        self.state.user.read().await.clone()
+    }
+    /// Changes the user that logs in
+    pub async fn set_user(&self, user: Map<String, Value>) {
+        *self.state.user.write().await = user;
+    }
+    /// Serves the mock on the address. Use port 0 to pick a free port
+    pub async fn start(
+        config: MockOidcConfig,
+        address: SocketAddr,
+    ) -> Result<RunningMockOidc, MockOidcError> {
+        // Fail early if the key can not be read
+        EncodingKey::from_rsa_pem(MOCK_SIGNING_KEY.as_bytes())?;
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+        let MockOidcConfig {
+            client_id,
+            client_secret,
+            user,
+        } = config;
+        let mock = MockOidc {
+            state: Arc::new(MockOidcState {
+                issuer: format!("http://{address}"),
+                client_id,
+                client_secret,
+                user: RwLock::new(user),
+                codes: RwLock::new(HashMap::new()),
+            }),
+        };
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .with_state(mock.state.clone());
+        let handle = tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, router).await {
+                error!(?err, "Mock OpenID provider stopped");
+            }
+        });
+        info!(?address, "Mock OpenID provider started");
+        Ok(RunningMockOidc {
+            address,
+            mock,
+            handle,
+        })
+    }
+}
+/// A mock that is being served. It is stopped when dropped
+#[derive(Debug)]
+pub struct RunningMockOidc {
+    pub address: SocketAddr,
+    pub mock: MockOidc,
+    handle: JoinHandle<()>,
+}
+impl RunningMockOidc {
+    pub fn issuer(&self) -> &str {
+        &self.mock.state.issuer
+    }
+    /// Config for an [OidcClient](super::OidcClient) that uses this mock
+    pub fn client_config(&self, redirect_url: impl Into<String>) -> OidcConfig {
+        let mut config = OidcConfig::new(
+            self.issuer(),
+            self.mock.state.client_id.clone(),
+            redirect_url,
+        );
+        config.client_secret = self.mock.state.client_secret.clone();
+        config
+    }
+}
+impl Drop for RunningMockOidc {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+/// Same error body as RFC 6749 section 5.2
+fn error_response(status: StatusCode, error: &str, description: impl Into<String>) -> Response {
+    (
+        status,
+        Json(json!({ "error": error, "error_description": description.into() })),
+    )
+        .into_response()
+}
+async fn discovery(State(state): State<Arc<MockOidcState>>) -> Json<Value> {
+    let issuer = &state.issuer;
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+async fn jwks() -> Json<Value> {
+    Json(json!({
+        "keys": [{
+            "kty": "RSA",
+            "use": "sig",
+            "alg": "RS256",
+            "kid": MOCK_KEY_ID,
+            "n": MOCK_SIGNING_KEY_MODULUS,
+            "e": MOCK_SIGNING_KEY_EXPONENT,
+        }]
+    }))
+}
+#[derive(Debug, Deserialize)]
+struct AuthorizeQuery {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+async fn authorize(
+    State(state): State<Arc<MockOidcState>>,
+    Query(query): Query<AuthorizeQuery>,
+) -> Response {
+    if query.response_type != "code" {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "unsupported_response_type",
+            "Only the code flow is supported",
+        );
+    }
+    if query.client_id != state.client_id {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "Unknown client",
+        );
+    }
+    let (Some(challenge), Some("S256")) =
+        (query.code_challenge, query.code_challenge_method.as_deref())
+    else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "A S256 code_challenge is required",
+        );
+    };
+    let Ok(mut redirect) = Url::parse(&query.redirect_uri) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "Invalid redirect_uri",
+        );
+    };
+    let code = random_string(32);
+    debug!(?code, "Mock OpenID provider issued a code");
+    state.codes.write().await.insert(
+        code.clone(),
+        IssuedCode {
+            code_challenge: challenge,
+            redirect_uri: query.redirect_uri,
+            nonce: query.nonce,
+            claims: state.user.read().await.clone(),
+        },
+    );
+    {
+        let mut pairs = redirect.query_pairs_mut();
+        pairs.append_pair("code", &code);
+        if let Some(request_state) = &query.state {
+            pairs.append_pair("state", request_state);
+        }
+    }
+    (StatusCode::SEE_OTHER, [(LOCATION, redirect.to_string())]).into_response()
+}
+#[derive(Debug, Deserialize)]
+struct TokenRequest {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    #[serde(default)]
+    client_secret: Option<String>,
+    #[serde(default)]
+    code_verifier: Option<String>,
+}
+async fn token(
+    State(state): State<Arc<MockOidcState>>,
+    Form(request): Form<TokenRequest>,
+) -> Response {
+    if request.grant_type != "authorization_code" {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "unsupported_grant_type",
+            "Only authorization_code is supported",
+        );
+    }
+    if request.client_id != state.client_id
+        || (state.client_secret.is_some() && request.client_secret != state.client_secret)
+    {
+        return error_response(StatusCode::UNAUTHORIZED, "invalid_client", "Invalid client");
+    }
+    // Codes can only be used once
+    let Some(issued) = state.codes.write().await.remove(&request.code) else {
+        return error_response(StatusCode::BAD_REQUEST, "invalid_grant", "Unknown code");
+    };
+    if issued.redirect_uri != request.redirect_uri {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "redirect_uri does not match",
+        );
+    }
+    if request.code_verifier.as_deref().map(code_challenge) != Some(issued.code_challenge) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "PKCE verification failed",
+        );
+    }
+    let now = Local::now();
+    let mut claims = issued.claims;
+    claims.insert("iss".to_owned(), json!(state.issuer));
+    claims.insert("aud".to_owned(), json!(state.client_id));
+    claims.insert("iat".to_owned(), json!(now.timestamp()));
+    claims.insert(
+        "exp".to_owned(),
+        json!((now + Duration::minutes(5)).timestamp()),
+    );
+    if let Some(nonce) = issued.nonce {
+        claims.insert("nonce".to_owned(), json!(nonce));
+    }
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(MOCK_KEY_ID.to_owned());
+    let id_token = EncodingKey::from_rsa_pem(MOCK_SIGNING_KEY.as_bytes())
+        .and_then(|key| encode(&header, &claims, &key));
+    match id_token {
+        Ok(id_token) => Json(json!({
+            "access_token": random_string(32),
+            "token_type": "Bearer",
+            "expires_in": 300,
+            "id_token": id_token,
+        }))
+        .into_response(),
+        Err(err) => {
+            error!(?err, "Mock OpenID provider failed to sign the ID token");
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                err.to_string(),
+            )
+        }
+    }
+}
+fn random_string(length: usize) -> String {
+    let mut rand = StdRng::from_os_rng();
+    (0..length)
+        .map(|_| rand.sample(Alphanumeric) as char)
+        .collect()
+}
+#[cfg(test)]
+mod tests {
+    use reqwest::{StatusCode, Url, header::LOCATION, redirect::Policy};
+    use serde_json::Value;
+
+    use super::{MockOidc, MockOidcConfig, mock_user};
+    use crate::user::auth::oidc::{OidcClient, OidcError, OidcProviderMetadata, code_challenge};
+
+    const REDIRECT_URL: &str = "http://localhost:8080/api/auth/login/oidc/callback";
+    /// Follows the login URL to the mock. Returns the code and state it redirects back with
+    async fn authorize(login_url: Url) -> anyhow::Result<(String, String)> {
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .build()?;
+        let response = client.get(login_url).send().await?;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let location = Url::parse(response.headers()[LOCATION].to_str()?)?;
+        assert!(location.as_str().starts_with(REDIRECT_URL));
+        let param = |name: &str| {
+            location
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+                .unwrap_or_default()
+        };
+        Ok((param("code"), param("state")))
+    }
+
+    #[tokio::test]
+    pub async fn login_round_trip() -> anyhow::Result<()> {
+        let mock = MockOidc::start(
+            MockOidcConfig {
+                client_secret: Some("secret".to_owned()),
+                ..Default::default()
+            },
+            ([127, 0, 0, 1], 0).into(),
+        )
+        .await?;
+        let client = OidcClient::new(mock.client_config(REDIRECT_URL))?;
+
+        let (code, state) = authorize(client.begin_login().await?.url).await?;
+        let claims = client.finish_login(&state, &code).await?;
+        assert_eq!(claims.iss, mock.issuer());
+        assert_eq!(claims.sub, "mock-user");
+        assert_eq!(claims.trusted_email(true), Some("mock.user@vcu.edu"));
+
+        // The state can not be used twice
+        let err = client.finish_login(&state, &code).await.unwrap_err();
+        assert!(matches!(err, OidcError::UnknownState), "{err:?}");
+
+        mock.mock
+            .set_user(mock_user("other", "other@vcu.edu", "Other", "User"))
+            .await;
+        let (code, state) = authorize(client.begin_login().await?.url).await?;
+        let claims = client.finish_login(&state, &code).await?;
+        assert_eq!(claims.sub, "other");
+        Ok(())
+    }
+    #[tokio::test]
+    pub async fn wrong_client_secret_is_rejected() -> anyhow::Result<()> {
+        let mock = MockOidc::start(
+            MockOidcConfig {
+                client_secret: Some("secret".to_owned()),
+                ..Default::default()
+            },
+            ([127, 0, 0, 1], 0).into(),
+        )
+        .await?;
+        let mut config = mock.client_config(REDIRECT_URL);
+        config.client_secret = Some("not-the-secret".to_owned());
+        let client = OidcClient::new(config)?;
+
+        let (code, state) = authorize(client.begin_login().await?.url).await?;
+        let err = client.finish_login(&state, &code).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                OidcError::Provider {
+                    status: StatusCode::UNAUTHORIZED,
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    pub async fn pending_logins_are_capped() -> anyhow::Result<()> {
+        let mock = MockOidc::start(MockOidcConfig::default(), ([127, 0, 0, 1], 0).into()).await?;
+        let mut config = mock.client_config(REDIRECT_URL);
+        config.max_pending_logins = 2;
+        let client = OidcClient::new(config)?;
+
+        let first = client.begin_login().await?;
+        client.begin_login().await?;
+        let err = client.begin_login().await.unwrap_err();
+        assert!(matches!(err, OidcError::TooManyPendingLogins), "{err:?}");
+
+        // Finishing a login frees its place
+        let (code, state) = authorize(first.url).await?;
+        assert_eq!(state, first.state);
+        client.finish_login(&state, &code).await?;
+        client.begin_login().await?;
+        Ok(())
+    }
+    /// Runs the flow by hand with a known verifier. Returns the token response
+    ///
+    /// `verifier_sent` replaces the verifier sent to the token endpoint
+    async fn exchange_by_hand(
+        metadata: &OidcProviderMetadata,
+        verifier_sent: Option<&str>,
+    ) -> anyhow::Result<Value> {
+        let verifier = "a-verifier-that-only-this-test-knows";
+        let mut login_url = Url::parse(&metadata.authorization_endpoint)?;
+        login_url
+            .query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", "mock-client")
+            .append_pair("redirect_uri", REDIRECT_URL)
+            .append_pair("state", "state")
+            .append_pair("nonce", "nonce")
+            .append_pair("code_challenge", &code_challenge(verifier))
+            .append_pair("code_challenge_method", "S256");
+        let (code, _) = authorize(login_url).await?;
+        let response = reqwest::Client::new()
+            .post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code.as_str()),
+                ("redirect_uri", REDIRECT_URL),
+                ("client_id", "mock-client"),
+                ("code_verifier", verifier_sent.unwrap_or(verifier)),
+            ])
+            .send()
+            .await?
+            .json()
+            .await?;
+        Ok(response)
+    }
+    #[tokio::test]
+    pub async fn id_tokens_are_validated() -> anyhow::Result<()> {
+        let mock = MockOidc::start(MockOidcConfig::default(), ([127, 0, 0, 1], 0).into()).await?;
+        let client = OidcClient::new(mock.client_config(REDIRECT_URL))?;
+        let metadata = client.metadata().await?;
+
+        let rejected = exchange_by_hand(metadata, Some("not-the-verifier")).await?;
+        assert_eq!(rejected["error"], "invalid_grant", "PKCE must be checked");
+
+        let response = exchange_by_hand(metadata, None).await?;
+        let id_token = response["id_token"].as_str().unwrap_or_default();
+        client.validate_id_token(id_token, "nonce").await?;
+
+        let err = client
+            .validate_id_token(id_token, "other-nonce")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, OidcError::NonceMismatch), "{err:?}");
+
+        let mut other_config = mock.client_config(REDIRECT_URL);
+        other_config.client_id = "other-client".to_owned();
+        let other_client = OidcClient::new(other_config)?;
+        let err = other_client
+            .validate_id_token(id_token, "nonce")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, OidcError::InvalidToken(_)), "{err:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,582 @@
+//! OpenID Connect single sign-on.
+//!
+//! Uses the authorization code flow with PKCE.
+//!
+//! 1. [OidcClient::begin_login] returns the URL of the provider's login page. The state, nonce and PKCE verifier are kept in memory.
+//!    The caller must also give the state to the browser. The backend uses a cookie.
+//! 2. The provider sends the user back to [OidcConfig::redirect_url] with a `code` and the `state`.
+//! 3. The caller checks that the browser has the state. Otherwise anyone could send a victim a callback URL for their own account.
+//! 4. [OidcClient::finish_login] exchanges the code for an ID token and validates it against the provider's JWKS.
+//!
+//! The provider's endpoints are found with `{issuer}/.well-known/openid-configuration`.
+//!
+//! Matching the claims to a local user is done by [find_or_provision_oidc_user](crate::database::user::auth::oidc::find_or_provision_oidc_user)
+use std::time::{Duration, Instant};
+
+use ahash::{HashMap, HashMapExt};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    jwk::{Jwk, JwkSet},
+};
+use rand::{Rng, SeedableRng, distr::Alphanumeric, rngs::StdRng};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::sync::{Mutex, OnceCell, RwLock};
+use tracing::{debug, instrument, warn};
+#[cfg(any(test, feature = "mock-oidc"))]
+pub mod mock;
+
+/// Algorithms accepted for ID tokens. Symmetric algorithms are not accepted
+const SUPPORTED_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("Invalid ID token: {0}")]
+    InvalidToken(#[from] jsonwebtoken::errors::Error),
+    /// The provider responded with an error
+    #[error("The OpenID provider returned an error ({status}): {message}")]
+    Provider { status: StatusCode, message: String },
+    #[error("The discovered issuer {found:?} does not match the configured issuer {expected:?}")]
+    IssuerMismatch { expected: String, found: String },
+    #[error("The login state is unknown or expired")]
+    UnknownState,
+    /// [OidcConfig::max_pending_logins] was reached
+    #[error("Too many logins are in progress")]
+    TooManyPendingLogins,
+    #[error("The provider does not have a key with the id {0:?}")]
+    UnknownKey(Option<String>),
+    #[error("ID tokens signed with {0:?} are not accepted")]
+    UnsupportedAlgorithm(Algorithm),
+    #[error("The ID token nonce does not match the login")]
+    NonceMismatch,
+    #[error("The token response did not contain an ID token")]
+    MissingIdToken,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcConfig {
+    /// The issuer of the provider. Discovery is done with `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    /// Sent to the token endpoint if set. PKCE is always used
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// Where the provider sends the user back to. Must point to `/api/auth/login/oidc/callback`
+    pub redirect_url: String,
+    /// Scopes to request besides `openid`
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// Where the user is sent after logging in
+    #[serde(default = "default_post_login_redirect")]
+    pub post_login_redirect: String,
+    /// Create a user if no user has the email of the claims.
+    ///
+    /// If false only existing users can log in.
+    #[serde(default)]
+    pub provision_users: bool,
+    /// Only trust emails the provider says are verified
+    #[serde(default = "default_true")]
+    pub require_verified_email: bool,
+    /// Roles given to users based on their claims. Checked on every login
+    #[serde(default)]
+    pub role_rules: Vec<OidcRoleRule>,
+    /// Seconds a user has to finish logging in at the provider
+    #[serde(default = "default_login_timeout")]
+    pub login_timeout: u64,
+    /// The most logins that can be in progress at once. New logins are refused until some finish or time out
+    #[serde(default = "default_max_pending_logins")]
+    pub max_pending_logins: usize,
+    /// Request timeout in seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+fn default_scopes() -> Vec<String> {
+    vec!["email".to_owned(), "profile".to_owned()]
+}
+fn default_post_login_redirect() -> String {
+    "/".to_owned()
+}
+fn default_true() -> bool {
+    true
+}
+fn default_login_timeout() -> u64 {
+    600
+}
+fn default_max_pending_logins() -> usize {
+    1000
+}
+fn default_timeout() -> u64 {
+    30
+}
+impl OidcConfig {
+    /// Creates a config with the default settings
+    pub fn new(
+        issuer: impl Into<String>,
+        client_id: impl Into<String>,
+        redirect_url: impl Into<String>,
+    ) -> Self {
+        Self {
+            issuer: issuer.into(),
+            client_id: client_id.into(),
+            client_secret: None,
+            redirect_url: redirect_url.into(),
+            scopes: default_scopes(),
+            post_login_redirect: default_post_login_redirect(),
+            provision_users: false,
+            require_verified_email: true,
+            role_rules: Vec::new(),
+            login_timeout: default_login_timeout(),
+            max_pending_logins: default_max_pending_logins(),
+            timeout: default_timeout(),
+        }
+    }
+    fn scope(&self) -> String {
+        let mut scope = vec!["openid"];
+        scope.extend(
+            self.scopes
+                .iter()
+                .map(String::as_str)
+                .filter(|scope| *scope != "openid"),
+        );
+        scope.join(" ")
+    }
+    /// The names of the roles the claims match
+    ///
+    /// `email` and `email_verified` are left out if the email can not be trusted. See [OidcClaims::trusted_email]
+    pub fn roles_for(&self, claims: &OidcClaims) -> Vec<&str> {
+        let mut claim_map = claims.as_map();
+        if claims.trusted_email(self.require_verified_email).is_none() {
+            claim_map.remove("email");
+            claim_map.remove("email_verified");
+        }
+        let mut roles: Vec<&str> = Vec::new();
+        for rule in &self.role_rules {
+            if rule.matches(&claim_map) && !roles.contains(&rule.role.as_str()) {
+                roles.push(&rule.role);
+            }
+        }
+        roles
+    }
+}
+/// Gives a local role to users whose claim matches
+///
+/// ```toml
+/// [[auth.oidc.role_rules]]
+/// claim = "groups"
+/// value = "son-clinicians"
+/// role = "Clinician"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OidcRoleRule {
+    /// The claim to check. Nested claims are separated by `.`
+    pub claim: String,
+    /// The value the claim must equal. If the claim is an array it must contain the value
+    pub value: String,
+    /// The name of the local role
+    pub role: String,
+}
+impl OidcRoleRule {
+    pub fn matches(&self, claims: &Map<String, Value>) -> bool {
+        let mut parts = self.claim.split('.');
+        let Some(mut current) = parts.next().and_then(|first| claims.get(first)) else {
+            return false;
+        };
+        for part in parts {
+            let Some(next) = current.get(part) else {
+                return false;
+            };
+            current = next;
+        }
+        match current {
+            Value::Array(values) => values.iter().any(|value| self.value_matches(value)),
+            value => self.value_matches(value),
+        }
+    }
+    fn value_matches(&self, value: &Value) -> bool {
+        match value {
+            Value::String(value) => *value == self.value,
+            Value::Bool(_) | Value::Number(_) => value.to_string() == self.value,
+            _ => false,
+        }
+    }
+}
+/// The claims of a validated ID token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OidcClaims {
+    pub iss: String,
+    /// The ID of the user at the provider
+    pub sub: String,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub given_name: Option<String>,
+    #[serde(default)]
+    pub family_name: Option<String>,
+    /// Every other claim. Used by [OidcRoleRule]
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+impl OidcClaims {
+    /// All claims including the named ones
+    pub fn as_map(&self) -> Map<String, Value> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map,
+            _ => self.other.clone(),
+        }
+    }
+    /// The email if it can be trusted
+    pub fn trusted_email(&self, require_verified: bool) -> Option<&str> {
+        if require_verified && self.email_verified != Some(true) {
+            return None;
+        }
+        self.email.as_deref().filter(|email| !email.is_empty())
+    }
+}
+/// The parts of `/.well-known/openid-configuration` that are used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OidcProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    #[serde(default)]
+    pub userinfo_endpoint: Option<String>,
+    #[serde(default)]
+    pub code_challenge_methods_supported: Vec<String>,
+}
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    id_token: Option<String>,
+}
+/// The error body of a token request. See RFC 6749 section 5.2
+#[derive(Debug, Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+/// A login started with [OidcClient::begin_login]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartedLogin {
+    /// The provider's login page
+    pub url: Url,
+    /// Must be stored in the browser. Compare it to the state of the callback before calling [OidcClient::finish_login]
+    pub state: String,
+}
+#[derive(Debug)]
+struct PendingLogin {
+    nonce: String,
+    code_verifier: String,
+    started: Instant,
+}
+#[derive(Debug)]
+pub struct OidcClient {
+    config: OidcConfig,
+    client: reqwest::Client,
+    metadata: OnceCell<OidcProviderMetadata>,
+    keys: RwLock<Option<JwkSet>>,
+    /// Logins that were started but not finished. Keyed by state. At most [OidcConfig::max_pending_logins]
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+impl OidcClient {
+    /// Creates the client. Discovery happens on the first login
+    pub fn new(config: OidcConfig) -> Result<Self, OidcError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(Self {
+            config,
+            client,
+            metadata: OnceCell::new(),
+            keys: RwLock::new(None),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+    /// The discovered provider metadata. Discovery is retried until it succeeds
+    #[instrument(skip(self))]
+    pub async fn metadata(&self) -> Result<&OidcProviderMetadata, OidcError> {
+        self.metadata.get_or_try_init(|| self.discover()).await
+    }
+    async fn discover(&self) -> Result<OidcProviderMetadata, OidcError> {
+        let issuer = self.config.issuer.trim_end_matches('/');
+        let url = format!("{issuer}/.well-known/openid-configuration");
+        debug!(?url, "Discovering OpenID provider");
+        let response = self.client.get(&url).send().await?;
+        let metadata: OidcProviderMetadata = check_status(response).await?.json().await?;
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(OidcError::IssuerMismatch {
+                expected: self.config.issuer.clone(),
+                found: metadata.issuer,
+            });
+        }
+        if !metadata.code_challenge_methods_supported.is_empty()
+            && !metadata
+                .code_challenge_methods_supported
+                .iter()
+                .any(|method| method == "S256")
+        {
+            warn!("The OpenID provider does not list S256 as a supported PKCE method");
+        }
+        Ok(metadata)
+    }
+    /// Starts a login. Returns the URL to send the user to and the state to store in their browser
+    #[instrument(skip(self))]
+    pub async fn begin_login(&self) -> Result<StartedLogin, OidcError> {
+        let metadata = self.metadata().await?;
+        let state = random_string(32);
+        let nonce = random_string(32);
+        let code_verifier = random_string(64);
+
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", &self.config.scope())
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge(&code_verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        let mut pending = self.pending.lock().await;
+        let timeout = self.login_timeout();
+        pending.retain(|_, login| login.started.elapsed() < timeout);
+        if pending.len() >= self.config.max_pending_logins {
+            warn!(
+                pending = pending.len(),
+                "Refusing login. Too many logins in progress"
+            );
+            return Err(OidcError::TooManyPendingLogins);
+        }
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                nonce,
+                code_verifier,
+                started: Instant::now(),
+            },
+        );
+        Ok(StartedLogin { url, state })
+    }
+    /// Finishes a login started with [Self::begin_login]. Returns the claims of the validated ID token
+    ///
+    /// A state can only be used once. The caller must check that the state came from the browser that started the login
+    #[instrument(skip(self, code))]
+    pub async fn finish_login(&self, state: &str, code: &str) -> Result<OidcClaims, OidcError> {
+        let login = self
+            .pending
+            .lock()
+            .await
+            .remove(state)
+            .filter(|login| login.started.elapsed() < self.login_timeout())
+            .ok_or(OidcError::UnknownState)?;
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let response = self
+            .client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?;
+        let token: TokenResponse = check_status(response).await?.json().await?;
+        let id_token = token.id_token.ok_or(OidcError::MissingIdToken)?;
+        self.validate_id_token(&id_token, &login.nonce).await
+    }
+    /// Checks the signature, issuer, audience, expiry and nonce of the ID token
+    pub async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<OidcClaims, OidcError> {
+        let header = decode_header(id_token)?;
+        if !SUPPORTED_ALGORITHMS.contains(&header.alg) {
+            return Err(OidcError::UnsupportedAlgorithm(header.alg));
+        }
+        let metadata = self.metadata().await?;
+        let key = self.decoding_key(header.kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<OidcClaims>(id_token, &key, &validation)?.claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OidcError::NonceMismatch);
+        }
+        Ok(claims)
+    }
+    /// Finds the key in the cached JWKS. The JWKS is fetched again if the key is not found. In case the keys were rotated
+    async fn decoding_key(&self, kid: Option<String>) -> Result<DecodingKey, OidcError> {
+        if let Some(keys) = self.keys.read().await.as_ref()
+            && let Some(jwk) = find_key(keys, kid.as_deref())
+        {
+            return Ok(DecodingKey::from_jwk(jwk)?);
+        }
+        let metadata = self.metadata().await?;
+        debug!(?kid, "Fetching the provider's JWKS");
+        let response = self.client.get(&metadata.jwks_uri).send().await?;
+        let keys: JwkSet = check_status(response).await?.json().await?;
+
+        let mut cached = self.keys.write().await;
+        let keys = cached.insert(keys);
+        let jwk = find_key(keys, kid.as_deref()).ok_or(OidcError::UnknownKey(kid))?;
+        Ok(DecodingKey::from_jwk(jwk)?)
+    }
+    fn login_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.login_timeout)
+    }
+}
+/// Finds the key with the id. Tokens without a key id can only be used if the provider has one key
+fn find_key<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+}
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, OidcError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = match serde_json::from_str::<TokenErrorResponse>(&body) {
+        Ok(TokenErrorResponse {
+            error,
+            error_description: Some(description),
+        }) => format!("{error}: {description}"),
+        Ok(TokenErrorResponse { error, .. }) => error,
+        Err(_) => body,
+    };
+    Err(OidcError::Provider { status, message })
+}
+/// The S256 PKCE challenge of the verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+fn random_string(length: usize) -> String {
+    let mut rand = StdRng::from_os_rng();
+    (0..length)
+        .map(|_| rand.sample(Alphanumeric) as char)
+        .collect()
+}
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn claims(value: Value) -> OidcClaims {
+        serde_json::from_value(value).unwrap()
+    }
+    #[test]
+    pub fn role_rules() {
+        let mut config = OidcConfig::new("http://localhost", "client", "http://localhost/cb");
+        config.role_rules = vec![
+            OidcRoleRule {
+                claim: "groups".to_owned(),
+                value: "son-clinicians".to_owned(),
+                role: "Clinician".to_owned(),
+            },
+            OidcRoleRule {
+                claim: "app.admin".to_owned(),
+                value: "true".to_owned(),
+                role: "Admin".to_owned(),
+            },
+            OidcRoleRule {
+                claim: "email".to_owned(),
+                value: "admin@vcu.edu".to_owned(),
+                role: "Admin".to_owned(),
+            },
+        ];
+        let clinician = claims(json!({
+            "iss": "http://localhost",
+            "sub": "1",
+            "groups": ["students", "son-clinicians"],
+        }));
+        assert_eq!(config.roles_for(&clinician), vec!["Clinician"]);
+
+        let admin = claims(json!({
+            "iss": "http://localhost",
+            "sub": "2",
+            "email": "admin@vcu.edu",
+            "email_verified": true,
+            "groups": "son-clinicians",
+            "app": { "admin": true },
+        }));
+        assert_eq!(config.roles_for(&admin), vec!["Clinician", "Admin"]);
+
+        let nobody = claims(json!({
+            "iss": "http://localhost",
+            "sub": "3",
+            "app": { "admin": false },
+        }));
+        assert!(config.roles_for(&nobody).is_empty());
+
+        let unverified = claims(json!({
+            "iss": "http://localhost",
+            "sub": "4",
+            "email": "admin@vcu.edu",
+            "email_verified": false,
+        }));
+        assert!(config.roles_for(&unverified).is_empty());
+
+        config.require_verified_email = false;
+        assert_eq!(config.roles_for(&unverified), vec!["Admin"]);
+    }
+    #[test]
+    pub fn unverified_emails_are_not_trusted() {
+        let unverified = claims(json!({
+            "iss": "http://localhost",
+            "sub": "1",
+            "email": "user@vcu.edu",
+        }));
+        assert_eq!(unverified.trusted_email(true), None);
+        assert_eq!(unverified.trusted_email(false), Some("user@vcu.edu"));
+    }
+    #[test]
+    pub fn pkce_challenge() {
+        // Example from RFC 7636 Appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
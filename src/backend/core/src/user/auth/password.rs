@@ -0,0 +1,112 @@
+//! Checks passwords against the [PasswordConfig] policy.
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::PasswordConfig;
+/// A rule of the password policy that a password breaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type")]
+pub enum PasswordViolation {
+    /// The password has fewer characters than `min_length`
+    TooShort {
+        min_length: u8,
+    },
+    /// The password has more characters than `max_length`
+    TooLong {
+        max_length: u8,
+    },
+    MissingUppercase,
+    MissingLowercase,
+    MissingNumber,
+    /// Special characters are anything that is not a letter, number or whitespace
+    MissingSpecial,
+    /// The new password is the same as the current password.
+    ///
+    /// Not returned by [PasswordConfig::validate]. Checked when a user changes their own password
+    SameAsCurrent,
+}
+impl PasswordConfig {
+    /// Returns every rule the password breaks. Empty if the password is valid
+    pub fn validate(&self, password: &str) -> Vec<PasswordViolation> {
+        let mut violations = Vec::new();
+        let length = password.chars().count();
+        if length < self.min_length as usize {
+            violations.push(PasswordViolation::TooShort {
+                min_length: self.min_length,
+            });
+        }
+        if length > self.max_length as usize {
+            violations.push(PasswordViolation::TooLong {
+                max_length: self.max_length,
+            });
+        }
+        let checks = [
+            (
+                self.require_uppercase,
+                char::is_uppercase as fn(char) -> bool,
+                PasswordViolation::MissingUppercase,
+            ),
+            (
+                self.require_lowercase,
+                char::is_lowercase,
+                PasswordViolation::MissingLowercase,
+            ),
+            (
+                self.require_number,
+                char::is_numeric,
+                PasswordViolation::MissingNumber,
+            ),
+            (
+                self.require_special,
+                is_special,
+                PasswordViolation::MissingSpecial,
+            ),
+        ];
+        for (required, check, violation) in checks {
+            if required && !password.chars().any(check) {
+                violations.push(violation);
+            }
+        }
+        violations
+    }
+}
+fn is_special(c: char) -> bool {
+    !c.is_alphanumeric() && !c.is_whitespace()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn default_policy() {
+        let config = PasswordConfig::default();
+        assert!(config.validate("Password123").is_empty());
+        assert_eq!(
+            config.validate("password"),
+            vec![
+                PasswordViolation::MissingUppercase,
+                PasswordViolation::MissingNumber
+            ]
+        );
+        assert_eq!(
+            config.validate("Pa1"),
+            vec![PasswordViolation::TooShort { min_length: 8 }]
+        );
+        assert_eq!(
+            config.validate(&format!("Pa1{}", "a".repeat(62))),
+            vec![PasswordViolation::TooLong { max_length: 64 }]
+        );
+    }
+    #[test]
+    pub fn special_characters() {
+        let config = PasswordConfig {
+            require_special: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate("Password 123"),
+            vec![PasswordViolation::MissingSpecial]
+        );
+        assert!(config.validate("Password!123").is_empty());
+    }
+}
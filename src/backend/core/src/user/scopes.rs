@@ -57,6 +57,15 @@ pub enum Permissions {
         category = "Participants"
     )]
     UpdateParticipants,
+    /// A user who can run research queries.
+    ///
+    /// Queries search across all participants so this is not part of the Clinician role
+    #[permission(
+        key = "research:query",
+        title = "Research Queries",
+        category = "Research"
+    )]
+    ResearchQuery,
     /// A user who can view appointments
     #[permission(key = "schedule:read", title = "View Schedule", category = "Schedule")]
     ReadSchedule,
@@ -1,6 +1,7 @@
 use std::sync::Once;
 
 use crate::database::DBError;
+use crate::red_cap::api::RedCapClientConfig;
 
 use super::db::DBTestingConfig;
 use serde::{Deserialize, Serialize};
@@ -14,6 +15,11 @@ use tracing_subscriber::util::SubscriberInitExt;
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CoreTestingConfig {
     pub database: Option<DBTestingConfig>,
+    /// Set `url` to the address of a [mock](crate::red_cap::mock) to test without the VCU VPN
+    #[serde(default)]
+    pub red_cap: RedCapClientConfig,
+    /// Deprecated. Use `token` under `[red_cap]`
+    #[serde(default, skip_serializing)]
     pub red_cap_token: Option<String>,
 }
 impl CoreTestingConfig {
@@ -91,7 +97,10 @@ pub mod testing {
             }
         };
         let content = std::fs::read_to_string(&file_path)?;
-        let config: CoreTestingConfig = toml::from_str(&content)?;
+        let mut config: CoreTestingConfig = toml::from_str(&content)?;
+        config
+            .red_cap
+            .apply_deprecated_token(config.red_cap_token.take());
 
         Ok(Some(config))
     }
@@ -58,8 +58,13 @@ impl CreateUserCommand {
             match password {
                 Ok(password) => {
                     let password_value = password.to_string();
-                    create_or_update_user_password(user.id, &password_value, &mut *transaction)
-                        .await?;
+                    create_or_update_user_password(
+                        user.id,
+                        &password_value,
+                        false,
+                        &mut *transaction,
+                    )
+                    .await?;
                     println!("Password set for user {}", user.username);
                 }
                 Err(e) => {
@@ -1,8 +1,12 @@
-use cs25_303_core::database::DatabaseConfig;
+use cs25_303_core::{database::DatabaseConfig, red_cap::api::RedCapClientConfig};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct DataToolConfig {
+    #[serde(default)]
+    pub red_cap: RedCapClientConfig,
+    /// Deprecated. Use `token` under `[red_cap]`
+    #[serde(default, skip_serializing)]
     pub red_cap_token: Option<String>,
     pub database: DatabaseConfig,
 }
@@ -14,10 +18,35 @@ pub fn load_config(
         return Ok(DataToolConfig::default());
     }
     let content = std::fs::read_to_string(file_path)?;
-    let config: DataToolConfig = toml::from_str(&content)?;
+    parse_config(&content)
+}
+fn parse_config(content: &str) -> Result<DataToolConfig, anyhow::Error> {
+    let mut config: DataToolConfig = toml::from_str(content)?;
+    config
+        .red_cap
+        .apply_deprecated_token(config.red_cap_token.take());
     Ok(config)
 }
 #[cfg(test)]
+mod tests {
+    #[test]
+    pub fn old_red_cap_token_key_is_read() -> anyhow::Result<()> {
+        let config = super::parse_config(
+            r#"
+red_cap_token = "MY-API-TOKEN"
+[database]
+user = "postgres"
+password = "password"
+database = "cs_25_303"
+host = "localhost"
+port = 5432
+"#,
+        )?;
+        assert_eq!(config.red_cap.token.as_deref(), Some("MY-API-TOKEN"));
+        Ok(())
+    }
+}
+#[cfg(test)]
 pub mod testing {
     #[derive(Debug, Clone, Deserialize, Serialize)]
     pub struct DataToolTestingConfig {
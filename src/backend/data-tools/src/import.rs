@@ -0,0 +1,47 @@
+use std::{path::PathBuf, str::FromStr};
+
+use clap::Args;
+use cs25_303_core::red_cap::{
+    api::Format,
+    converter::RedCapConverter,
+    tasks::import::{ExportFile, import_record},
+};
+use tracing::error;
+
+use crate::config::DataToolConfig;
+
+#[derive(Debug, Clone, Args)]
+pub struct ImportFileCommand {
+    /// A flat Red Cap export of raw values
+    pub file: PathBuf,
+    /// The format of the file. `csv` or `json`
+    ///
+    /// Defaults to the file extension
+    #[clap(short, long, value_parser = Format::from_str)]
+    pub format: Option<Format>,
+}
+impl ImportFileCommand {
+    pub async fn run(self, config: DataToolConfig) -> anyhow::Result<()> {
+        let export = ExportFile::open(&self.file, self.format)?;
+        let database = cs25_303_core::database::connect(config.database.try_into()?, true).await?;
+        let mut converter = RedCapConverter::new(database.clone()).await?;
+
+        let total = export.records.len();
+        println!("Importing {total} records from {}", self.file.display());
+        let mut failed = Vec::new();
+        for (index, (record_id, rows)) in export.records.into_iter().enumerate() {
+            match import_record(record_id, rows, &database, &mut converter).await {
+                Ok(_) => println!("[{}/{total}] Imported record {record_id}", index + 1),
+                Err(err) => {
+                    error!(?record_id, ?err, "Failed to import record");
+                    println!("[{}/{total}] Record {record_id} failed: {err}", index + 1);
+                    failed.push(record_id);
+                }
+            }
+        }
+        if !failed.is_empty() {
+            anyhow::bail!("{} records failed to import: {:?}", failed.len(), failed);
+        }
+        Ok(())
+    }
+}
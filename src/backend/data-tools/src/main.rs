@@ -1,7 +1,10 @@
 use anyhow::Context;
 use clap::Parser;
 use config::DataToolConfig;
-use cs25_303_core::database::DatabaseConfig;
+use cs25_303_core::{
+    database::{DatabaseConfig, red_cap::questions::default::validate_default_questions},
+    red_cap::api::RedCapClientConfig,
+};
 use human_panic::setup_panic;
 use pull::PullParticipant;
 use random::RandomParticipantsCommand;
@@ -9,8 +12,11 @@ use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{Layer, filter, layer::SubscriberExt, util::SubscriberInitExt};
 pub mod admin;
 pub mod config;
+pub mod import;
+pub mod mock;
 pub mod pull;
 pub mod push;
+pub mod questions;
 pub mod random;
 use std::path::{Path, PathBuf};
 #[derive(Debug, Clone, Parser)]
@@ -35,14 +41,36 @@ pub enum Commands {
     /// ## WARNING
     /// You must be connected to the VCU VPN to pull from redcap
     PullParticipant(PullParticipant),
+    /// Pull every record from redcap
+    ///
+    /// Completed records are saved to a checkpoint file so an interrupted run can be resumed
+    ///
+    /// ## WARNING
+    /// You must be connected to the VCU VPN to pull from redcap
+    PullAllParticipants(pull::PullAllParticipants),
     /// Push a participant to redcap
     ///
     /// ## WARNING
     ///
     /// You must be connected to the VCU VPN to push to redcap
     PushParticipant(push::PushParticipant),
+    /// Imports a flat CSV or JSON export of Red Cap
+    ///
+    /// Does not need access to Red Cap
+    ImportFile(import::ImportFileCommand),
     CreateUser(admin::CreateUserCommand),
     SaveDefaultConfig,
+    /// Checks the requirement scripts of the default questions
+    ValidateQuestions,
+    /// Compares the questions with the Red Cap data dictionary
+    ///
+    /// ## WARNING
+    /// You must be connected to the VCU VPN to access redcap
+    DiffQuestions(questions::DiffQuestionsCommand),
+    /// Runs a local Red Cap API for testing without the VCU VPN
+    MockRedCap(mock::MockRedCapCommand),
+    /// Runs a local OpenID Connect provider for testing single sign-on
+    MockOidc(mock::MockOidcCommand),
 }
 
 #[tokio::main]
@@ -60,12 +88,19 @@ async fn main() -> anyhow::Result<()> {
         Commands::PullParticipant(command) => {
             pull::execute(command, config_file).await?;
         }
+        Commands::PullAllParticipants(command) => {
+            pull::execute_all(command, config_file).await?;
+        }
         Commands::PushParticipant(command) => {
             push::execute(command, config_file).await?;
         }
+        Commands::ImportFile(command) => {
+            command.run(config_file).await?;
+        }
         Commands::SaveDefaultConfig => {
             let default_config = DataToolConfig {
-                red_cap_token: Some("MY-API-TOKEN".to_string()),
+                red_cap: RedCapClientConfig::with_token("MY-API-TOKEN"),
+                red_cap_token: None,
                 database: DatabaseConfig::default(),
             };
             let toml = toml::to_string(&default_config)?;
@@ -75,6 +110,25 @@ async fn main() -> anyhow::Result<()> {
         Commands::CreateUser(command) => {
             command.run(config_file).await?;
         }
+        Commands::ValidateQuestions => {
+            let invalid = validate_default_questions().await?;
+            if !invalid.is_empty() {
+                for invalid in &invalid {
+                    println!("{invalid}");
+                }
+                anyhow::bail!("{} invalid requirement scripts", invalid.len());
+            }
+            info!("All requirement scripts are valid");
+        }
+        Commands::DiffQuestions(command) => {
+            command.run(config_file).await?;
+        }
+        Commands::MockRedCap(command) => {
+            command.run().await?;
+        }
+        Commands::MockOidc(command) => {
+            command.run().await?;
+        }
     }
     Ok(())
 }
@@ -0,0 +1,92 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::Args;
+use cs25_303_core::{
+    red_cap::mock::{MockRedCap, MockRedCapConfig},
+    user::auth::oidc::mock::{MockOidc, MockOidcConfig, mock_user},
+};
+use tracing::info;
+
+#[derive(Debug, Clone, Args)]
+pub struct MockRedCapCommand {
+    /// The address to serve the mock on
+    #[clap(long, default_value = "127.0.0.1:8081")]
+    pub bind: SocketAddr,
+    /// The token clients must send
+    #[clap(long, default_value = "MOCK-RED-CAP-TOKEN")]
+    pub token: String,
+    /// Where to save the records. Records are only kept in memory if not set
+    #[clap(long)]
+    pub file: Option<PathBuf>,
+}
+impl MockRedCapCommand {
+    pub async fn run(self) -> anyhow::Result<()> {
+        let Self { bind, token, file } = self;
+        let mock = MockRedCap::new(MockRedCapConfig { token, file })
+            .await?
+            .start(bind)
+            .await?;
+        info!(
+            "Mock Red Cap running at {}. Set `red_cap.url` to this in your config",
+            mock.url()
+        );
+        tokio::signal::ctrl_c().await?;
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Args)]
+pub struct MockOidcCommand {
+    /// The address to serve the mock on
+    #[clap(long, default_value = "127.0.0.1:8082")]
+    pub bind: SocketAddr,
+    #[clap(long, default_value = "mock-client")]
+    pub client_id: String,
+    /// If set the token endpoint requires it
+    #[clap(long)]
+    pub client_secret: Option<String>,
+    /// The `sub` claim of the user that logs in
+    #[clap(long, default_value = "mock-user")]
+    pub sub: String,
+    #[clap(long, default_value = "mock.user@vcu.edu")]
+    pub email: String,
+    #[clap(long, default_value = "Mock")]
+    pub first_name: String,
+    #[clap(long, default_value = "User")]
+    pub last_name: String,
+    /// Put in the `groups` claim. For testing role rules
+    #[clap(long, value_delimiter = ',')]
+    pub groups: Vec<String>,
+}
+impl MockOidcCommand {
+    pub async fn run(self) -> anyhow::Result<()> {
+        let Self {
+            bind,
+            client_id,
+            client_secret,
+            sub,
+            email,
+            first_name,
+            last_name,
+            groups,
+        } = self;
+        let mut user = mock_user(&sub, &email, &first_name, &last_name);
+        if !groups.is_empty() {
+            user.insert("groups".to_owned(), groups.into());
+        }
+        let mock = MockOidc::start(
+            MockOidcConfig {
+                client_id,
+                client_secret,
+                user,
+            },
+            bind,
+        )
+        .await?;
+        info!(
+            "Mock OpenID provider running at {}. Set `auth.oidc.issuer` to this in your config",
+            mock.issuer()
+        );
+        tokio::signal::ctrl_c().await?;
+        Ok(())
+    }
+}
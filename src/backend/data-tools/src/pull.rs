@@ -1,10 +1,27 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fmt::Display,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
 use anyhow::Context;
 use clap::Args;
-use cs25_303_core::red_cap::{
-    api::RedcapClient,
-    converter::RedCapConverter,
-    tasks::{pull_case_notes, pull_goals, pull_medications, pull_record_base_types},
+use cs25_303_core::{
+    database::red_cap::sync_events::RedCapSyncInitiator,
+    red_cap::{
+        api::RedcapClient,
+        converter::RedCapConverter,
+        tasks::{pull_case_notes, pull_goals, pull_medications, pull_record_base_types},
+    },
 };
+use sqlx::PgPool;
+use tabled::{Table, Tabled};
+use tokio::sync::{Mutex, mpsc};
+use tracing::{error, info, warn};
 
 use crate::config::DataToolConfig;
 
@@ -17,13 +34,16 @@ pub struct PullParticipant {
 }
 
 pub async fn execute(pull: PullParticipant, config: DataToolConfig) -> anyhow::Result<()> {
-    let Some(api_key) = config.red_cap_token else {
+    if config.red_cap.token.is_none() {
         anyhow::bail!("No redcap token provided")
-    };
+    }
 
-    let client = RedcapClient::new(api_key)
+    let client = RedcapClient::with_config(&config.red_cap)
         .await
         .context("Failed to connect to the redcap system")?;
+    let client = client.with_initiator(RedCapSyncInitiator::Job(
+        "data-tools pull-participant".to_owned(),
+    ));
     let database = cs25_303_core::database::connect(config.database.try_into()?, true).await?;
 
     let mut converter: RedCapConverter = RedCapConverter::new(database.clone()).await?;
@@ -35,3 +55,236 @@ pub async fn execute(pull: PullParticipant, config: DataToolConfig) -> anyhow::R
     pull_case_notes(pull.participant_id, &database, &mut converter, &client).await?;
     Ok(())
 }
+
+#[derive(Debug, Clone, Args)]
+pub struct PullAllParticipants {
+    /// How many records to pull at the same time
+    #[clap(short, long, default_value = "4")]
+    pub concurrency: usize,
+    /// File used to record the completed record ids
+    ///
+    /// Records listed in this file are skipped so an interrupted run can be resumed
+    #[clap(long, default_value = "pull-all.checkpoint")]
+    pub checkpoint: PathBuf,
+    /// Ignore and replace the existing checkpoint file
+    #[clap(long)]
+    pub restart: bool,
+}
+/// The part of a record that failed to be pulled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PullStep {
+    Participant,
+    Medications,
+    Goals,
+    CaseNotes,
+}
+impl Display for PullStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PullStep::Participant => "Participant",
+            PullStep::Medications => "Medications",
+            PullStep::Goals => "Goals",
+            PullStep::CaseNotes => "Case Notes",
+        };
+        f.write_str(name)
+    }
+}
+#[derive(Debug, Clone, Tabled)]
+pub struct PullFailure {
+    #[tabled(rename = "Record")]
+    pub record_id: i32,
+    #[tabled(rename = "Form")]
+    pub step: PullStep,
+    #[tabled(rename = "Error")]
+    pub error: String,
+}
+#[derive(Debug)]
+struct RecordPullResult {
+    record_id: i32,
+    failures: Vec<PullFailure>,
+}
+
+pub async fn execute_all(pull: PullAllParticipants, config: DataToolConfig) -> anyhow::Result<()> {
+    if config.red_cap.token.is_none() {
+        anyhow::bail!("No redcap token provided")
+    }
+    if pull.concurrency == 0 {
+        anyhow::bail!("Concurrency must be at least 1")
+    }
+    let client = RedcapClient::with_config(&config.red_cap)
+        .await
+        .context("Failed to connect to the redcap system")?;
+    let client = client.with_initiator(RedCapSyncInitiator::Job(
+        "data-tools pull-all-participants".to_owned(),
+    ));
+    let client = Arc::new(client);
+    let database = cs25_303_core::database::connect(config.database.try_into()?, true).await?;
+
+    if pull.restart && pull.checkpoint.exists() {
+        std::fs::remove_file(&pull.checkpoint)?;
+    }
+    let completed = load_checkpoint(&pull.checkpoint)?;
+
+    let record_ids = client.get_record_ids(None).await?;
+    let total = record_ids.len();
+    let remaining: VecDeque<i32> = record_ids
+        .into_iter()
+        .filter(|record_id| !completed.contains(record_id))
+        .collect();
+    let skipped = total - remaining.len();
+    if skipped > 0 {
+        info!(?skipped, "Skipping records completed by a previous run");
+    }
+    println!(
+        "Pulling {} of {} records with {} workers",
+        remaining.len(),
+        total,
+        pull.concurrency
+    );
+    let to_pull = remaining.len();
+    let queue = Arc::new(Mutex::new(remaining));
+    let (sender, mut receiver) = mpsc::channel(pull.concurrency);
+
+    let mut workers = Vec::with_capacity(pull.concurrency);
+    for _ in 0..pull.concurrency {
+        let converter = RedCapConverter::new(database.clone()).await?;
+        workers.push(tokio::spawn(pull_worker(
+            queue.clone(),
+            sender.clone(),
+            database.clone(),
+            converter,
+            client.clone(),
+        )));
+    }
+    // Only the workers hold senders now so the channel closes when they finish
+    drop(sender);
+
+    let mut checkpoint = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&pull.checkpoint)
+        .with_context(|| format!("Failed to open {}", pull.checkpoint.display()))?;
+    let started = Instant::now();
+    let mut finished = 0;
+    let mut failed_records = 0;
+    let mut failures = Vec::new();
+    while let Some(result) = receiver.recv().await {
+        finished += 1;
+        if result.failures.is_empty() {
+            writeln!(checkpoint, "{}", result.record_id)?;
+            checkpoint.flush()?;
+            println!(
+                "[{finished}/{to_pull}] Pulled record {} ({:.0?} elapsed)",
+                result.record_id,
+                started.elapsed()
+            );
+        } else {
+            failed_records += 1;
+            println!(
+                "[{finished}/{to_pull}] Record {} had {} failures",
+                result.record_id,
+                result.failures.len()
+            );
+            failures.extend(result.failures);
+        }
+    }
+    for worker in workers {
+        worker.await?;
+    }
+
+    println!(
+        "Pulled {} records in {:.0?}. {} records failed",
+        finished - failed_records,
+        started.elapsed(),
+        failed_records
+    );
+    if !failures.is_empty() {
+        failures.sort_by_key(|failure| failure.record_id);
+        println!("{}", Table::new(&failures));
+        anyhow::bail!(
+            "{failed_records} records failed to pull. Run the command again to retry them"
+        );
+    }
+    Ok(())
+}
+/// Reads the record ids that were completed by a previous run
+fn load_checkpoint(path: &Path) -> anyhow::Result<BTreeSet<i32>> {
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut completed = BTreeSet::new();
+    for line in contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        match line.parse() {
+            Ok(record_id) => {
+                completed.insert(record_id);
+            }
+            Err(err) => {
+                warn!(?line, ?err, "Ignoring invalid line in checkpoint");
+            }
+        }
+    }
+    Ok(completed)
+}
+async fn pull_worker(
+    queue: Arc<Mutex<VecDeque<i32>>>,
+    results: mpsc::Sender<RecordPullResult>,
+    database: PgPool,
+    mut converter: RedCapConverter,
+    client: Arc<RedcapClient>,
+) {
+    loop {
+        let Some(record_id) = queue.lock().await.pop_front() else {
+            break;
+        };
+        let failures = pull_all_forms(record_id, &database, &mut converter, &client).await;
+        if results
+            .send(RecordPullResult {
+                record_id,
+                failures,
+            })
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+/// Pulls every form of a record. Continues past failures so each failing form is reported
+///
+/// If the participant itself fails the rest of the forms are skipped as they depend on it
+async fn pull_all_forms(
+    record_id: i32,
+    database: &PgPool,
+    converter: &mut RedCapConverter,
+    client: &RedcapClient,
+) -> Vec<PullFailure> {
+    let mut failures = Vec::new();
+    let mut failed = |step: PullStep, error: String| {
+        error!(?record_id, %step, %error, "Failed to pull record");
+        failures.push(PullFailure {
+            record_id,
+            step,
+            error,
+        });
+    };
+    if let Err(err) = pull_record_base_types(record_id, database, converter, client).await {
+        failed(PullStep::Participant, err.to_string());
+        return failures;
+    }
+    if let Err(err) = pull_medications(record_id, database, client).await {
+        failed(PullStep::Medications, err.to_string());
+    }
+    if let Err(err) = pull_goals(record_id, database, client).await {
+        failed(PullStep::Goals, err.to_string());
+    }
+    if let Err(err) = pull_case_notes(record_id, database, converter, client).await {
+        failed(PullStep::CaseNotes, err.to_string());
+    }
+    failures
+}
@@ -1,6 +1,6 @@
 use clap::Args;
 use cs25_303_core::{
-    database::red_cap::participants::Participants,
+    database::red_cap::{participants::Participants, sync_events::RedCapSyncInitiator},
     red_cap::{api::RedcapClient, converter::RedCapConverter, tasks::push::*},
 };
 
@@ -12,17 +12,40 @@ pub struct PushParticipant {
     pub participant_id: Option<i32>,
     #[clap(long)]
     pub all: bool,
+    /// Show what would be sent to redcap and how it differs from the current values without pushing
+    #[clap(long)]
+    pub dry_run: bool,
 }
 
 pub async fn execute(pull: PushParticipant, config: DataToolConfig) -> anyhow::Result<()> {
-    let Some(api_key) = config.red_cap_token else {
+    if config.red_cap.token.is_none() {
         anyhow::bail!("No redcap token provided")
-    };
+    }
 
-    let client = RedcapClient::new(api_key).await?;
+    let client = RedcapClient::with_config(&config.red_cap)
+        .await?
+        .with_initiator(RedCapSyncInitiator::Job(
+            "data-tools push-participant".to_owned(),
+        ));
     let database = cs25_303_core::database::connect(config.database.try_into()?, true).await?;
 
     let mut converter: RedCapConverter = RedCapConverter::new(database.clone()).await?;
+    if pull.dry_run {
+        let participants = match (pull.participant_id, pull.all) {
+            (Some(participant_id), _) => vec![participant_id],
+            (None, true) => Participants::get_all_ids(&database).await?,
+            (None, false) => {
+                anyhow::bail!("You must provide either a participant id or the --all flag")
+            }
+        };
+        for participant_id in participants {
+            let preview =
+                preview_participant_push(participant_id, &database, &mut converter, &client)
+                    .await?;
+            print_preview(&preview);
+        }
+        return Ok(());
+    }
     if let Some(participant_id) = pull.participant_id {
         push_participant_to_red_cap(participant_id, &database, &mut converter, &client).await?;
         push_participant_medications_to_red_cap(participant_id, &database, &client).await?;
@@ -45,3 +68,36 @@ pub async fn execute(pull: PushParticipant, config: DataToolConfig) -> anyhow::R
         anyhow::bail!("You must provide either a participant id or the --all flag");
     }
 }
+fn print_preview(preview: &ParticipantPushPreview) {
+    if preview.new_record {
+        println!(
+            "Participant {} would be created as record {}",
+            preview.participant_id, preview.record_id
+        );
+    } else {
+        println!(
+            "Participant {} (record {})",
+            preview.participant_id, preview.record_id
+        );
+    }
+    for row in &preview.rows {
+        let row_name = match (&row.repeat_instrument, &row.repeat_instance) {
+            (Some(instrument), Some(instance)) => format!("{instrument} #{instance}"),
+            (Some(instrument), None) => instrument.clone(),
+            _ => "Record".to_owned(),
+        };
+        if row.changes.is_empty() {
+            println!("  {row_name}: No changes");
+            continue;
+        }
+        println!("  {row_name}:");
+        for change in &row.changes {
+            println!(
+                "    {}: {:?} -> {:?}",
+                change.field,
+                change.old.as_deref().unwrap_or_default(),
+                change.new
+            );
+        }
+    }
+}
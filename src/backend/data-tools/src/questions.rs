@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Args;
+use cs25_303_core::red_cap::{
+    api::RedcapClient,
+    question_diff::{QuestionWithOptions, diff_questions, generate_question_files},
+};
+use tracing::info;
+
+use crate::config::DataToolConfig;
+
+#[derive(Debug, Clone, Args)]
+pub struct DiffQuestionsCommand {
+    /// Fields that are read outside of the question system and should not be reported as unmapped
+    #[clap(short, long, value_delimiter = ',')]
+    pub ignore: Vec<String>,
+    /// Write a question file for each form with unmapped fields to this directory
+    #[clap(short, long)]
+    pub generate: Option<PathBuf>,
+}
+impl DiffQuestionsCommand {
+    pub async fn run(self, config: DataToolConfig) -> anyhow::Result<()> {
+        let client = RedcapClient::with_config(&config.red_cap)
+            .await
+            .context("Failed to connect to the redcap system")?;
+        let database = cs25_303_core::database::connect(config.database.try_into()?, true).await?;
+
+        let fields = client.get_metadata().await?;
+        let questions = QuestionWithOptions::get_all_active(&database).await?;
+        info!(
+            fields = fields.len(),
+            questions = questions.len(),
+            "Comparing questions with Red Cap"
+        );
+        let mut diff = diff_questions(&fields, &questions);
+        diff.unmapped_fields
+            .retain(|field| !self.ignore.contains(&field.field_name));
+
+        for difference in &diff.differences {
+            println!("{difference}");
+        }
+        for field in &diff.unmapped_fields {
+            println!(
+                "{}: Unmapped {} field in {}",
+                field.field_name, field.field_type, field.form_name
+            );
+        }
+        println!(
+            "{} differences and {} unmapped fields",
+            diff.differences.len(),
+            diff.unmapped_fields.len()
+        );
+
+        if let Some(directory) = self.generate {
+            std::fs::create_dir_all(&directory)?;
+            for (file_name, questions) in generate_question_files(&diff.unmapped_fields) {
+                let path = directory.join(file_name);
+                std::fs::write(&path, serde_json::to_string_pretty(&questions)?)?;
+                println!("Wrote {}", path.display());
+            }
+        }
+        Ok(())
+    }
+}
@@ -107,9 +107,10 @@ pub async fn generate_participants(count: usize, database: PgPool) -> anyhow::Re
             generate_random_case_note_on(&mut random_sets, part.clone(), date_of_visit, &database)
                 .await?;
         }
-        ParticipantGoals::process_red_cap_indexes(part.id, &database).await?;
-        ParticipantGoalsSteps::process_red_cap_indexes(part.id, &database).await?;
-        ParticipantMedications::process_medications_indexes(part.id, &database).await?;
+        let mut connection = database.acquire().await?;
+        ParticipantGoals::process_red_cap_indexes(part.id, &mut connection).await?;
+        ParticipantGoalsSteps::process_red_cap_indexes(part.id, &mut connection).await?;
+        ParticipantMedications::process_medications_indexes(part.id, &mut connection).await?;
     }
     Ok(())
 }